@@ -0,0 +1,152 @@
+// Runs a WebAssembly module made by `foxglove --wasm`, in a browser or in node.
+//
+//     import { run } from "./foxglove.js";
+//
+//     await run(await (await fetch("program.wasm")).arrayBuffer());
+//
+// Text the program prints is passed to `write`, which defaults to stdout in
+// node and to one `console.log` per line elsewhere. A runtime error rejects
+// the returned promise.
+
+export async function run(bytes, write = defaultWriter()) {
+  let memory;
+
+  const decoder = new TextDecoder();
+  const str = (ptr, len) => decoder.decode(new Uint8Array(memory.buffer, ptr, len));
+
+  const fail = (message) => {
+    throw new Error(`Runtime error: ${message}`);
+  };
+
+  const env = {
+    write_str: (ptr, len) => write(str(ptr, len)),
+    // negative numbers get a minus sign in every base, rather than their
+    // two's complement
+    write_int: (n, radix) => write(n.toString(radix)),
+    write_float: (x) => write(showFloat(x)),
+    write_fixed: (x, digits) => write(fixed(x, digits)),
+    write_char: (c, debug) => write(debug ? `'${escapeChar(c)}'` : String.fromCodePoint(c)),
+    pow_float: Math.pow,
+    rem_float: (a, b) => a % b,
+    fail: (ptr, len) => fail(str(ptr, len)),
+    fail_to_int: (x) => fail(`cannot convert ${showFloat(x)} to int`),
+    fail_to_char: (n) => fail(`${n} is not a valid char`),
+  };
+
+  const { instance } = await WebAssembly.instantiate(bytes, { env });
+
+  memory = instance.exports.memory;
+
+  try {
+    instance.exports.main();
+  } finally {
+    write.flush?.();
+  }
+}
+
+function defaultWriter() {
+  if (typeof process !== "undefined" && process.stdout) {
+    return (text) => process.stdout.write(text);
+  }
+
+  let line = "";
+
+  const write = (text) => {
+    const lines = (line + text).split("\n");
+
+    line = lines.pop();
+    lines.forEach((line) => console.log(line));
+  };
+
+  write.flush = () => {
+    if (line) {
+      console.log(line);
+      line = "";
+    }
+  };
+
+  return write;
+}
+
+// Shows a float the way the interpreter does, with a fractional part even
+// if it's a whole number and an exponent if it's very large or very small
+function showFloat(x) {
+  if (Number.isNaN(x)) return "NaN";
+  if (x === Infinity) return "inf";
+  if (x === -Infinity) return "-inf";
+  if (x === 0) return Object.is(x, -0) ? "-0.0" : "0.0";
+
+  if (Math.abs(x) >= 1e16 || Math.abs(x) < 1e-4) {
+    // the fewest digits that read back as the same float
+    const [mantissa, exponent] = x.toExponential().split("e");
+
+    return `${mantissa}e${Number(exponent)}`;
+  }
+
+  const text = String(x);
+
+  return text.includes(".") ? text : `${text}.0`;
+}
+
+// Shows a float with `digits` digits after the point, from its exact value and
+// rounding ties to even, which `toFixed` doesn't do
+function fixed(x, digits) {
+  if (Number.isNaN(x)) return "NaN";
+  if (x === Infinity) return "inf";
+  if (x === -Infinity) return "-inf";
+
+  const negative = x < 0 || Object.is(x, -0);
+
+  // x is exactly mantissa * 2 ** exponent
+  const view = new DataView(new ArrayBuffer(8));
+
+  view.setFloat64(0, Math.abs(x));
+
+  const bits = view.getBigUint64(0);
+  const biased = Number(bits >> 52n);
+  let mantissa = bits & ((1n << 52n) - 1n);
+
+  if (biased !== 0) mantissa |= 1n << 52n;
+
+  const exponent = Math.max(biased, 1) - 1075;
+  const scaled = mantissa * 10n ** BigInt(digits);
+
+  let n;
+
+  if (exponent >= 0) {
+    n = scaled << BigInt(exponent);
+  } else {
+    const divisor = 1n << BigInt(-exponent);
+    const remainder = (scaled % divisor) * 2n;
+
+    n = scaled / divisor;
+
+    if (remainder > divisor || (remainder === divisor && n % 2n === 1n)) n += 1n;
+  }
+
+  const text = n.toString().padStart(digits + 1, "0");
+  const point = text.length - digits;
+  const shown = digits > 0 ? `${text.slice(0, point)}.${text.slice(point)}` : text;
+
+  return negative ? `-${shown}` : shown;
+}
+
+// Escapes a char the way it's shown inside quotes
+function escapeChar(c) {
+  switch (c) {
+    case 0x09:
+      return "\\t";
+    case 0x0a:
+      return "\\n";
+    case 0x0d:
+      return "\\r";
+    case 0x27:
+      return "\\'";
+    case 0x5c:
+      return "\\\\";
+  }
+
+  if (c < 0x20 || c === 0x7f) return `\\u{${c.toString(16)}}`;
+
+  return String.fromCodePoint(c);
+}
@@ -1,4 +1,4 @@
-use crate::Spanned;
+use crate::{Span, Spanned};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Ast<'src> {
@@ -10,29 +10,125 @@ pub enum Statement<'src> {
     Expr(s!(Expr<'src>)),
     Block(s!(Vec<s!(Statement<'src>)>)),
     Let {
+        mutable: bool,
         name: s!(&'src str),
-        ty: Option<s!(Type)>,
+        ty: Option<s!(Type<'src>)>,
+        value: s!(Expr<'src>),
+    },
+    /// Only allowed at the top level of a file
+    Const {
+        name: s!(&'src str),
+        ty: Option<s!(Type<'src>)>,
         value: s!(Expr<'src>),
     },
     Function {
         name: s!(&'src str),
-        params: s!(Vec<(s!(&'src str), s!(Type))>),
-        return_ty: Option<s!(Type)>,
+        /// The type parameters in `func name<T: Trait, U>(...)`, with the
+        /// traits each one has to implement
+        generics: Vec<(s!(&'src str), Vec<s!(&'src str)>)>,
+        params: s!(Vec<(s!(&'src str), s!(Type<'src>))>),
+        return_ty: Option<s!(Type<'src>)>,
         body: Box<s!(Statement<'src>)>,
     },
     Assign {
         name: s!(&'src str),
         value: s!(Expr<'src>),
     },
-    Print(Option<s!(Expr<'src>)>),
+    /// `name[index] = value;`, setting a list element or inserting into a map
+    SetIndex {
+        name: s!(&'src str),
+        index: s!(Expr<'src>),
+        value: s!(Expr<'src>),
+    },
     Return(Option<s!(Expr<'src>)>),
+    Enum {
+        name: s!(&'src str),
+        variants: s!(Vec<(s!(&'src str), Vec<s!(Type<'src>)>)>),
+    },
+    Struct {
+        name: s!(&'src str),
+        fields: s!(Vec<(s!(&'src str), s!(Type<'src>))>),
+    },
+    TypeAlias {
+        name: s!(&'src str),
+        ty: s!(Type<'src>),
+    },
+    /// `break 'label value;` out of a labeled block, or `break;` out of the
+    /// innermost loop, which can have a value if it's a `loop`
+    Break {
+        label: Option<s!(&'src str)>,
+        value: Option<s!(Expr<'src>)>,
+    },
+    Continue,
+    While {
+        cond: s!(Expr<'src>),
+        body: s!(Vec<s!(Statement<'src>)>),
+    },
+    /// `for x in iter { ... }` or `for i, x in iter { ... }`. A list gives its
+    /// elements, or indices and elements, a map its keys, or keys and values,
+    /// a str its chars, or indices and chars, and a range its ints, or indices
+    /// and ints.
+    For {
+        first: s!(&'src str),
+        second: Option<s!(&'src str)>,
+        iter: s!(Expr<'src>),
+        body: s!(Vec<s!(Statement<'src>)>),
+    },
+    Import {
+        path: s!(String),
+        /// The name of a module imported with `import name;`, whose top level
+        /// bindings are reached through `name::binding` instead of being
+        /// brought into scope like with `import "path";`
+        module: Option<s!(&'src str)>,
+        /// The index of the imported file in the source map, filled in by the loader
+        file: usize,
+        /// The imported file's statements, filled in by the loader
+        statements: Vec<s!(Statement<'src>)>,
+    },
+    Trait {
+        name: s!(&'src str),
+        methods: Vec<s!(MethodSig<'src>)>,
+    },
+    /// `impl Trait for Type { ... }`, or `impl Type { ... }` for methods of
+    /// the type's own
+    Impl {
+        trait_: Option<s!(&'src str)>,
+        ty: s!(Type<'src>),
+        methods: Vec<(s!(MethodSig<'src>), s!(Statement<'src>))>,
+    },
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum Type {
-    Num,
+/// `func name(self, x: int): str`, the signature of a method in a trait or an impl
+#[derive(Clone, Debug, PartialEq)]
+pub struct MethodSig<'src> {
+    pub name: s!(&'src str),
+    /// The span of `self`
+    pub self_: Span,
+    /// The parameters after `self`
+    pub params: s!(Vec<(s!(&'src str), s!(Type<'src>))>),
+    pub return_ty: Option<s!(Type<'src>)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Type<'src> {
+    Int,
+    Float,
     Bool,
+    Str,
+    Char,
     Unit,
+    /// `range`, the type of `a..b` and `a..=b`
+    Range,
+    Named(&'src str),
+    Optional(Box<Type<'src>>),
+    List(Box<Type<'src>>),
+    Map(Box<Type<'src>>, Box<Type<'src>>),
+    Result(Box<Type<'src>>, Box<Type<'src>>),
+    Record(Vec<(&'src str, Type<'src>)>),
+    /// `func(int, str): bool`, the return type is unit if it's left out
+    Func(Vec<Type<'src>>, Box<Type<'src>>),
+    /// `&T`, or `&mut T` if it's mutable, only usable for parameters
+    Ref(bool, Box<Type<'src>>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -52,18 +148,161 @@ pub enum Expr<'src> {
         callee: Box<s!(Expr<'src>)>,
         args: s!(Vec<s!(Expr<'src>)>),
     },
+    FieldAccess {
+        expr: Box<s!(Expr<'src>)>,
+        field: s!(&'src str),
+    },
+    Index {
+        expr: Box<s!(Expr<'src>)>,
+        index: Box<s!(Expr<'src>)>,
+    },
+    /// `Enum::Variant(args)`, or `module::name` which is told apart from a
+    /// variant by the typechecker. `args` is `None` without the parentheses.
+    Variant {
+        enum_: s!(&'src str),
+        variant: s!(&'src str),
+        args: Option<s!(Vec<s!(Expr<'src>)>)>,
+    },
+    Match {
+        expr: Box<s!(Expr<'src>)>,
+        arms: s!(Vec<(s!(Pattern<'src>), s!(Expr<'src>))>),
+    },
+    Some(Box<s!(Expr<'src>)>),
+    Ok(Box<s!(Expr<'src>)>),
+    Err(Box<s!(Expr<'src>)>),
+    /// `expr?`, returning the error of a result from the enclosing function
+    Try(Box<s!(Expr<'src>)>),
+    Is {
+        expr: Box<s!(Expr<'src>)>,
+        variant: s!(&'src str),
+    },
+    /// `&expr` or `&mut var`, passing a reference to a function
+    Ref {
+        mutable: bool,
+        expr: Box<s!(Expr<'src>)>,
+    },
+    /// `expr in collection`, whether an element of a list, a key of a map, a
+    /// char of a str or an int in a range is equal to `expr`
+    In {
+        expr: Box<s!(Expr<'src>)>,
+        collection: Box<s!(Expr<'src>)>,
+    },
+    /// `start..end`, or `start..=end` which includes `end`
+    Range {
+        start: Box<s!(Expr<'src>)>,
+        end: Box<s!(Expr<'src>)>,
+        inclusive: bool,
+    },
+    /// `expr as ty`
+    Cast {
+        expr: Box<s!(Expr<'src>)>,
+        ty: s!(Type<'src>),
+    },
+    Record(Vec<(s!(&'src str), s!(Expr<'src>))>),
+    /// `Name { field = value, ... }`
+    Struct {
+        name: s!(&'src str),
+        fields: Vec<(s!(&'src str), s!(Expr<'src>))>,
+    },
+    List(s!(Vec<s!(Expr<'src>)>)),
+    /// `{ key: value, ... }`, or `{:}` for an empty map
+    Map(s!(Vec<(s!(Expr<'src>), s!(Expr<'src>))>)),
+    LabeledBlock {
+        label: s!(&'src str),
+        statements: s!(Vec<s!(Statement<'src>)>),
+        tail: Option<Box<s!(Expr<'src>)>>,
+    },
+    If {
+        cond: Box<s!(Expr<'src>)>,
+        then: s!(Block<'src>),
+        /// `else if` is an else block holding only the nested `if`
+        else_: Option<s!(Block<'src>)>,
+    },
+    /// `loop { ... }`, which only ends by breaking out of it, and has the type
+    /// of the values it's broken out of with
+    Loop {
+        body: s!(Vec<s!(Statement<'src>)>),
+    },
+    Block(Block<'src>),
+    /// `try { ... } catch e { ... }`, running the handler with the message of
+    /// a runtime error in the body instead of stopping the program
+    Catch {
+        body: s!(Block<'src>),
+        binding: s!(&'src str),
+        handler: s!(Block<'src>),
+    },
+    /// `|x, y: int| body`, where parameters without a type have theirs inferred
+    Closure {
+        params: s!(Vec<(s!(&'src str), Option<s!(Type<'src>)>)>),
+        body: Box<s!(Expr<'src>)>,
+    },
+    /// A string with `{expr}` holes, alternating between text and holes
+    Interpolated(Vec<StrPart<'src>>),
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum StrPart<'src> {
+    Text(s!(String)),
+    Expr(s!(Expr<'src>)),
+    /// `{}` or `{:spec}`, filled in by an argument of `print`, `println` or
+    /// `format`, which are the only places a string with one can be used
+    Placeholder(s!(Option<FormatSpec>)),
+}
+
+/// How a placeholder shows its argument
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatSpec {
+    /// `{:?}`, quoting strings and chars
+    Debug,
+    /// `{:x}`, an int in hexadecimal
+    Hex,
+    /// `{:b}`, an int in binary
+    Binary,
+    /// `{:o}`, an int in octal
+    Octal,
+    /// `{:.2}`, a float with this many digits after the point
+    Precision(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block<'src> {
+    pub statements: Vec<s!(Statement<'src>)>,
+    pub tail: Option<Box<s!(Expr<'src>)>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern<'src> {
+    Ident(s!(&'src str)),
+    Variant {
+        variant: s!(&'src str),
+        bindings: s!(Vec<s!(&'src str)>),
+    },
+    /// `some(x)`, matching an optional that holds a value
+    Some(s!(&'src str)),
+    /// `none`, matching an empty optional
+    None,
+    /// `ok(x)`, matching a successful result
+    Ok(s!(&'src str)),
+    /// `err(e)`, matching a failed result
+    Err(s!(&'src str)),
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Literal {
-    Num(f64),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Char(char),
     Bool(bool),
     Unit,
+    None,
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PrefixOp {
     Negate,
+    Not,
+    BitNot,
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -72,6 +311,13 @@ pub enum BinOp {
     Subtract,
     Multiply,
     Divide,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
     Equals,
     NotEquals,
     LessThan,
@@ -80,6 +326,7 @@ pub enum BinOp {
     GreaterThanOrEqual,
     LogicalAnd,
     LogicalOr,
+    Coalesce,
 }
 
 macro_rules! s {
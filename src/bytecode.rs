@@ -0,0 +1,1414 @@
+use crate::gc::Handle;
+use crate::interpreter::Value;
+use crate::prelude::Builtin;
+use crate::typed_ast::*;
+use crate::{Span, Spanned};
+use std::collections::HashMap;
+
+/// A compiled program, made of functions whose instructions index into the
+/// tables kept next to them
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    pub functions: Vec<Function>,
+    /// The function holding the top level code of the main file, which runs
+    /// the other files' top level code where they're imported
+    pub main: usize,
+    pub constants: Vec<Value>,
+    /// Names of structs, enum variants, fields and methods
+    pub names: Vec<String>,
+    /// The fields of records and structs, in the order their values are pushed
+    pub shapes: Vec<Vec<String>>,
+    /// The types values are cast to
+    pub types: Vec<Type>,
+    /// The names of the top level variables of every file
+    pub globals: Vec<String>,
+    /// The function of every method, by the trait (`None` for a type's own
+    /// methods), the method and the name of the type it's for
+    pub methods: HashMap<(Option<String>, String, String), usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub name: String,
+    pub params: usize,
+    /// The number of slots for the params and local variables, which come
+    /// first in the function's part of the stack
+    pub locals: usize,
+    pub code: Vec<Op>,
+    /// Where each instruction came from, for runtime errors
+    pub spans: Vec<Span>,
+}
+
+/// An instruction. Stack heights are counted from the first value above
+/// the running function's locals.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Const(usize),
+    Pop,
+    /// Pushes the value on top of the stack again
+    Dup,
+    /// Pushes a local, through its cell if it's been borrowed
+    LoadLocal(usize),
+    /// Pops into a local being declared, replacing whatever an earlier
+    /// variable left in its slot
+    InitLocal(usize),
+    /// Pops into a local, through its cell if it's been borrowed
+    StoreLocal(usize),
+    LoadGlobal(usize),
+    InitGlobal(usize),
+    StoreGlobal(usize),
+    /// Pushes a copy of a variable taken when the running function or
+    /// closure was created
+    LoadCapture(usize),
+    /// Pushes a mutable reference to a local, moving its value into a cell
+    /// if it isn't borrowed yet
+    RefLocal(usize),
+    RefGlobal(usize),
+    Prefix(PrefixOp),
+    /// Any operator other than the short-circuiting ones
+    Binary(BinOp),
+    /// An operator with a constant on the right, which replaces the
+    /// constant being pushed first
+    BinaryConst(BinOp, usize),
+    /// An operator with a local on the right, which replaces the local
+    /// being pushed first
+    BinaryLocal(BinOp, usize),
+    Jump(usize),
+    /// Pops a bool and jumps if it's false
+    JumpIfFalse(usize),
+    /// Pops a bool and jumps if it's true
+    JumpIfTrue(usize),
+    /// Unwraps an optional holding a value and jumps, or pops an empty one
+    Coalesce(usize),
+    /// Calls the function or closure below the arguments, replacing it and
+    /// them with the result
+    Call {
+        args: usize,
+    },
+    /// Calls a method on the value below the arguments, found by the
+    /// receiver's type
+    CallMethod {
+        trait_: Option<usize>,
+        method: usize,
+        args: usize,
+    },
+    CallBuiltin {
+        builtin: Builtin,
+        args: usize,
+    },
+    /// Ends the running function with the value on top of the stack
+    Return,
+    /// Pops the variables a nested function captures, and pushes the function
+    MakeFunction {
+        function: usize,
+        captures: usize,
+    },
+    MakeClosure {
+        function: usize,
+        captures: usize,
+    },
+    MakeList(usize),
+    /// Pops pairs of keys and values
+    MakeMap(usize),
+    MakeRecord {
+        shape: usize,
+    },
+    MakeStruct {
+        name: usize,
+        shape: usize,
+    },
+    MakeVariant {
+        enum_: usize,
+        variant: usize,
+        fields: usize,
+    },
+    MakeRange {
+        inclusive: bool,
+    },
+    WrapSome,
+    WrapOk,
+    WrapErr,
+    GetField(usize),
+    /// Pops an index and a collection, and pushes the element
+    Index,
+    /// Pops an index and a value, and puts the value in a local at the index
+    SetIndexLocal(usize),
+    SetIndexGlobal(usize),
+    /// Pops a collection and a value, and pushes whether it's in there
+    In,
+    /// Replaces an enum value with whether it's the named variant
+    Is(usize),
+    Cast(usize),
+    /// Unwraps an ok result, or returns an err result from the function
+    Try,
+    /// Pops a value, and pushes what the pattern binds if it matches or
+    /// jumps otherwise
+    Unpack(Unpack, usize),
+    /// Pushes what a `for` loop binds for the next item of the collection in
+    /// a local, counting items in another local, or jumps if there are no
+    /// items left
+    Next {
+        collection: usize,
+        counter: usize,
+        bindings: Bindings,
+        done: usize,
+    },
+    /// Drops values until the stack is `height` high, keeping the value on
+    /// top if `keep` is set, when leaving a loop or block early
+    Truncate {
+        height: usize,
+        keep: bool,
+    },
+    /// Starts catching runtime errors, which go on at the handler with the
+    /// stack as high as it is here and the error's message pushed
+    Catch(usize),
+    /// Stops catching errors with the running function's innermost `Catch`
+    EndCatch,
+}
+
+/// What `Op::Unpack` checks a value against
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unpack {
+    /// A variant by name, binding its fields
+    Variant {
+        variant: usize,
+        fields: usize,
+    },
+    Some,
+    None,
+    Ok,
+    Err,
+}
+
+/// What a `for` loop binds for each item
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bindings {
+    /// The index or key and the element or value
+    Both,
+    /// The key of a map
+    First,
+    /// The element of anything else
+    Second,
+}
+
+impl Program {
+    /// How many values an instruction adds to the stack, or removes if it's
+    /// negative, when it doesn't jump. `Truncate` isn't counted, since it
+    /// depends on the height before it.
+    pub fn stack_effect(&self, op: Op) -> isize {
+        let n = |n: usize| n as isize;
+
+        match op {
+            Op::Const(_)
+            | Op::Dup
+            | Op::LoadLocal(_)
+            | Op::LoadGlobal(_)
+            | Op::LoadCapture(_)
+            | Op::RefLocal(_)
+            | Op::RefGlobal(_) => 1,
+            Op::Pop
+            | Op::InitLocal(_)
+            | Op::StoreLocal(_)
+            | Op::InitGlobal(_)
+            | Op::StoreGlobal(_)
+            | Op::Binary(_)
+            | Op::JumpIfFalse(_)
+            | Op::JumpIfTrue(_)
+            | Op::Coalesce(_)
+            | Op::Return
+            | Op::MakeRange { .. }
+            | Op::Index
+            | Op::In => -1,
+            Op::Prefix(_)
+            | Op::BinaryConst(..)
+            | Op::BinaryLocal(..)
+            | Op::Jump(_)
+            | Op::WrapSome
+            | Op::WrapOk
+            | Op::WrapErr
+            | Op::GetField(_)
+            | Op::Is(_)
+            | Op::Cast(_)
+            | Op::Try
+            | Op::Truncate { .. }
+            | Op::Catch(_)
+            | Op::EndCatch => 0,
+            Op::SetIndexLocal(_) | Op::SetIndexGlobal(_) => -2,
+            Op::Call { args } | Op::CallMethod { args, .. } => -n(args),
+            Op::CallBuiltin { args, .. } => 1 - n(args),
+            Op::MakeFunction { captures, .. } | Op::MakeClosure { captures, .. } => 1 - n(captures),
+            Op::MakeList(elements) => 1 - n(elements),
+            Op::MakeMap(entries) => 1 - 2 * n(entries),
+            Op::MakeRecord { shape } | Op::MakeStruct { shape, .. } => {
+                1 - n(self.shapes[shape].len())
+            }
+            Op::MakeVariant { fields, .. } => 1 - n(fields),
+            Op::Unpack(Unpack::Variant { fields, .. }, _) => n(fields) - 1,
+            Op::Unpack(Unpack::Some | Unpack::Ok | Unpack::Err, _) => 0,
+            Op::Unpack(Unpack::None, _) => -1,
+            Op::Next { bindings, .. } => match bindings {
+                Bindings::Both => 2,
+                Bindings::First | Bindings::Second => 1,
+            },
+        }
+    }
+}
+
+impl Op {
+    /// Where the instruction jumps to, if it can jump
+    pub fn target(self) -> Option<usize> {
+        match self {
+            Op::Jump(to)
+            | Op::JumpIfFalse(to)
+            | Op::JumpIfTrue(to)
+            | Op::Coalesce(to)
+            | Op::Unpack(_, to)
+            | Op::Next { done: to, .. }
+            | Op::Catch(to) => Some(to),
+            _ => None,
+        }
+    }
+
+    pub fn target_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            Op::Jump(to)
+            | Op::JumpIfFalse(to)
+            | Op::JumpIfTrue(to)
+            | Op::Coalesce(to)
+            | Op::Unpack(_, to)
+            | Op::Next { done: to, .. }
+            | Op::Catch(to) => Some(to),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+
+            writeln!(
+                f,
+                "function {} '{}' ({} params, {} locals):",
+                i, function.name, function.params, function.locals
+            )?;
+
+            for (at, op) in function.code.iter().enumerate() {
+                writeln!(f, "{:>5}  {:?}", at, op)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Compiles a typechecked program into bytecode
+pub fn compile(ast: Spanned<TypedAst>) -> Program {
+    let mut compiler = Compiler {
+        program: Program::default(),
+        builders: vec![Builder::new(vec![], vec![])],
+        globals: HashMap::new(),
+        module: 0,
+        names: HashMap::new(),
+    };
+
+    compiler.declare_globals(&ast.0.statements.0, 0);
+    compiler.compile_top_level(ast.0.statements.0);
+
+    compiler.constant(Value::Unit, ast.1);
+    compiler.emit(Op::Return, ast.1);
+
+    compiler.program.main = compiler.finish_function("<main>", 0);
+
+    compiler.program
+}
+
+struct Compiler<'src> {
+    program: Program,
+    /// The functions being compiled, innermost last
+    builders: Vec<Builder<'src>>,
+    /// The global slots of top level variables, by file and name
+    globals: HashMap<(usize, &'src str), usize>,
+    /// The file whose top level variables are the globals in scope
+    module: usize,
+    /// Where each name is in `Program::names`
+    names: HashMap<String, usize>,
+}
+
+/// The state of a function being compiled
+struct Builder<'src> {
+    code: Vec<Op>,
+    spans: Vec<Span>,
+    /// Local variables with their slots, by scope, innermost last
+    scopes: Vec<Vec<(&'src str, usize)>>,
+    /// The variables the function captures, in the order they're stored
+    captures: Vec<&'src str>,
+    /// The first slot that isn't held by a variable in scope
+    next_slot: usize,
+    /// The most slots in use at once
+    locals: usize,
+    /// The number of values on the stack above the locals at this point
+    height: usize,
+    /// The loops and labeled blocks around this point, innermost last
+    targets: Vec<Target<'src>>,
+    /// The number of `try` bodies around this point, whose catches have to
+    /// be ended when jumping out of them
+    catches: usize,
+}
+
+/// Somewhere a `break` or `continue` can jump out of
+struct Target<'src> {
+    kind: TargetKind<'src>,
+    /// The stack height where it starts
+    height: usize,
+    /// Where a `continue` jumps to, for loops
+    continue_to: usize,
+    /// Jumps to the end, filled in once the end is known
+    breaks: Vec<usize>,
+    /// The number of `try` bodies around it
+    catches: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TargetKind<'src> {
+    /// A loop that's a statement, whose breaks' values are dropped
+    Statement,
+    /// A `loop` expression, ending with the value of a break
+    Expr,
+    /// A labeled block, ending with the value of a break
+    Block(&'src str),
+}
+
+/// Where a variable is kept
+enum Slot {
+    Local(usize),
+    Global(usize),
+    Capture(usize),
+}
+
+impl<'src> Builder<'src> {
+    fn new(params: Vec<&'src str>, captures: Vec<&'src str>) -> Self {
+        let locals = params.len();
+
+        Self {
+            code: vec![],
+            spans: vec![],
+            scopes: vec![params.into_iter().zip(0..).collect()],
+            captures,
+            next_slot: locals,
+            locals,
+            height: 0,
+            targets: vec![],
+            catches: 0,
+        }
+    }
+}
+
+impl<'src> Compiler<'src> {
+    fn builder(&mut self) -> &mut Builder<'src> {
+        self.builders.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        let effect = self.program.stack_effect(op);
+        let builder = self.builder();
+
+        builder.code.push(op);
+        builder.spans.push(span);
+        builder.height = builder.height.checked_add_signed(effect).unwrap();
+
+        builder.code.len() - 1
+    }
+
+    /// The position the next instruction will have
+    fn here(&mut self) -> usize {
+        self.builder().code.len()
+    }
+
+    /// Points the jump at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+
+        *self.builder().code[at].target_mut().unwrap() = target;
+    }
+
+    /// Sets the stack height where code is joined from several places, since
+    /// the code before it may end with a jump elsewhere
+    fn set_height(&mut self, height: usize) {
+        self.builder().height = height;
+    }
+
+    fn height(&mut self) -> usize {
+        self.builder().height
+    }
+
+    fn constant(&mut self, value: Value, span: Span) {
+        self.program.constants.push(value);
+
+        self.emit(Op::Const(self.program.constants.len() - 1), span);
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.names.get(name) {
+            return id;
+        }
+
+        self.program.names.push(name.to_string());
+        self.names
+            .insert(name.to_string(), self.program.names.len() - 1);
+
+        self.program.names.len() - 1
+    }
+
+    fn push_scope(&mut self) {
+        self.builder().scopes.push(vec![]);
+    }
+
+    fn pop_scope(&mut self) {
+        let builder = self.builder();
+        let scope = builder.scopes.pop().unwrap();
+
+        builder.next_slot -= scope.len();
+    }
+
+    fn declare_local(&mut self, name: &'src str) -> usize {
+        let builder = self.builder();
+        let slot = builder.next_slot;
+
+        builder.scopes.last_mut().unwrap().push((name, slot));
+        builder.next_slot += 1;
+        builder.locals = builder.locals.max(builder.next_slot);
+
+        slot
+    }
+
+    /// Whether code is being compiled at the top level of a file, where
+    /// variables are globals
+    fn top_level(&self) -> bool {
+        self.builders.len() == 1 && self.builders[0].scopes.len() == 1
+    }
+
+    /// Gives every top level variable of a file and the files it imports a
+    /// global slot ahead of time, since functions can use them before
+    /// they're declared
+    fn declare_globals(&mut self, statements: &[Spanned<Statement<'src>>], file: usize) {
+        for statement in statements {
+            match &statement.0 {
+                Statement::Let { name, .. } | Statement::Function { name, .. } => {
+                    let globals = &mut self.program.globals;
+
+                    self.globals.entry((file, name.0)).or_insert_with(|| {
+                        globals.push(name.0.to_string());
+                        globals.len() - 1
+                    });
+                }
+                Statement::Import {
+                    module: None,
+                    statements,
+                    ..
+                } => self.declare_globals(statements, file),
+                Statement::Import {
+                    module: Some(_),
+                    file,
+                    statements,
+                    ..
+                } => self.declare_globals(statements, *file),
+                _ => {}
+            }
+        }
+    }
+
+    fn resolve(&self, name: &str) -> Slot {
+        let builder = self.builders.last().unwrap();
+
+        if let Some(slot) = builder
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(other, _)| *other == name)
+            .map(|(_, slot)| *slot)
+        {
+            return Slot::Local(slot);
+        }
+
+        if let Some(i) = builder.captures.iter().position(|other| *other == name) {
+            return Slot::Capture(i);
+        }
+
+        Slot::Global(self.globals[&(self.module, name)])
+    }
+
+    fn load(&mut self, name: Spanned<&'src str>) {
+        let op = match self.resolve(name.0) {
+            Slot::Local(slot) => Op::LoadLocal(slot),
+            Slot::Global(slot) => Op::LoadGlobal(slot),
+            Slot::Capture(i) => Op::LoadCapture(i),
+        };
+
+        self.emit(op, name.1);
+    }
+
+    /// Compiles the top level statements of a file, defining its functions
+    /// before the rest of it runs like the interpreter does
+    fn compile_top_level(&mut self, statements: Vec<Spanned<Statement<'src>>>) {
+        let (functions, rest): (Vec<_>, Vec<_>) = statements
+            .into_iter()
+            .partition(|statement| matches!(statement.0, Statement::Function { .. }));
+
+        for (statement, span) in functions {
+            let Statement::Function {
+                name, params, body, ..
+            } = statement
+            else {
+                unreachable!()
+            };
+
+            let function = self.compile_function(name.0, &params.0, vec![], *body);
+
+            self.constant(
+                Value::Func {
+                    name: name.0.to_string(),
+                    id: Handle::permanent(function),
+                },
+                span,
+            );
+            self.emit(Op::InitGlobal(self.globals[&(self.module, name.0)]), span);
+        }
+
+        for statement in rest {
+            self.compile_statement(statement);
+        }
+    }
+
+    /// Compiles a function or method, returning its index
+    fn compile_function(
+        &mut self,
+        name: &'src str,
+        params: &[(Spanned<&'src str>, Spanned<Type>)],
+        captures: Vec<&'src str>,
+        body: Spanned<Statement<'src>>,
+    ) -> usize {
+        let span = body.1;
+
+        self.builders.push(Builder::new(
+            params.iter().map(|(param, _)| param.0).collect(),
+            captures,
+        ));
+
+        self.compile_statement(body);
+
+        // reached by falling off the end of the body
+        self.constant(Value::Unit, span);
+        self.emit(Op::Return, span);
+
+        self.finish_function(name, params.len())
+    }
+
+    /// Adds the innermost function being compiled to the program, returning
+    /// its index
+    fn finish_function(&mut self, name: &str, params: usize) -> usize {
+        let builder = self.builders.pop().unwrap();
+
+        self.program.functions.push(Function {
+            name: name.to_string(),
+            params,
+            locals: builder.locals,
+            code: builder.code,
+            spans: builder.spans,
+        });
+
+        self.program.functions.len() - 1
+    }
+
+    fn compile_statement(&mut self, statement: Spanned<Statement<'src>>) {
+        let span = statement.1;
+
+        match statement.0 {
+            Statement::Expr(expr) => {
+                let span = expr.1;
+
+                self.compile_expr(expr);
+                self.emit(Op::Pop, span);
+            }
+            Statement::Block(statements) => {
+                self.push_scope();
+
+                for statement in statements.0 {
+                    self.compile_statement(statement);
+                }
+
+                self.pop_scope();
+            }
+            Statement::Let { name, value, .. } => {
+                self.compile_expr(value);
+
+                if self.top_level() {
+                    self.emit(Op::InitGlobal(self.globals[&(self.module, name.0)]), span);
+                } else {
+                    let slot = self.declare_local(name.0);
+
+                    self.emit(Op::InitLocal(slot), span);
+                }
+            }
+            Statement::Function {
+                name,
+                params,
+                captures,
+                body,
+                ..
+            } => {
+                // a nested function gets itself after its captures, to be able
+                // to call itself
+                let function = self.compile_function(
+                    name.0,
+                    &params.0,
+                    captures
+                        .iter()
+                        .map(|capture| capture.0)
+                        .chain(std::iter::once(name.0))
+                        .collect(),
+                    *body,
+                );
+
+                for capture in &captures {
+                    self.load(*capture);
+                }
+
+                self.emit(
+                    Op::MakeFunction {
+                        function,
+                        captures: captures.len(),
+                    },
+                    span,
+                );
+
+                let slot = self.declare_local(name.0);
+
+                self.emit(Op::InitLocal(slot), span);
+            }
+            Statement::Assign { name, value } => {
+                self.compile_expr(value);
+
+                let op = match self.resolve(name.0) {
+                    Slot::Local(slot) => Op::StoreLocal(slot),
+                    Slot::Global(slot) => Op::StoreGlobal(slot),
+                    Slot::Capture(_) => unreachable!("captured variables can't be assigned"),
+                };
+
+                self.emit(op, span);
+            }
+            Statement::SetIndex { name, index, value } => {
+                let index_span = index.1;
+
+                self.compile_expr(index);
+                self.compile_expr(value);
+
+                let op = match self.resolve(name.0) {
+                    Slot::Local(slot) => Op::SetIndexLocal(slot),
+                    Slot::Global(slot) => Op::SetIndexGlobal(slot),
+                    Slot::Capture(_) => unreachable!("captured variables can't be assigned"),
+                };
+
+                self.emit(op, index_span);
+            }
+            Statement::Return(expr) => {
+                self.compile_expr(expr);
+                self.emit(Op::Return, span);
+            }
+            Statement::Break { label, value } => {
+                let before = self.height();
+
+                self.compile_expr(value);
+
+                let builder = self.builder();
+
+                let target = builder
+                    .targets
+                    .iter()
+                    .rposition(|target| match (label, target.kind) {
+                        (Some(label), TargetKind::Block(other)) => label.0 == other,
+                        (None, TargetKind::Statement | TargetKind::Expr) => true,
+                        _ => false,
+                    })
+                    .unwrap();
+
+                let (kind, height) = (builder.targets[target].kind, builder.targets[target].height);
+
+                if kind == TargetKind::Statement {
+                    self.emit(Op::Pop, span);
+
+                    if self.height() != height {
+                        self.emit(
+                            Op::Truncate {
+                                height,
+                                keep: false,
+                            },
+                            span,
+                        );
+                    }
+                } else if self.height() != height + 1 {
+                    self.emit(Op::Truncate { height, keep: true }, span);
+                }
+
+                self.end_catches(target, span);
+
+                let jump = self.emit(Op::Jump(0), span);
+
+                self.builder().targets[target].breaks.push(jump);
+
+                // whatever follows in the same block is never reached
+                self.set_height(before);
+            }
+            Statement::Continue => {
+                let builder = self.builder();
+
+                let target = builder
+                    .targets
+                    .iter()
+                    .rposition(|target| !matches!(target.kind, TargetKind::Block(_)))
+                    .unwrap();
+
+                let (height, continue_to) = (
+                    builder.targets[target].height,
+                    builder.targets[target].continue_to,
+                );
+
+                if self.height() != height {
+                    self.emit(
+                        Op::Truncate {
+                            height,
+                            keep: false,
+                        },
+                        span,
+                    );
+                }
+
+                self.end_catches(target, span);
+                self.emit(Op::Jump(continue_to), span);
+            }
+            Statement::While { cond, body } => {
+                let start = self.here();
+
+                self.push_target(TargetKind::Statement, start);
+
+                let cond_span = cond.1;
+
+                self.compile_expr(cond);
+
+                let exit = self.emit(Op::JumpIfFalse(0), cond_span);
+
+                self.compile_loop_body(body.0, start, span);
+                self.patch(exit);
+                self.pop_target();
+            }
+            Statement::For {
+                first,
+                second,
+                iter,
+                body,
+            } => {
+                let iter_span = iter.1;
+
+                let bindings = match (second, &iter.0.ty) {
+                    (Some(_), _) => Bindings::Both,
+                    (None, Type::Map(..)) => Bindings::First,
+                    (None, _) => Bindings::Second,
+                };
+
+                let is_str = iter.0.ty == Type::Str;
+
+                self.push_scope();
+                self.compile_expr(iter);
+
+                // a str is looped over as the list of its chars
+                if is_str {
+                    self.emit(
+                        Op::CallBuiltin {
+                            builtin: Builtin::Chars,
+                            args: 1,
+                        },
+                        iter_span,
+                    );
+                }
+
+                let collection = self.declare_local("");
+                self.emit(Op::InitLocal(collection), iter_span);
+
+                self.constant(Value::Int(0), iter_span);
+
+                let counter = self.declare_local("");
+                self.emit(Op::InitLocal(counter), iter_span);
+
+                let start = self.here();
+
+                self.push_target(TargetKind::Statement, start);
+
+                let next = self.emit(
+                    Op::Next {
+                        collection,
+                        counter,
+                        bindings,
+                        done: 0,
+                    },
+                    iter_span,
+                );
+
+                self.push_scope();
+
+                for name in [Some(first), second].into_iter().flatten().rev() {
+                    let slot = self.declare_local(name.0);
+
+                    self.emit(Op::InitLocal(slot), name.1);
+                }
+
+                self.compile_loop_body(body.0, start, span);
+                self.pop_scope();
+                self.patch(next);
+                self.pop_target();
+                self.pop_scope();
+            }
+            Statement::Import {
+                module: None,
+                statements,
+                ..
+            } => self.compile_top_level(statements),
+            Statement::Import {
+                module: Some(_),
+                file,
+                statements,
+                ..
+            } => {
+                let importer = std::mem::replace(&mut self.module, file);
+
+                self.compile_top_level(statements);
+
+                self.module = importer;
+            }
+            Statement::Impl {
+                trait_,
+                ty,
+                methods,
+                ..
+            } => {
+                for method in methods {
+                    let Statement::Function {
+                        name, params, body, ..
+                    } = method.0
+                    else {
+                        unreachable!()
+                    };
+
+                    let function = self.compile_function(name.0, &params.0, vec![], *body);
+
+                    self.program.methods.insert(
+                        (
+                            trait_.map(|trait_| trait_.0.to_string()),
+                            name.0.to_string(),
+                            ty.0.type_name(),
+                        ),
+                        function,
+                    );
+                }
+            }
+            // constants were already replaced with their values
+            Statement::Enum { .. }
+            | Statement::Struct { .. }
+            | Statement::TypeAlias { .. }
+            | Statement::Trait { .. }
+            | Statement::Const { .. } => {}
+        }
+    }
+
+    /// Compiles the body of a loop in a scope of its own, jumping back to
+    /// `start` at the end of it, and points its breaks past the jump
+    fn compile_loop_body(&mut self, body: Vec<Spanned<Statement<'src>>>, start: usize, span: Span) {
+        self.push_scope();
+
+        for statement in body {
+            self.compile_statement(statement);
+        }
+
+        self.pop_scope();
+        self.emit(Op::Jump(start), span);
+    }
+
+    fn push_target(&mut self, kind: TargetKind<'src>, continue_to: usize) {
+        let height = self.height();
+        let catches = self.builder().catches;
+
+        self.builder().targets.push(Target {
+            kind,
+            height,
+            continue_to,
+            breaks: vec![],
+            catches,
+        });
+    }
+
+    /// Ends the catches of the `try` bodies a jump to `target` leaves
+    fn end_catches(&mut self, target: usize, span: Span) {
+        let builder = self.builder();
+
+        for _ in builder.targets[target].catches..builder.catches {
+            self.emit(Op::EndCatch, span);
+        }
+    }
+
+    /// Points the breaks of the innermost loop or block to the next
+    /// instruction, and sets the height they leave the stack at
+    fn pop_target(&mut self) {
+        let target = self.builder().targets.pop().unwrap();
+
+        for jump in target.breaks {
+            self.patch(jump);
+        }
+
+        self.set_height(match target.kind {
+            TargetKind::Statement => target.height,
+            TargetKind::Expr | TargetKind::Block(_) => target.height + 1,
+        });
+    }
+
+    /// Compiles the statements and tail of a block in a scope of its own
+    fn compile_block(
+        &mut self,
+        statements: Vec<Spanned<Statement<'src>>>,
+        tail: Option<Box<Spanned<Expr<'src>>>>,
+        span: Span,
+    ) {
+        self.push_scope();
+
+        for statement in statements {
+            self.compile_statement(statement);
+        }
+
+        match tail {
+            Some(tail) => self.compile_expr(*tail),
+            None => self.constant(Value::Unit, span),
+        }
+
+        self.pop_scope();
+    }
+
+    fn compile_expr(&mut self, expr: Spanned<Expr<'src>>) {
+        let span = expr.1;
+
+        match expr.0.expr {
+            ExprKind::Var(name) => self.load(name),
+            ExprKind::ModuleVar { file, name, .. } => {
+                self.emit(Op::LoadGlobal(self.globals[&(file, name.0)]), name.1);
+            }
+            ExprKind::Literal(literal) => {
+                let value = match literal.0 {
+                    Literal::Int(n) => Value::Int(n),
+                    Literal::Float(n) => Value::Float(n),
+                    Literal::Str(s) => Value::Str(s),
+                    Literal::Char(c) => Value::Char(c),
+                    Literal::Bool(b) => Value::Bool(b),
+                    Literal::Unit => Value::Unit,
+                    Literal::None => Value::Optional(None),
+                };
+
+                self.constant(value, span);
+            }
+            ExprKind::Prefix { op, expr } => {
+                self.compile_expr(*expr);
+                self.emit(Op::Prefix(op.0), span);
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let height = self.height();
+
+                self.compile_expr(*lhs);
+
+                let jump = match op.0 {
+                    BinOp::LogicalAnd | BinOp::LogicalOr => {
+                        self.emit(Op::Dup, span);
+
+                        let jump = self.emit(
+                            if op.0 == BinOp::LogicalAnd {
+                                Op::JumpIfFalse(0)
+                            } else {
+                                Op::JumpIfTrue(0)
+                            },
+                            span,
+                        );
+
+                        self.emit(Op::Pop, span);
+
+                        Some(jump)
+                    }
+                    BinOp::Coalesce => Some(self.emit(Op::Coalesce(0), span)),
+                    _ => None,
+                };
+
+                self.compile_expr(*rhs);
+
+                match jump {
+                    Some(jump) => {
+                        self.patch(jump);
+                        self.set_height(height + 1);
+                    }
+                    None => {
+                        self.emit(Op::Binary(op.0), span);
+                    }
+                }
+            }
+            ExprKind::Call { callee, args } => {
+                self.compile_expr(*callee);
+
+                let count = args.0.len();
+
+                for arg in args.0 {
+                    self.compile_expr(arg);
+                }
+
+                self.emit(Op::Call { args: count }, span);
+            }
+            ExprKind::MethodCall {
+                receiver,
+                trait_,
+                method,
+                args,
+            } => {
+                self.compile_expr(*receiver);
+
+                let count = args.0.len();
+
+                for arg in args.0 {
+                    self.compile_expr(arg);
+                }
+
+                let op = Op::CallMethod {
+                    trait_: trait_.map(|trait_| self.name(&trait_)),
+                    method: self.name(method.0),
+                    args: count,
+                };
+
+                self.emit(op, span);
+            }
+            ExprKind::BuiltinCall { builtin, args } => {
+                let count = args.0.len();
+
+                for arg in args.0 {
+                    self.compile_expr(arg);
+                }
+
+                self.emit(
+                    Op::CallBuiltin {
+                        builtin: builtin.0,
+                        args: count,
+                    },
+                    span,
+                );
+            }
+            ExprKind::Variant { variant, args, .. } => {
+                // the enum may have been named through an alias, the type has its real name
+                let Type::Enum(enum_) = &expr.0.ty else {
+                    unreachable!()
+                };
+
+                let enum_ = self.name(enum_);
+                let variant = self.name(variant.0);
+                let fields = args.0.len();
+
+                for arg in args.0 {
+                    self.compile_expr(arg);
+                }
+
+                self.emit(
+                    Op::MakeVariant {
+                        enum_,
+                        variant,
+                        fields,
+                    },
+                    span,
+                );
+            }
+            ExprKind::Match { expr, arms } => {
+                let height = self.height();
+
+                self.push_scope();
+                self.compile_expr(*expr);
+
+                let value = self.declare_local("");
+                self.emit(Op::InitLocal(value), span);
+
+                let mut ends = vec![];
+                let mut next = None;
+
+                for (pattern, body) in arms.0 {
+                    // where the previous arm goes if it doesn't match
+                    if let Some(next) = next.take() {
+                        self.patch(next);
+                    }
+
+                    self.push_scope();
+                    self.emit(Op::LoadLocal(value), pattern.1);
+
+                    let (unpack, bindings) = match pattern.0 {
+                        Pattern::Binding(name) => (None, vec![name]),
+                        Pattern::Variant { variant, bindings } => (
+                            Some(Unpack::Variant {
+                                variant: self.name(variant.0),
+                                fields: bindings.0.len(),
+                            }),
+                            bindings.0,
+                        ),
+                        Pattern::Some(name) => (Some(Unpack::Some), vec![name]),
+                        Pattern::None => (Some(Unpack::None), vec![]),
+                        Pattern::Ok(name) => (Some(Unpack::Ok), vec![name]),
+                        Pattern::Err(name) => (Some(Unpack::Err), vec![name]),
+                    };
+
+                    next = unpack.map(|unpack| self.emit(Op::Unpack(unpack, 0), pattern.1));
+
+                    for name in bindings.into_iter().rev() {
+                        let slot = self.declare_local(name.0);
+
+                        self.emit(Op::InitLocal(slot), name.1);
+                    }
+
+                    self.compile_expr(body);
+
+                    ends.push(self.emit(Op::Jump(0), span));
+
+                    self.pop_scope();
+                    self.set_height(height);
+                }
+
+                // the typechecker made sure one of the arms matches, so the
+                // last one can't fail, but the value it would have still
+                // keeps the stack as high as the other arms leave it
+                if let Some(next) = next {
+                    self.patch(next);
+                    self.constant(Value::Unit, span);
+                }
+
+                for end in ends {
+                    self.patch(end);
+                }
+
+                self.pop_scope();
+                self.set_height(height + 1);
+            }
+            ExprKind::Some(expr) => {
+                self.compile_expr(*expr);
+                self.emit(Op::WrapSome, span);
+            }
+            ExprKind::Ok(expr) => {
+                self.compile_expr(*expr);
+                self.emit(Op::WrapOk, span);
+            }
+            ExprKind::Err(expr) => {
+                self.compile_expr(*expr);
+                self.emit(Op::WrapErr, span);
+            }
+            ExprKind::Try(expr) => {
+                self.compile_expr(*expr);
+                self.emit(Op::Try, span);
+            }
+            ExprKind::Is { expr, variant } => {
+                self.compile_expr(*expr);
+
+                let variant = self.name(variant.0);
+
+                self.emit(Op::Is(variant), span);
+            }
+            ExprKind::Ref { mutable, expr } => match expr.0.expr {
+                ExprKind::Var(name) if mutable => {
+                    let op = match self.resolve(name.0) {
+                        Slot::Local(slot) => Op::RefLocal(slot),
+                        Slot::Global(slot) => Op::RefGlobal(slot),
+                        Slot::Capture(_) => unreachable!("captured variables can't be borrowed"),
+                    };
+
+                    self.emit(op, name.1);
+                }
+                // an immutable reference can't be told apart from a copy
+                _ => self.compile_expr(*expr),
+            },
+            ExprKind::In { expr, collection } => {
+                self.compile_expr(*expr);
+                self.compile_expr(*collection);
+                self.emit(Op::In, span);
+            }
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                self.compile_expr(*start);
+                self.compile_expr(*end);
+                self.emit(Op::MakeRange { inclusive }, span);
+            }
+            ExprKind::Cast { expr, ty } => {
+                self.compile_expr(*expr);
+                self.program.types.push(ty.0);
+                self.emit(Op::Cast(self.program.types.len() - 1), span);
+            }
+            ExprKind::Record(fields) => {
+                let shape = self.shape(fields.iter().map(|(name, _)| name.0));
+
+                for (_, value) in fields {
+                    self.compile_expr(value);
+                }
+
+                self.emit(Op::MakeRecord { shape }, span);
+            }
+            ExprKind::Struct { name, fields } => {
+                let name = self.name(name.0);
+                let shape = self.shape(fields.iter().map(|(name, _)| name.0));
+
+                for (_, value) in fields {
+                    self.compile_expr(value);
+                }
+
+                self.emit(Op::MakeStruct { name, shape }, span);
+            }
+            ExprKind::List(elements) => {
+                let count = elements.0.len();
+
+                for element in elements.0 {
+                    self.compile_expr(element);
+                }
+
+                self.emit(Op::MakeList(count), span);
+            }
+            ExprKind::Map(entries) => {
+                let count = entries.0.len();
+
+                for (key, value) in entries.0 {
+                    self.compile_expr(key);
+                    self.compile_expr(value);
+                }
+
+                self.emit(Op::MakeMap(count), span);
+            }
+            ExprKind::FieldAccess { expr, field } => {
+                self.compile_expr(*expr);
+
+                let field = self.name(field.0);
+
+                self.emit(Op::GetField(field), span);
+            }
+            ExprKind::Index { expr, index } => {
+                let index_span = index.1;
+
+                self.compile_expr(*expr);
+                self.compile_expr(*index);
+                self.emit(Op::Index, index_span);
+            }
+            ExprKind::LabeledBlock {
+                label,
+                statements,
+                tail,
+            } => {
+                self.push_target(TargetKind::Block(label.0), 0);
+                self.compile_block(statements.0, tail, span);
+                self.pop_target();
+            }
+            ExprKind::If { cond, then, else_ } => {
+                let height = self.height();
+
+                self.compile_expr(*cond);
+
+                let to_else = self.emit(Op::JumpIfFalse(0), span);
+
+                self.compile_block(then.0.statements, then.0.tail, then.1);
+
+                let to_end = self.emit(Op::Jump(0), span);
+
+                self.patch(to_else);
+                self.set_height(height);
+
+                match else_ {
+                    Some(else_) => self.compile_block(else_.0.statements, else_.0.tail, else_.1),
+                    None => self.constant(Value::Unit, span),
+                }
+
+                self.patch(to_end);
+                self.set_height(height + 1);
+            }
+            ExprKind::Loop { body, .. } => {
+                let start = self.here();
+
+                self.push_target(TargetKind::Expr, start);
+                self.compile_loop_body(body.0, start, span);
+                self.pop_target();
+            }
+            ExprKind::Block(block) => self.compile_block(block.statements, block.tail, span),
+            ExprKind::Catch {
+                body,
+                binding,
+                handler,
+            } => {
+                let height = self.height();
+
+                let catch = self.emit(Op::Catch(0), span);
+
+                self.builder().catches += 1;
+                self.compile_block(body.0.statements, body.0.tail, body.1);
+                self.builder().catches -= 1;
+
+                self.emit(Op::EndCatch, span);
+
+                let to_end = self.emit(Op::Jump(0), span);
+
+                // an error leaves its message where the body's value would be
+                self.patch(catch);
+                self.set_height(height + 1);
+
+                self.push_scope();
+
+                let slot = self.declare_local(binding.0);
+
+                self.emit(Op::InitLocal(slot), binding.1);
+                self.compile_block(handler.0.statements, handler.0.tail, handler.1);
+                self.pop_scope();
+
+                self.patch(to_end);
+            }
+            ExprKind::Closure {
+                params,
+                captures,
+                body,
+            } => {
+                let body_span = body.1;
+
+                self.builders.push(Builder::new(
+                    params.0.iter().map(|(param, _)| param.0).collect(),
+                    captures.iter().map(|capture| capture.0).collect(),
+                ));
+
+                self.compile_expr(*body);
+                self.emit(Op::Return, body_span);
+
+                let function = self.finish_function("<closure>", params.0.len());
+
+                for capture in &captures {
+                    self.load(*capture);
+                }
+
+                self.emit(
+                    Op::MakeClosure {
+                        function,
+                        captures: captures.len(),
+                    },
+                    span,
+                );
+            }
+        }
+    }
+
+    fn shape<'a>(&mut self, fields: impl Iterator<Item = &'a str>) -> usize {
+        self.program
+            .shapes
+            .push(fields.map(|field| field.to_string()).collect());
+
+        self.program.shapes.len() - 1
+    }
+}
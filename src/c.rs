@@ -0,0 +1,739 @@
+use crate::mir::*;
+use crate::typed_ast::{BinOp, FormatSpec, PrefixOp};
+use std::fmt::Write;
+
+/// Translates a program to a C99 source file. Only ints, floats, bools,
+/// chars and unit values are supported, along with functions that don't
+/// capture anything and printing.
+///
+/// Every operand is stored in a variable of its own before the operation
+/// that uses it, since C leaves the order of evaluating operands and
+/// arguments unspecified.
+pub fn compile(program: &Program) -> String {
+    let mut globals = String::new();
+    let mut prototypes = String::new();
+    let mut functions = String::new();
+
+    for (index, global) in program.globals.iter().enumerate() {
+        if let Some(ty) = global.ty {
+            writeln!(globals, "{} {};", c_type(ty), global_name(program, index)).unwrap();
+        }
+
+        writeln!(globals, "bool {}_initialized;", global_name(program, index)).unwrap();
+    }
+
+    for (index, function) in program.functions.iter().enumerate().skip(1) {
+        let params = function.locals[..function.params]
+            .iter()
+            .map(|local| c_type(local.ty))
+            .collect::<Vec<_>>();
+
+        writeln!(
+            prototypes,
+            "{} {}({});",
+            function.returns.map_or("void", c_type),
+            function_name(program, index),
+            if params.is_empty() {
+                "void".to_string()
+            } else {
+                params.join(", ")
+            }
+        )
+        .unwrap();
+
+        let params = (0..function.params)
+            .map(|local| {
+                format!(
+                    "{} {}",
+                    c_type(function.locals[local].ty),
+                    local_name(function, local)
+                )
+            })
+            .collect::<Vec<_>>();
+
+        write!(
+            functions,
+            "\n{} {}({}) {{\n{}}}\n",
+            function.returns.map_or("void", c_type),
+            function_name(program, index),
+            if params.is_empty() {
+                "void".to_string()
+            } else {
+                params.join(", ")
+            },
+            body(program, function, false)
+        )
+        .unwrap();
+    }
+
+    let mut out = String::from(RUNTIME);
+
+    for part in [globals, prototypes] {
+        if !part.is_empty() {
+            writeln!(out, "\n{}", part.trim_end()).unwrap();
+        }
+    }
+
+    out.push_str(&functions);
+
+    write!(
+        out,
+        "\nint main(void) {{\n{}}}\n",
+        body(program, &program.functions[0], true)
+    )
+    .unwrap();
+
+    out
+}
+
+/// The functions every translated program starts with, for output and for
+/// the operations that have to be checked for errors
+const RUNTIME: &str = r#"#include <inttypes.h>
+#include <math.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+int64_t fox_ticks;
+
+void fox_fail(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "Runtime error: %s\n", message);
+    exit(1);
+}
+
+/* Shows a float with a fractional part even if it's a whole number, and an
+   exponent if it's very large or very small, using the fewest digits that
+   read back as the same float */
+void fox_show_float(double x, char *out) {
+    char buf[32], digits[20] = "";
+    int precision, count = 0, exponent, i;
+    const char *p;
+
+    if (isnan(x)) {
+        strcpy(out, "NaN");
+        return;
+    }
+
+    if (isinf(x)) {
+        strcpy(out, x < 0 ? "-inf" : "inf");
+        return;
+    }
+
+    if (x == 0) {
+        strcpy(out, signbit(x) ? "-0.0" : "0.0");
+        return;
+    }
+
+    for (precision = 0; precision < 17; precision++) {
+        snprintf(buf, sizeof buf, "%.*e", precision, x);
+
+        if (strtod(buf, NULL) == x) {
+            break;
+        }
+    }
+
+    p = buf;
+
+    if (*p == '-') {
+        *out++ = *p++;
+    }
+
+    for (; *p != 'e'; p++) {
+        if (*p != '.') {
+            digits[count++] = *p;
+        }
+    }
+
+    exponent = atoi(p + 1);
+
+    if (fabs(x) >= 1e16 || fabs(x) < 1e-4) {
+        *out++ = digits[0];
+
+        if (count > 1) {
+            *out++ = '.';
+            memcpy(out, digits + 1, count - 1);
+            out += count - 1;
+        }
+
+        sprintf(out, "e%d", exponent);
+    } else if (exponent < 0) {
+        *out++ = '0';
+        *out++ = '.';
+
+        for (i = 0; i < -exponent - 1; i++) {
+            *out++ = '0';
+        }
+
+        memcpy(out, digits, count);
+        out[count] = '\0';
+    } else {
+        for (i = 0; i <= exponent; i++) {
+            *out++ = i < count ? digits[i] : '0';
+        }
+
+        *out++ = '.';
+
+        if (count > exponent + 1) {
+            memcpy(out, digits + exponent + 1, count - exponent - 1);
+            out += count - exponent - 1;
+        } else {
+            *out++ = '0';
+        }
+
+        *out = '\0';
+    }
+}
+
+void fox_write_str(const char *s, size_t len) {
+    fwrite(s, 1, len, stdout);
+}
+
+/* Negative numbers get a minus sign in every base, rather than their two's
+   complement */
+void fox_write_int(int64_t n, int radix) {
+    char buf[65];
+    int i = sizeof buf;
+    uint64_t magnitude = n < 0 ? -(uint64_t)n : (uint64_t)n;
+
+    if (radix == 10) {
+        printf("%" PRId64, n);
+        return;
+    }
+
+    do {
+        buf[--i] = "0123456789abcdef"[magnitude % radix];
+        magnitude /= radix;
+    } while (magnitude > 0);
+
+    if (n < 0) {
+        putchar('-');
+    }
+
+    fwrite(buf + i, 1, sizeof buf - i, stdout);
+}
+
+void fox_write_float(double x) {
+    char buf[64];
+
+    fox_show_float(x, buf);
+    fputs(buf, stdout);
+}
+
+void fox_write_fixed(double x, int digits) {
+    if (isnan(x) || isinf(x)) {
+        fox_write_float(x);
+    } else {
+        printf("%.*f", digits, x);
+    }
+}
+
+/* Writes a char, quoted and escaped if `debug` is set */
+void fox_write_char(uint32_t c, bool debug) {
+    char buf[4];
+    int len;
+
+    if (debug) {
+        putchar('\'');
+
+        switch (c) {
+        case '\t':
+            fputs("\\t", stdout);
+            break;
+        case '\n':
+            fputs("\\n", stdout);
+            break;
+        case '\r':
+            fputs("\\r", stdout);
+            break;
+        case '\'':
+        case '\\':
+            putchar('\\');
+            putchar((int)c);
+            break;
+        default:
+            if (c < 0x20 || c == 0x7f) {
+                printf("\\u{%" PRIx32 "}", c);
+            } else {
+                fox_write_char(c, false);
+            }
+        }
+
+        putchar('\'');
+        return;
+    }
+
+    if (c < 0x80) {
+        buf[0] = (char)c;
+        len = 1;
+    } else if (c < 0x800) {
+        buf[0] = (char)(0xc0 | c >> 6);
+        buf[1] = (char)(0x80 | (c & 0x3f));
+        len = 2;
+    } else if (c < 0x10000) {
+        buf[0] = (char)(0xe0 | c >> 12);
+        buf[1] = (char)(0x80 | (c >> 6 & 0x3f));
+        buf[2] = (char)(0x80 | (c & 0x3f));
+        len = 3;
+    } else {
+        buf[0] = (char)(0xf0 | c >> 18);
+        buf[1] = (char)(0x80 | (c >> 12 & 0x3f));
+        buf[2] = (char)(0x80 | (c >> 6 & 0x3f));
+        buf[3] = (char)(0x80 | (c & 0x3f));
+        len = 4;
+    }
+
+    fwrite(buf, 1, len, stdout);
+}
+
+int64_t fox_add(int64_t a, int64_t b) {
+    if ((b > 0 && a > INT64_MAX - b) || (b < 0 && a < INT64_MIN - b)) {
+        fox_fail("integer overflow");
+    }
+
+    return a + b;
+}
+
+int64_t fox_sub(int64_t a, int64_t b) {
+    if ((b < 0 && a > INT64_MAX + b) || (b > 0 && a < INT64_MIN + b)) {
+        fox_fail("integer overflow");
+    }
+
+    return a - b;
+}
+
+int64_t fox_mul(int64_t a, int64_t b) {
+    if (a > 0 ? (b > 0 ? a > INT64_MAX / b : b < INT64_MIN / a)
+              : (b > 0 ? a < INT64_MIN / b : a != 0 && b < INT64_MAX / a)) {
+        fox_fail("integer overflow");
+    }
+
+    return a * b;
+}
+
+int64_t fox_div(int64_t a, int64_t b) {
+    if (b == 0) {
+        fox_fail("division by zero");
+    }
+
+    if (a == INT64_MIN && b == -1) {
+        fox_fail("integer overflow");
+    }
+
+    return a / b;
+}
+
+int64_t fox_rem(int64_t a, int64_t b) {
+    if (b == 0) {
+        fox_fail("division by zero");
+    }
+
+    if (a == INT64_MIN && b == -1) {
+        fox_fail("integer overflow");
+    }
+
+    return a % b;
+}
+
+/* Exponentiation by squaring, which fails in the same cases as the
+   interpreter's */
+int64_t fox_pow(int64_t base, int64_t exponent) {
+    int64_t acc = 1;
+
+    if (exponent < 0) {
+        fox_fail("negative exponent");
+    }
+
+    if (exponent > UINT32_MAX) {
+        fox_fail("integer overflow");
+    }
+
+    if (exponent == 0) {
+        return 1;
+    }
+
+    while (exponent > 1) {
+        if (exponent & 1) {
+            acc = fox_mul(acc, base);
+        }
+
+        exponent >>= 1;
+        base = fox_mul(base, base);
+    }
+
+    return fox_mul(acc, base);
+}
+
+int64_t fox_shl(int64_t a, int64_t b) {
+    if (b < 0 || b > 63) {
+        fox_fail("integer overflow");
+    }
+
+    return (int64_t)((uint64_t)a << b);
+}
+
+int64_t fox_shr(int64_t a, int64_t b) {
+    if (b < 0 || b > 63) {
+        fox_fail("integer overflow");
+    }
+
+    return a < 0 ? ~(~a >> b) : a >> b;
+}
+
+int64_t fox_neg(int64_t a) {
+    if (a == INT64_MIN) {
+        fox_fail("integer overflow");
+    }
+
+    return -a;
+}
+
+/* Rounds towards zero, like integer division */
+int64_t fox_float_to_int(double x) {
+    char message[96];
+
+    if (!(fabs(x) < 9223372036854775807.0)) {
+        strcpy(message, "cannot convert ");
+        fox_show_float(x, message + strlen(message));
+        strcat(message, " to int");
+        fox_fail(message);
+    }
+
+    return (int64_t)x;
+}
+
+uint32_t fox_int_to_char(int64_t n) {
+    char message[64];
+
+    if (n < 0 || n > 0x10ffff || (n >= 0xd800 && n <= 0xdfff)) {
+        snprintf(message, sizeof message, "%" PRId64 " is not a valid char", n);
+        fox_fail(message);
+    }
+
+    return (uint32_t)n;
+}
+"#;
+
+fn c_type(ty: Ty) -> &'static str {
+    match ty {
+        Ty::Int => "int64_t",
+        Ty::Float => "double",
+        Ty::Bool => "bool",
+        Ty::Char => "uint32_t",
+    }
+}
+
+/// A C string literal holding `text`, with anything other than printable
+/// ASCII escaped. `?` is escaped too, so it can't start a trigraph.
+fn string_literal(text: &str) -> String {
+    let mut literal = String::from("\"");
+
+    for byte in text.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => write!(literal, "\\{}", byte as char).unwrap(),
+            b'\n' => literal.push_str("\\n"),
+            b' '..=b'~' => literal.push(byte as char),
+            _ => write!(literal, "\\{:03o}", byte).unwrap(),
+        }
+    }
+
+    literal.push('"');
+    literal
+}
+
+// every name has its index added to it, since C has a single scope for
+// functions and globals
+
+fn global_name(program: &Program, global: GlobalId) -> String {
+    format!("g_{}_{}", program.globals[global].name, global)
+}
+
+fn function_name(program: &Program, function: FunctionId) -> String {
+    format!("f_{}_{}", program.functions[function].name, function)
+}
+
+fn local_name(function: &Function, local: Local) -> String {
+    match &function.locals[local].name {
+        Some(name) => format!("v_{}_{}", name.0, local),
+        None => format!("t{}", local),
+    }
+}
+
+/// Translates the body of a function, where each block is a C label and
+/// jumps between them are `goto`s. `main` returns 0 instead of nothing.
+fn body(program: &Program, function: &Function, main: bool) -> String {
+    // locals that are never read aren't declared, since C warns about them
+    let mut read = vec![false; function.locals.len()];
+
+    let mut mark = |operand: &Operand| {
+        if let Operand::Local(local) = operand {
+            read[*local] = true;
+        }
+    };
+
+    for block in &function.blocks {
+        for statement in &block.statements {
+            statement.operands(&mut mark);
+        }
+
+        if let Some(operand) = block.terminator.operand() {
+            mark(operand);
+        }
+    }
+
+    let mut out = String::new();
+
+    for (local, decl) in function.locals.iter().enumerate().skip(function.params) {
+        if read[local] {
+            writeln!(
+                out,
+                "    {} {};",
+                c_type(decl.ty),
+                local_name(function, local)
+            )
+            .unwrap();
+        }
+    }
+
+    // params that are never read can't be left out, so they're used here
+    for (local, read) in read.iter().enumerate().take(function.params) {
+        if !read {
+            writeln!(out, "    (void){};", local_name(function, local)).unwrap();
+        }
+    }
+
+    // a block only needs a label if something jumps to it, rather than the
+    // block before it falling through to it
+    let mut targets = vec![false; function.blocks.len()];
+    let mut code = vec![];
+
+    for (index, block) in function.blocks.iter().enumerate() {
+        let mut lines = vec![];
+
+        for statement in &block.statements {
+            lines.extend(translate_statement(program, function, statement, &read));
+        }
+
+        let next = index + 1;
+
+        match &block.terminator {
+            Terminator::Goto(target) if *target == next => {}
+            Terminator::Goto(target) => {
+                targets[*target] = true;
+                lines.push(format!("goto bb{};", target));
+            }
+            Terminator::Branch(cond, then, else_) => {
+                let cond = operand(function, cond);
+
+                if *else_ == next {
+                    targets[*then] = true;
+                    lines.push(format!("if ({}) goto bb{};", cond, then));
+                } else if *then == next {
+                    targets[*else_] = true;
+                    lines.push(format!("if (!{}) goto bb{};", cond, else_));
+                } else {
+                    targets[*then] = true;
+                    targets[*else_] = true;
+                    lines.push(format!("if ({}) goto bb{};", cond, then));
+                    lines.push(format!("goto bb{};", else_));
+                }
+            }
+            Terminator::Return(Some(value)) => {
+                lines.push(format!("return {};", operand(function, value)))
+            }
+            Terminator::Return(None) if main => lines.push("return 0;".to_string()),
+            Terminator::Return(None) => lines.push("return;".to_string()),
+            Terminator::Unreachable => lines.push("abort();".to_string()),
+        }
+
+        code.push(lines);
+    }
+
+    for (index, lines) in code.into_iter().enumerate() {
+        // a label has to be followed by a statement
+        if targets[index] {
+            writeln!(out, "bb{}:;", index).unwrap();
+        }
+
+        for line in lines {
+            writeln!(out, "    {}", line).unwrap();
+        }
+    }
+
+    out
+}
+
+fn translate_statement(
+    program: &Program,
+    function: &Function,
+    statement: &Statement,
+    read: &[bool],
+) -> Vec<String> {
+    match statement {
+        Statement::Assign(local, value) if read[*local] => vec![format!(
+            "{} = {};",
+            local_name(function, *local),
+            rvalue(program, function, value)
+        )],
+        // nothing happens when a value that's never read is only copied
+        Statement::Assign(_, Rvalue::Use(_)) | Statement::Eval(Rvalue::Use(_)) => vec![],
+        // but it's still worked out for the errors it can cause
+        Statement::Assign(_, value) => {
+            vec![format!("(void)({});", rvalue(program, function, value))]
+        }
+        Statement::Eval(value) => vec![format!("{};", rvalue(program, function, value))],
+        Statement::SetGlobal(global, value) => vec![format!(
+            "{} = {};",
+            global_name(program, *global),
+            operand(function, value)
+        )],
+        Statement::Initialize(global) => {
+            vec![format!(
+                "{}_initialized = true;",
+                global_name(program, *global)
+            )]
+        }
+        Statement::CheckInitialized(global, _) => vec![format!(
+            "if (!{}_initialized) fox_fail({});",
+            global_name(program, *global),
+            string_literal(&format!(
+                "'{}' is used before it's initialized",
+                program.globals[*global].name
+            ))
+        )],
+        Statement::Print(pieces) => pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Str(text) => write_str(text),
+                Piece::Value(value, ty, spec) => write_value(operand(function, value), *ty, *spec),
+            })
+            .collect(),
+        Statement::Flush => vec!["fflush(stdout);".to_string()],
+    }
+}
+
+fn write_str(text: &str) -> String {
+    format!("fox_write_str({}, {});", string_literal(text), text.len())
+}
+
+/// Writes a value the way a placeholder with `spec` shows it
+fn write_value(value: String, ty: Ty, spec: Option<FormatSpec>) -> String {
+    match (ty, spec) {
+        (Ty::Int, spec) => {
+            let radix = match spec {
+                Some(FormatSpec::Hex) => 16,
+                Some(FormatSpec::Binary) => 2,
+                Some(FormatSpec::Octal) => 8,
+                _ => 10,
+            };
+
+            format!("fox_write_int({}, {});", value, radix)
+        }
+        (Ty::Float, Some(FormatSpec::Precision(digits))) => {
+            format!("fox_write_fixed({}, {});", value, digits)
+        }
+        (Ty::Float, _) => format!("fox_write_float({});", value),
+        (Ty::Bool, _) => format!("fputs({} ? \"true\" : \"false\", stdout);", value),
+        (Ty::Char, spec) => format!(
+            "fox_write_char({}, {});",
+            value,
+            spec == Some(FormatSpec::Debug)
+        ),
+    }
+}
+
+/// A variable or literal holding an operand's value, where negative numbers
+/// are in parentheses so they can follow another operator
+fn operand(function: &Function, operand: &Operand) -> String {
+    match *operand {
+        Operand::Local(local) => local_name(function, local),
+        Operand::Const(Const::Int(i64::MIN)) => "INT64_MIN".to_string(),
+        Operand::Const(Const::Int(n)) => format!("INT64_C({})", n),
+        Operand::Const(Const::Float(n)) if n.is_nan() => "NAN".to_string(),
+        Operand::Const(Const::Float(n)) if n.is_infinite() => {
+            if n > 0.0 { "INFINITY" } else { "(-INFINITY)" }.to_string()
+        }
+        // reads back as the same float
+        Operand::Const(Const::Float(n)) if n.is_sign_negative() => format!("({:?})", n),
+        Operand::Const(Const::Float(n)) => format!("{:?}", n),
+        Operand::Const(Const::Char(c)) => format!("UINT32_C({})", c as u32),
+        Operand::Const(Const::Bool(b)) => b.to_string(),
+    }
+}
+
+fn rvalue(program: &Program, function: &Function, value: &Rvalue) -> String {
+    match value {
+        Rvalue::Use(value) => operand(function, value),
+        Rvalue::Prefix(op, ty, value, _) => {
+            let value = operand(function, value);
+
+            match (op, ty) {
+                (PrefixOp::Negate, Ty::Int) => format!("fox_neg({})", value),
+                (PrefixOp::Negate, _) => format!("-{}", value),
+                (PrefixOp::Not, _) => format!("!{}", value),
+                (PrefixOp::BitNot, _) => format!("~{}", value),
+            }
+        }
+        Rvalue::Binary(op, ty, lhs, rhs, _) => {
+            binary(*op, *ty, operand(function, lhs), operand(function, rhs))
+        }
+        Rvalue::Cast(from, to, value, _) => {
+            let value = operand(function, value);
+
+            match (from, to) {
+                (Ty::Int, Ty::Float) => format!("(double){}", value),
+                (Ty::Float, Ty::Int) => format!("fox_float_to_int({})", value),
+                (Ty::Int, Ty::Char) => format!("fox_int_to_char({})", value),
+                _ => format!("(int64_t){}", value),
+            }
+        }
+        Rvalue::Global(global) => global_name(program, *global),
+        Rvalue::Call(callee, args, _) => format!(
+            "{}({})",
+            function_name(program, *callee),
+            args.iter()
+                .map(|arg| operand(function, arg))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Rvalue::Tick => "fox_ticks++".to_string(),
+    }
+}
+
+/// An operator other than the short-circuiting ones applied to operands of
+/// type `ty`
+fn binary(op: BinOp, ty: Ty, lhs: String, rhs: String) -> String {
+    let call = |function: &str| format!("{}({}, {})", function, lhs, rhs);
+    let infix = |operator: &str| format!("{} {} {}", lhs, operator, rhs);
+
+    match (ty, op) {
+        (Ty::Int, BinOp::Add) => call("fox_add"),
+        (Ty::Int, BinOp::Subtract) => call("fox_sub"),
+        (Ty::Int, BinOp::Multiply) => call("fox_mul"),
+        (Ty::Int, BinOp::Divide) => call("fox_div"),
+        (Ty::Int, BinOp::Modulo) => call("fox_rem"),
+        (Ty::Int, BinOp::Power) => call("fox_pow"),
+        (Ty::Int, BinOp::ShiftLeft) => call("fox_shl"),
+        (Ty::Int, BinOp::ShiftRight) => call("fox_shr"),
+        (Ty::Int, BinOp::BitAnd) => infix("&"),
+        (Ty::Int, BinOp::BitOr) => infix("|"),
+        (Ty::Int, BinOp::BitXor) => infix("^"),
+        (Ty::Float, BinOp::Add) => infix("+"),
+        (Ty::Float, BinOp::Subtract) => infix("-"),
+        (Ty::Float, BinOp::Multiply) => infix("*"),
+        (Ty::Float, BinOp::Divide) => infix("/"),
+        (Ty::Float, BinOp::Modulo) => call("fmod"),
+        (Ty::Float, BinOp::Power) => call("pow"),
+        // C's own operators already order bools, and chars as the uint32_t
+        // code points they're written as
+        (_, BinOp::Equals) => infix("=="),
+        (_, BinOp::NotEquals) => infix("!="),
+        (_, BinOp::LessThan) => infix("<"),
+        (_, BinOp::LessThanOrEqual) => infix("<="),
+        (_, BinOp::GreaterThan) => infix(">"),
+        (_, BinOp::GreaterThanOrEqual) => infix(">="),
+        _ => unreachable!(),
+    }
+}
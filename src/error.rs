@@ -1,5 +1,4 @@
-use crate::typecheck::TypeInfo;
-use crate::typed_ast::{BinOp, PrefixOp, Type};
+use crate::typed_ast::{source_name, BinOp, FormatSpec, PrefixOp, Type};
 use crate::{Span, Spanned};
 use ariadne::{Color, Fmt};
 use chumsky::error::RichReason;
@@ -14,12 +13,57 @@ pub enum Error {
         found: Option<String>,
     },
     Custom(Span, String),
+    /// An error while running the program, along with the calls that were
+    /// running, innermost first, as the function that was called and where
+    Runtime {
+        message: String,
+        span: Span,
+        trace: Vec<Spanned<String>>,
+    },
+    /// A program that was stopped for using more of a resource than it's
+    /// allowed to, with the calls that were running like a runtime error
+    Exhausted {
+        resource: Resource,
+        span: Span,
+        trace: Vec<Spanned<String>>,
+    },
     Many(Vec<Error>),
 }
 
-type Message = String;
-type Spans = Vec<Spanned<(String, Color)>>;
-type Notes = Vec<String>;
+/// What a running program can run out of, with the limit it had
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resource {
+    /// The most calls that can be running at once
+    Depth(usize),
+    /// The interpreter's own stack, with how many calls were running when it
+    /// ran out
+    Stack(usize),
+    /// The most steps it can take
+    Fuel(u64),
+    /// The most functions, closures and borrowed variables the VM can have
+    /// at once
+    Heap(usize),
+}
+
+impl std::fmt::Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resource::Depth(limit) => write!(f, "the call depth limit of {} was reached", limit),
+            Resource::Stack(calls) => {
+                write!(f, "the stack ran out of room with {} calls running", calls)
+            }
+            Resource::Fuel(limit) => write!(f, "the fuel of {} steps ran out", limit),
+            Resource::Heap(limit) => write!(f, "the heap limit of {} values was reached", limit),
+        }
+    }
+}
+
+/// How many of the calls running when a runtime error happens are shown
+const TRACE_LIMIT: usize = 8;
+
+pub type Message = String;
+pub type Spans = Vec<Spanned<(String, Color)>>;
+pub type Notes = Vec<String>;
 
 impl Error {
     pub fn make_report(&self) -> Vec<(Message, Spans, Notes)> {
@@ -67,7 +111,70 @@ impl Error {
                     vec![],
                 )]
             }
-            Error::Many(errors) => errors.iter().map(Error::make_report).flatten().collect(),
+            Error::Runtime {
+                message,
+                span,
+                trace,
+            } => vec![runtime_report(
+                format!("Runtime error: {}", message),
+                *span,
+                trace,
+            )],
+            Error::Exhausted {
+                resource,
+                span,
+                trace,
+            } => vec![runtime_report(
+                format!("Resource exhausted: {}", resource),
+                *span,
+                trace,
+            )],
+            Error::Many(errors) => errors.iter().flat_map(Error::make_report).collect(),
+        }
+    }
+
+    /// Moves the spans of a syntax error, used when a file's tokens are
+    /// placed after the other files of the program
+    pub fn map_spans(self, f: &impl Fn(Span) -> Span) -> Self {
+        match self {
+            Error::ExpectedFound {
+                span,
+                expected,
+                found,
+            } => Error::ExpectedFound {
+                span: f(span),
+                expected,
+                found,
+            },
+            Error::Custom(span, msg) => Error::Custom(f(span), msg),
+            Error::Runtime {
+                message,
+                span,
+                trace,
+            } => Error::Runtime {
+                message,
+                span: f(span),
+                trace: trace
+                    .into_iter()
+                    .map(|(function, span)| (function, f(span)))
+                    .collect(),
+            },
+            Error::Exhausted {
+                resource,
+                span,
+                trace,
+            } => Error::Exhausted {
+                resource,
+                span: f(span),
+                trace: trace
+                    .into_iter()
+                    .map(|(function, span)| (function, f(span)))
+                    .collect(),
+            },
+            Error::Many(errors) => {
+                Error::Many(errors.into_iter().map(|e| e.map_spans(f)).collect())
+            }
+            Error::Typecheck(_) => self,
         }
     }
 
@@ -79,9 +186,62 @@ impl Error {
                 TypecheckError::TypeMismatch { .. } => 4,
                 TypecheckError::CannotApplyUnaryOperator { .. } => 5,
                 TypecheckError::CannotApplyBinaryOperator { .. } => 6,
+                TypecheckError::NotARecord { .. } => 7,
+                TypecheckError::UndefinedType { .. } => 8,
+                TypecheckError::NoSuchVariant { .. } => 9,
+                TypecheckError::WrongNumberOfArguments { .. } => 10,
+                TypecheckError::DuplicateVariant { .. } => 11,
+                TypecheckError::NonExhaustiveMatch { .. } => 12,
+                TypecheckError::NotAnEnum { .. } => 13,
+                TypecheckError::RecursiveTypeAlias { .. } => 14,
+                TypecheckError::OptionalNotUnwrapped { .. } => 15,
+                TypecheckError::NoSuchField { .. } => 16,
+                TypecheckError::DuplicateField { .. } => 17,
+                TypecheckError::MissingField { .. } => 18,
+                TypecheckError::NotCallable { .. } => 19,
+                TypecheckError::UndefinedLabel { .. } => 20,
+                TypecheckError::ListElementMismatch { .. } => 21,
+                TypecheckError::CircularImport { .. } => 22,
+                TypecheckError::ImportCollision { .. } => 23,
+                TypecheckError::ReturnOutsideFunction { .. } => 24,
+                TypecheckError::MissingReturn { .. } => 25,
+                TypecheckError::CannotInferBindingType { .. } => 26,
+                TypecheckError::AssignToImmutable { .. } => 27,
+                TypecheckError::NotAList { .. } => 28,
+                TypecheckError::NotAStruct { .. } => 29,
+                TypecheckError::MissingStructField { .. } => 30,
+                TypecheckError::AssignToCapture { .. } => 31,
+                TypecheckError::DuplicateTypeParam { .. } => 32,
+                TypecheckError::UndefinedTrait { .. } => 33,
+                TypecheckError::TraitNotImplemented { .. } => 34,
+                TypecheckError::NoSuchMethod { .. } => 35,
+                TypecheckError::MissingTraitMethod { .. } => 36,
+                TypecheckError::NotATraitMethod { .. } => 37,
+                TypecheckError::DuplicateImpl { .. } => 38,
+                TypecheckError::CannotImplement { .. } => 39,
+                TypecheckError::DuplicateMethod { .. } => 40,
+                TypecheckError::NotConstant { .. } => 41,
+                TypecheckError::AssignToConst { .. } => 42,
+                TypecheckError::BreakOutsideLoop { .. } => 43,
+                TypecheckError::ContinueOutsideLoop { .. } => 44,
+                TypecheckError::CannotCast { .. } => 45,
+                TypecheckError::NotIterable { .. } => 46,
+                TypecheckError::MapEntryMismatch { .. } => 47,
+                TypecheckError::NotAFormatString { .. } => 48,
+                TypecheckError::MissingFormatArg { .. } => 49,
+                TypecheckError::ExtraFormatArg { .. } => 50,
+                TypecheckError::FormatArgMismatch { .. } => 51,
+                TypecheckError::PlaceholderOutsideFormat { .. } => 52,
+                TypecheckError::BorrowImmutable { .. } => 53,
+                TypecheckError::UnexpectedRef { .. } => 54,
+                TypecheckError::NotBorrowable { .. } => 55,
+                TypecheckError::RefOutsideCall { .. } => 56,
+                TypecheckError::BreakValueOutOfLoop { .. } => 57,
+                TypecheckError::UsedBeforeInit { .. } => 58,
+                TypecheckError::InfiniteType { .. } => 59,
             },
             Error::ExpectedFound { .. } => 1,
-            Error::Custom(_, _) => 0,
+            Error::Custom(_, _) | Error::Runtime { .. } | Error::Exhausted { .. } => 0,
             Error::Many(errs) => errs.iter().map(Error::code).max().unwrap_or(0),
         }
     }
@@ -99,8 +259,8 @@ pub enum TypecheckError {
     TypeMismatch {
         span1: Span,
         span2: Span,
-        ty1: TypeInfo,
-        ty2: TypeInfo,
+        ty1: Type,
+        ty2: Type,
     },
     CannotApplyUnaryOperator {
         span: Span,
@@ -113,6 +273,267 @@ pub enum TypecheckError {
         ty1: Type,
         ty2: Type,
     },
+    NotARecord {
+        span: Span,
+        field: String,
+        ty: Type,
+    },
+    UndefinedType {
+        name: String,
+        span: Span,
+    },
+    NoSuchVariant {
+        enum_name: String,
+        variant: String,
+        span: Span,
+    },
+    WrongNumberOfArguments {
+        span: Span,
+        expected: usize,
+        found: usize,
+    },
+    DuplicateVariant {
+        name: String,
+        span1: Span,
+        span2: Span,
+    },
+    NonExhaustiveMatch {
+        span: Span,
+        missing: Vec<String>,
+    },
+    NotAnEnum {
+        span: Span,
+        ty: Type,
+    },
+    RecursiveTypeAlias {
+        name: String,
+        span: Span,
+    },
+    OptionalNotUnwrapped {
+        span: Span,
+        ty: Type,
+    },
+    NoSuchField {
+        span: Span,
+        field: String,
+        ty: Type,
+    },
+    DuplicateField {
+        name: String,
+        span1: Span,
+        span2: Span,
+    },
+    MissingField {
+        span: Span,
+        field: String,
+        ty: Type,
+    },
+    NotCallable {
+        span: Span,
+        ty: Type,
+    },
+    UndefinedLabel {
+        label: String,
+        span: Span,
+    },
+    ListElementMismatch {
+        span: Span,
+        ty: Type,
+        expected: Type,
+        expected_span: Span,
+    },
+    CircularImport {
+        span: Span,
+        cycle: Vec<String>,
+    },
+    ImportCollision {
+        name: String,
+        imported: Span,
+        span: Span,
+    },
+    ReturnOutsideFunction {
+        span: Span,
+    },
+    MissingReturn {
+        span: Span,
+        ty: Type,
+    },
+    CannotInferBindingType {
+        name: String,
+        span: Span,
+        /// What is known about the type, with `_` for the unknown parts
+        ty: Type,
+    },
+    AssignToImmutable {
+        name: String,
+        span: Span,
+        declared: Span,
+    },
+    NotAList {
+        span: Span,
+        ty: Type,
+    },
+    NotAStruct {
+        name: String,
+        span: Span,
+    },
+    MissingStructField {
+        name: String,
+        field: String,
+        span: Span,
+        declared: Span,
+    },
+    AssignToCapture {
+        name: String,
+        span: Span,
+        /// The closure that captured the variable, or the name of the function
+        closure: Span,
+        function: bool,
+    },
+    DuplicateTypeParam {
+        name: String,
+        span1: Span,
+        span2: Span,
+    },
+    UndefinedTrait {
+        name: String,
+        span: Span,
+    },
+    TraitNotImplemented {
+        trait_: String,
+        ty: Type,
+        span: Span,
+    },
+    NoSuchMethod {
+        method: String,
+        ty: Type,
+        span: Span,
+        /// The methods the type does have, from its own impl and its traits
+        available: Vec<String>,
+    },
+    MissingTraitMethod {
+        trait_: String,
+        method: String,
+        span: Span,
+        /// Where the method is declared, built-in traits aren't declared anywhere
+        declared: Option<Span>,
+    },
+    NotATraitMethod {
+        trait_: String,
+        method: String,
+        span: Span,
+    },
+    DuplicateImpl {
+        trait_: String,
+        ty: Type,
+        span1: Span,
+        span2: Span,
+    },
+    CannotImplement {
+        ty: Type,
+        span: Span,
+    },
+    DuplicateMethod {
+        name: String,
+        span1: Span,
+        span2: Span,
+    },
+    NotConstant {
+        name: String,
+        span: Span,
+    },
+    AssignToConst {
+        name: String,
+        span: Span,
+        declared: Span,
+    },
+    BreakOutsideLoop {
+        span: Span,
+    },
+    ContinueOutsideLoop {
+        span: Span,
+    },
+    CannotCast {
+        from: Type,
+        to: Type,
+        span: Span,
+    },
+    NotIterable {
+        span: Span,
+        ty: Type,
+    },
+    MapEntryMismatch {
+        /// Whether it's the key of the entry that has the wrong type, rather
+        /// than its value
+        is_key: bool,
+        span: Span,
+        ty: Type,
+        expected: Type,
+        expected_span: Span,
+    },
+    /// The first argument of `print`, `println` or `format` isn't a string literal
+    NotAFormatString {
+        builtin: String,
+        span: Span,
+    },
+    MissingFormatArg {
+        /// The span of the placeholder
+        span: Span,
+        /// The number of the placeholder, counting from 1
+        number: usize,
+        args: usize,
+    },
+    ExtraFormatArg {
+        /// The span of the first argument without a placeholder
+        span: Span,
+        placeholders: usize,
+        format: Span,
+    },
+    FormatArgMismatch {
+        spec: FormatSpec,
+        placeholder: Span,
+        span: Span,
+        ty: Type,
+    },
+    PlaceholderOutsideFormat {
+        span: Span,
+    },
+    BorrowImmutable {
+        name: String,
+        span: Span,
+        declared: Span,
+    },
+    UnexpectedRef {
+        span: Span,
+        /// The type of the parameter the reference is passed for
+        param: Type,
+    },
+    NotBorrowable {
+        span: Span,
+    },
+    RefOutsideCall {
+        span: Span,
+    },
+    /// A `break` with a value out of a `while` or `for` loop
+    BreakValueOutOfLoop {
+        span: Span,
+    },
+    /// A top level variable used before its `let` has run
+    UsedBeforeInit {
+        name: String,
+        span: Span,
+        declared: Span,
+        /// The use of the function that uses the variable, when it isn't used
+        /// directly by a top level statement
+        call: Option<Span>,
+    },
+    /// A type that would have to contain itself, at `span1`, to be the same
+    /// as `ty`, at `span2`
+    InfiniteType {
+        span1: Span,
+        span2: Span,
+        ty: Type,
+    },
 }
 
 impl TypecheckError {
@@ -145,8 +566,8 @@ impl TypecheckError {
             } => (
                 "Type mismatch".to_string(),
                 vec![
-                    ((format!("Type '{:?}' here", ty1), Color::Yellow), *span1),
-                    ((format!("Type '{:?}' here", ty2), Color::Yellow), *span2),
+                    ((format!("Type '{}' here", ty1), Color::Yellow), *span1),
+                    ((format!("Type '{}' here", ty2), Color::Yellow), *span2),
                 ],
                 vec![],
             ),
@@ -154,13 +575,13 @@ impl TypecheckError {
                 format!(
                     "Cannot apply operator '{}' to type '{}'",
                     op.fg(Color::Yellow),
-                    format!("{:?}", ty).fg(Color::Yellow)
+                    ty.fg(Color::Yellow)
                 ),
                 vec![(
                     (
                         format!(
                             "Cannot apply this operator to type '{}'",
-                            format!("{:?}", ty).fg(Color::Yellow)
+                            ty.fg(Color::Yellow)
                         ),
                         Color::Yellow,
                     ),
@@ -172,15 +593,49 @@ impl TypecheckError {
                 format!(
                     "Cannot apply binary operator '{}' to types '{}' and '{}'",
                     op.fg(Color::Yellow),
-                    format!("{:?}", ty1).fg(Color::Yellow),
-                    format!("{:?}", ty2).fg(Color::Yellow)
+                    ty1.fg(Color::Yellow),
+                    ty2.fg(Color::Yellow)
                 ),
                 vec![(
                     (
                         format!(
                             "Cannot apply this operator to types '{}' and '{}'",
-                            format!("{:?}", ty1).fg(Color::Yellow),
-                            format!("{:?}", ty2).fg(Color::Yellow)
+                            ty1.fg(Color::Yellow),
+                            ty2.fg(Color::Yellow)
+                        ),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                match (ty1, op.trait_()) {
+                    (Type::Enum(name) | Type::Struct(name), Some((trait_, _))) if ty1 == ty2 => {
+                        vec![format!(
+                            "help: implement the '{}' trait for '{}' to use '{}' with it",
+                            trait_,
+                            source_name(name),
+                            op
+                        )]
+                    }
+                    (Type::Param(name), Some((trait_, _))) if ty1 == ty2 => {
+                        vec![format!(
+                            "help: bound '{}' by the '{}' trait, as in '{}: {}', to use '{}' with it",
+                            name, trait_, name, trait_, op
+                        )]
+                    }
+                    _ => vec![],
+                },
+            ),
+            TypecheckError::NotARecord { span, field, ty } => (
+                format!(
+                    "Cannot access field '{}' on type '{}'",
+                    field.fg(Color::Yellow),
+                    ty.fg(Color::Yellow)
+                ),
+                vec![(
+                    (
+                        format!(
+                            "This has type '{}', which is not a record",
+                            ty.fg(Color::Yellow)
                         ),
                         Color::Yellow,
                     ),
@@ -188,13 +643,1046 @@ impl TypecheckError {
                 )],
                 vec![],
             ),
-        }
-    }
-}
-
-impl From<TypecheckError> for Error {
-    fn from(err: TypecheckError) -> Self {
-        Self::Typecheck(err)
+            TypecheckError::UndefinedType { name, span } => (
+                format!("Undefined type '{}'", name.fg(Color::Yellow)),
+                vec![(
+                    ("not found in this scope".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::NoSuchVariant {
+                enum_name,
+                variant,
+                span,
+            } => (
+                format!(
+                    "Enum '{}' has no variant named '{}'",
+                    source_name(enum_name).fg(Color::Yellow),
+                    variant.fg(Color::Yellow)
+                ),
+                vec![(
+                    (
+                        format!(
+                            "No such variant in '{}'",
+                            source_name(enum_name).fg(Color::Yellow)
+                        ),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::WrongNumberOfArguments {
+                span,
+                expected,
+                found,
+            } => (
+                format!(
+                    "Expected {} arguments, found {}",
+                    expected.fg(Color::Yellow),
+                    found.fg(Color::Yellow)
+                ),
+                vec![(
+                    (
+                        format!("{} arguments given here", found.fg(Color::Yellow)),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::DuplicateVariant { name, span1, span2 } => (
+                format!("Duplicate variant '{}'", name.fg(Color::Yellow)),
+                vec![
+                    (("First defined here".to_string(), Color::Yellow), *span1),
+                    (("Defined again here".to_string(), Color::Yellow), *span2),
+                ],
+                vec![],
+            ),
+            TypecheckError::NonExhaustiveMatch { span, missing } => (
+                "Non-exhaustive match".to_string(),
+                vec![(
+                    (
+                        format!(
+                            "Patterns not covered: {}",
+                            missing
+                                .iter()
+                                .map(|variant| format!("'{}'", variant.fg(Color::Yellow)))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![match missing.as_slice() {
+                    [only] if only == "_" => "help: add a '_' arm to match anything".to_string(),
+                    _ => format!(
+                        "help: add an arm for each missing pattern, like '{} => ...', or a '_' arm to match anything",
+                        missing[0]
+                    ),
+                }],
+            ),
+            TypecheckError::NotAnEnum { span, ty } => (
+                format!(
+                    "Type '{}' is not an enum",
+                    ty.fg(Color::Yellow)
+                ),
+                vec![(
+                    (
+                        format!(
+                            "This has type '{}', which has no variants",
+                            ty.fg(Color::Yellow)
+                        ),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::RecursiveTypeAlias { name, span } => (
+                format!("Type alias '{}' refers to itself", name.fg(Color::Yellow)),
+                vec![(
+                    ("Recursive reference here".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec!["help: use an enum to define a recursive type".to_string()],
+            ),
+            TypecheckError::OptionalNotUnwrapped { span, ty } => (
+                format!(
+                    "Optional type '{}' must be unwrapped before use",
+                    ty.fg(Color::Yellow)
+                ),
+                vec![(
+                    ("This value might be 'none'".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec!["help: use '??' to provide a default value".to_string()],
+            ),
+            TypecheckError::NoSuchField { span, field, ty } => (
+                format!(
+                    "Record type '{}' has no field named '{}'",
+                    ty.fg(Color::Yellow),
+                    field.fg(Color::Yellow)
+                ),
+                vec![(("Unknown field".to_string(), Color::Yellow), *span)],
+                vec![],
+            ),
+            TypecheckError::DuplicateField { name, span1, span2 } => (
+                format!("Duplicate field '{}'", name.fg(Color::Yellow)),
+                vec![
+                    (("First defined here".to_string(), Color::Yellow), *span1),
+                    (("Defined again here".to_string(), Color::Yellow), *span2),
+                ],
+                vec![],
+            ),
+            TypecheckError::MissingField { span, field, ty } => (
+                format!("Missing field '{}'", field.fg(Color::Yellow)),
+                vec![(
+                    (
+                        format!(
+                            "This has type '{}', which has no field '{}'",
+                            ty.fg(Color::Yellow),
+                            field.fg(Color::Yellow)
+                        ),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::NotCallable { span, ty } => (
+                format!(
+                    "Cannot call a value of type '{}'",
+                    ty.fg(Color::Yellow)
+                ),
+                vec![(
+                    (
+                        format!(
+                            "This has type '{}', which is not a function",
+                            ty.fg(Color::Yellow)
+                        ),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::UndefinedLabel { label, span } => (
+                format!("Undefined label '{}'", label.fg(Color::Yellow)),
+                vec![(
+                    (
+                        "No enclosing block has this label".to_string(),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::ListElementMismatch {
+                span,
+                ty,
+                expected,
+                expected_span,
+            } => (
+                "List element has the wrong type".to_string(),
+                vec![
+                    (
+                        (
+                            format!(
+                                "This has type '{}', but the elements before it are '{}'",
+                                ty.fg(Color::Yellow),
+                                expected.fg(Color::Yellow)
+                            ),
+                            Color::Yellow,
+                        ),
+                        *span,
+                    ),
+                    (
+                        (
+                            format!(
+                                "Element type inferred as '{}' here",
+                                expected.fg(Color::Yellow)
+                            ),
+                            Color::Yellow,
+                        ),
+                        *expected_span,
+                    ),
+                ],
+                vec![],
+            ),
+            TypecheckError::CircularImport { span, cycle } => (
+                "Circular import".to_string(),
+                vec![(
+                    (
+                        "This import leads back to a file that is still being loaded".to_string(),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![format!("import chain: {}", cycle.join(" -> "))],
+            ),
+            TypecheckError::ImportCollision {
+                name,
+                imported,
+                span,
+            } => (
+                format!("'{}' is defined more than once", name.fg(Color::Yellow)),
+                vec![
+                    (("Imported here".to_string(), Color::Yellow), *imported),
+                    (("Defined again here".to_string(), Color::Yellow), *span),
+                ],
+                vec![],
+            ),
+            TypecheckError::ReturnOutsideFunction { span } => (
+                "Return outside of a function".to_string(),
+                vec![(
+                    (
+                        "There is no function to return from".to_string(),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::MissingReturn { span, ty } => (
+                "Function might not return a value".to_string(),
+                vec![(
+                    (
+                        format!(
+                            "Not every path through this function returns a '{}'",
+                            ty.fg(Color::Yellow)
+                        ),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::CannotInferBindingType { name, span, ty } => (
+                format!("Cannot infer the type of '{}'", name.fg(Color::Yellow)),
+                vec![(
+                    (
+                        "Nothing here determines this variable's type".to_string(),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![format!(
+                    "help: give it a type annotation, 'let {}: {} = ...;' with the '_' filled in",
+                    name, ty
+                )],
+            ),
+            TypecheckError::AssignToImmutable {
+                name,
+                span,
+                declared,
+            } => (
+                format!(
+                    "Cannot assign to immutable variable '{}'",
+                    name.fg(Color::Yellow)
+                ),
+                vec![
+                    (("Assigned here".to_string(), Color::Yellow), *span),
+                    (("Declared here".to_string(), Color::Yellow), *declared),
+                ],
+                vec![format!(
+                    "help: declare it with 'let mut {}' to allow assigning to it",
+                    name
+                )],
+            ),
+            TypecheckError::NotAList { span, ty } => (
+                format!(
+                    "Cannot index into type '{}'",
+                    ty.fg(Color::Yellow)
+                ),
+                vec![(
+                    (
+                        format!(
+                            "This has type '{}', which is not a list or a map",
+                            ty.fg(Color::Yellow)
+                        ),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                if *ty == Type::Str {
+                    vec!["help: a str can only be indexed with a range, as in 's[1..3]'".to_string()]
+                } else {
+                    vec![]
+                },
+            ),
+            TypecheckError::NotAStruct { name, span } => (
+                format!("Type '{}' is not a struct", name.fg(Color::Yellow)),
+                vec![(
+                    (
+                        "This type can't be built with fields".to_string(),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::MissingStructField {
+                name,
+                field,
+                span,
+                declared,
+            } => (
+                format!(
+                    "Missing field '{}' of struct '{}'",
+                    field.fg(Color::Yellow),
+                    name.fg(Color::Yellow)
+                ),
+                vec![
+                    (("Not given a value here".to_string(), Color::Yellow), *span),
+                    (
+                        ("Field declared here".to_string(), Color::Yellow),
+                        *declared,
+                    ),
+                ],
+                vec![],
+            ),
+            TypecheckError::AssignToCapture {
+                name,
+                span,
+                closure,
+                function,
+            } => {
+                let kind = if *function { "function" } else { "closure" };
+
+                (
+                    format!(
+                        "Cannot assign to captured variable '{}'",
+                        name.fg(Color::Yellow)
+                    ),
+                    vec![
+                        (("Assigned here".to_string(), Color::Yellow), *span),
+                        (
+                            (format!("Captured by this {}", kind), Color::Yellow),
+                            *closure,
+                        ),
+                    ],
+                    vec![format!("help: {}s only have a copy of the variables they capture, return the new value from the {} instead", kind, kind)],
+                )
+            }
+            TypecheckError::DuplicateTypeParam { name, span1, span2 } => (
+                format!("Duplicate type parameter '{}'", name.fg(Color::Yellow)),
+                vec![
+                    (("First defined here".to_string(), Color::Yellow), *span1),
+                    (("Defined again here".to_string(), Color::Yellow), *span2),
+                ],
+                vec![],
+            ),
+            TypecheckError::UndefinedTrait { name, span } => (
+                format!("Undefined trait '{}'", name.fg(Color::Yellow)),
+                vec![(
+                    ("not found in this scope".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::TraitNotImplemented { trait_, ty, span } => (
+                format!(
+                    "Type '{}' doesn't implement trait '{}'",
+                    ty.fg(Color::Yellow),
+                    trait_.fg(Color::Yellow)
+                ),
+                vec![(
+                    (
+                        "Required by a type parameter bound here".to_string(),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![format!(
+                    "help: implement it with 'impl {} for ... {{ ... }}' before this is used",
+                    trait_
+                )],
+            ),
+            TypecheckError::NoSuchMethod {
+                method,
+                ty,
+                span,
+                available,
+            } => (
+                format!(
+                    "Type '{}' has no method named '{}'",
+                    ty.fg(Color::Yellow),
+                    method.fg(Color::Yellow)
+                ),
+                vec![(("Unknown method".to_string(), Color::Yellow), *span)],
+                if available.is_empty() {
+                    vec![]
+                } else {
+                    vec![format!(
+                        "Methods it has: {}",
+                        available
+                            .iter()
+                            .map(|method| format!("'{}'", method.fg(Color::Yellow)))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )]
+                },
+            ),
+            TypecheckError::MissingTraitMethod {
+                trait_,
+                method,
+                span,
+                declared,
+            } => (
+                format!(
+                    "Missing method '{}' of trait '{}'",
+                    method.fg(Color::Yellow),
+                    trait_.fg(Color::Yellow)
+                ),
+                std::iter::once((("Not implemented here".to_string(), Color::Yellow), *span))
+                    .chain(declared.map(|declared| {
+                        (("Method declared here".to_string(), Color::Yellow), declared)
+                    }))
+                    .collect(),
+                vec![],
+            ),
+            TypecheckError::NotATraitMethod {
+                trait_,
+                method,
+                span,
+            } => (
+                format!(
+                    "Method '{}' is not a member of trait '{}'",
+                    method.fg(Color::Yellow),
+                    trait_.fg(Color::Yellow)
+                ),
+                vec![(("Not in the trait".to_string(), Color::Yellow), *span)],
+                vec![],
+            ),
+            TypecheckError::DuplicateImpl {
+                trait_,
+                ty,
+                span1,
+                span2,
+            } => (
+                format!(
+                    "Trait '{}' is implemented twice for type '{}'",
+                    trait_.fg(Color::Yellow),
+                    ty.fg(Color::Yellow)
+                ),
+                vec![
+                    (("First implemented here".to_string(), Color::Yellow), *span1),
+                    (("Implemented again here".to_string(), Color::Yellow), *span2),
+                ],
+                vec![],
+            ),
+            TypecheckError::CannotImplement { ty, span } => (
+                format!(
+                    "Cannot write an impl for type '{}'",
+                    ty.fg(Color::Yellow)
+                ),
+                vec![(("Implemented here".to_string(), Color::Yellow), *span)],
+                vec![
+                    "help: only primitive types, enums and structs can have impls".to_string(),
+                ],
+            ),
+            TypecheckError::DuplicateMethod { name, span1, span2 } => (
+                format!("Duplicate method '{}'", name.fg(Color::Yellow)),
+                vec![
+                    (("First defined here".to_string(), Color::Yellow), *span1),
+                    (("Defined again here".to_string(), Color::Yellow), *span2),
+                ],
+                vec![],
+            ),
+            TypecheckError::NotConstant { name, span } => (
+                format!(
+                    "The value of constant '{}' is not known before running the program",
+                    name.fg(Color::Yellow)
+                ),
+                vec![(
+                    ("This can't be evaluated ahead of time".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec![format!(
+                    "help: use 'let {}' for values that are only known while running",
+                    name
+                )],
+            ),
+            TypecheckError::AssignToConst {
+                name,
+                span,
+                declared,
+            } => (
+                format!("Cannot assign to constant '{}'", name.fg(Color::Yellow)),
+                vec![
+                    (("Assigned here".to_string(), Color::Yellow), *span),
+                    (("Declared here".to_string(), Color::Yellow), *declared),
+                ],
+                vec![format!(
+                    "help: declare it with 'let mut {}' to allow assigning to it",
+                    name
+                )],
+            ),
+            TypecheckError::BreakOutsideLoop { span } => (
+                "Cannot break outside of a loop".to_string(),
+                vec![(
+                    ("No enclosing loop to break out of".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::ContinueOutsideLoop { span } => (
+                "Cannot continue outside of a loop".to_string(),
+                vec![(
+                    ("No enclosing loop to continue".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::CannotCast { from, to, span } => (
+                format!(
+                    "Cannot cast type '{}' to '{}'",
+                    from.fg(Color::Yellow),
+                    to.fg(Color::Yellow)
+                ),
+                vec![(
+                    (
+                        format!("This has type '{}'", from.fg(Color::Yellow)),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec!["help: 'int', 'float', 'bool' and 'char' can be cast to 'str', 'str' to 'int' and 'float', 'int' and 'float' to each other, 'bool' and 'char' to 'int', and 'int' to 'char'".to_string()],
+            ),
+            TypecheckError::NotIterable { span, ty } => (
+                format!(
+                    "Cannot iterate over type '{}'",
+                    ty.fg(Color::Yellow)
+                ),
+                vec![(
+                    (
+                        format!(
+                            "This has type '{}', which is not a list, a map, a str or a range",
+                            ty.fg(Color::Yellow)
+                        ),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::MapEntryMismatch {
+                is_key,
+                span,
+                ty,
+                expected,
+                expected_span,
+            } => {
+                let part = if *is_key { "key" } else { "value" };
+
+                (
+                    format!("Map {} has the wrong type", part),
+                    vec![
+                        (
+                            (
+                                format!(
+                                    "This has type '{}', but the {}s before it are '{}'",
+                                    ty.fg(Color::Yellow),
+                                    part,
+                                    expected.fg(Color::Yellow)
+                                ),
+                                Color::Yellow,
+                            ),
+                            *span,
+                        ),
+                        (
+                            (
+                                format!(
+                                    "{} type inferred as '{}' here",
+                                    if *is_key { "Key" } else { "Value" },
+                                    expected.fg(Color::Yellow)
+                                ),
+                                Color::Yellow,
+                            ),
+                            *expected_span,
+                        ),
+                    ],
+                    vec![],
+                )
+            }
+            TypecheckError::NotAFormatString { builtin, span } => (
+                format!(
+                    "The format string of '{}' has to be a string literal",
+                    builtin.fg(Color::Yellow)
+                ),
+                vec![(
+                    ("This isn't a string literal".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec![format!(
+                    "help: to show a value, use a placeholder for it, as in '{}(\"{{}}\", value)'",
+                    builtin
+                )],
+            ),
+            TypecheckError::MissingFormatArg { span, number, args } => (
+                "Placeholder has no argument".to_string(),
+                vec![(
+                    (
+                        format!(
+                            "This is placeholder {}, but only {} {} given",
+                            number,
+                            args,
+                            if *args == 1 {
+                                "argument was"
+                            } else {
+                                "arguments were"
+                            }
+                        ),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::ExtraFormatArg {
+                span,
+                placeholders,
+                format,
+            } => (
+                "Argument has no placeholder".to_string(),
+                vec![
+                    (
+                        ("This argument isn't used".to_string(), Color::Yellow),
+                        *span,
+                    ),
+                    (
+                        (
+                            format!(
+                                "This format string has {} placeholder{}",
+                                placeholders,
+                                if *placeholders == 1 { "" } else { "s" }
+                            ),
+                            Color::Yellow,
+                        ),
+                        *format,
+                    ),
+                ],
+                vec![],
+            ),
+            TypecheckError::FormatArgMismatch {
+                spec,
+                placeholder,
+                span,
+                ty,
+            } => {
+                let expected = spec.arg_type().unwrap();
+
+                (
+                    format!(
+                        "Placeholder '{}' can't show type '{}'",
+                        spec.fg(Color::Yellow),
+                        ty.fg(Color::Yellow)
+                    ),
+                    vec![
+                        (
+                            (
+                                format!(
+                                    "This placeholder needs an argument of type '{}'",
+                                    expected.fg(Color::Yellow)
+                                ),
+                                Color::Yellow,
+                            ),
+                            *placeholder,
+                        ),
+                        (
+                            (
+                                format!(
+                                    "This has type '{}'",
+                                    ty.fg(Color::Yellow)
+                                ),
+                                Color::Yellow,
+                            ),
+                            *span,
+                        ),
+                    ],
+                    vec![],
+                )
+            }
+            TypecheckError::PlaceholderOutsideFormat { span } => (
+                "Placeholder outside of a format string".to_string(),
+                vec![(
+                    (
+                        "This is only filled in by 'print', 'println' or 'format'".to_string(),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec!["help: write '\\{' to put a '{' in the string".to_string()],
+            ),
+            TypecheckError::BorrowImmutable {
+                name,
+                span,
+                declared,
+            } => (
+                format!(
+                    "Cannot borrow immutable variable '{}' as mutable",
+                    name.fg(Color::Yellow)
+                ),
+                vec![
+                    (("Borrowed here".to_string(), Color::Yellow), *span),
+                    (("Declared here".to_string(), Color::Yellow), *declared),
+                ],
+                vec![format!(
+                    "help: declare it with 'let mut {}' to allow borrowing it mutably",
+                    name
+                )],
+            ),
+            TypecheckError::UnexpectedRef { span, param } => (
+                "Unexpected reference".to_string(),
+                vec![(
+                    (
+                        format!(
+                            "This is passed for a parameter of type '{}'",
+                            param.fg(Color::Yellow)
+                        ),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec!["help: remove the '&'".to_string()],
+            ),
+            TypecheckError::NotBorrowable { span } => (
+                "Only variables can be borrowed mutably".to_string(),
+                vec![(
+                    ("This is not a variable".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec!["help: assign it to a 'let mut' variable first".to_string()],
+            ),
+            TypecheckError::RefOutsideCall { span } => (
+                "References can only be passed as arguments".to_string(),
+                vec![(
+                    ("This is not an argument".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec![],
+            ),
+            TypecheckError::BreakValueOutOfLoop { span } => (
+                "Only a 'loop' can be broken out of with a value".to_string(),
+                vec![(
+                    (
+                        "This breaks out of a 'while' or 'for' loop".to_string(),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec!["help: remove the value, or use 'loop' instead".to_string()],
+            ),
+            TypecheckError::UsedBeforeInit {
+                name,
+                span,
+                declared,
+                call,
+            } => {
+                let mut spans = vec![
+                    (("Used here".to_string(), Color::Yellow), *span),
+                    (
+                        ("Initialized here, after it's used".to_string(), Color::Yellow),
+                        *declared,
+                    ),
+                ];
+
+                if let Some(call) = call {
+                    spans.push((
+                        (
+                            format!("Called here, before '{}' is initialized", name),
+                            Color::Yellow,
+                        ),
+                        *call,
+                    ));
+                }
+
+                (
+                    format!(
+                        "Variable '{}' is used before it's initialized",
+                        name.fg(Color::Yellow)
+                    ),
+                    spans,
+                    vec![format!(
+                        "help: move 'let {}' above the first statement that uses it",
+                        name
+                    )],
+                )
+            }
+            TypecheckError::InfiniteType { span1, span2, ty } => (
+                "Infinite type".to_string(),
+                vec![
+                    (
+                        ("This type would have to contain itself".to_string(), Color::Yellow),
+                        *span1,
+                    ),
+                    (
+                        (format!("to be the same as type '{}' here", ty), Color::Yellow),
+                        *span2,
+                    ),
+                ],
+                vec![],
+            ),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Warning {
+    UnreachableMatchArm {
+        span: Span,
+    },
+    /// Only reported with `--warn-shadowing`
+    Shadowing {
+        name: String,
+        span: Span,
+        previous: Span,
+    },
+    /// Code after a statement that never finishes, like a `return`
+    UnreachableCode {
+        span: Span,
+        cause: Span,
+    },
+    /// A variable, parameter or function that nothing reads
+    UnreadBinding {
+        name: String,
+        span: Span,
+    },
+    /// Only reported with `--explain-types`, as the sites of the types the
+    /// binding's type was unified with, up to where it came from, and then
+    /// the same for each part of that which was only known later
+    InferredType {
+        name: String,
+        span: Span,
+        ty: Type,
+        steps: Vec<Vec<Span>>,
+    },
+    /// Only reported with `--verbose`, when compiling removes the variable
+    UnusedVariable {
+        name: String,
+        span: Span,
+    },
+    /// Only reported with `--verbose`, when compiling removes the expression
+    UnusedValue {
+        span: Span,
+    },
+}
+
+impl Warning {
+    pub fn make_report(&self) -> (Message, Spans, Notes) {
+        match self {
+            Warning::UnreachableMatchArm { span } => (
+                "Unreachable match arm".to_string(),
+                vec![(
+                    (
+                        "This pattern is already covered by earlier arms".to_string(),
+                        Color::Yellow,
+                    ),
+                    *span,
+                )],
+                vec![],
+            ),
+            Warning::Shadowing {
+                name,
+                span,
+                previous,
+            } => (
+                format!("'{}' shadows an earlier binding", name.fg(Color::Yellow)),
+                vec![
+                    (("The new binding".to_string(), Color::Yellow), *span),
+                    (("Shadows this one".to_string(), Color::Yellow), *previous),
+                ],
+                vec![],
+            ),
+            Warning::UnreachableCode { span, cause } => (
+                "Unreachable code".to_string(),
+                vec![
+                    (("This is never run".to_string(), Color::Yellow), *span),
+                    (
+                        (
+                            "Because nothing after this is reached".to_string(),
+                            Color::Yellow,
+                        ),
+                        *cause,
+                    ),
+                ],
+                vec![],
+            ),
+            Warning::UnreadBinding { name, span } => (
+                format!("'{}' is never read", name.fg(Color::Yellow)),
+                vec![(
+                    ("Nothing reads this binding".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec![format!(
+                    "help: if this is on purpose, name it '_{}' instead",
+                    name
+                )],
+            ),
+            Warning::InferredType {
+                name,
+                span,
+                ty,
+                steps,
+            } => (
+                format!("'{}' has type '{}'", name.fg(Color::Yellow), ty),
+                std::iter::once((("The binding".to_string(), Color::Yellow), *span))
+                    .chain(steps.iter().enumerate().flat_map(|(i, chain)| {
+                        chain.iter().enumerate().map(move |(j, step)| {
+                            let message = match (i, j + 1 == chain.len()) {
+                                (0, false) => "Which has the same type as this",
+                                (0, true) => "Which is where its type comes from",
+                                (_, false) => "Part of its type is the same as this",
+                                (_, true) => "Which is where that part of its type comes from",
+                            };
+
+                            ((message.to_string(), Color::Yellow), *step)
+                        })
+                    }))
+                    .collect(),
+                vec![],
+            ),
+            Warning::UnusedVariable { name, span } => (
+                format!("'{}' is never used", name.fg(Color::Yellow)),
+                vec![(
+                    ("Nothing reads this variable".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec![],
+            ),
+            Warning::UnusedValue { span } => (
+                "Unused value".to_string(),
+                vec![(
+                    ("This value is thrown away".to_string(), Color::Yellow),
+                    *span,
+                )],
+                vec![],
+            ),
+        }
+    }
+
+    pub fn code(&self) -> u32 {
+        match self {
+            Warning::UnreachableMatchArm { .. } => 1,
+            Warning::Shadowing { .. } => 2,
+            Warning::UnusedVariable { .. } => 3,
+            Warning::UnusedValue { .. } => 4,
+            Warning::UnreadBinding { .. } => 5,
+            Warning::UnreachableCode { .. } => 6,
+            Warning::InferredType { .. } => 7,
+        }
+    }
+}
+
+impl From<TypecheckError> for Error {
+    fn from(err: TypecheckError) -> Self {
+        Self::Typecheck(err)
+    }
+}
+
+/// A runtime error's message, with the span where it happened and the calls
+/// that were running
+fn runtime_report(
+    message: String,
+    span: Span,
+    trace: &[Spanned<String>],
+) -> (Message, Spans, Notes) {
+    let mut spans = vec![(
+        (
+            match trace.first() {
+                Some((function, _)) => format!("Fails here, in {}", callee(function)),
+                None => "Fails here".to_string(),
+            },
+            Color::Yellow,
+        ),
+        span,
+    )];
+
+    // the calls made from the same place, like by a recursive
+    // function, are shown once
+    let mut calls: Vec<(&Spanned<String>, usize)> = vec![];
+
+    for call in trace {
+        match calls.iter_mut().find(|(other, _)| *other == call) {
+            Some((_, times)) => *times += 1,
+            None => calls.push((call, 1)),
+        }
+    }
+
+    for ((function, call), times) in calls.iter().take(TRACE_LIMIT) {
+        let label = match times {
+            1 => format!("Calls {}", callee(function)),
+            times => format!("Calls {}, {} times", callee(function), times),
+        };
+
+        spans.push(((label, Color::Yellow), *call));
+    }
+
+    let hidden = calls
+        .iter()
+        .skip(TRACE_LIMIT)
+        .map(|(_, times)| times)
+        .sum::<usize>();
+
+    (
+        message,
+        spans,
+        if hidden > 0 {
+            vec![format!("{} calls further out aren't shown", hidden)]
+        } else {
+            vec![]
+        },
+    )
+}
+
+/// How a function that was called is shown in a runtime error's trace
+fn callee(function: &str) -> String {
+    match function {
+        "<closure>" => "a closure".to_string(),
+        function => format!("'{}'", source_name(function)),
     }
 }
 
@@ -203,7 +1691,7 @@ impl From<Rich<'_, String>> for Error {
         fn convert_reason(reason: RichReason<String>, span: Span) -> Error {
             match reason {
                 RichReason::ExpectedFound { expected, found } => Error::ExpectedFound {
-                    span: span,
+                    span,
                     expected: expected.iter().map(ToString::to_string).collect(),
                     found: found.map(|s| s.to_string()),
                 },
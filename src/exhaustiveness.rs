@@ -0,0 +1,478 @@
+use crate::typed_ast::{Pattern, Type};
+
+/// What a pattern says about the values it matches, without the names it
+/// binds
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pat {
+    /// Matches any value, like a binding
+    Wild,
+    /// Matches the values made with a constructor, like a variant or `some`,
+    /// whose fields match the patterns inside it
+    Ctor(String, Vec<Pat>),
+}
+
+impl Pat {
+    pub fn from_pattern(pattern: &Pattern) -> Self {
+        match pattern {
+            Pattern::Binding(_) => Pat::Wild,
+            Pattern::Variant { variant, bindings } => {
+                Pat::Ctor(variant.0.to_string(), vec![Pat::Wild; bindings.0.len()])
+            }
+            Pattern::Some(_) => Pat::Ctor("some".to_string(), vec![Pat::Wild]),
+            Pattern::None => Pat::Ctor("none".to_string(), vec![]),
+            Pattern::Ok(_) => Pat::Ctor("ok".to_string(), vec![Pat::Wild]),
+            Pattern::Err(_) => Pat::Ctor("err".to_string(), vec![Pat::Wild]),
+        }
+    }
+}
+
+impl std::fmt::Display for Pat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pat::Wild => write!(f, "_"),
+            Pat::Ctor(name, fields) if fields.is_empty() => write!(f, "{}", name),
+            Pat::Ctor(name, fields) => write!(
+                f,
+                "{}({})",
+                name,
+                fields
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// The constructors of a type along with the types of their fields, or
+/// `None` if its values can't be listed that way, like ints
+pub type Constructors<'a> = &'a dyn Fn(&Type) -> Option<Vec<(String, Vec<Type>)>>;
+
+pub struct Report {
+    /// Whether each arm matches a value no arm before it does
+    pub reachable: Vec<bool>,
+    /// Patterns for the values that no arm matches
+    pub missing: Vec<Pat>,
+}
+
+/// Checks the patterns of the arms of a match on a value of type `ty`.
+///
+/// The patterns are rows of a matrix, with a column for each value that's
+/// being matched, which starts as just the one. A pattern is useful if it
+/// matches a value no row above it does, which is worked out one column at a
+/// time: a constructor in the first column only has to be compared with the
+/// rows that can start with it, and its fields become new columns.
+pub fn check(arms: &[Pat], ty: &Type, constructors: Constructors) -> Report {
+    let types = [ty.clone()];
+
+    let mut matrix: Vec<Vec<Pat>> = Vec::with_capacity(arms.len());
+    let mut reachable = Vec::with_capacity(arms.len());
+
+    for arm in arms {
+        let row = vec![arm.clone()];
+
+        reachable.push(useful(&matrix, &row, &types, constructors));
+        matrix.push(row);
+    }
+
+    let missing = missing(&matrix, &types, constructors)
+        .into_iter()
+        .map(|mut row| row.remove(0))
+        .collect();
+
+    Report { reachable, missing }
+}
+
+/// Whether `row` matches a value that none of the rows of `matrix` do
+fn useful(matrix: &[Vec<Pat>], row: &[Pat], types: &[Type], constructors: Constructors) -> bool {
+    let Some((first, rest)) = row.split_first() else {
+        return matrix.is_empty();
+    };
+
+    match first {
+        Pat::Ctor(name, fields) => {
+            let types = field_types(&types[0], name, fields.len(), constructors)
+                .into_iter()
+                .chain(types[1..].iter().cloned())
+                .collect::<Vec<_>>();
+
+            useful(
+                &specialize_matrix(matrix, name, fields.len()),
+                &[fields.as_slice(), rest].concat(),
+                &types,
+                constructors,
+            )
+        }
+        // a wildcard is useful if it's useful for one of the constructors,
+        // but if some of them aren't in the first column, it's useful for
+        // those exactly when the rest of the row is useful for the rows that
+        // start with a wildcard
+        Pat::Wild => match complete(matrix, &types[0], constructors) {
+            Some(all) => all.into_iter().any(|(name, fields)| {
+                let arity = fields.len();
+                let types = fields
+                    .into_iter()
+                    .chain(types[1..].iter().cloned())
+                    .collect::<Vec<_>>();
+
+                useful(
+                    &specialize_matrix(matrix, &name, arity),
+                    &specialize(row, &name, arity).unwrap(),
+                    &types,
+                    constructors,
+                )
+            }),
+            None => useful(&default_matrix(matrix), rest, &types[1..], constructors),
+        },
+    }
+}
+
+/// The rows of values with types `types` that none of the rows of `matrix`
+/// match, as few as can be found by going through the constructors that are
+/// in the first column
+fn missing(matrix: &[Vec<Pat>], types: &[Type], constructors: Constructors) -> Vec<Vec<Pat>> {
+    let Some((ty, rest)) = types.split_first() else {
+        return if matrix.is_empty() {
+            vec![vec![]]
+        } else {
+            vec![]
+        };
+    };
+
+    if let Some(all) = complete(matrix, ty, constructors) {
+        let mut rows = vec![];
+
+        for (name, fields) in all {
+            let arity = fields.len();
+            let types = fields
+                .into_iter()
+                .chain(rest.iter().cloned())
+                .collect::<Vec<_>>();
+
+            for row in missing(
+                &specialize_matrix(matrix, &name, arity),
+                &types,
+                constructors,
+            ) {
+                let (fields, rest) = row.split_at(arity);
+
+                rows.push(
+                    std::iter::once(Pat::Ctor(name.clone(), fields.to_vec()))
+                        .chain(rest.iter().cloned())
+                        .collect(),
+                );
+            }
+        }
+
+        return rows;
+    }
+
+    let rows = missing(&default_matrix(matrix), rest, constructors);
+
+    // the constructors that aren't in the first column are the ones missing,
+    // or any value if they can't be listed
+    let heads = heads(matrix);
+    let firsts = match constructors(ty) {
+        Some(all) => all
+            .into_iter()
+            .filter(|(name, _)| !heads.contains(&name.as_str()))
+            .map(|(name, fields)| Pat::Ctor(name, vec![Pat::Wild; fields.len()]))
+            .collect(),
+        None => vec![Pat::Wild],
+    };
+
+    rows.into_iter()
+        .flat_map(|row| {
+            firsts.iter().map(move |first| {
+                std::iter::once(first.clone())
+                    .chain(row.iter().cloned())
+                    .collect()
+            })
+        })
+        .collect()
+}
+
+/// The constructors of `ty`, if every one of them is in the first column of
+/// `matrix`
+fn complete(
+    matrix: &[Vec<Pat>],
+    ty: &Type,
+    constructors: Constructors,
+) -> Option<Vec<(String, Vec<Type>)>> {
+    let heads = heads(matrix);
+
+    constructors(ty).filter(|all| all.iter().all(|(name, _)| heads.contains(&name.as_str())))
+}
+
+/// The names of the constructors in the first column of `matrix`
+fn heads(matrix: &[Vec<Pat>]) -> Vec<&str> {
+    matrix
+        .iter()
+        .filter_map(|row| match &row[0] {
+            Pat::Ctor(name, _) => Some(name.as_str()),
+            Pat::Wild => None,
+        })
+        .collect()
+}
+
+/// The types of the fields of the constructor `name` of `ty`
+fn field_types(ty: &Type, name: &str, arity: usize, constructors: Constructors) -> Vec<Type> {
+    constructors(ty)
+        .and_then(|all| all.into_iter().find(|(other, _)| other == name))
+        .map(|(_, fields)| fields)
+        // a constructor the type doesn't have was already reported, and a
+        // type without constructors only lets wildcards through
+        .unwrap_or_else(|| vec![Type::Unit; arity])
+}
+
+/// The row for the values made with the constructor `name`, with its fields
+/// in place of the first column, or `None` if the row can't match them
+fn specialize(row: &[Pat], name: &str, arity: usize) -> Option<Vec<Pat>> {
+    let fields = match &row[0] {
+        Pat::Wild => vec![Pat::Wild; arity],
+        Pat::Ctor(other, fields) if other == name => fields.clone(),
+        Pat::Ctor(..) => return None,
+    };
+
+    Some(fields.into_iter().chain(row[1..].iter().cloned()).collect())
+}
+
+fn specialize_matrix(matrix: &[Vec<Pat>], name: &str, arity: usize) -> Vec<Vec<Pat>> {
+    matrix
+        .iter()
+        .filter_map(|row| specialize(row, name, arity))
+        .collect()
+}
+
+/// The rows that start with a wildcard, without it
+fn default_matrix(matrix: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    matrix
+        .iter()
+        .filter(|row| row[0] == Pat::Wild)
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctor(name: &str, fields: Vec<Pat>) -> Pat {
+        Pat::Ctor(name.to_string(), fields)
+    }
+
+    fn shape() -> Type {
+        Type::Enum("Shape".to_string())
+    }
+
+    fn wrapper() -> Type {
+        Type::Enum("Wrapper".to_string())
+    }
+
+    /// The constructors of `enum Shape { Circle(float), Square(float), Dot }`,
+    /// `enum Wrapper { Wrap(Shape), Empty }` and optionals
+    fn constructors(ty: &Type) -> Option<Vec<(String, Vec<Type>)>> {
+        let all = match ty {
+            Type::Enum(name) if name == "Shape" => vec![
+                ("Circle", vec![Type::Float]),
+                ("Square", vec![Type::Float]),
+                ("Dot", vec![]),
+            ],
+            Type::Enum(name) if name == "Wrapper" => {
+                vec![("Wrap", vec![shape()]), ("Empty", vec![])]
+            }
+            Type::Optional(inner) => vec![("some", vec![(**inner).clone()]), ("none", vec![])],
+            _ => return None,
+        };
+
+        Some(
+            all.into_iter()
+                .map(|(name, fields)| (name.to_string(), fields))
+                .collect(),
+        )
+    }
+
+    /// Checks the arms, returning which are reachable and the missing
+    /// patterns as they're shown
+    fn check_arms(arms: &[Pat], ty: &Type) -> (Vec<bool>, Vec<String>) {
+        let report = check(arms, ty, &constructors);
+
+        (
+            report.reachable,
+            report.missing.iter().map(ToString::to_string).collect(),
+        )
+    }
+
+    #[test]
+    fn every_variant_covered() {
+        let arms = [
+            ctor("Circle", vec![Pat::Wild]),
+            ctor("Square", vec![Pat::Wild]),
+            ctor("Dot", vec![]),
+        ];
+
+        assert_eq!(check_arms(&arms, &shape()), (vec![true; 3], vec![]));
+    }
+
+    #[test]
+    fn missing_variants_are_suggested() {
+        let arms = [ctor("Circle", vec![Pat::Wild])];
+
+        assert_eq!(
+            check_arms(&arms, &shape()),
+            (vec![true], vec!["Square(_)".to_string(), "Dot".to_string()])
+        );
+    }
+
+    #[test]
+    fn wildcard_covers_the_rest() {
+        let arms = [ctor("Dot", vec![]), Pat::Wild];
+
+        assert_eq!(check_arms(&arms, &shape()), (vec![true, true], vec![]));
+    }
+
+    #[test]
+    fn variant_after_wildcard_is_unreachable() {
+        let arms = [Pat::Wild, ctor("Dot", vec![])];
+
+        assert_eq!(check_arms(&arms, &shape()), (vec![true, false], vec![]));
+    }
+
+    #[test]
+    fn wildcard_after_every_variant_is_unreachable() {
+        let arms = [
+            ctor("Circle", vec![Pat::Wild]),
+            ctor("Square", vec![Pat::Wild]),
+            ctor("Dot", vec![]),
+            Pat::Wild,
+        ];
+
+        assert_eq!(
+            check_arms(&arms, &shape()),
+            (vec![true, true, true, false], vec![])
+        );
+    }
+
+    #[test]
+    fn repeated_variant_is_redundant() {
+        let arms = [
+            ctor("Dot", vec![]),
+            ctor("Circle", vec![Pat::Wild]),
+            ctor("Dot", vec![]),
+            ctor("Square", vec![Pat::Wild]),
+        ];
+
+        assert_eq!(
+            check_arms(&arms, &shape()),
+            (vec![true, true, false, true], vec![])
+        );
+    }
+
+    #[test]
+    fn nested_enum_missing_inner_variant() {
+        let arms = [
+            ctor("Wrap", vec![ctor("Circle", vec![Pat::Wild])]),
+            ctor("Wrap", vec![ctor("Square", vec![Pat::Wild])]),
+            ctor("Empty", vec![]),
+        ];
+
+        assert_eq!(
+            check_arms(&arms, &wrapper()),
+            (vec![true; 3], vec!["Wrap(Dot)".to_string()])
+        );
+    }
+
+    #[test]
+    fn nested_enum_covered_by_wildcard() {
+        let arms = [
+            ctor("Wrap", vec![Pat::Wild]),
+            ctor("Wrap", vec![ctor("Dot", vec![])]),
+            ctor("Empty", vec![]),
+        ];
+
+        assert_eq!(
+            check_arms(&arms, &wrapper()),
+            (vec![true, false, true], vec![])
+        );
+    }
+
+    #[test]
+    fn type_without_constructors_needs_a_wildcard() {
+        assert_eq!(check_arms(&[], &Type::Int), (vec![], vec!["_".to_string()]));
+        assert_eq!(check_arms(&[Pat::Wild], &Type::Int), (vec![true], vec![]));
+    }
+
+    #[test]
+    fn empty_match_misses_every_variant() {
+        assert_eq!(
+            check_arms(&[], &shape()),
+            (
+                vec![],
+                vec![
+                    "Circle(_)".to_string(),
+                    "Square(_)".to_string(),
+                    "Dot".to_string()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn nested_optional_missing_inner_none() {
+        let ty = Type::Optional(Box::new(Type::Optional(Box::new(Type::Int))));
+        let arms = [
+            ctor("some", vec![ctor("some", vec![Pat::Wild])]),
+            ctor("none", vec![]),
+        ];
+
+        assert_eq!(
+            check_arms(&arms, &ty),
+            (vec![true, true], vec!["some(none)".to_string()])
+        );
+    }
+
+    #[test]
+    fn usefulness_across_columns() {
+        let types = [shape(), shape()];
+        let matrix = [
+            vec![ctor("Dot", vec![]), Pat::Wild],
+            vec![Pat::Wild, ctor("Dot", vec![])],
+        ];
+
+        let useful = |row: &[Pat]| useful(&matrix, row, &types, &constructors);
+
+        assert!(!useful(&[ctor("Dot", vec![]), ctor("Dot", vec![])]));
+        assert!(!useful(&[
+            ctor("Circle", vec![Pat::Wild]),
+            ctor("Dot", vec![])
+        ]));
+        assert!(useful(&[
+            ctor("Circle", vec![Pat::Wild]),
+            ctor("Square", vec![Pat::Wild])
+        ]));
+        assert!(useful(&[Pat::Wild, Pat::Wild]));
+    }
+
+    #[test]
+    fn missing_rows_across_columns() {
+        let types = [shape(), wrapper()];
+        let matrix = [
+            vec![Pat::Wild, ctor("Empty", vec![])],
+            vec![ctor("Dot", vec![]), Pat::Wild],
+        ];
+
+        let missing = missing(&matrix, &types, &constructors)
+            .into_iter()
+            .map(|row| row.iter().map(ToString::to_string).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            missing,
+            vec![
+                vec!["Circle(_)".to_string(), "Wrap(_)".to_string()],
+                vec!["Square(_)".to_string(), "Wrap(_)".to_string()],
+            ]
+        );
+    }
+}
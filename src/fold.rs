@@ -0,0 +1,453 @@
+use crate::typed_ast::*;
+use crate::{Span, Spanned};
+
+/// Evaluates an expression ahead of time, if all of it is known before
+/// running the program
+pub fn fold_constant(expr: Spanned<Expr>) -> Option<Spanned<Literal>> {
+    match fold_expr(expr).0.expr {
+        ExprKind::Literal(literal) => Some(literal),
+        _ => None,
+    }
+}
+
+/// Replaces operators whose result is known before running the program with
+/// that result.
+///
+/// Logical operators are folded as soon as their left operand is known,
+/// without looking at the right operand, so that folding never evaluates
+/// anything the program itself would have skipped.
+pub fn fold(ast: Spanned<TypedAst>) -> Spanned<TypedAst> {
+    let (statements, span) = ast.0.statements;
+
+    (
+        TypedAst {
+            statements: (statements.into_iter().map(fold_statement).collect(), span),
+            types: ast.0.types,
+        },
+        ast.1,
+    )
+}
+
+fn fold_statement(stmt: Spanned<Statement>) -> Spanned<Statement> {
+    (
+        match stmt.0 {
+            Statement::Expr(expr) => Statement::Expr(fold_expr(expr)),
+            Statement::Block(statements) => Statement::Block((
+                statements.0.into_iter().map(fold_statement).collect(),
+                statements.1,
+            )),
+            Statement::Let {
+                mutable,
+                name,
+                ty,
+                value,
+            } => Statement::Let {
+                mutable,
+                name,
+                ty,
+                value: fold_expr(value),
+            },
+            Statement::Function {
+                name,
+                generics,
+                params,
+                return_ty,
+                captures,
+                body,
+            } => Statement::Function {
+                name,
+                generics,
+                params,
+                return_ty,
+                captures,
+                body: Box::new(fold_statement(*body)),
+            },
+            Statement::Assign { name, value } => Statement::Assign {
+                name,
+                value: fold_expr(value),
+            },
+            Statement::SetIndex { name, index, value } => Statement::SetIndex {
+                name,
+                index: fold_expr(index),
+                value: fold_expr(value),
+            },
+            Statement::Return(expr) => Statement::Return(fold_expr(expr)),
+            Statement::Import {
+                path,
+                module,
+                file,
+                statements,
+            } => Statement::Import {
+                path,
+                module,
+                file,
+                statements: statements.into_iter().map(fold_statement).collect(),
+            },
+            Statement::Break { label, value } => Statement::Break {
+                label,
+                value: fold_expr(value),
+            },
+            Statement::While { cond, body } => Statement::While {
+                cond: fold_expr(cond),
+                body: (body.0.into_iter().map(fold_statement).collect(), body.1),
+            },
+            Statement::For {
+                first,
+                second,
+                iter,
+                body,
+            } => Statement::For {
+                first,
+                second,
+                iter: fold_expr(iter),
+                body: (body.0.into_iter().map(fold_statement).collect(), body.1),
+            },
+            Statement::Impl {
+                trait_,
+                ty,
+                methods,
+            } => Statement::Impl {
+                trait_,
+                ty,
+                methods: methods.into_iter().map(fold_statement).collect(),
+            },
+            stmt @ (Statement::Enum { .. }
+            | Statement::Struct { .. }
+            | Statement::TypeAlias { .. }
+            | Statement::Trait { .. }
+            | Statement::Const { .. }
+            | Statement::Continue) => stmt,
+        },
+        stmt.1,
+    )
+}
+
+fn fold_expr(expr: Spanned<Expr>) -> Spanned<Expr> {
+    let ty = expr.0.ty;
+
+    let kind = match expr.0.expr {
+        ExprKind::Prefix { op, expr: operand } => {
+            let operand = fold_expr(*operand);
+
+            match literal(&operand).and_then(|literal| fold_prefix(op.0, literal)) {
+                Some(literal) => ExprKind::Literal((literal, expr.1)),
+                None => ExprKind::Prefix {
+                    op,
+                    expr: Box::new(operand),
+                },
+            }
+        }
+        ExprKind::Binary { op, lhs, rhs } => {
+            let lhs = fold_expr(*lhs);
+
+            match (op.0, literal(&lhs)) {
+                (BinOp::LogicalOr, Some(&Literal::Bool(true)))
+                | (BinOp::LogicalAnd, Some(&Literal::Bool(false))) => {
+                    return with_span(lhs, expr.1)
+                }
+                (BinOp::LogicalOr, Some(&Literal::Bool(false)))
+                | (BinOp::LogicalAnd, Some(&Literal::Bool(true))) => return fold_expr(*rhs),
+                _ => {}
+            }
+
+            let rhs = fold_expr(*rhs);
+
+            match (op.0, literal(&rhs)) {
+                (BinOp::LogicalOr, Some(&Literal::Bool(false)))
+                | (BinOp::LogicalAnd, Some(&Literal::Bool(true))) => return lhs,
+                _ => {}
+            }
+
+            let folded = match (literal(&lhs), literal(&rhs)) {
+                (Some(a), Some(b)) => fold_binary(op.0, a, b),
+                _ => None,
+            };
+
+            match folded {
+                Some(literal) => ExprKind::Literal((literal, expr.1)),
+                None => ExprKind::Binary {
+                    op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+            }
+        }
+        ExprKind::Call { callee, args } => ExprKind::Call {
+            callee: Box::new(fold_expr(*callee)),
+            args: (args.0.into_iter().map(fold_expr).collect(), args.1),
+        },
+        ExprKind::MethodCall {
+            receiver,
+            trait_,
+            method,
+            args,
+        } => ExprKind::MethodCall {
+            receiver: Box::new(fold_expr(*receiver)),
+            trait_,
+            method,
+            args: (args.0.into_iter().map(fold_expr).collect(), args.1),
+        },
+        ExprKind::BuiltinCall { builtin, args } => ExprKind::BuiltinCall {
+            builtin,
+            args: (args.0.into_iter().map(fold_expr).collect(), args.1),
+        },
+        ExprKind::Variant {
+            enum_,
+            variant,
+            args,
+        } => ExprKind::Variant {
+            enum_,
+            variant,
+            args: (args.0.into_iter().map(fold_expr).collect(), args.1),
+        },
+        ExprKind::Match { expr, arms } => ExprKind::Match {
+            expr: Box::new(fold_expr(*expr)),
+            arms: (
+                arms.0
+                    .into_iter()
+                    .map(|(pattern, body)| (pattern, fold_expr(body)))
+                    .collect(),
+                arms.1,
+            ),
+        },
+        ExprKind::Some(expr) => ExprKind::Some(Box::new(fold_expr(*expr))),
+        ExprKind::Ok(expr) => ExprKind::Ok(Box::new(fold_expr(*expr))),
+        ExprKind::Err(expr) => ExprKind::Err(Box::new(fold_expr(*expr))),
+        ExprKind::Try(expr) => ExprKind::Try(Box::new(fold_expr(*expr))),
+        ExprKind::Is { expr, variant } => ExprKind::Is {
+            expr: Box::new(fold_expr(*expr)),
+            variant,
+        },
+        ExprKind::In { expr, collection } => ExprKind::In {
+            expr: Box::new(fold_expr(*expr)),
+            collection: Box::new(fold_expr(*collection)),
+        },
+        ExprKind::Range {
+            start,
+            end,
+            inclusive,
+        } => ExprKind::Range {
+            start: Box::new(fold_expr(*start)),
+            end: Box::new(fold_expr(*end)),
+            inclusive,
+        },
+        ExprKind::Ref { mutable, expr } => ExprKind::Ref {
+            mutable,
+            expr: Box::new(fold_expr(*expr)),
+        },
+        ExprKind::Cast { expr: value, ty } => {
+            let value = fold_expr(*value);
+
+            // an int converted to a float is known ahead of time, which
+            // constants with mixed arithmetic need
+            match (literal(&value), &ty.0) {
+                (Some(&Literal::Int(n)), Type::Float) => {
+                    ExprKind::Literal((Literal::Float(n as f64), expr.1))
+                }
+                _ => ExprKind::Cast {
+                    expr: Box::new(value),
+                    ty,
+                },
+            }
+        }
+        ExprKind::Record(fields) => ExprKind::Record(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name, fold_expr(value)))
+                .collect(),
+        ),
+        ExprKind::Struct { name, fields } => ExprKind::Struct {
+            name,
+            fields: fields
+                .into_iter()
+                .map(|(name, value)| (name, fold_expr(value)))
+                .collect(),
+        },
+        ExprKind::List(elements) => {
+            ExprKind::List((elements.0.into_iter().map(fold_expr).collect(), elements.1))
+        }
+        ExprKind::Map(entries) => ExprKind::Map((
+            entries
+                .0
+                .into_iter()
+                .map(|(key, value)| (fold_expr(key), fold_expr(value)))
+                .collect(),
+            entries.1,
+        )),
+        ExprKind::FieldAccess { expr, field } => ExprKind::FieldAccess {
+            expr: Box::new(fold_expr(*expr)),
+            field,
+        },
+        ExprKind::Index { expr, index } => ExprKind::Index {
+            expr: Box::new(fold_expr(*expr)),
+            index: Box::new(fold_expr(*index)),
+        },
+        ExprKind::LabeledBlock {
+            label,
+            statements,
+            tail,
+        } => ExprKind::LabeledBlock {
+            label,
+            statements: (
+                statements.0.into_iter().map(fold_statement).collect(),
+                statements.1,
+            ),
+            tail: tail.map(|tail| Box::new(fold_expr(*tail))),
+        },
+        ExprKind::If { cond, then, else_ } => ExprKind::If {
+            cond: Box::new(fold_expr(*cond)),
+            then: fold_block(then),
+            else_: else_.map(fold_block),
+        },
+        ExprKind::Loop { body, breaks } => ExprKind::Loop {
+            body: (body.0.into_iter().map(fold_statement).collect(), body.1),
+            breaks,
+        },
+        ExprKind::Block(block) => ExprKind::Block(fold_block((block, expr.1)).0),
+        ExprKind::Catch {
+            body,
+            binding,
+            handler,
+        } => ExprKind::Catch {
+            body: fold_block(body),
+            binding,
+            handler: fold_block(handler),
+        },
+        ExprKind::Closure {
+            params,
+            captures,
+            body,
+        } => ExprKind::Closure {
+            params,
+            captures,
+            body: Box::new(fold_expr(*body)),
+        },
+        kind @ (ExprKind::Var(_) | ExprKind::ModuleVar { .. } | ExprKind::Literal(_)) => kind,
+    };
+
+    (Expr { expr: kind, ty }, expr.1)
+}
+
+fn fold_block(block: Spanned<Block>) -> Spanned<Block> {
+    (
+        Block {
+            statements: block.0.statements.into_iter().map(fold_statement).collect(),
+            tail: block.0.tail.map(|tail| Box::new(fold_expr(*tail))),
+        },
+        block.1,
+    )
+}
+
+fn literal<'a>(expr: &'a Spanned<Expr>) -> Option<&'a Literal> {
+    match &expr.0.expr {
+        ExprKind::Literal((literal, _)) => Some(literal),
+        _ => None,
+    }
+}
+
+/// Widens an already folded operand to cover the span of the whole operation
+fn with_span(expr: Spanned<Expr>, span: Span) -> Spanned<Expr> {
+    match expr.0.expr {
+        ExprKind::Literal((literal, _)) => (
+            Expr {
+                expr: ExprKind::Literal((literal, span)),
+                ty: expr.0.ty,
+            },
+            span,
+        ),
+        _ => expr,
+    }
+}
+
+/// The result of a prefix operator on a constant, or `None` if it fails at
+/// runtime
+pub fn fold_prefix(op: PrefixOp, operand: &Literal) -> Option<Literal> {
+    match (op, operand) {
+        (PrefixOp::Negate, Literal::Int(n)) => n.checked_neg().map(Literal::Int),
+        (PrefixOp::Negate, Literal::Float(n)) => Some(Literal::Float(-n)),
+        (PrefixOp::Not, Literal::Bool(b)) => Some(Literal::Bool(!b)),
+        (PrefixOp::BitNot, Literal::Int(n)) => Some(Literal::Int(!n)),
+        _ => None,
+    }
+}
+
+/// The result of an operator other than the short-circuiting ones on two
+/// constants, or `None` if it fails at runtime, which is left for the
+/// program to report
+pub fn fold_binary(op: BinOp, lhs: &Literal, rhs: &Literal) -> Option<Literal> {
+    match (lhs, rhs) {
+        (&Literal::Int(a), &Literal::Int(b)) => fold_int(op, a, b),
+        (&Literal::Float(a), &Literal::Float(b)) => Some(fold_float(op, a, b)),
+        (&Literal::Bool(a), &Literal::Bool(b)) => Some(fold_bool(op, a, b)),
+        _ => None,
+    }
+}
+
+fn fold_int(op: BinOp, a: i64, b: i64) -> Option<Literal> {
+    Some(match op {
+        BinOp::Add => Literal::Int(a.checked_add(b)?),
+        BinOp::Subtract => Literal::Int(a.checked_sub(b)?),
+        BinOp::Multiply => Literal::Int(a.checked_mul(b)?),
+        BinOp::Divide => Literal::Int(a.checked_div(b)?),
+        BinOp::Modulo => Literal::Int(a.checked_rem(b)?),
+        BinOp::Power => Literal::Int(a.checked_pow(u32::try_from(b).ok()?)?),
+        BinOp::BitAnd => Literal::Int(a & b),
+        BinOp::BitOr => Literal::Int(a | b),
+        BinOp::BitXor => Literal::Int(a ^ b),
+        BinOp::ShiftLeft => Literal::Int(a.checked_shl(u32::try_from(b).ok()?)?),
+        BinOp::ShiftRight => Literal::Int(a.checked_shr(u32::try_from(b).ok()?)?),
+        BinOp::Equals => Literal::Bool(a == b),
+        BinOp::NotEquals => Literal::Bool(a != b),
+        BinOp::LessThan => Literal::Bool(a < b),
+        BinOp::LessThanOrEqual => Literal::Bool(a <= b),
+        BinOp::GreaterThan => Literal::Bool(a > b),
+        BinOp::GreaterThanOrEqual => Literal::Bool(a >= b),
+        BinOp::LogicalAnd | BinOp::LogicalOr | BinOp::Coalesce => unreachable!(),
+    })
+}
+
+fn fold_float(op: BinOp, a: f64, b: f64) -> Literal {
+    match op {
+        BinOp::Add => Literal::Float(a + b),
+        BinOp::Subtract => Literal::Float(a - b),
+        BinOp::Multiply => Literal::Float(a * b),
+        BinOp::Divide => Literal::Float(a / b),
+        BinOp::Modulo => Literal::Float(a % b),
+        BinOp::Power => Literal::Float(a.powf(b)),
+        BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::ShiftLeft | BinOp::ShiftRight => {
+            unreachable!()
+        }
+        BinOp::Equals => Literal::Bool(a == b),
+        BinOp::NotEquals => Literal::Bool(a != b),
+        BinOp::LessThan => Literal::Bool(a < b),
+        BinOp::LessThanOrEqual => Literal::Bool(a <= b),
+        BinOp::GreaterThan => Literal::Bool(a > b),
+        BinOp::GreaterThanOrEqual => Literal::Bool(a >= b),
+        BinOp::LogicalAnd | BinOp::LogicalOr | BinOp::Coalesce => unreachable!(),
+    }
+}
+
+fn fold_bool(op: BinOp, a: bool, b: bool) -> Literal {
+    match op {
+        BinOp::Equals => Literal::Bool(a == b),
+        BinOp::NotEquals => Literal::Bool(a != b),
+        BinOp::LessThan => Literal::Bool(!a & b),
+        BinOp::LessThanOrEqual => Literal::Bool(a <= b),
+        BinOp::GreaterThan => Literal::Bool(a & !b),
+        BinOp::GreaterThanOrEqual => Literal::Bool(a >= b),
+        BinOp::LogicalAnd => Literal::Bool(a && b),
+        BinOp::LogicalOr => Literal::Bool(a || b),
+        BinOp::Add
+        | BinOp::Subtract
+        | BinOp::Multiply
+        | BinOp::Divide
+        | BinOp::Modulo
+        | BinOp::Power
+        | BinOp::BitAnd
+        | BinOp::BitOr
+        | BinOp::BitXor
+        | BinOp::ShiftLeft
+        | BinOp::ShiftRight
+        | BinOp::Coalesce => unreachable!(),
+    }
+}
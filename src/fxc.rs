@@ -0,0 +1,980 @@
+use crate::bytecode::{Bindings, Function, Op, Program, Unpack};
+use crate::gc::Handle;
+use crate::interpreter::Value;
+use crate::loader::SourceMap;
+use crate::prelude::Builtin;
+use crate::typed_ast::{BinOp, FormatSpec, PrefixOp, Type};
+use crate::verifier;
+use crate::Span;
+
+/// The start of every `.fxc` file
+const MAGIC: &[u8; 4] = b"FXC\0";
+
+/// The version of the format, which has to go up whenever the bytecode or
+/// the way it's written changes, so that older files are rejected
+const VERSION: u32 = 2;
+
+const BIN_OPS: [BinOp; 20] = [
+    BinOp::Add,
+    BinOp::Subtract,
+    BinOp::Multiply,
+    BinOp::Divide,
+    BinOp::Modulo,
+    BinOp::Power,
+    BinOp::BitAnd,
+    BinOp::BitOr,
+    BinOp::BitXor,
+    BinOp::ShiftLeft,
+    BinOp::ShiftRight,
+    BinOp::Equals,
+    BinOp::NotEquals,
+    BinOp::LessThan,
+    BinOp::LessThanOrEqual,
+    BinOp::GreaterThan,
+    BinOp::GreaterThanOrEqual,
+    BinOp::LogicalAnd,
+    BinOp::LogicalOr,
+    BinOp::Coalesce,
+];
+
+const PREFIX_OPS: [PrefixOp; 3] = [PrefixOp::Negate, PrefixOp::Not, PrefixOp::BitNot];
+
+/// Writes a compiled program to the contents of a `.fxc` file, along with
+/// the source files its spans point into so runtime errors can be shown
+/// without them
+pub fn write(program: &Program, sources: &SourceMap) -> Vec<u8> {
+    let mut writer = Writer {
+        out: MAGIC.to_vec(),
+    };
+
+    writer.out.extend(VERSION.to_le_bytes());
+
+    let sources = sources.sources().collect::<Vec<_>>();
+
+    writer.usize(sources.len());
+
+    for (name, source) in sources {
+        writer.str(&name);
+        writer.str(source);
+    }
+
+    writer.program(program);
+
+    writer.out
+}
+
+/// Reads a program that `write` wrote, with its source files, making sure
+/// the VM can run it
+pub fn read(bytes: &[u8]) -> Result<(Program, SourceMap), String> {
+    if bytes.get(..4) != Some(MAGIC) {
+        return Err("isn't a compiled foxglove program".to_string());
+    }
+
+    let version = bytes
+        .get(4..8)
+        .map(|version| u32::from_le_bytes(version.try_into().unwrap()));
+
+    if version != Some(VERSION) {
+        return Err(
+            "was built by a different version of foxglove, it has to be built again".to_string(),
+        );
+    }
+
+    let mut reader = Reader { bytes, at: 8 };
+
+    let (program, sources) = reader
+        .contents()
+        .filter(|_| reader.at == bytes.len())
+        .ok_or_else(|| "is damaged and can't be read".to_string())?;
+
+    verifier::verify(&program).map_err(|e| format!("has invalid bytecode: {}", e))?;
+
+    Ok((program, sources))
+}
+
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, n: u8) {
+        self.out.push(n);
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.out.extend(n.to_le_bytes());
+    }
+
+    fn usize(&mut self, n: usize) {
+        self.u64(n as u64);
+    }
+
+    fn bool(&mut self, b: bool) {
+        self.u8(b as u8);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.out.extend(s.as_bytes());
+    }
+
+    fn option(&mut self, n: Option<usize>) {
+        match n {
+            Some(n) => {
+                self.u8(1);
+                self.usize(n);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn program(&mut self, program: &Program) {
+        self.usize(program.functions.len());
+
+        for function in &program.functions {
+            self.function(function);
+        }
+
+        self.usize(program.main);
+
+        self.usize(program.constants.len());
+
+        for constant in &program.constants {
+            self.value(constant);
+        }
+
+        self.usize(program.names.len());
+
+        for name in &program.names {
+            self.str(name);
+        }
+
+        self.usize(program.shapes.len());
+
+        for shape in &program.shapes {
+            self.usize(shape.len());
+
+            for field in shape {
+                self.str(field);
+            }
+        }
+
+        self.usize(program.types.len());
+
+        for ty in &program.types {
+            self.ty(ty);
+        }
+
+        self.usize(program.globals.len());
+
+        for global in &program.globals {
+            self.str(global);
+        }
+
+        // sorted so that building the same program gives the same file
+        let mut methods = program.methods.iter().collect::<Vec<_>>();
+        methods.sort();
+
+        self.usize(methods.len());
+
+        for ((trait_, method, type_name), function) in methods {
+            match trait_ {
+                Some(trait_) => {
+                    self.u8(1);
+                    self.str(trait_);
+                }
+                None => self.u8(0),
+            }
+
+            self.str(method);
+            self.str(type_name);
+            self.usize(*function);
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.str(&function.name);
+        self.usize(function.params);
+        self.usize(function.locals);
+
+        self.usize(function.code.len());
+
+        for (op, span) in function.code.iter().zip(&function.spans) {
+            self.op(*op);
+            self.usize(span.start);
+            self.usize(span.end);
+        }
+    }
+
+    fn op(&mut self, op: Op) {
+        match op {
+            Op::Const(constant) => {
+                self.u8(0);
+                self.usize(constant);
+            }
+            Op::Pop => self.u8(1),
+            Op::Dup => self.u8(2),
+            Op::LoadLocal(slot) => {
+                self.u8(3);
+                self.usize(slot);
+            }
+            Op::InitLocal(slot) => {
+                self.u8(4);
+                self.usize(slot);
+            }
+            Op::StoreLocal(slot) => {
+                self.u8(5);
+                self.usize(slot);
+            }
+            Op::LoadGlobal(slot) => {
+                self.u8(6);
+                self.usize(slot);
+            }
+            Op::InitGlobal(slot) => {
+                self.u8(7);
+                self.usize(slot);
+            }
+            Op::StoreGlobal(slot) => {
+                self.u8(8);
+                self.usize(slot);
+            }
+            Op::LoadCapture(i) => {
+                self.u8(9);
+                self.usize(i);
+            }
+            Op::RefLocal(slot) => {
+                self.u8(10);
+                self.usize(slot);
+            }
+            Op::RefGlobal(slot) => {
+                self.u8(11);
+                self.usize(slot);
+            }
+            Op::Prefix(op) => {
+                self.u8(12);
+                self.prefix_op(op);
+            }
+            Op::Binary(op) => {
+                self.u8(13);
+                self.bin_op(op);
+            }
+            Op::BinaryConst(op, constant) => {
+                self.u8(14);
+                self.bin_op(op);
+                self.usize(constant);
+            }
+            Op::BinaryLocal(op, slot) => {
+                self.u8(15);
+                self.bin_op(op);
+                self.usize(slot);
+            }
+            Op::Jump(to) => {
+                self.u8(16);
+                self.usize(to);
+            }
+            Op::JumpIfFalse(to) => {
+                self.u8(17);
+                self.usize(to);
+            }
+            Op::JumpIfTrue(to) => {
+                self.u8(18);
+                self.usize(to);
+            }
+            Op::Coalesce(to) => {
+                self.u8(19);
+                self.usize(to);
+            }
+            Op::Call { args } => {
+                self.u8(20);
+                self.usize(args);
+            }
+            Op::CallMethod {
+                trait_,
+                method,
+                args,
+            } => {
+                self.u8(21);
+                self.option(trait_);
+                self.usize(method);
+                self.usize(args);
+            }
+            Op::CallBuiltin { builtin, args } => {
+                self.u8(22);
+                self.builtin(builtin);
+                self.usize(args);
+            }
+            Op::Return => self.u8(23),
+            Op::MakeFunction { function, captures } => {
+                self.u8(24);
+                self.usize(function);
+                self.usize(captures);
+            }
+            Op::MakeClosure { function, captures } => {
+                self.u8(25);
+                self.usize(function);
+                self.usize(captures);
+            }
+            Op::MakeList(elements) => {
+                self.u8(26);
+                self.usize(elements);
+            }
+            Op::MakeMap(entries) => {
+                self.u8(27);
+                self.usize(entries);
+            }
+            Op::MakeRecord { shape } => {
+                self.u8(28);
+                self.usize(shape);
+            }
+            Op::MakeStruct { name, shape } => {
+                self.u8(29);
+                self.usize(name);
+                self.usize(shape);
+            }
+            Op::MakeVariant {
+                enum_,
+                variant,
+                fields,
+            } => {
+                self.u8(30);
+                self.usize(enum_);
+                self.usize(variant);
+                self.usize(fields);
+            }
+            Op::MakeRange { inclusive } => {
+                self.u8(31);
+                self.bool(inclusive);
+            }
+            Op::WrapSome => self.u8(32),
+            Op::WrapOk => self.u8(33),
+            Op::WrapErr => self.u8(34),
+            Op::GetField(name) => {
+                self.u8(35);
+                self.usize(name);
+            }
+            Op::Index => self.u8(36),
+            Op::SetIndexLocal(slot) => {
+                self.u8(37);
+                self.usize(slot);
+            }
+            Op::SetIndexGlobal(slot) => {
+                self.u8(38);
+                self.usize(slot);
+            }
+            Op::In => self.u8(39),
+            Op::Is(variant) => {
+                self.u8(40);
+                self.usize(variant);
+            }
+            Op::Cast(ty) => {
+                self.u8(41);
+                self.usize(ty);
+            }
+            Op::Try => self.u8(42),
+            Op::Unpack(pattern, to) => {
+                self.u8(43);
+
+                match pattern {
+                    Unpack::Variant { variant, fields } => {
+                        self.u8(0);
+                        self.usize(variant);
+                        self.usize(fields);
+                    }
+                    Unpack::Some => self.u8(1),
+                    Unpack::None => self.u8(2),
+                    Unpack::Ok => self.u8(3),
+                    Unpack::Err => self.u8(4),
+                }
+
+                self.usize(to);
+            }
+            Op::Next {
+                collection,
+                counter,
+                bindings,
+                done,
+            } => {
+                self.u8(44);
+                self.usize(collection);
+                self.usize(counter);
+                self.u8(match bindings {
+                    Bindings::Both => 0,
+                    Bindings::First => 1,
+                    Bindings::Second => 2,
+                });
+                self.usize(done);
+            }
+            Op::Truncate { height, keep } => {
+                self.u8(45);
+                self.usize(height);
+                self.bool(keep);
+            }
+            Op::Catch(to) => {
+                self.u8(46);
+                self.usize(to);
+            }
+            Op::EndCatch => self.u8(47),
+        }
+    }
+
+    fn bin_op(&mut self, op: BinOp) {
+        self.u8(BIN_OPS.iter().position(|other| *other == op).unwrap() as u8);
+    }
+
+    fn prefix_op(&mut self, op: PrefixOp) {
+        self.u8(PREFIX_OPS.iter().position(|other| *other == op).unwrap() as u8);
+    }
+
+    fn builtin(&mut self, builtin: Builtin) {
+        match builtin {
+            Builtin::Tick => self.u8(0),
+            Builtin::Chars => self.u8(1),
+            Builtin::Len => self.u8(2),
+            Builtin::Print => self.u8(3),
+            Builtin::Println => self.u8(4),
+            Builtin::Format => self.u8(5),
+            Builtin::ToStr => self.u8(6),
+            Builtin::ToStrWith(spec) => {
+                self.u8(7);
+
+                match spec {
+                    FormatSpec::Debug => self.u8(0),
+                    FormatSpec::Hex => self.u8(1),
+                    FormatSpec::Binary => self.u8(2),
+                    FormatSpec::Octal => self.u8(3),
+                    FormatSpec::Precision(digits) => {
+                        self.u8(4);
+                        self.usize(digits);
+                    }
+                }
+            }
+        }
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        match ty {
+            Type::Int => self.u8(0),
+            Type::Float => self.u8(1),
+            Type::Bool => self.u8(2),
+            Type::Str => self.u8(3),
+            Type::Char => self.u8(4),
+            Type::Unit => self.u8(5),
+            Type::Range => self.u8(6),
+            Type::Enum(name) => {
+                self.u8(7);
+                self.str(name);
+            }
+            Type::Struct(name) => {
+                self.u8(8);
+                self.str(name);
+            }
+            Type::Param(name) => {
+                self.u8(9);
+                self.str(name);
+            }
+            Type::Optional(inner) => {
+                self.u8(10);
+                self.ty(inner);
+            }
+            Type::List(inner) => {
+                self.u8(11);
+                self.ty(inner);
+            }
+            Type::Map(key, value) => {
+                self.u8(12);
+                self.ty(key);
+                self.ty(value);
+            }
+            Type::Result(ok, err) => {
+                self.u8(13);
+                self.ty(ok);
+                self.ty(err);
+            }
+            Type::Record(fields) => {
+                self.u8(14);
+                self.usize(fields.len());
+
+                for (name, ty) in fields {
+                    self.str(name);
+                    self.ty(ty);
+                }
+            }
+            Type::Func(params, ret) => {
+                self.u8(15);
+                self.usize(params.len());
+
+                for param in params {
+                    self.ty(param);
+                }
+
+                self.ty(ret);
+            }
+            Type::Ref(mutable, inner) => {
+                self.u8(16);
+                self.bool(*mutable);
+                self.ty(inner);
+            }
+            Type::Var(_) => unreachable!("type variables are solved before compiling"),
+        }
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Int(n) => {
+                self.u8(0);
+                self.u64(*n as u64);
+            }
+            Value::Float(n) => {
+                self.u8(1);
+                self.u64(n.to_bits());
+            }
+            Value::Str(s) => {
+                self.u8(2);
+                self.str(s);
+            }
+            Value::Char(c) => {
+                self.u8(3);
+                self.u64(*c as u64);
+            }
+            Value::Bool(b) => {
+                self.u8(4);
+                self.bool(*b);
+            }
+            Value::Unit => self.u8(5),
+            Value::Variant {
+                enum_,
+                variant,
+                fields,
+            } => {
+                self.u8(6);
+                self.str(enum_);
+                self.str(variant);
+                self.values(fields);
+            }
+            Value::Optional(inner) => {
+                self.u8(7);
+                self.bool(inner.is_some());
+
+                if let Some(inner) = inner {
+                    self.value(inner);
+                }
+            }
+            Value::Result(result) => {
+                self.u8(8);
+                self.bool(result.is_ok());
+
+                match result {
+                    Ok(value) | Err(value) => self.value(value),
+                }
+            }
+            Value::Record(fields) => {
+                self.u8(9);
+                self.fields(fields);
+            }
+            Value::Struct { name, fields } => {
+                self.u8(10);
+                self.str(name);
+                self.fields(fields);
+            }
+            Value::List(elements) => {
+                self.u8(11);
+                self.values(elements);
+            }
+            Value::Map(entries) => {
+                self.u8(12);
+                self.usize(entries.len());
+
+                for (key, value) in entries {
+                    self.value(key);
+                    self.value(value);
+                }
+            }
+            Value::Range { start, end } => {
+                self.u8(13);
+                self.u64(*start as u64);
+                self.u64(*end as u64);
+            }
+            // only the program's own functions can be constants
+            Value::Func { name, id } => {
+                self.u8(14);
+                self.str(name);
+                self.usize(id.index());
+            }
+            Value::Closure { .. } | Value::Ref(_) => {
+                unreachable!("closures and references are never constants")
+            }
+        }
+    }
+
+    fn values(&mut self, values: &[Value]) {
+        self.usize(values.len());
+
+        for value in values {
+            self.value(value);
+        }
+    }
+
+    fn fields(&mut self, fields: &[(String, Value)]) {
+        self.usize(fields.len());
+
+        for (name, value) in fields {
+            self.str(name);
+            self.value(value);
+        }
+    }
+}
+
+/// Reads what `Writer` wrote, returning `None` if the bytes don't make sense
+/// or run out
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.at)?;
+
+        self.at += 1;
+
+        Some(byte)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let bytes = self.bytes.get(self.at..self.at + 8)?;
+
+        self.at += 8;
+
+        Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        self.u64()?.try_into().ok()
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.usize()?;
+        let bytes = self.bytes.get(self.at..self.at.checked_add(len)?)?;
+
+        self.at += len;
+
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+
+    fn option(&mut self) -> Option<Option<usize>> {
+        match self.bool()? {
+            true => Some(Some(self.usize()?)),
+            false => Some(None),
+        }
+    }
+
+    /// Reads `len` things, without trusting `len` to reserve space up front
+    fn many<T>(&mut self, mut read: impl FnMut(&mut Self) -> Option<T>) -> Option<Vec<T>> {
+        let len = self.usize()?;
+        let mut items = vec![];
+
+        for _ in 0..len {
+            items.push(read(self)?);
+        }
+
+        Some(items)
+    }
+
+    fn contents(&mut self) -> Option<(Program, SourceMap)> {
+        let mut sources = SourceMap::default();
+
+        for _ in 0..self.usize()? {
+            let name = self.str()?;
+            let source = self.str()?;
+
+            // leaked like the loader's, for the same lifetime
+            sources.add(name, source.leak());
+        }
+
+        let program = self.program()?;
+
+        // runtime errors are shown at the span of the instruction that failed
+        program
+            .functions
+            .iter()
+            .flat_map(|function| &function.spans)
+            .all(|span| sources.contains(*span))
+            .then_some((program, sources))
+    }
+
+    fn program(&mut self) -> Option<Program> {
+        let functions = self.many(Self::function)?;
+        let main = self.usize()?;
+        let constants = self.many(Self::value)?;
+        let names = self.many(Self::str)?;
+        let shapes = self.many(|reader| reader.many(Self::str))?;
+        let types = self.many(Self::ty)?;
+        let globals = self.many(Self::str)?;
+
+        let methods = self
+            .many(|reader| {
+                let trait_ = match reader.bool()? {
+                    true => Some(reader.str()?),
+                    false => None,
+                };
+
+                Some(((trait_, reader.str()?, reader.str()?), reader.usize()?))
+            })?
+            .into_iter()
+            .collect();
+
+        Some(Program {
+            functions,
+            main,
+            constants,
+            names,
+            shapes,
+            types,
+            globals,
+            methods,
+        })
+    }
+
+    fn function(&mut self) -> Option<Function> {
+        let name = self.str()?;
+        let params = self.usize()?;
+        let locals = self.usize()?;
+
+        let (code, spans) = self
+            .many(|reader| {
+                let op = reader.op()?;
+                let span = Span::from(reader.usize()?..reader.usize()?);
+
+                Some((op, span))
+            })?
+            .into_iter()
+            .unzip();
+
+        Some(Function {
+            name,
+            params,
+            locals,
+            code,
+            spans,
+        })
+    }
+
+    fn op(&mut self) -> Option<Op> {
+        let op = match self.u8()? {
+            0 => Op::Const(self.usize()?),
+            1 => Op::Pop,
+            2 => Op::Dup,
+            3 => Op::LoadLocal(self.usize()?),
+            4 => Op::InitLocal(self.usize()?),
+            5 => Op::StoreLocal(self.usize()?),
+            6 => Op::LoadGlobal(self.usize()?),
+            7 => Op::InitGlobal(self.usize()?),
+            8 => Op::StoreGlobal(self.usize()?),
+            9 => Op::LoadCapture(self.usize()?),
+            10 => Op::RefLocal(self.usize()?),
+            11 => Op::RefGlobal(self.usize()?),
+            12 => Op::Prefix(self.prefix_op()?),
+            13 => Op::Binary(self.bin_op()?),
+            14 => Op::BinaryConst(self.bin_op()?, self.usize()?),
+            15 => Op::BinaryLocal(self.bin_op()?, self.usize()?),
+            16 => Op::Jump(self.usize()?),
+            17 => Op::JumpIfFalse(self.usize()?),
+            18 => Op::JumpIfTrue(self.usize()?),
+            19 => Op::Coalesce(self.usize()?),
+            20 => Op::Call {
+                args: self.usize()?,
+            },
+            21 => Op::CallMethod {
+                trait_: self.option()?,
+                method: self.usize()?,
+                args: self.usize()?,
+            },
+            22 => Op::CallBuiltin {
+                builtin: self.builtin()?,
+                args: self.usize()?,
+            },
+            23 => Op::Return,
+            24 => Op::MakeFunction {
+                function: self.usize()?,
+                captures: self.usize()?,
+            },
+            25 => Op::MakeClosure {
+                function: self.usize()?,
+                captures: self.usize()?,
+            },
+            26 => Op::MakeList(self.usize()?),
+            27 => Op::MakeMap(self.usize()?),
+            28 => Op::MakeRecord {
+                shape: self.usize()?,
+            },
+            29 => Op::MakeStruct {
+                name: self.usize()?,
+                shape: self.usize()?,
+            },
+            30 => Op::MakeVariant {
+                enum_: self.usize()?,
+                variant: self.usize()?,
+                fields: self.usize()?,
+            },
+            31 => Op::MakeRange {
+                inclusive: self.bool()?,
+            },
+            32 => Op::WrapSome,
+            33 => Op::WrapOk,
+            34 => Op::WrapErr,
+            35 => Op::GetField(self.usize()?),
+            36 => Op::Index,
+            37 => Op::SetIndexLocal(self.usize()?),
+            38 => Op::SetIndexGlobal(self.usize()?),
+            39 => Op::In,
+            40 => Op::Is(self.usize()?),
+            41 => Op::Cast(self.usize()?),
+            42 => Op::Try,
+            43 => {
+                let pattern = match self.u8()? {
+                    0 => Unpack::Variant {
+                        variant: self.usize()?,
+                        fields: self.usize()?,
+                    },
+                    1 => Unpack::Some,
+                    2 => Unpack::None,
+                    3 => Unpack::Ok,
+                    4 => Unpack::Err,
+                    _ => return None,
+                };
+
+                Op::Unpack(pattern, self.usize()?)
+            }
+            44 => Op::Next {
+                collection: self.usize()?,
+                counter: self.usize()?,
+                bindings: match self.u8()? {
+                    0 => Bindings::Both,
+                    1 => Bindings::First,
+                    2 => Bindings::Second,
+                    _ => return None,
+                },
+                done: self.usize()?,
+            },
+            45 => Op::Truncate {
+                height: self.usize()?,
+                keep: self.bool()?,
+            },
+            46 => Op::Catch(self.usize()?),
+            47 => Op::EndCatch,
+            _ => return None,
+        };
+
+        Some(op)
+    }
+
+    fn bin_op(&mut self) -> Option<BinOp> {
+        BIN_OPS.get(self.u8()? as usize).copied()
+    }
+
+    fn prefix_op(&mut self) -> Option<PrefixOp> {
+        PREFIX_OPS.get(self.u8()? as usize).copied()
+    }
+
+    fn builtin(&mut self) -> Option<Builtin> {
+        let builtin = match self.u8()? {
+            0 => Builtin::Tick,
+            1 => Builtin::Chars,
+            2 => Builtin::Len,
+            3 => Builtin::Print,
+            4 => Builtin::Println,
+            5 => Builtin::Format,
+            6 => Builtin::ToStr,
+            7 => Builtin::ToStrWith(match self.u8()? {
+                0 => FormatSpec::Debug,
+                1 => FormatSpec::Hex,
+                2 => FormatSpec::Binary,
+                3 => FormatSpec::Octal,
+                4 => FormatSpec::Precision(self.usize()?),
+                _ => return None,
+            }),
+            _ => return None,
+        };
+
+        Some(builtin)
+    }
+
+    fn ty(&mut self) -> Option<Type> {
+        let ty = match self.u8()? {
+            0 => Type::Int,
+            1 => Type::Float,
+            2 => Type::Bool,
+            3 => Type::Str,
+            4 => Type::Char,
+            5 => Type::Unit,
+            6 => Type::Range,
+            7 => Type::Enum(self.str()?),
+            8 => Type::Struct(self.str()?),
+            9 => Type::Param(self.str()?),
+            10 => Type::Optional(Box::new(self.ty()?)),
+            11 => Type::List(Box::new(self.ty()?)),
+            12 => Type::Map(Box::new(self.ty()?), Box::new(self.ty()?)),
+            13 => Type::Result(Box::new(self.ty()?), Box::new(self.ty()?)),
+            14 => Type::Record(self.many(|reader| Some((reader.str()?, reader.ty()?)))?),
+            15 => Type::Func(self.many(Self::ty)?, Box::new(self.ty()?)),
+            16 => Type::Ref(self.bool()?, Box::new(self.ty()?)),
+            _ => return None,
+        };
+
+        Some(ty)
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        let value = match self.u8()? {
+            0 => Value::Int(self.u64()? as i64),
+            1 => Value::Float(f64::from_bits(self.u64()?)),
+            2 => Value::Str(self.str()?),
+            3 => Value::Char(char::from_u32(self.u64()?.try_into().ok()?)?),
+            4 => Value::Bool(self.bool()?),
+            5 => Value::Unit,
+            6 => Value::Variant {
+                enum_: self.str()?,
+                variant: self.str()?,
+                fields: self.many(Self::value)?,
+            },
+            7 => Value::Optional(match self.bool()? {
+                true => Some(Box::new(self.value()?)),
+                false => None,
+            }),
+            8 => Value::Result(match self.bool()? {
+                true => Ok(Box::new(self.value()?)),
+                false => Err(Box::new(self.value()?)),
+            }),
+            9 => Value::Record(self.many(Self::field)?),
+            10 => Value::Struct {
+                name: self.str()?,
+                fields: self.many(Self::field)?,
+            },
+            11 => Value::List(self.many(Self::value)?),
+            12 => Value::Map(self.many(|reader| Some((reader.value()?, reader.value()?)))?),
+            13 => Value::Range {
+                start: self.u64()? as i64,
+                end: self.u64()? as i64,
+            },
+            14 => Value::Func {
+                name: self.str()?,
+                id: Handle::permanent(self.usize()?),
+            },
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    fn field(&mut self) -> Option<(String, Value)> {
+        Some((self.str()?, self.value()?))
+    }
+}
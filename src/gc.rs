@@ -0,0 +1,392 @@
+use crate::error::Resource;
+use crate::interpreter::Value;
+#[cfg(feature = "rc")]
+use std::{cell::RefCell, rc::Rc};
+
+/// The values the VM keeps outside of its stack, which are the cells of
+/// variables that have been borrowed mutably and the functions and closures
+/// that have been created. `Value::Ref`, `Value::Func` and `Value::Closure`
+/// index them.
+///
+/// They're freed by a tracing collector once there are enough of them,
+/// keeping the ones that can be reached from the roots the VM gives it. With
+/// the `rc` feature they're instead freed when the last handle to them is
+/// dropped, which never pauses the program but leaks the ones in a cycle.
+/// The slots of the ones that are freed are used again, so the values that
+/// index the rest don't have to change.
+pub struct Heap {
+    pub cells: Vec<Value>,
+    pub instances: Vec<Instance>,
+    /// How many of the first instances are the program's own functions,
+    /// which are never freed
+    functions: usize,
+    free_cells: Vec<usize>,
+    free_instances: Vec<usize>,
+    /// How many objects can be in use before the next collection
+    #[cfg(not(feature = "rc"))]
+    threshold: usize,
+    /// The objects whose last handle has been dropped, which are freed the
+    /// next time there's an allocation
+    #[cfg(feature = "rc")]
+    dropped_cells: Rc<RefCell<Vec<usize>>>,
+    #[cfg(feature = "rc")]
+    dropped_instances: Rc<RefCell<Vec<usize>>>,
+    /// The most objects that can be in use at once
+    limit: Option<usize>,
+}
+
+/// A function or closure along with its captured variables
+pub struct Instance {
+    pub function: usize,
+    pub captures: Vec<Value>,
+}
+
+/// What a `Value::Ref`, `Value::Func` or `Value::Closure` holds, which is
+/// the index of its object in the heap it's from
+#[cfg(not(feature = "rc"))]
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct Handle(usize);
+
+/// What a `Value::Ref`, `Value::Func` or `Value::Closure` holds, which is
+/// the index of its object in the heap it's from, along with a count of the
+/// handles to it
+#[cfg(feature = "rc")]
+#[derive(Clone)]
+pub struct Handle(Rc<Slot>);
+
+#[cfg(feature = "rc")]
+struct Slot {
+    index: usize,
+    /// Where the index goes once the last handle is dropped, or `None` if
+    /// the object is never freed
+    dropped: Option<Rc<RefCell<Vec<usize>>>>,
+}
+
+#[cfg(not(feature = "rc"))]
+impl Handle {
+    /// A handle to an object that's never freed, like the program's own
+    /// functions or the interpreter's cells
+    pub fn permanent(index: usize) -> Self {
+        Handle(index)
+    }
+
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+#[cfg(feature = "rc")]
+impl Handle {
+    /// A handle to an object that's never freed, like the program's own
+    /// functions or the interpreter's cells
+    pub fn permanent(index: usize) -> Self {
+        Handle(Rc::new(Slot {
+            index,
+            dropped: None,
+        }))
+    }
+
+    fn counted(index: usize, dropped: &Rc<RefCell<Vec<usize>>>) -> Self {
+        Handle(Rc::new(Slot {
+            index,
+            dropped: Some(Rc::clone(dropped)),
+        }))
+    }
+
+    pub fn index(&self) -> usize {
+        self.0.index
+    }
+}
+
+#[cfg(feature = "rc")]
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(dropped) = &self.dropped {
+            dropped.borrow_mut().push(self.index);
+        }
+    }
+}
+
+#[cfg(feature = "rc")]
+impl std::fmt::Debug for Handle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Handle").field(&self.index()).finish()
+    }
+}
+
+#[cfg(feature = "rc")]
+impl PartialEq for Handle {
+    fn eq(&self, other: &Self) -> bool {
+        self.index() == other.index()
+    }
+}
+
+#[cfg(feature = "rc")]
+impl PartialOrd for Handle {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.index().partial_cmp(&other.index())
+    }
+}
+
+/// Something on the heap that's been reached but not traced yet
+enum Object {
+    Cell(usize),
+    Instance(usize),
+}
+
+/// How many objects can be in use before the first collection
+#[cfg(not(feature = "rc"))]
+const INITIAL_THRESHOLD: usize = 1024;
+
+impl Heap {
+    /// A heap with an instance without captures for each of the program's
+    /// functions
+    pub fn new(functions: usize, limit: Option<usize>) -> Self {
+        Heap {
+            cells: vec![],
+            instances: (0..functions)
+                .map(|function| Instance {
+                    function,
+                    captures: vec![],
+                })
+                .collect(),
+            functions,
+            free_cells: vec![],
+            free_instances: vec![],
+            #[cfg(not(feature = "rc"))]
+            threshold: INITIAL_THRESHOLD,
+            #[cfg(feature = "rc")]
+            dropped_cells: Rc::default(),
+            #[cfg(feature = "rc")]
+            dropped_instances: Rc::default(),
+            limit,
+        }
+    }
+
+    /// How many cells and instances other than the program's functions are
+    /// in use
+    pub fn len(&self) -> usize {
+        self.cells.len() - self.free_cells.len() + self.instances.len()
+            - self.functions
+            - self.free_instances.len()
+    }
+
+    /// Makes sure there's room for one more object, collecting garbage first
+    /// if there are enough objects for that. Everything the program can
+    /// still use has to be reachable from `roots` and the instances in
+    /// `running`.
+    pub fn reserve<'a>(
+        &mut self,
+        roots: impl Iterator<Item = &'a Value>,
+        running: impl Iterator<Item = usize>,
+    ) -> Result<(), Resource> {
+        #[cfg(not(feature = "rc"))]
+        {
+            let len = self.len();
+
+            if len < self.threshold && self.limit.is_none_or(|limit| len < limit) {
+                return Ok(());
+            }
+        }
+
+        self.collect(roots, running);
+
+        let len = self.len();
+
+        match self.limit {
+            Some(limit) if len >= limit => Err(Resource::Heap(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn cell(&mut self, value: Value) -> Handle {
+        let id = match self.free_cells.pop() {
+            Some(id) => {
+                self.cells[id] = value;
+                id
+            }
+            None => {
+                self.cells.push(value);
+                self.cells.len() - 1
+            }
+        };
+
+        #[cfg(feature = "rc")]
+        return Handle::counted(id, &self.dropped_cells);
+
+        #[cfg(not(feature = "rc"))]
+        Handle(id)
+    }
+
+    pub fn instance(&mut self, instance: Instance) -> Handle {
+        let id = match self.free_instances.pop() {
+            Some(id) => {
+                self.instances[id] = instance;
+                id
+            }
+            None => {
+                self.instances.push(instance);
+                self.instances.len() - 1
+            }
+        };
+
+        #[cfg(feature = "rc")]
+        return Handle::counted(id, &self.dropped_instances);
+
+        #[cfg(not(feature = "rc"))]
+        Handle(id)
+    }
+
+    /// Marks every object that can be reached from the roots, and frees the
+    /// rest
+    #[cfg(not(feature = "rc"))]
+    fn collect<'a>(
+        &mut self,
+        roots: impl Iterator<Item = &'a Value>,
+        running: impl Iterator<Item = usize>,
+    ) {
+        let (cells, instances) = self.mark(roots, running);
+
+        // what the freed objects hold is dropped now, rather than when
+        // their slot is used again
+        self.free_cells.clear();
+
+        for (id, marked) in cells.into_iter().enumerate() {
+            if !marked {
+                self.cells[id] = Value::Unit;
+                self.free_cells.push(id);
+            }
+        }
+
+        self.free_instances.clear();
+
+        for (id, marked) in instances.into_iter().enumerate().skip(self.functions) {
+            if !marked {
+                self.instances[id].captures = vec![];
+                self.free_instances.push(id);
+            }
+        }
+
+        // collecting is worth it again once the heap has doubled
+        self.threshold = (2 * self.len()).max(INITIAL_THRESHOLD);
+    }
+
+    /// Frees the objects whose last handle has been dropped, which drops the
+    /// handles they hold and can free more. Nothing has to be traced, so the
+    /// roots aren't needed.
+    #[cfg(feature = "rc")]
+    fn collect<'a>(
+        &mut self,
+        _roots: impl Iterator<Item = &'a Value>,
+        _running: impl Iterator<Item = usize>,
+    ) {
+        loop {
+            // the borrows end before anything is dropped, which pushes to them
+            let cell = self.dropped_cells.borrow_mut().pop();
+            let instance = self.dropped_instances.borrow_mut().pop();
+
+            if let Some(id) = cell {
+                self.cells[id] = Value::Unit;
+                self.free_cells.push(id);
+            }
+
+            if let Some(id) = instance {
+                self.instances[id].captures = vec![];
+                self.free_instances.push(id);
+            }
+
+            if cell.is_none() && instance.is_none() {
+                break;
+            }
+        }
+    }
+
+    /// How many objects are still in use without being reachable from the
+    /// roots, which means they're kept by a cycle of handles and will never
+    /// be freed
+    #[cfg(all(feature = "rc", debug_assertions))]
+    pub fn cycles<'a>(&mut self, roots: impl Iterator<Item = &'a Value>) -> usize {
+        self.collect(std::iter::empty(), std::iter::empty());
+
+        let (cells, instances) = self.mark(roots, std::iter::empty());
+        let reached = cells.into_iter().filter(|&marked| marked).count()
+            + instances
+                .into_iter()
+                .skip(self.functions)
+                .filter(|&marked| marked)
+                .count();
+
+        self.len() - reached
+    }
+
+    /// Finds the cells and instances that can be reached from the roots
+    fn mark<'a>(
+        &self,
+        roots: impl Iterator<Item = &'a Value>,
+        running: impl Iterator<Item = usize>,
+    ) -> (Vec<bool>, Vec<bool>) {
+        let mut cells = vec![false; self.cells.len()];
+        let mut instances = vec![false; self.instances.len()];
+
+        let mut work = running.map(Object::Instance).collect::<Vec<_>>();
+
+        for root in roots {
+            references(root, &mut work);
+        }
+
+        while let Some(object) = work.pop() {
+            match object {
+                Object::Cell(id) => {
+                    if !std::mem::replace(&mut cells[id], true) {
+                        references(&self.cells[id], &mut work);
+                    }
+                }
+                Object::Instance(id) => {
+                    if !std::mem::replace(&mut instances[id], true) {
+                        for capture in &self.instances[id].captures {
+                            references(capture, &mut work);
+                        }
+                    }
+                }
+            }
+        }
+
+        (cells, instances)
+    }
+}
+
+/// Adds the objects a value refers to, including through the values inside
+/// it, to `work`
+fn references(value: &Value, work: &mut Vec<Object>) {
+    let mut values = vec![value];
+
+    while let Some(value) = values.pop() {
+        match value {
+            Value::Ref(id) => work.push(Object::Cell(id.index())),
+            Value::Func { id, .. } | Value::Closure { id } => {
+                work.push(Object::Instance(id.index()))
+            }
+            Value::Variant { fields, .. } | Value::List(fields) => values.extend(fields),
+            Value::Optional(Some(value)) | Value::Result(Ok(value) | Err(value)) => {
+                values.push(value)
+            }
+            Value::Record(fields) | Value::Struct { fields, .. } => {
+                values.extend(fields.iter().map(|(_, value)| value))
+            }
+            Value::Map(entries) => {
+                for (key, value) in entries {
+                    values.push(key);
+                    values.push(value);
+                }
+            }
+            Value::Int(_)
+            | Value::Float(_)
+            | Value::Str(_)
+            | Value::Char(_)
+            | Value::Bool(_)
+            | Value::Unit
+            | Value::Optional(None)
+            | Value::Range { .. } => {}
+        }
+    }
+}
@@ -1,29 +1,142 @@
+use crate::error::{Error, Resource};
+use crate::gc::Handle;
+use crate::prelude::Builtin;
 use crate::typecheck::Scopes;
 use crate::typed_ast::*;
-use crate::Spanned;
+use crate::{Span, Spanned};
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
 
-pub fn interpret(ast: Spanned<TypedAst>) -> Result<(), String> {
-    let mut interpreter = Interpreter::new();
+/// How far a program can go before it's stopped, with `None` for no limit
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// The most calls that can be running at once
+    pub depth: Option<usize>,
+    /// The most steps it can take, which are instructions in the VM, and
+    /// statements, expressions and loop iterations in the interpreter
+    pub fuel: Option<u64>,
+    /// The most functions, closures and borrowed variables the VM can have
+    /// at once
+    pub heap: Option<usize>,
+}
+
+/// How big the interpreter's stack is, which every call in the program
+/// takes several frames of
+const STACK_SIZE: usize = 1 << 30;
+
+/// How much of the interpreter's stack is left free for the call that's
+/// running, since it's only checked for room when calling
+const STACK_RESERVE: usize = STACK_SIZE / 4;
 
-    interpreter.interpret_ast(ast)
+pub fn interpret(ast: Spanned<TypedAst>, limits: Limits) -> Result<(), Error> {
+    // the main thread's stack only fits a few hundred calls, and running out
+    // of it aborts the process, which is stopped with an error before then
+    std::thread::scope(|scope| {
+        let interpreter = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, || Interpreter::new(limits).interpret_ast(ast))
+            .unwrap();
+
+        interpreter
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
 }
 
 struct Interpreter<'src> {
     vars: Scopes<&'src str, Value>,
+    /// Every function definition that has been run, indexed by `Value::Func`
+    functions: Vec<Rc<Function<'src>>>,
+    /// Every closure that has been created, indexed by `Value::Closure`
+    closures: Vec<Rc<Closure<'src>>>,
+    /// The values of variables that have been borrowed mutably, indexed by
+    /// `Value::Ref`, which is left in the variable and the parameter
+    cells: Vec<Value>,
+    /// The functions of every method implemented so far, by the trait (`None`
+    /// for a type's own methods), the method and the name of the type it's for
+    methods: HashMap<(Option<String>, &'src str, String), usize>,
+    /// The file of the module whose top level scope is the second one in
+    /// `vars`, the main file is always file 0
+    module: usize,
+    /// The top level scopes of the other modules that have been run, by file
+    modules: HashMap<usize, HashMap<&'src str, Value>>,
+    /// The calls a runtime error has unwound through, innermost first, as
+    /// the function that was called and where
+    trace: Vec<Spanned<String>>,
+    ticks: u64,
+    limits: Limits,
+    /// How many calls are running
+    depth: usize,
+    /// Where the interpreter's stack starts, to tell how much of it the
+    /// running calls take up
+    stack_base: usize,
+    /// How many steps have been taken
+    steps: u64,
+}
+
+struct Function<'src> {
+    params: Vec<&'src str>,
+    body: Spanned<Statement<'src>>,
+    /// Copies of the captured variables, taken when the function was defined
+    captures: Vec<(&'src str, Value)>,
+    /// The file of the module it was defined in, which has to be the current
+    /// module while it runs
+    module: usize,
+}
+
+struct Closure<'src> {
+    params: Vec<&'src str>,
+    body: Spanned<Expr<'src>>,
+    /// Copies of the captured variables, taken when the closure was created
+    captures: Vec<(&'src str, Value)>,
 }
 
 impl<'src> Interpreter<'src> {
-    fn new() -> Self {
+    fn new(limits: Limits) -> Self {
         Self {
             vars: Scopes::new(),
+            functions: Vec::new(),
+            closures: Vec::new(),
+            cells: Vec::new(),
+            methods: HashMap::new(),
+            module: 0,
+            modules: HashMap::new(),
+            trace: Vec::new(),
+            ticks: 0,
+            limits,
+            depth: 0,
+            stack_base: stack_address(),
+            steps: 0,
         }
     }
 
-    fn interpret_ast(&mut self, ast: Spanned<TypedAst<'src>>) -> Result<(), String> {
+    fn interpret_ast(&mut self, ast: Spanned<TypedAst<'src>>) -> Result<(), Error> {
         self.vars.push_scope();
 
-        for statement in ast.0.statements.0 {
-            self.interpret_statement(statement)?;
+        for statement in self.hoist_functions(ast.0.statements.0) {
+            match self.interpret_statement(statement) {
+                Ok(_) => {}
+                Err(Unwind::Error((message, span))) => {
+                    return Err(Error::Runtime {
+                        message,
+                        span,
+                        trace: std::mem::take(&mut self.trace),
+                    })
+                }
+                Err(Unwind::Exhausted((resource, span))) => {
+                    return Err(Error::Exhausted {
+                        resource,
+                        span,
+                        trace: std::mem::take(&mut self.trace),
+                    })
+                }
+                // the typechecker only accepts breaks inside a block with that label or a
+                // loop, continues inside a loop, and returns inside a function
+                Err(Unwind::Break { .. } | Unwind::Continue | Unwind::Return(_)) => {
+                    unreachable!()
+                }
+            }
         }
 
         self.vars.pop_scope();
@@ -34,7 +147,11 @@ impl<'src> Interpreter<'src> {
     fn interpret_statement(
         &mut self,
         statement: Spanned<Statement<'src>>,
-    ) -> Result<ControlFlow, String> {
+    ) -> Result<ControlFlow, Unwind<'src>> {
+        let span = statement.1;
+
+        self.step(span)?;
+
         match statement.0 {
             Statement::Expr(expr) => {
                 let _ = self.interpret_expr(expr)?;
@@ -44,15 +161,13 @@ impl<'src> Interpreter<'src> {
             Statement::Block(statements) => {
                 self.vars.push_scope();
 
-                for statement in statements.0 {
-                    self.interpret_statement(statement)?;
-                }
+                let result = self.interpret_statements(statements.0);
 
                 self.vars.pop_scope();
 
-                Ok(ControlFlow::Normal)
+                result
             }
-            Statement::Let { name, ty: _, value } => {
+            Statement::Let { name, value, .. } => {
                 let value = self.interpret_expr(value)?;
 
                 self.vars.insert(name.0, value);
@@ -62,26 +177,29 @@ impl<'src> Interpreter<'src> {
             Statement::Function {
                 name,
                 params,
-                return_ty,
+                captures,
                 body,
+                ..
             } => {
-                todo!();
+                self.bind_function(name, &params.0, &captures, *body);
 
                 Ok(ControlFlow::Normal)
             }
             Statement::Assign { name, value } => {
                 let value = self.interpret_expr(value)?;
 
-                let var = self.vars.get_mut(&name.0).unwrap();
-
-                *var = value;
+                *self.var_mut(name.0) = value;
 
                 Ok(ControlFlow::Normal)
             }
-            Statement::Print(expr) => {
-                let value = self.interpret_expr(expr)?;
+            Statement::SetIndex { name, index, value } => {
+                let index_span = index.1;
 
-                println!("{}", value);
+                let index = self.interpret_expr(index)?;
+                let value = self.interpret_expr(value)?;
+
+                set_index(self.var_mut(name.0), index, value)
+                    .map_err(|message| Unwind::Error((message, index_span)))?;
 
                 Ok(ControlFlow::Normal)
             }
@@ -90,79 +208,1289 @@ impl<'src> Interpreter<'src> {
 
                 Ok(ControlFlow::Return(value))
             }
+            Statement::Import {
+                module: None,
+                statements,
+                ..
+            } => {
+                for statement in self.hoist_functions(statements) {
+                    self.interpret_statement(statement)?;
+                }
+
+                Ok(ControlFlow::Normal)
+            }
+            Statement::Import {
+                module: Some(_),
+                file,
+                statements,
+                ..
+            } => {
+                // a module imported again elsewhere has no statements, and has
+                // already been run
+                if !self.modules.contains_key(&file) {
+                    let importer = self.switch_module(file);
+
+                    for statement in self.hoist_functions(statements) {
+                        self.interpret_statement(statement)?;
+                    }
+
+                    self.switch_module(importer);
+                }
+
+                Ok(ControlFlow::Normal)
+            }
+            Statement::Break { label, value } => {
+                let value = self.interpret_expr(value)?;
+
+                Err(Unwind::Break {
+                    label: label.map(|label| label.0),
+                    value,
+                })
+            }
+            Statement::Continue => Err(Unwind::Continue),
+            Statement::While { cond, body } => {
+                while let Value::Bool(true) = self.interpret_expr(cond.clone())? {
+                    if let Some(flow) = self.interpret_iteration(vec![], &body.0)? {
+                        return Ok(flow);
+                    }
+                }
+
+                Ok(ControlFlow::Normal)
+            }
+            Statement::For {
+                first,
+                second,
+                iter,
+                body,
+            } => {
+                let iter = self.interpret_expr(iter)?;
+
+                // a single name gets the keys of a map, but the elements of a list
+                let keys_only = matches!(iter, Value::Map(_));
+
+                // ranges are iterated lazily, since they can be much larger than
+                // anything that's been stored
+                let pairs: Box<dyn Iterator<Item = (Value, Value)>> = match iter {
+                    Value::List(elements) => Box::new((0..).map(Value::Int).zip(elements)),
+                    Value::Str(s) => Box::new(
+                        (0..)
+                            .map(Value::Int)
+                            .zip(s.chars().map(Value::Char).collect::<Vec<_>>()),
+                    ),
+                    Value::Map(entries) => Box::new(entries.into_iter()),
+                    Value::Range { start, end } => {
+                        Box::new((0..).map(Value::Int).zip((start..end).map(Value::Int)))
+                    }
+                    _ => unreachable!(),
+                };
+
+                for (a, b) in pairs {
+                    self.step(span)?;
+
+                    let bindings = match second {
+                        Some(second) => vec![(first.0, a), (second.0, b)],
+                        None if keys_only => vec![(first.0, a)],
+                        None => vec![(first.0, b)],
+                    };
+
+                    if let Some(flow) = self.interpret_iteration(bindings, &body.0)? {
+                        return Ok(flow);
+                    }
+                }
+
+                Ok(ControlFlow::Normal)
+            }
+            Statement::Impl {
+                trait_,
+                ty,
+                methods,
+            } => {
+                for method in methods {
+                    let Statement::Function {
+                        name, params, body, ..
+                    } = method.0
+                    else {
+                        unreachable!()
+                    };
+
+                    let id = self.define_function(&params.0, vec![], *body);
+
+                    self.methods.insert(
+                        (
+                            trait_.map(|trait_| trait_.0.to_string()),
+                            name.0,
+                            ty.0.type_name(),
+                        ),
+                        id,
+                    );
+                }
+
+                Ok(ControlFlow::Normal)
+            }
+            // constants were already replaced with their values
+            Statement::Enum { .. }
+            | Statement::Struct { .. }
+            | Statement::TypeAlias { .. }
+            | Statement::Trait { .. }
+            | Statement::Const { .. } => Ok(ControlFlow::Normal),
         }
     }
 
-    fn interpret_expr(&self, expr: Spanned<Expr>) -> Result<Value, String> {
+    fn interpret_expr(&mut self, expr: Spanned<Expr<'src>>) -> Result<Value, Unwind<'src>> {
+        let span = expr.1;
+
+        self.step(span)?;
+
         match expr.0.expr {
-            ExprKind::Var(name) => Ok(self.vars.get(&name.0).unwrap().clone()),
+            // a function can be called before a variable it uses has been
+            // declared, now that functions are defined ahead of the rest
+            ExprKind::Var(name) => self.var(name),
+            ExprKind::ModuleVar { file, name, .. } => {
+                // a closure can be called while the module it names is current
+                let value = if file == self.module {
+                    self.vars.get_in(1, name.0)
+                } else {
+                    self.modules[&file].get(name.0)
+                };
+
+                Ok(self.deref(value.unwrap()))
+            }
             ExprKind::Literal(literal) => Ok(match literal.0 {
-                Literal::Num(n) => Value::Num(n),
+                Literal::Int(n) => Value::Int(n),
+                Literal::Float(n) => Value::Float(n),
+                Literal::Str(s) => Value::Str(s),
+                Literal::Char(c) => Value::Char(c),
                 Literal::Bool(b) => Value::Bool(b),
                 Literal::Unit => Value::Unit,
+                Literal::None => Value::Optional(None),
             }),
             ExprKind::Prefix { op, expr } => {
                 let value = self.interpret_expr(*expr)?;
 
-                match op.0 {
-                    PrefixOp::Negate => match &value {
-                        Value::Num(n) => Ok(Value::Num(-n)),
-                        _ => unreachable!(),
-                    },
-                }
+                prefix(op.0, value).map_err(|message| Unwind::Error((message, span)))
             }
             ExprKind::Binary { op, lhs, rhs } => {
                 let lhs = self.interpret_expr(*lhs)?;
+
+                match (op.0, &lhs) {
+                    (BinOp::Coalesce, Value::Optional(Some(value))) => return Ok(*value.clone()),
+                    (BinOp::Coalesce, Value::Optional(None)) => return self.interpret_expr(*rhs),
+                    (BinOp::LogicalAnd, Value::Bool(false))
+                    | (BinOp::LogicalOr, Value::Bool(true)) => return Ok(lhs),
+                    _ => {}
+                }
+
                 let rhs = self.interpret_expr(*rhs)?;
 
-                match (lhs, rhs) {
-                    (Value::Num(a), Value::Num(b)) => match op.0 {
-                        BinOp::Add => Ok(Value::Num(a + b)),
-                        BinOp::Subtract => Ok(Value::Num(a - b)),
-                        BinOp::Multiply => Ok(Value::Num(a * b)),
-                        BinOp::Divide => Ok(Value::Num(a / b)),
-                        BinOp::Equals => Ok(Value::Bool(a == b)),
-                        BinOp::NotEquals => Ok(Value::Bool(a != b)),
-                        BinOp::LessThan => Ok(Value::Bool(a < b)),
-                        BinOp::LessThanOrEqual => Ok(Value::Bool(a <= b)),
-                        BinOp::GreaterThan => Ok(Value::Bool(a > b)),
-                        BinOp::GreaterThanOrEqual => Ok(Value::Bool(a >= b)),
-                        _ => unreachable!(),
-                    },
-                    (Value::Bool(a), Value::Bool(b)) => match op.0 {
-                        BinOp::Add | BinOp::Subtract | BinOp::Multiply | BinOp::Divide => {
-                            unreachable!()
+                binary(op.0, lhs, rhs).map_err(|message| Unwind::Error((message, span)))
+            }
+            ExprKind::Call { callee, args } => {
+                let callee = self.interpret_expr(*callee)?;
+
+                let args = args
+                    .0
+                    .into_iter()
+                    .map(|arg| self.interpret_expr(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                self.check_depth(span)?;
+
+                let (name, result) = match callee {
+                    Value::Func { name, id } => (name, self.call_function(id.index(), args)),
+                    Value::Closure { id } => {
+                        ("<closure>".to_string(), self.call_closure(id.index(), args))
+                    }
+                    _ => unreachable!(),
+                };
+
+                self.traced(result, name, span)
+            }
+            ExprKind::MethodCall {
+                receiver,
+                trait_,
+                method,
+                args,
+            } => {
+                let receiver = self.interpret_expr(*receiver)?;
+
+                // found from the value, since a type parameter can stand for any type
+                let id = self.methods[&(trait_, method.0, receiver.type_name())];
+
+                let args = std::iter::once(Ok(receiver))
+                    .chain(args.0.into_iter().map(|arg| self.interpret_expr(arg)))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                self.check_depth(span)?;
+
+                let result = self.call_function(id, args);
+
+                self.traced(result, method.0.to_string(), span)
+            }
+            ExprKind::Closure {
+                params,
+                captures,
+                body,
+            } => {
+                let id = self.closures.len();
+
+                let captures = captures
+                    .iter()
+                    .map(|name| (name.0, self.deref(self.vars.get(name.0).unwrap())))
+                    .collect();
+
+                self.closures.push(Rc::new(Closure {
+                    params: params.0.iter().map(|(param, _)| param.0).collect(),
+                    body: *body,
+                    captures,
+                }));
+
+                Ok(Value::Closure {
+                    id: Handle::permanent(id),
+                })
+            }
+            ExprKind::Ref { mutable, expr } => match expr.0.expr {
+                ExprKind::Var(name) if mutable => {
+                    let var = self.vars.get_mut(&name.0).ok_or_else(|| {
+                        Unwind::Error((
+                            format!("'{}' is used before it's initialized", source_name(name.0)),
+                            name.1,
+                        ))
+                    })?;
+
+                    // a variable that is already borrowed, like a parameter
+                    // taking a mutable reference, shares its cell
+                    if let Value::Ref(id) = var {
+                        return Ok(Value::Ref(id.clone()));
+                    }
+
+                    let id = Handle::permanent(self.cells.len());
+                    let value = std::mem::replace(var, Value::Ref(id.clone()));
+                    self.cells.push(value);
+
+                    Ok(Value::Ref(id))
+                }
+                // an immutable reference can't be told apart from a copy
+                _ => self.interpret_expr(*expr),
+            },
+            ExprKind::LabeledBlock {
+                label,
+                statements,
+                tail,
+            } => match self.interpret_block(statements.0, tail) {
+                Err(Unwind::Break {
+                    label: break_label,
+                    value,
+                }) if break_label == Some(label.0) => Ok(value),
+                result => result,
+            },
+            ExprKind::If { cond, then, else_ } => match self.interpret_expr(*cond)? {
+                Value::Bool(true) => self.interpret_block(then.0.statements, then.0.tail),
+                Value::Bool(false) => match else_ {
+                    Some(else_) => self.interpret_block(else_.0.statements, else_.0.tail),
+                    None => Ok(Value::Unit),
+                },
+                _ => unreachable!(),
+            },
+            ExprKind::Loop { body, .. } => loop {
+                self.step(span)?;
+
+                match self.interpret_block(body.0.clone(), None) {
+                    Ok(_) | Err(Unwind::Continue) => {}
+                    Err(Unwind::Break { label: None, value }) => break Ok(value),
+                    Err(unwind) => break Err(unwind),
+                }
+            },
+            ExprKind::Block(block) => self.interpret_block(block.statements, block.tail),
+            // every scope and module the body was in has been left by the
+            // time its error gets here
+            ExprKind::Catch {
+                body,
+                binding,
+                handler,
+            } => match self.interpret_block(body.0.statements, body.0.tail) {
+                Err(Unwind::Error((message, _))) => {
+                    self.trace.clear();
+
+                    self.vars.push_scope();
+                    self.vars.insert(binding.0, Value::Str(message));
+
+                    let value = self.interpret_block(handler.0.statements, handler.0.tail);
+
+                    self.vars.pop_scope();
+
+                    value
+                }
+                result => result,
+            },
+            ExprKind::BuiltinCall { builtin, args } => {
+                let args = args
+                    .0
+                    .into_iter()
+                    .map(|arg| self.interpret_expr(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                call_builtin(builtin.0, args, &mut self.ticks)
+                    .map_err(|message| Unwind::Error((message, span)))
+            }
+            ExprKind::Variant { variant, args, .. } => Ok(Value::Variant {
+                // the enum may have been named through an alias, the type has its real name
+                enum_: match expr.0.ty {
+                    Type::Enum(name) => name,
+                    _ => unreachable!(),
+                },
+                variant: variant.0.to_string(),
+                fields: args
+                    .0
+                    .into_iter()
+                    .map(|arg| self.interpret_expr(arg))
+                    .collect::<Result<_, _>>()?,
+            }),
+            ExprKind::Record(fields) => Ok(Value::Record(
+                fields
+                    .into_iter()
+                    .map(|(name, value)| Ok((name.0.to_string(), self.interpret_expr(value)?)))
+                    .collect::<Result<_, Unwind>>()?,
+            )),
+            ExprKind::Struct { name, fields } => Ok(Value::Struct {
+                name: name.0.to_string(),
+                fields: fields
+                    .into_iter()
+                    .map(|(name, value)| Ok((name.0.to_string(), self.interpret_expr(value)?)))
+                    .collect::<Result<_, Unwind>>()?,
+            }),
+            ExprKind::List(elements) => Ok(Value::List(
+                elements
+                    .0
+                    .into_iter()
+                    .map(|element| self.interpret_expr(element))
+                    .collect::<Result<_, _>>()?,
+            )),
+            ExprKind::Map(entries) => {
+                let mut map: Vec<(Value, Value)> = Vec::with_capacity(entries.0.len());
+
+                // a key written twice keeps its first position and its last value
+                for (key, value) in entries.0 {
+                    let key = self.interpret_expr(key)?;
+                    let value = self.interpret_expr(value)?;
+
+                    match map.iter_mut().find(|(other, _)| *other == key) {
+                        Some((_, existing)) => *existing = value,
+                        None => map.push((key, value)),
+                    }
+                }
+
+                Ok(Value::Map(map))
+            }
+            ExprKind::FieldAccess { expr, field } => match self.interpret_expr(*expr)? {
+                Value::Record(fields) | Value::Struct { fields, .. } => Ok(fields
+                    .into_iter()
+                    .find(|(name, _)| name == field.0)
+                    .unwrap()
+                    .1),
+                _ => unreachable!(),
+            },
+            ExprKind::Index { expr, index } => {
+                let index_span = index.1;
+
+                let collection = self.interpret_expr(*expr)?;
+                let index = self.interpret_expr(*index)?;
+
+                get_index(collection, index).map_err(|message| Unwind::Error((message, index_span)))
+            }
+            ExprKind::Some(expr) => {
+                Ok(Value::Optional(Some(Box::new(self.interpret_expr(*expr)?))))
+            }
+            ExprKind::Ok(expr) => Ok(Value::Result(Ok(Box::new(self.interpret_expr(*expr)?)))),
+            ExprKind::Err(expr) => Ok(Value::Result(Err(Box::new(self.interpret_expr(*expr)?)))),
+            ExprKind::Try(expr) => match self.interpret_expr(*expr)? {
+                Value::Result(Ok(value)) => Ok(*value),
+                Value::Result(Err(error)) => Err(Unwind::Return(Value::Result(Err(error)))),
+                _ => unreachable!(),
+            },
+            ExprKind::Is { expr, variant } => match self.interpret_expr(*expr)? {
+                Value::Variant {
+                    variant: value_variant,
+                    ..
+                } => Ok(Value::Bool(value_variant == variant.0)),
+                _ => unreachable!(),
+            },
+            ExprKind::In { expr, collection } => {
+                let value = self.interpret_expr(*expr)?;
+                let collection = self.interpret_expr(*collection)?;
+
+                contains(&collection, &value)
+                    .map(Value::Bool)
+                    .map_err(|message| Unwind::Error((message, span)))
+            }
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let start = self.interpret_expr(*start)?;
+                let end = self.interpret_expr(*end)?;
+
+                range(start, end, inclusive).map_err(|message| Unwind::Error((message, span)))
+            }
+            ExprKind::Cast { expr, ty } => {
+                let value = self.interpret_expr(*expr)?;
+
+                cast(value, &ty.0).map_err(|message| Unwind::Error((message, span)))
+            }
+            ExprKind::Match { expr, arms } => {
+                let value = self.interpret_expr(*expr)?;
+
+                for (pattern, body) in arms.0 {
+                    let bound = match (pattern.0, &value) {
+                        (Pattern::Binding(name), value) => vec![(name.0, value.clone())],
+                        (
+                            Pattern::Variant { variant, bindings },
+                            Value::Variant {
+                                variant: value_variant,
+                                fields,
+                                ..
+                            },
+                        ) if variant.0 == value_variant => bindings
+                            .0
+                            .into_iter()
+                            .map(|binding| binding.0)
+                            .zip(fields.iter().cloned())
+                            .collect(),
+                        (Pattern::Some(name), Value::Optional(Some(inner))) => {
+                            vec![(name.0, *inner.clone())]
+                        }
+                        (Pattern::None, Value::Optional(None)) => vec![],
+                        (Pattern::Ok(name), Value::Result(Ok(inner)))
+                        | (Pattern::Err(name), Value::Result(Err(inner))) => {
+                            vec![(name.0, *inner.clone())]
                         }
-                        BinOp::Equals => Ok(Value::Bool(a == b)),
-                        BinOp::NotEquals => Ok(Value::Bool(a != b)),
-                        BinOp::LessThan => Ok(Value::Bool(a < b)),
-                        BinOp::LessThanOrEqual => Ok(Value::Bool(a <= b)),
-                        BinOp::GreaterThan => Ok(Value::Bool(a > b)),
-                        BinOp::GreaterThanOrEqual => Ok(Value::Bool(a >= b)),
-                        BinOp::LogicalAnd => Ok(Value::Bool(a && b)),
-                        BinOp::LogicalOr => Ok(Value::Bool(a || b)),
+                        _ => continue,
+                    };
+
+                    self.vars.push_scope();
+
+                    for (name, value) in bound {
+                        self.vars.insert(name, value);
+                    }
+
+                    let value = self.interpret_expr(body);
+
+                    self.vars.pop_scope();
+
+                    return value;
+                }
+
+                unreachable!()
+            }
+        }
+    }
+
+    fn interpret_block(
+        &mut self,
+        statements: Vec<Spanned<Statement<'src>>>,
+        tail: Option<Box<Spanned<Expr<'src>>>>,
+    ) -> Result<Value, Unwind<'src>> {
+        self.vars.push_scope();
+
+        let result = self
+            .interpret_statements(statements)
+            .and_then(|flow| match flow {
+                // a return inside an expression has to unwind through it
+                ControlFlow::Return(value) => Err(Unwind::Return(value)),
+                ControlFlow::Normal => match tail {
+                    Some(tail) => self.interpret_expr(*tail),
+                    None => Ok(Value::Unit),
+                },
+            });
+
+        self.vars.pop_scope();
+
+        result
+    }
+
+    fn interpret_statements(
+        &mut self,
+        statements: Vec<Spanned<Statement<'src>>>,
+    ) -> Result<ControlFlow, Unwind<'src>> {
+        for statement in statements {
+            if let ControlFlow::Return(value) = self.interpret_statement(statement)? {
+                return Ok(ControlFlow::Return(value));
+            }
+        }
+
+        Ok(ControlFlow::Normal)
+    }
+
+    /// Runs one iteration of a loop's body in a new scope holding `bindings`,
+    /// returning how the loop should end if it shouldn't go on
+    fn interpret_iteration(
+        &mut self,
+        bindings: Vec<(&'src str, Value)>,
+        body: &[Spanned<Statement<'src>>],
+    ) -> Result<Option<ControlFlow>, Unwind<'src>> {
+        self.vars.push_scope();
+
+        for (name, value) in bindings {
+            self.vars.insert(name, value);
+        }
+
+        let result = self.interpret_statements(body.to_vec());
+
+        self.vars.pop_scope();
+
+        match result {
+            Ok(ControlFlow::Normal) | Err(Unwind::Continue) => Ok(None),
+            Ok(ControlFlow::Return(value)) => Ok(Some(ControlFlow::Return(value))),
+            Err(Unwind::Break { label: None, .. }) => Ok(Some(ControlFlow::Normal)),
+            Err(unwind) => Err(unwind),
+        }
+    }
+
+    /// Defines the functions among a file's top level statements before the
+    /// rest of them are run, since they can be called before they're
+    /// declared, and returns the rest
+    fn hoist_functions(
+        &mut self,
+        statements: Vec<Spanned<Statement<'src>>>,
+    ) -> Vec<Spanned<Statement<'src>>> {
+        statements
+            .into_iter()
+            .filter_map(|statement| match statement {
+                (
+                    Statement::Function {
+                        name,
+                        params,
+                        captures,
+                        body,
+                        ..
                     },
-                    _ => unreachable!(),
+                    _,
+                ) => {
+                    self.bind_function(name, &params.0, &captures, *body);
+
+                    None
                 }
+                statement => Some(statement),
+            })
+            .collect()
+    }
+
+    fn bind_function(
+        &mut self,
+        name: Spanned<&'src str>,
+        params: &[(Spanned<&'src str>, Spanned<Type>)],
+        captures: &[Spanned<&'src str>],
+        body: Spanned<Statement<'src>>,
+    ) {
+        let func = Value::Func {
+            name: name.0.to_string(),
+            id: Handle::permanent(self.functions.len()),
+        };
+
+        // a function inside a block can't see that block when it's called, so
+        // it gets itself along with its captures to be able to call itself
+        let captures = captures
+            .iter()
+            .map(|name| (name.0, self.deref(self.vars.get(name.0).unwrap())))
+            .chain(std::iter::once((name.0, func.clone())))
+            .collect();
+
+        self.define_function(params, captures, body);
+
+        self.vars.insert(name.0, func);
+    }
+
+    fn define_function(
+        &mut self,
+        params: &[(Spanned<&'src str>, Spanned<Type>)],
+        captures: Vec<(&'src str, Value)>,
+        body: Spanned<Statement<'src>>,
+    ) -> usize {
+        self.functions.push(Rc::new(Function {
+            params: params.iter().map(|(param, _)| param.0).collect(),
+            body,
+            captures,
+            module: self.module,
+        }));
+
+        self.functions.len() - 1
+    }
+
+    /// Makes the module in `file` current by putting its top level scope in
+    /// place of the current module's, returning the file of the module that
+    /// was current before
+    fn switch_module(&mut self, file: usize) -> usize {
+        let scope = self.modules.remove(&file).unwrap_or_default();
+        let previous_scope = self.vars.replace_scope(1, scope);
+        let previous = std::mem::replace(&mut self.module, file);
+
+        self.modules.insert(previous, previous_scope);
+
+        previous
+    }
+
+    /// The value of a variable, through its cell if it has been borrowed
+    fn var(&self, name: Spanned<&'src str>) -> Result<Value, Unwind<'src>> {
+        self.vars
+            .get(&name.0)
+            .map(|value| self.deref(value))
+            .ok_or_else(|| {
+                Unwind::Error((
+                    format!("'{}' is used before it's initialized", source_name(name.0)),
+                    name.1,
+                ))
+            })
+    }
+
+    /// The variable being assigned to, or its cell if it has been borrowed
+    fn var_mut(&mut self, name: &'src str) -> &mut Value {
+        match self.vars.get_mut(&name).unwrap() {
+            Value::Ref(id) => &mut self.cells[id.index()],
+            var => var,
+        }
+    }
+
+    fn deref(&self, value: &Value) -> Value {
+        match value {
+            Value::Ref(id) => self.cells[id.index()].clone(),
+            value => value.clone(),
+        }
+    }
+
+    fn call_function(&mut self, id: usize, args: Vec<Value>) -> Result<Value, Unwind<'src>> {
+        let function = Rc::clone(&self.functions[id]);
+
+        let caller_module =
+            (function.module != self.module).then(|| self.switch_module(function.module));
+
+        // only the top level scope of the function's module is kept, everything
+        // else it uses was captured
+        let caller_scopes = self.vars.split_off(2);
+
+        self.vars.push_scope();
+
+        for (name, value) in &function.captures {
+            self.vars.insert(name, value.clone());
+        }
+
+        for (param, arg) in function.params.iter().zip(args) {
+            self.vars.insert(param, arg);
+        }
+
+        self.depth += 1;
+
+        let result = self.interpret_statement(function.body.clone());
+
+        self.depth -= 1;
+
+        self.vars.pop_scope();
+        self.vars.extend(caller_scopes);
+
+        if let Some(caller_module) = caller_module {
+            self.switch_module(caller_module);
+        }
+
+        match result {
+            Ok(ControlFlow::Return(value)) | Err(Unwind::Return(value)) => Ok(value),
+            Ok(ControlFlow::Normal) => Ok(Value::Unit),
+            Err(
+                unwind @ (Unwind::Break { .. }
+                | Unwind::Continue
+                | Unwind::Error(_)
+                | Unwind::Exhausted(_)),
+            ) => Err(unwind),
+        }
+    }
+
+    /// Counts a step at `span`, stopping the program once it's out of fuel
+    fn step(&mut self, span: Span) -> Result<(), Unwind<'src>> {
+        self.steps += 1;
+
+        match self.limits.fuel {
+            Some(fuel) if self.steps > fuel => Err(Unwind::Exhausted((Resource::Fuel(fuel), span))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stops the program before a call at `span` that would run more calls
+    /// at once than it's allowed to, or than there's room for on the stack.
+    /// How much room a call takes depends on how deeply its expressions nest
+    /// and on how the interpreter was compiled, so that's measured rather
+    /// than being a number of calls.
+    fn check_depth(&self, span: Span) -> Result<(), Unwind<'src>> {
+        match self.limits.depth {
+            Some(depth) if self.depth >= depth => {
+                Err(Unwind::Exhausted((Resource::Depth(depth), span)))
             }
+            _ if self.stack_base.abs_diff(stack_address()) > STACK_SIZE - STACK_RESERVE => {
+                Err(Unwind::Exhausted((Resource::Stack(self.depth), span)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds a call to the trace if a runtime error unwound through it
+    fn traced(
+        &mut self,
+        result: Result<Value, Unwind<'src>>,
+        function: String,
+        span: Span,
+    ) -> Result<Value, Unwind<'src>> {
+        if let Err(Unwind::Error(_) | Unwind::Exhausted(_)) = result {
+            self.trace.push((function, span));
+        }
+
+        result
+    }
+
+    fn call_closure(&mut self, id: usize, args: Vec<Value>) -> Result<Value, Unwind<'src>> {
+        let closure = Rc::clone(&self.closures[id]);
+
+        // a closure can outlive the scopes it was created in, so it only sees its
+        // captured copies on top of the caller's scopes, which the typechecker
+        // makes sure it never reaches past
+        self.vars.push_scope();
+
+        for (name, value) in &closure.captures {
+            self.vars.insert(name, value.clone());
+        }
+
+        for (param, arg) in closure.params.iter().zip(args) {
+            self.vars.insert(param, arg);
+        }
+
+        self.depth += 1;
+
+        let result = self.interpret_expr(closure.body.clone());
+
+        self.depth -= 1;
+
+        self.vars.pop_scope();
+
+        match result {
+            Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+            Err(
+                unwind @ (Unwind::Break { .. }
+                | Unwind::Continue
+                | Unwind::Error(_)
+                | Unwind::Exhausted(_)),
+            ) => Err(unwind),
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum Value {
-    Num(f64),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Char(char),
     Bool(bool),
     Unit,
+    Variant {
+        enum_: String,
+        variant: String,
+        fields: Vec<Value>,
+    },
+    Optional(Option<Box<Value>>),
+    Result(Result<Box<Value>, Box<Value>>),
+    Record(Vec<(String, Value)>),
+    Struct {
+        name: String,
+        fields: Vec<(String, Value)>,
+    },
+    List(Vec<Value>),
+    /// Entries are kept in the order their keys were first inserted
+    Map(Vec<(Value, Value)>),
+    /// The end isn't included, an inclusive range has 1 added to its end
+    Range {
+        start: i64,
+        end: i64,
+    },
+    Func {
+        name: String,
+        id: Handle,
+    },
+    Closure {
+        id: Handle,
+    },
+    /// A mutable reference, only ever held by a variable, indexing
+    /// `Interpreter::cells`
+    Ref(Handle),
+}
+
+/// Where the top of the stack is, roughly, for the function calling this
+fn stack_address() -> usize {
+    let here = 0u8;
+
+    std::ptr::addr_of!(here) as usize
+}
+
+impl Value {
+    /// The name of the value's type, as [`Type::type_name`] gives it, for the
+    /// types that can implement traits
+    pub fn type_name(&self) -> String {
+        match self {
+            Value::Int(_) => "int".to_string(),
+            Value::Float(_) => "float".to_string(),
+            Value::Str(_) => "str".to_string(),
+            Value::Char(_) => "char".to_string(),
+            Value::Bool(_) => "bool".to_string(),
+            Value::Unit => "#".to_string(),
+            Value::Variant { enum_, .. } => enum_.clone(),
+            Value::Struct { name, .. } => name.clone(),
+            Value::Optional(_)
+            | Value::Result(_)
+            | Value::Record(_)
+            | Value::List(_)
+            | Value::Map(_)
+            | Value::Range { .. }
+            | Value::Func { .. }
+            | Value::Closure { .. }
+            | Value::Ref(_) => unreachable!(),
+        }
+    }
 }
 
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Value::Num(n) => write!(f, "{}", n),
+            Value::Int(n) => write!(f, "{}", n),
+            // always shows a fractional part, so floats can be told apart from ints
+            Value::Float(n) => write!(f, "{:?}", n),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Char(c) => write!(f, "{}", c),
             Value::Bool(b) => write!(f, "{}", b),
             Value::Unit => write!(f, "#"),
+            Value::Variant {
+                enum_,
+                variant,
+                fields,
+            } => {
+                write!(f, "{}::{}", source_name(enum_), variant)?;
+
+                if !fields.is_empty() {
+                    write!(f, "(")?;
+
+                    for (i, field) in fields.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+
+                        write!(f, "{}", Nested(field))?;
+                    }
+
+                    write!(f, ")")?;
+                }
+
+                Ok(())
+            }
+            Value::Optional(Some(value)) => write!(f, "some({})", Nested(value)),
+            Value::Optional(None) => write!(f, "none"),
+            Value::Result(Ok(value)) => write!(f, "ok({})", Nested(value)),
+            Value::Result(Err(error)) => write!(f, "err({})", Nested(error)),
+            Value::Record(fields) => write_fields(f, fields),
+            Value::Struct { name, fields } => {
+                write!(f, "{} ", source_name(name))?;
+
+                write_fields(f, fields)
+            }
+            Value::List(elements) => {
+                write!(f, "[")?;
+
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}", Nested(element))?;
+                }
+
+                write!(f, "]")
+            }
+            Value::Map(entries) if entries.is_empty() => write!(f, "{{:}}"),
+            Value::Map(entries) => {
+                write!(f, "{{ ")?;
+
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}: {}", Nested(key), Nested(value))?;
+                }
+
+                write!(f, " }}")
+            }
+            Value::Range { start, end } => write!(f, "{}..{}", start, end),
+            Value::Func { name, .. } => write!(f, "<func {}>", name),
+            Value::Closure { .. } => write!(f, "<closure>"),
+            Value::Ref(_) => write!(f, "<ref>"),
+        }
+    }
+}
+
+fn write_fields(f: &mut std::fmt::Formatter<'_>, fields: &[(String, Value)]) -> std::fmt::Result {
+    write!(f, "{{ ")?;
+
+    for (i, (name, value)) in fields.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+
+        write!(f, "{} = {}", name, Nested(value))?;
+    }
+
+    write!(f, " }}")
+}
+
+/// Displays a value inside another one, where strings are quoted so that
+/// e.g. `["a, b"]` can't be confused with `["a", "b"]`
+struct Nested<'a>(&'a Value);
+
+impl std::fmt::Display for Nested<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::Char(c) => write!(f, "{:?}", c),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Why evaluation stopped before reaching the end of an expression
+#[derive(Clone, Debug, PartialEq)]
+pub enum Unwind<'src> {
+    /// Out of a labeled block, or the innermost loop if there's no label
+    Break {
+        label: Option<&'src str>,
+        value: Value,
+    },
+    Continue,
+    Return(Value),
+    /// A runtime error, which stops the program unless it's caught
+    Error(Spanned<String>),
+    /// A limit being reached, which always stops the program
+    Exhausted(Spanned<Resource>),
+}
+
+/// Applies a prefix operator to a value
+pub fn prefix(op: PrefixOp, value: Value) -> Result<Value, String> {
+    match (op, value) {
+        (PrefixOp::Negate, Value::Int(n)) => n
+            .checked_neg()
+            .map(Value::Int)
+            .ok_or_else(|| "integer overflow".to_string()),
+        (PrefixOp::Negate, Value::Float(n)) => Ok(Value::Float(-n)),
+        (PrefixOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+        (PrefixOp::BitNot, Value::Int(n)) => Ok(Value::Int(!n)),
+        _ => Err(wrong_type()),
+    }
+}
+
+/// Applies a binary operator other than the short-circuiting `&&`, `||` and
+/// `??` to two values
+pub fn binary(op: BinOp, lhs: Value, rhs: Value) -> Result<Value, String> {
+    match (lhs, rhs) {
+        (Value::Int(a), Value::Int(b)) => match op {
+            BinOp::Add => checked_int(a.checked_add(b)),
+            BinOp::Subtract => checked_int(a.checked_sub(b)),
+            BinOp::Multiply => checked_int(a.checked_mul(b)),
+            BinOp::Divide if b == 0 => Err("division by zero".to_string()),
+            BinOp::Divide => checked_int(a.checked_div(b)),
+            BinOp::Modulo if b == 0 => Err("division by zero".to_string()),
+            BinOp::Modulo => checked_int(a.checked_rem(b)),
+            BinOp::Power if b < 0 => Err("negative exponent".to_string()),
+            BinOp::Power => checked_int(u32::try_from(b).ok().and_then(|b| a.checked_pow(b))),
+            BinOp::BitAnd => Ok(Value::Int(a & b)),
+            BinOp::BitOr => Ok(Value::Int(a | b)),
+            BinOp::BitXor => Ok(Value::Int(a ^ b)),
+            BinOp::ShiftLeft => checked_int(u32::try_from(b).ok().and_then(|b| a.checked_shl(b))),
+            BinOp::ShiftRight => checked_int(u32::try_from(b).ok().and_then(|b| a.checked_shr(b))),
+            BinOp::Equals => Ok(Value::Bool(a == b)),
+            BinOp::NotEquals => Ok(Value::Bool(a != b)),
+            BinOp::LessThan => Ok(Value::Bool(a < b)),
+            BinOp::LessThanOrEqual => Ok(Value::Bool(a <= b)),
+            BinOp::GreaterThan => Ok(Value::Bool(a > b)),
+            BinOp::GreaterThanOrEqual => Ok(Value::Bool(a >= b)),
+            _ => Err(wrong_type()),
+        },
+        (Value::Float(a), Value::Float(b)) => match op {
+            BinOp::Add => Ok(Value::Float(a + b)),
+            BinOp::Subtract => Ok(Value::Float(a - b)),
+            BinOp::Multiply => Ok(Value::Float(a * b)),
+            BinOp::Divide => Ok(Value::Float(a / b)),
+            BinOp::Modulo => Ok(Value::Float(a % b)),
+            BinOp::Power => Ok(Value::Float(a.powf(b))),
+            BinOp::Equals => Ok(Value::Bool(a == b)),
+            BinOp::NotEquals => Ok(Value::Bool(a != b)),
+            BinOp::LessThan => Ok(Value::Bool(a < b)),
+            BinOp::LessThanOrEqual => Ok(Value::Bool(a <= b)),
+            BinOp::GreaterThan => Ok(Value::Bool(a > b)),
+            BinOp::GreaterThanOrEqual => Ok(Value::Bool(a >= b)),
+            _ => Err(wrong_type()),
+        },
+        (Value::Str(a), Value::Str(b)) => match op {
+            BinOp::Add => Ok(Value::Str(a + &b)),
+            BinOp::Equals => Ok(Value::Bool(a == b)),
+            BinOp::NotEquals => Ok(Value::Bool(a != b)),
+            _ => Err(wrong_type()),
+        },
+        (Value::Bool(a), Value::Bool(b)) => match op {
+            BinOp::Add
+            | BinOp::Subtract
+            | BinOp::Multiply
+            | BinOp::Divide
+            | BinOp::Modulo
+            | BinOp::Power
+            | BinOp::BitAnd
+            | BinOp::BitOr
+            | BinOp::BitXor
+            | BinOp::ShiftLeft
+            | BinOp::ShiftRight
+            | BinOp::Coalesce => Err(wrong_type()),
+            BinOp::Equals => Ok(Value::Bool(a == b)),
+            BinOp::NotEquals => Ok(Value::Bool(a != b)),
+            BinOp::LessThan => Ok(Value::Bool(!a & b)),
+            BinOp::LessThanOrEqual => Ok(Value::Bool(a <= b)),
+            BinOp::GreaterThan => Ok(Value::Bool(a & !b)),
+            BinOp::GreaterThanOrEqual => Ok(Value::Bool(a >= b)),
+            BinOp::LogicalAnd => Ok(Value::Bool(a && b)),
+            BinOp::LogicalOr => Ok(Value::Bool(a || b)),
+        },
+        (Value::Char(a), Value::Char(b)) => match op {
+            BinOp::Equals => Ok(Value::Bool(a == b)),
+            BinOp::NotEquals => Ok(Value::Bool(a != b)),
+            BinOp::LessThan => Ok(Value::Bool(a < b)),
+            BinOp::LessThanOrEqual => Ok(Value::Bool(a <= b)),
+            BinOp::GreaterThan => Ok(Value::Bool(a > b)),
+            BinOp::GreaterThanOrEqual => Ok(Value::Bool(a >= b)),
+            _ => Err(wrong_type()),
+        },
+        (a @ Value::Range { .. }, b @ Value::Range { .. }) => match op {
+            BinOp::Equals => Ok(Value::Bool(a == b)),
+            BinOp::NotEquals => Ok(Value::Bool(a != b)),
+            _ => Err(wrong_type()),
+        },
+        (Value::Unit, Value::Unit) => match op {
+            BinOp::Equals => Ok(Value::Bool(true)),
+            BinOp::NotEquals => Ok(Value::Bool(false)),
+            _ => Err(wrong_type()),
+        },
+        _ => Err(wrong_type()),
+    }
+}
+
+/// The element of a list or the slice of a list or str at `index`, or the
+/// value of a map's key as an optional
+pub fn get_index(collection: Value, index: Value) -> Result<Value, String> {
+    let (elements, i) = match (collection, index) {
+        (Value::Map(entries), key) => {
+            return Ok(Value::Optional(
+                entries
+                    .into_iter()
+                    .find(|(other, _)| *other == key)
+                    .map(|(_, value)| Box::new(value)),
+            ))
+        }
+        (Value::List(elements), Value::Range { start, end }) => {
+            let range = slice_range(start, end, elements.len(), "list")?;
+
+            return Ok(Value::List(elements[range].to_vec()));
+        }
+        (Value::Str(s), Value::Range { start, end }) => {
+            let chars = s.chars().collect::<Vec<_>>();
+            let range = slice_range(start, end, chars.len(), "str")?;
+
+            return Ok(Value::Str(chars[range].iter().collect()));
+        }
+        (Value::List(elements), Value::Int(i)) => (elements, i),
+        _ => return Err(wrong_type()),
+    };
+
+    usize::try_from(i)
+        .ok()
+        .and_then(|i| elements.get(i))
+        .cloned()
+        .ok_or_else(|| {
+            format!(
+                "index {} is out of range for a list of length {}",
+                i,
+                elements.len()
+            )
+        })
+}
+
+/// Replaces the element of a list at `index`, or the value of a map's key,
+/// adding the key if it isn't there yet
+pub fn set_index(target: &mut Value, index: Value, value: Value) -> Result<(), String> {
+    match (target, index) {
+        (Value::List(elements), Value::Int(i)) => {
+            let len = elements.len();
+
+            let element = usize::try_from(i)
+                .ok()
+                .and_then(|i| elements.get_mut(i))
+                .ok_or_else(|| {
+                    format!("index {} is out of range for a list of length {}", i, len)
+                })?;
+
+            *element = value;
+        }
+        (Value::Map(entries), key) => match entries.iter_mut().find(|(other, _)| *other == key) {
+            Some((_, existing)) => *existing = value,
+            None => entries.push((key, value)),
+        },
+        _ => return Err(wrong_type()),
+    }
+
+    Ok(())
+}
+
+/// Whether `value` is an element of a list, a key of a map, a char of a str
+/// or an int in a range
+pub fn contains(collection: &Value, value: &Value) -> Result<bool, String> {
+    match collection {
+        Value::List(elements) => Ok(elements.contains(value)),
+        Value::Map(entries) => Ok(entries.iter().any(|(key, _)| key == value)),
+        Value::Str(s) => Ok(matches!(value, Value::Char(c) if s.contains(*c))),
+        Value::Range { start, end } => {
+            Ok(matches!(value, Value::Int(n) if (*start..*end).contains(n)))
+        }
+        _ => Err(wrong_type()),
+    }
+}
+
+/// The range from `start` to `end`, including `end` if `inclusive` is set
+pub fn range(start: Value, end: Value, inclusive: bool) -> Result<Value, String> {
+    let (Value::Int(start), Value::Int(end)) = (start, end) else {
+        return Err(wrong_type());
+    };
+
+    let end = if inclusive {
+        end.checked_add(1)
+            .ok_or_else(|| "integer overflow".to_string())?
+    } else {
+        end
+    };
+
+    Ok(Value::Range { start, end })
+}
+
+/// Converts a value to another base type with `as`
+pub fn cast(value: Value, ty: &Type) -> Result<Value, String> {
+    match (value, ty) {
+        (value, Type::Str) => Ok(Value::Str(value.to_string())),
+        (Value::Int(n), Type::Float) => Ok(Value::Float(n as f64)),
+        (Value::Bool(b), Type::Int) => Ok(Value::Int(b as i64)),
+        (Value::Char(c), Type::Int) => Ok(Value::Int(c as i64)),
+        (Value::Int(n), Type::Char) => u32::try_from(n)
+            .ok()
+            .and_then(char::from_u32)
+            .map(Value::Char)
+            .ok_or_else(|| format!("{} is not a valid char", n)),
+        // rounds towards zero, like integer division
+        (Value::Float(n), Type::Int) if n.is_finite() && n.abs() < i64::MAX as f64 => {
+            Ok(Value::Int(n as i64))
+        }
+        (Value::Float(n), Type::Int) => Err(format!("cannot convert {:?} to int", n)),
+        (Value::Str(s), Type::Int) => s
+            .trim()
+            .parse()
+            .map(Value::Int)
+            .map_err(|_| format!("cannot convert {:?} to int", s)),
+        (Value::Str(s), Type::Float) => s
+            .trim()
+            .parse()
+            .map(Value::Float)
+            .map_err(|_| format!("cannot convert {:?} to float", s)),
+        (value, _) => Ok(value),
+    }
+}
+
+/// Runs a builtin, with `ticks` being the counter `tick()` returns
+pub fn call_builtin(builtin: Builtin, args: Vec<Value>, ticks: &mut u64) -> Result<Value, String> {
+    match (builtin, args.first()) {
+        (Builtin::Tick, _) => {
+            let tick = *ticks;
+
+            *ticks += 1;
+
+            Ok(Value::Int(tick as i64))
+        }
+        (Builtin::Chars, Some(Value::Str(s))) => {
+            Ok(Value::List(s.chars().map(Value::Char).collect()))
+        }
+        (Builtin::Len, Some(Value::Str(s))) => Ok(Value::Int(s.chars().count() as i64)),
+        (Builtin::Print, Some(Value::Str(s))) => {
+            print!("{}", s);
+            std::io::stdout().flush().unwrap();
+
+            Ok(Value::Unit)
         }
+        (Builtin::Println, Some(Value::Str(s))) => {
+            println!("{}", s);
+
+            Ok(Value::Unit)
+        }
+        (Builtin::ToStr, Some(value)) => Ok(Value::Str(to_str(value, None))),
+        (Builtin::ToStrWith(spec), Some(value)) => Ok(Value::Str(to_str(value, Some(spec)))),
+        // format calls are replaced by the typechecker
+        _ => Err(wrong_type()),
+    }
+}
+
+/// The text a placeholder with `spec` shows for a value, which is what
+/// `print` shows without one. A spec for another type is left out.
+pub fn to_str(value: &Value, spec: Option<FormatSpec>) -> String {
+    match (spec, value) {
+        (Some(FormatSpec::Debug), value) => Nested(value).to_string(),
+        (Some(FormatSpec::Hex), Value::Int(n)) => radix(*n, format!("{:x}", n.unsigned_abs())),
+        (Some(FormatSpec::Binary), Value::Int(n)) => radix(*n, format!("{:b}", n.unsigned_abs())),
+        (Some(FormatSpec::Octal), Value::Int(n)) => radix(*n, format!("{:o}", n.unsigned_abs())),
+        (Some(FormatSpec::Precision(digits)), Value::Float(n)) => format!("{:.*}", digits, n),
+        (_, value) => value.to_string(),
+    }
+}
+
+/// Puts a minus sign in front of the digits of an int shown in another base
+/// if it's negative, rather than showing its two's complement
+fn radix(n: i64, digits: String) -> String {
+    if n < 0 {
+        format!("-{}", digits)
+    } else {
+        digits
+    }
+}
+
+/// The error for a value an operation can't be used with. The typechecker
+/// rules these out, so only bytecode from a damaged or handcrafted `.fxc`
+/// file gets one.
+pub fn wrong_type() -> String {
+    "a value has the wrong type for this".to_string()
+}
+
+/// Turns the result of a checked integer operation into a value, failing on overflow
+fn checked_int(result: Option<i64>) -> Result<Value, String> {
+    result
+        .map(Value::Int)
+        .ok_or_else(|| "integer overflow".to_string())
+}
+
+/// The part of a list or str of length `len` covered by the range `start..end`,
+/// failing if the range goes outside of it
+fn slice_range(
+    start: i64,
+    end: i64,
+    len: usize,
+    kind: &str,
+) -> Result<std::ops::Range<usize>, String> {
+    match (usize::try_from(start), usize::try_from(end)) {
+        (Ok(start), Ok(end)) if start <= end && end <= len => Ok(start..end),
+        _ => Err(format!(
+            "range {}..{} is out of range for a {} of length {}",
+            start, end, kind, len
+        )),
     }
 }
 
@@ -0,0 +1,518 @@
+//! Serialises a typed AST to JSON for external tooling.
+//!
+//! The schema is versioned by [`SCHEMA_VERSION`], which is bumped whenever a
+//! node kind or field is renamed or removed. Adding new kinds or fields does
+//! not bump the version.
+//!
+//! - The root is `{ "version", "span", "statements" }`.
+//! - Every span is `{ "start", "end" }`, as byte offsets into the source.
+//! - Every statement, expression and pattern is an object with a `"kind"`
+//!   (`snake_case` variant name) and a `"span"`, plus the fields of that kind.
+//!   Expressions also carry their inferred `"type"`.
+//! - Types are strings in source syntax, e.g. `"int"`, `"Shape?"` or
+//!   `"{ x: int }"`.
+//! - Names (variables, fields, labels, ...) are `{ "name", "span" }`.
+//! - Blocks (the branches of an `if`) are `{ "span", "statements", "tail" }`.
+
+use crate::typed_ast::*;
+use crate::{Span, Spanned};
+use serde_json::{json, Value};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+pub fn ast_to_json(ast: &Spanned<TypedAst>) -> Value {
+    json!({
+        "version": SCHEMA_VERSION,
+        "span": span(ast.1),
+        "statements": statements(&ast.0.statements.0),
+    })
+}
+
+fn span(span: Span) -> Value {
+    json!({ "start": span.start, "end": span.end })
+}
+
+fn name(name: &Spanned<&str>) -> Value {
+    json!({ "name": name.0, "span": span(name.1) })
+}
+
+fn ty(ty: &Spanned<Type>) -> Value {
+    json!({ "type": ty.0.to_string(), "span": span(ty.1) })
+}
+
+fn statements(statements: &[Spanned<Statement>]) -> Value {
+    statements.iter().map(statement).collect()
+}
+
+fn exprs(exprs: &[Spanned<Expr>]) -> Value {
+    exprs.iter().map(expr).collect()
+}
+
+fn node(kind: &str, node_span: Span, fields: Value) -> Value {
+    let mut node = json!({ "kind": kind, "span": span(node_span) });
+
+    if let (Value::Object(node), Value::Object(fields)) = (&mut node, fields) {
+        node.extend(fields);
+    }
+
+    node
+}
+
+fn statement(stmt: &Spanned<Statement>) -> Value {
+    match &stmt.0 {
+        Statement::Expr(value) => node("expr", stmt.1, json!({ "expr": expr(value) })),
+        Statement::Block(body) => node(
+            "block",
+            stmt.1,
+            json!({ "statements": statements(&body.0) }),
+        ),
+        Statement::Let {
+            mutable,
+            name: let_name,
+            ty: let_ty,
+            value,
+        } => node(
+            "let",
+            stmt.1,
+            json!({
+                "mutable": mutable,
+                "name": name(let_name),
+                "ty": ty(let_ty),
+                "value": expr(value),
+            }),
+        ),
+        Statement::Const {
+            name: const_name,
+            ty: const_ty,
+            value,
+        } => node(
+            "const",
+            stmt.1,
+            json!({
+                "name": name(const_name),
+                "ty": ty(const_ty),
+                "value": literal_value(&value.0),
+            }),
+        ),
+        Statement::Function {
+            name: func_name,
+            generics,
+            params,
+            return_ty,
+            captures,
+            body,
+        } => node(
+            "function",
+            stmt.1,
+            json!({
+                "name": name(func_name),
+                "generics": generics
+                    .iter()
+                    .map(|(generic, bounds)| json!({
+                        "name": name(generic),
+                        "bounds": bounds.iter().map(name).collect::<Value>(),
+                    }))
+                    .collect::<Value>(),
+                "params": params
+                    .0
+                    .iter()
+                    .map(|(param, param_ty)| json!({ "name": name(param), "ty": ty(param_ty) }))
+                    .collect::<Value>(),
+                "return_ty": ty(return_ty),
+                "captures": captures.iter().map(name).collect::<Value>(),
+                "body": statement(body),
+            }),
+        ),
+        Statement::Assign {
+            name: assign_name,
+            value,
+        } => node(
+            "assign",
+            stmt.1,
+            json!({ "name": name(assign_name), "value": expr(value) }),
+        ),
+        Statement::SetIndex {
+            name: set_name,
+            index,
+            value,
+        } => node(
+            "set_index",
+            stmt.1,
+            json!({ "name": name(set_name), "index": expr(index), "value": expr(value) }),
+        ),
+        Statement::Return(value) => node("return", stmt.1, json!({ "expr": expr(value) })),
+        Statement::Enum {
+            name: enum_name,
+            variants,
+        } => node(
+            "enum",
+            stmt.1,
+            json!({
+                "name": name(enum_name),
+                "variants": variants
+                    .0
+                    .iter()
+                    .map(|(variant, fields)| json!({
+                        "name": name(variant),
+                        "fields": fields.iter().map(ty).collect::<Value>(),
+                    }))
+                    .collect::<Value>(),
+            }),
+        ),
+        Statement::Struct {
+            name: struct_name,
+            fields,
+        } => node(
+            "struct",
+            stmt.1,
+            json!({
+                "name": name(struct_name),
+                "fields": fields
+                    .0
+                    .iter()
+                    .map(|(field, field_ty)| json!({ "name": name(field), "ty": ty(field_ty) }))
+                    .collect::<Value>(),
+            }),
+        ),
+        Statement::TypeAlias {
+            name: alias_name,
+            ty: alias_ty,
+        } => node(
+            "type_alias",
+            stmt.1,
+            json!({ "name": name(alias_name), "ty": ty(alias_ty) }),
+        ),
+        Statement::Import {
+            path,
+            module,
+            file,
+            statements: imported,
+        } => node(
+            "import",
+            stmt.1,
+            json!({
+                "path": { "path": path.0, "span": span(path.1) },
+                "module": module.as_ref().map(name),
+                "file": file,
+                "statements": statements(imported),
+            }),
+        ),
+        Statement::Break { label, value } => node(
+            "break",
+            stmt.1,
+            json!({ "label": label.as_ref().map(name), "value": expr(value) }),
+        ),
+        Statement::Continue => node("continue", stmt.1, json!({})),
+        Statement::While { cond, body } => node(
+            "while",
+            stmt.1,
+            json!({ "cond": expr(cond), "body": statements(&body.0) }),
+        ),
+        Statement::For {
+            first,
+            second,
+            iter,
+            body,
+        } => node(
+            "for",
+            stmt.1,
+            json!({
+                "first": name(first),
+                "second": second.as_ref().map(name),
+                "iter": expr(iter),
+                "body": statements(&body.0),
+            }),
+        ),
+        Statement::Trait {
+            name: trait_name,
+            methods,
+        } => node(
+            "trait",
+            stmt.1,
+            json!({
+                "name": name(trait_name),
+                "methods": methods
+                    .iter()
+                    .map(|(method, method_ty)| json!({ "name": name(method), "ty": ty(method_ty) }))
+                    .collect::<Value>(),
+            }),
+        ),
+        Statement::Impl {
+            trait_,
+            ty: impl_ty,
+            methods,
+        } => node(
+            "impl",
+            stmt.1,
+            json!({
+                "trait": trait_.as_ref().map(name),
+                "ty": ty(impl_ty),
+                "methods": statements(methods),
+            }),
+        ),
+    }
+}
+
+fn expr(e: &Spanned<Expr>) -> Value {
+    let (kind, fields) = match &e.0.expr {
+        ExprKind::Var(var) => ("var", json!({ "name": name(var) })),
+        ExprKind::ModuleVar {
+            module,
+            file,
+            name: var,
+        } => (
+            "module_var",
+            json!({ "module": name(module), "file": file, "name": name(var) }),
+        ),
+        ExprKind::Literal(literal) => ("literal", json!({ "value": literal_value(&literal.0) })),
+        ExprKind::Prefix { op, expr: operand } => (
+            "prefix",
+            json!({
+                "op": { "op": op.0.to_string(), "span": span(op.1) },
+                "expr": expr(operand),
+            }),
+        ),
+        ExprKind::Binary { op, lhs, rhs } => (
+            "binary",
+            json!({
+                "op": { "op": op.0.to_string(), "span": span(op.1) },
+                "lhs": expr(lhs),
+                "rhs": expr(rhs),
+            }),
+        ),
+        ExprKind::Call { callee, args } => (
+            "call",
+            json!({ "callee": expr(callee), "args": exprs(&args.0) }),
+        ),
+        ExprKind::MethodCall {
+            receiver,
+            trait_,
+            method,
+            args,
+        } => (
+            "method_call",
+            json!({
+                "receiver": expr(receiver),
+                "trait": trait_,
+                "method": name(method),
+                "args": exprs(&args.0),
+            }),
+        ),
+        ExprKind::BuiltinCall { builtin, args } => (
+            "builtin_call",
+            json!({
+                "builtin": { "name": builtin.0.to_string(), "span": span(builtin.1) },
+                "args": exprs(&args.0),
+            }),
+        ),
+        ExprKind::Variant {
+            enum_,
+            variant,
+            args,
+        } => (
+            "variant",
+            json!({
+                "enum": name(enum_),
+                "variant": name(variant),
+                "args": exprs(&args.0),
+            }),
+        ),
+        ExprKind::Match {
+            expr: scrutinee,
+            arms,
+        } => (
+            "match",
+            json!({
+                "expr": expr(scrutinee),
+                "arms": arms
+                    .0
+                    .iter()
+                    .map(|(arm_pattern, body)| json!({
+                        "pattern": pattern(arm_pattern),
+                        "body": expr(body),
+                    }))
+                    .collect::<Value>(),
+            }),
+        ),
+        ExprKind::Some(inner) => ("some", json!({ "expr": expr(inner) })),
+        ExprKind::Ok(inner) => ("ok", json!({ "expr": expr(inner) })),
+        ExprKind::Err(inner) => ("err", json!({ "expr": expr(inner) })),
+        ExprKind::Try(inner) => ("try", json!({ "expr": expr(inner) })),
+        ExprKind::Is {
+            expr: value,
+            variant,
+        } => (
+            "is",
+            json!({ "expr": expr(value), "variant": name(variant) }),
+        ),
+        ExprKind::In {
+            expr: value,
+            collection,
+        } => (
+            "in",
+            json!({ "expr": expr(value), "collection": expr(collection) }),
+        ),
+        ExprKind::Range {
+            start,
+            end,
+            inclusive,
+        } => (
+            "range",
+            json!({ "start": expr(start), "end": expr(end), "inclusive": inclusive }),
+        ),
+        ExprKind::Ref {
+            mutable,
+            expr: value,
+        } => ("ref", json!({ "mutable": mutable, "expr": expr(value) })),
+        ExprKind::Cast {
+            expr: value,
+            ty: cast_ty,
+        } => ("cast", json!({ "expr": expr(value), "ty": ty(cast_ty) })),
+        ExprKind::Record(fields) => (
+            "record",
+            json!({
+                "fields": fields
+                    .iter()
+                    .map(|(field, value)| json!({ "name": name(field), "value": expr(value) }))
+                    .collect::<Value>(),
+            }),
+        ),
+        ExprKind::Struct {
+            name: struct_name,
+            fields,
+        } => (
+            "struct",
+            json!({
+                "name": name(struct_name),
+                "fields": fields
+                    .iter()
+                    .map(|(field, value)| json!({ "name": name(field), "value": expr(value) }))
+                    .collect::<Value>(),
+            }),
+        ),
+        ExprKind::List(elements) => ("list", json!({ "elements": exprs(&elements.0) })),
+        ExprKind::Map(entries) => (
+            "map",
+            json!({
+                "entries": entries
+                    .0
+                    .iter()
+                    .map(|(key, value)| json!({ "key": expr(key), "value": expr(value) }))
+                    .collect::<Value>(),
+            }),
+        ),
+        ExprKind::FieldAccess {
+            expr: record,
+            field,
+        } => (
+            "field_access",
+            json!({ "expr": expr(record), "field": name(field) }),
+        ),
+        ExprKind::Index { expr: list, index } => {
+            ("index", json!({ "expr": expr(list), "index": expr(index) }))
+        }
+        ExprKind::LabeledBlock {
+            label,
+            statements: body,
+            tail,
+        } => (
+            "labeled_block",
+            json!({
+                "label": name(label),
+                "statements": statements(&body.0),
+                "tail": tail.as_deref().map(expr),
+            }),
+        ),
+        ExprKind::Block(body) => (
+            "block",
+            json!({
+                "statements": statements(&body.statements),
+                "tail": body.tail.as_deref().map(expr),
+            }),
+        ),
+        ExprKind::Closure {
+            params,
+            captures,
+            body,
+        } => (
+            "closure",
+            json!({
+                "params": params
+                    .0
+                    .iter()
+                    .map(|(param, param_ty)| json!({ "name": name(param), "ty": ty(param_ty) }))
+                    .collect::<Value>(),
+                "captures": captures.iter().map(name).collect::<Value>(),
+                "body": expr(body),
+            }),
+        ),
+        ExprKind::Loop { body, breaks } => (
+            "loop",
+            json!({ "body": statements(&body.0), "breaks": breaks }),
+        ),
+        ExprKind::If { cond, then, else_ } => (
+            "if",
+            json!({
+                "cond": expr(cond),
+                "then": block(then),
+                "else": else_.as_ref().map(block),
+            }),
+        ),
+        ExprKind::Catch {
+            body,
+            binding,
+            handler,
+        } => (
+            "catch",
+            json!({
+                "body": block(body),
+                "binding": name(binding),
+                "handler": block(handler),
+            }),
+        ),
+    };
+
+    let mut node = node(kind, e.1, fields);
+
+    node["type"] = json!(e.0.ty.to_string());
+
+    node
+}
+
+fn block(b: &Spanned<Block>) -> Value {
+    json!({
+        "span": span(b.1),
+        "statements": statements(&b.0.statements),
+        "tail": b.0.tail.as_deref().map(expr),
+    })
+}
+
+fn pattern(p: &Spanned<Pattern>) -> Value {
+    match &p.0 {
+        Pattern::Binding(binding) => node("binding", p.1, json!({ "name": name(binding) })),
+        Pattern::Some(binding) => node("some", p.1, json!({ "name": name(binding) })),
+        Pattern::None => node("none", p.1, json!({})),
+        Pattern::Ok(binding) => node("ok", p.1, json!({ "name": name(binding) })),
+        Pattern::Err(binding) => node("err", p.1, json!({ "name": name(binding) })),
+        Pattern::Variant { variant, bindings } => node(
+            "variant",
+            p.1,
+            json!({
+                "variant": name(variant),
+                "bindings": bindings.0.iter().map(name).collect::<Value>(),
+            }),
+        ),
+    }
+}
+
+fn literal_value(literal: &Literal) -> Value {
+    match literal {
+        Literal::Int(n) => json!(n),
+        Literal::Float(n) => json!(n),
+        Literal::Str(s) => json!(s),
+        Literal::Char(c) => json!(c.to_string()),
+        Literal::Bool(b) => json!(b),
+        Literal::Unit => json!("#"),
+        Literal::None => Value::Null,
+    }
+}
@@ -4,45 +4,304 @@ use chumsky::prelude::*;
 
 pub fn lexer<'src>(
 ) -> impl Parser<'src, &'src str, Vec<Spanned<Token<'src>>>, extra::Err<Rich<'src, char, Span>>> {
-    let literal = literal_lexer();
+    token_lexer()
+        .padded_by(comment_lexer().repeated())
+        .padded()
+        .recover_with(skip_then_retry_until(any().ignored(), end()))
+        .repeated()
+        .collect::<Vec<_>>()
+        .map(|tokens| tokens.into_iter().flatten().collect())
+}
 
-    let unit = just("#").to(Token::Unit).boxed();
+/// Lexes a single token, or every token making up an interpolated string
+fn token_lexer<'src>(
+) -> impl Parser<'src, &'src str, Vec<Spanned<Token<'src>>>, extra::Err<Rich<'src, char, Span>>> + Clone
+{
+    recursive(|token| {
+        let literal = literal_lexer();
 
-    let op = op_lexer();
+        let unit = just("#").to(Token::Unit).boxed();
 
-    let control = control_lexer();
+        let op = op_lexer();
 
-    let keyword = keyword_lexer();
+        let control = control_lexer();
 
-    let ident = text::ident().map(Token::Ident).boxed();
+        let keyword = keyword_lexer();
 
-    let token = choice((literal, unit, op, control, keyword, ident)).boxed();
+        let ident = text::ident().map(Token::Ident).boxed();
+
+        let label = just('\'')
+            .ignore_then(text::ident())
+            .map(Token::Label)
+            .boxed();
+
+        let single = choice((literal, unit, op, control, keyword, ident, label))
+            .map_with_span(|tok, span| vec![(tok, span)])
+            .boxed();
+
+        choice((interpolated_str_lexer(token), single))
+    })
+}
 
-    let comment = just("//")
+fn comment_lexer<'src>(
+) -> impl Parser<'src, &'src str, (), extra::Err<Rich<'src, char, Span>>> + Clone {
+    just("//")
         .then(any().and_is(just('\n').not()).repeated())
         .padded()
-        .boxed();
+        .ignored()
+        .boxed()
+}
 
-    token
-        .map_with_span(|tok, span| (tok, span))
-        .padded_by(comment.repeated())
-        .padded()
-        .recover_with(skip_then_retry_until(any().ignored(), end()))
+/// Lexes a string with `{expr}` holes in it into a `StrStart` token, the
+/// tokens of each hole separated by `StrMiddle` tokens, and a `StrEnd` token
+fn interpolated_str_lexer<'src>(
+    token: impl Parser<'src, &'src str, Vec<Spanned<Token<'src>>>, extra::Err<Rich<'src, char, Span>>>
+        + Clone
+        + 'src,
+) -> impl Parser<'src, &'src str, Vec<Spanned<Token<'src>>>, extra::Err<Rich<'src, char, Span>>> + Clone
+{
+    let text = none_of("\\\"{")
+        .or(escape_lexer())
         .repeated()
-        .collect()
+        .collect::<String>();
+
+    // braces inside a hole have to be balanced, the first unmatched one closes it
+    let hole = recursive(
+        |hole: Recursive<dyn Parser<_, Vec<Spanned<Token<'src>>>, _>>| {
+            choice((
+                just('{')
+                    .to(Token::Control(Control::LeftCurly))
+                    .map_with_span(|tok, span| (tok, span))
+                    .then(hole)
+                    .then(
+                        just('}')
+                            .to(Token::Control(Control::RightCurly))
+                            .map_with_span(|tok, span| (tok, span)),
+                    )
+                    .map(|((open, mut tokens), close)| {
+                        tokens.insert(0, open);
+                        tokens.push(close);
+                        tokens
+                    }),
+                token.and_is(just('}').not()),
+            ))
+            .padded_by(comment_lexer().repeated())
+            .padded()
+            .repeated()
+            .collect::<Vec<_>>()
+            .map(|tokens| tokens.into_iter().flatten().collect::<Vec<_>>())
+        },
+    )
+    .boxed();
+
+    let start = just('"')
+        .ignore_then(text.clone())
+        .then_ignore(just('{'))
+        .map(Token::StrStart)
+        .map_with_span(|tok, span| (tok, span));
+
+    let middle = just('}')
+        .ignore_then(text.clone())
+        .then_ignore(just('{'))
+        .map(Token::StrMiddle)
+        .map_with_span(|tok, span| (tok, span));
+
+    let end = just('}')
+        .ignore_then(text)
+        .then_ignore(just('"'))
+        .map(Token::StrEnd)
+        .map_with_span(|tok, span| (tok, span));
+
+    start
+        .then(hole.clone())
+        .then(middle.then(hole).repeated().collect::<Vec<_>>())
+        .then(end)
+        .map(|(((start, first), rest), end)| {
+            let mut tokens = vec![start];
+
+            tokens.extend(first);
+
+            for (middle, hole) in rest {
+                tokens.push(middle);
+                tokens.extend(hole);
+            }
+
+            tokens.push(end);
+
+            tokens
+        })
+        .boxed()
+}
+
+fn escape_lexer<'src>(
+) -> impl Parser<'src, &'src str, char, extra::Err<Rich<'src, char, Span>>> + Clone {
+    just('\\')
+        .ignore_then(choice((
+            just('\\'),
+            just('"'),
+            just('\''),
+            just('{'),
+            just('}'),
+            just('n').to('\n'),
+            just('t').to('\t'),
+        )))
+        .boxed()
 }
 
 fn literal_lexer<'src>(
 ) -> impl Parser<'src, &'src str, Token<'src>, extra::Err<Rich<'src, char, Span>>> {
-    let num = text::int(10)
-        .then(just('.').then(text::digits(10)).or_not())
-        .slice()
-        .from_str()
-        .unwrapped()
-        .map(Token::Num)
+    // `42i` and `42f` are an int and a float, and `3.0f` is a float too
+    let suffix = one_of("if")
+        .then_ignore(
+            any()
+                .filter(|c: &char| c.is_alphanumeric() || *c == '_')
+                .not(),
+        )
+        .or_not();
+
+    let number = separated_digits(text::int(10))
+        .then(
+            just('.')
+                .ignore_then(separated_digits(text::digits(10)))
+                .or_not(),
+        )
+        .then(suffix)
+        // errors are emitted instead of failing the literal, so lexing carries on after it
+        .validate(
+            |((int, fraction), suffix), span, emitter| match (fraction, suffix) {
+                (Some(fraction), suffix) => {
+                    if suffix == Some('i') {
+                        emitter.emit(Rich::custom(
+                            span,
+                            "A literal with a fractional part can't have an 'i' suffix",
+                        ));
+                    }
+
+                    Token::Float(format!("{}.{}", int, fraction).parse().unwrap())
+                }
+                (None, Some('f')) => Token::Float(int.parse().unwrap()),
+                (None, _) => int.parse().map(Token::Int).unwrap_or_else(|_| {
+                    emitter.emit(Rich::custom(span, "Integer literal is too large"));
+                    Token::Int(0)
+                }),
+            },
+        )
+        .boxed();
+
+    // every letter and digit after the prefix is taken, so that one that
+    // isn't valid in the radix is reported instead of starting a new token
+    let radix_int = just('0')
+        .ignore_then(choice((
+            just('x').to(("0x", 16)),
+            just('b').to(("0b", 2)),
+            just('o').to(("0o", 8)),
+        )))
+        .then(
+            any()
+                .filter(|c: &char| c.is_alphanumeric() || *c == '_')
+                .repeated()
+                .slice()
+                .map_with_span(|digits: &str, span: Span| (digits, span)),
+        )
+        .validate(|((prefix, radix), (digits, digits_span)), span, emitter| {
+            let mut valid = true;
+
+            for (offset, c) in digits.char_indices() {
+                if c != '_' && !c.is_digit(radix) {
+                    let start = digits_span.start + offset;
+
+                    emitter.emit(Rich::custom(
+                        Span::new(start, start + c.len_utf8()),
+                        format!("Invalid digit '{}' in a '{}' literal", c, prefix),
+                    ));
+
+                    valid = false;
+                }
+            }
+
+            let digits = digits.replace('_', "");
+
+            if digits.is_empty() {
+                emitter.emit(Rich::custom(
+                    span,
+                    format!("Expected digits after '{}'", prefix),
+                ));
+            } else if valid {
+                match i64::from_str_radix(&digits, radix) {
+                    Ok(n) => return Token::Int(n),
+                    Err(_) => emitter.emit(Rich::custom(span, "Integer literal is too large")),
+                }
+            }
+
+            Token::Int(0)
+        })
         .boxed();
 
-    choice((num,)).boxed()
+    let multiline_str = just("\"\"\"")
+        .ignore_then(any().and_is(just("\"\"\"").not()).repeated().slice())
+        .then_ignore(just("\"\"\""))
+        .map(strip_indent)
+        .map(Token::Str)
+        .boxed();
+
+    let str = none_of("\\\"")
+        .or(escape_lexer())
+        .repeated()
+        .collect()
+        .delimited_by(just('"'), just('"'))
+        .map(Token::Str)
+        .boxed();
+
+    let char = none_of("\\'")
+        .or(escape_lexer())
+        .delimited_by(just('\''), just('\''))
+        .map(Token::Char)
+        .boxed();
+
+    choice((radix_int, number, multiline_str, str, char)).boxed()
+}
+
+/// Digits starting with `start` that can be grouped with underscores, like
+/// `1_000_000`, with the underscores removed
+fn separated_digits<'src, O>(
+    start: impl Parser<'src, &'src str, O, extra::Err<Rich<'src, char, Span>>> + Clone,
+) -> impl Parser<'src, &'src str, String, extra::Err<Rich<'src, char, Span>>> + Clone {
+    start
+        .then(
+            just('_')
+                .repeated()
+                .at_least(1)
+                .then(text::digits(10))
+                .repeated(),
+        )
+        .slice()
+        .map(|digits: &str| digits.replace('_', ""))
+}
+
+/// Turns the raw contents of a `"""` string into its value.
+///
+/// A line break straight after the opening quotes and the whitespace-only
+/// line holding the closing quotes are dropped, then the indentation common
+/// to every non-blank line is removed from each line.
+fn strip_indent(raw: &str) -> String {
+    let raw = raw.strip_prefix('\n').unwrap_or(raw);
+
+    let raw = match raw.rsplit_once('\n') {
+        Some((rest, last)) if last.trim().is_empty() => rest,
+        _ => raw,
+    };
+
+    let indent = raw
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    raw.lines()
+        .map(|line| line.get(indent..).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 fn op_lexer<'src>() -> impl Parser<'src, &'src str, Token<'src>, extra::Err<Rich<'src, char, Span>>>
@@ -50,16 +309,29 @@ fn op_lexer<'src>() -> impl Parser<'src, &'src str, Token<'src>, extra::Err<Rich
     choice((
         just("==").to(Operator::Equals),
         just("!=").to(Operator::NotEquals),
-        just("<").to(Operator::LessThan),
+        just("<<").to(Operator::ShiftLeft),
         just("<=").to(Operator::LessThanOrEqual),
-        just(">").to(Operator::GreaterThan),
         just(">=").to(Operator::GreaterThanOrEqual),
+        just("<").to(Operator::LessThan),
+        just(">").to(Operator::GreaterThan),
         just("&&").to(Operator::LogicalAnd),
         just("||").to(Operator::LogicalOr),
+        just("??").to(Operator::Coalesce),
+        just("**").to(Operator::StarStar),
+        just("+=").to(Operator::PlusEquals),
+        just("-=").to(Operator::MinusEquals),
+        just("*=").to(Operator::StarEquals),
+        just("/=").to(Operator::SlashEquals),
         just("+").to(Operator::Plus),
         just("-").to(Operator::Minus),
         just("*").to(Operator::Star),
         just("/").to(Operator::Slash),
+        just("%").to(Operator::Percent),
+        just("!").to(Operator::Not),
+        just("&").to(Operator::Ampersand),
+        just("|").to(Operator::Pipe),
+        just("^").to(Operator::Caret),
+        just("~").to(Operator::Tilde),
     ))
     .map(Token::Operator)
     .boxed()
@@ -69,27 +341,102 @@ fn control_lexer<'src>(
 ) -> impl Parser<'src, &'src str, Token<'src>, extra::Err<Rich<'src, char, Span>>> {
     choice((
         just(";").to(Token::Control(Control::Semicolon)),
+        just("::").to(Token::Control(Control::DoubleColon)),
         just(":").to(Token::Control(Control::Colon)),
+        just("=>").to(Token::Control(Control::FatArrow)),
         just("=").to(Token::Control(Control::Equals)),
         just("(").to(Token::Control(Control::LeftParen)),
         just(")").to(Token::Control(Control::RightParen)),
         just("{").to(Token::Control(Control::LeftCurly)),
         just("}").to(Token::Control(Control::RightCurly)),
+        just("[").to(Token::Control(Control::LeftSquare)),
+        just("]").to(Token::Control(Control::RightSquare)),
         just(",").to(Token::Control(Control::Comma)),
+        just("?").to(Token::Control(Control::Question)),
+        just("..=").to(Token::Control(Control::DotDotEquals)),
+        just("..").to(Token::Control(Control::DotDot)),
+        just(".").to(Token::Control(Control::Dot)),
     ))
     .boxed()
 }
 
 fn keyword_lexer<'src>(
 ) -> impl Parser<'src, &'src str, Token<'src>, extra::Err<Rich<'src, char, Span>>> {
+    // split in two since `choice` takes at most 26 parsers
     choice((
-        just("print").to(Keyword::Print),
-        just("let").to(Keyword::Let),
-        just("true").to(Keyword::True),
-        just("false").to(Keyword::False),
-        just("func").to(Keyword::Func),
-        just("return").to(Keyword::Return),
+        choice((
+            text::keyword("let").to(Keyword::Let),
+            text::keyword("true").to(Keyword::True),
+            text::keyword("false").to(Keyword::False),
+            text::keyword("func").to(Keyword::Func),
+            text::keyword("enum").to(Keyword::Enum),
+            text::keyword("type").to(Keyword::Type),
+            text::keyword("none").to(Keyword::None),
+            text::keyword("some").to(Keyword::Some),
+            text::keyword("import").to(Keyword::Import),
+            text::keyword("mut").to(Keyword::Mut),
+            text::keyword("struct").to(Keyword::Struct),
+            text::keyword("ok").to(Keyword::Ok),
+            text::keyword("err").to(Keyword::Err),
+            text::keyword("trait").to(Keyword::Trait),
+            text::keyword("impl").to(Keyword::Impl),
+            text::keyword("const").to(Keyword::Const),
+        )),
+        choice((
+            text::keyword("return").to(Keyword::Return),
+            text::keyword("match").to(Keyword::Match),
+            text::keyword("break").to(Keyword::Break),
+            text::keyword("is").to(Keyword::Is),
+            text::keyword("if").to(Keyword::If),
+            text::keyword("else").to(Keyword::Else),
+            text::keyword("for").to(Keyword::For),
+            text::keyword("while").to(Keyword::While),
+            text::keyword("loop").to(Keyword::Loop),
+            text::keyword("continue").to(Keyword::Continue),
+            text::keyword("as").to(Keyword::As),
+            text::keyword("in").to(Keyword::In),
+            text::keyword("try").to(Keyword::Try),
+            text::keyword("catch").to(Keyword::Catch),
+        )),
     ))
     .map(Token::Keyword)
     .boxed()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_the_common_indentation() {
+        let raw = "\n    first\n      second\n    third\n    ";
+
+        assert_eq!(strip_indent(raw), "first\n  second\nthird");
+    }
+
+    #[test]
+    fn blank_lines_do_not_count_towards_the_indentation() {
+        let raw = "\n        a\n\n  \n        b\n        ";
+
+        assert_eq!(strip_indent(raw), "a\n\n\nb");
+    }
+
+    #[test]
+    fn keeps_text_on_the_opening_and_closing_lines() {
+        assert_eq!(strip_indent("one line"), "one line");
+        assert_eq!(strip_indent("  a\n  b"), "a\nb");
+        assert_eq!(strip_indent("\n  a\n  b"), "a\nb");
+    }
+
+    #[test]
+    fn lexes_a_multiline_string() {
+        let source = "\"\"\"\n    hello\n      world\n    \"\"\"";
+        let (tokens, errors) = lexer().parse(source).into_output_errors();
+
+        assert!(errors.is_empty());
+        assert_eq!(
+            tokens.unwrap()[0].0,
+            Token::Str("hello\n  world".to_string())
+        );
+    }
+}
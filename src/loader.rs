@@ -0,0 +1,297 @@
+use crate::ast::{Ast, Statement};
+use crate::error::{Error, TypecheckError};
+use crate::{lexer, parser, Span, Spanned};
+use chumsky::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Every file that makes up a program, laid out one after the other in a
+/// single range of offsets so that a plain `Span` identifies both the file
+/// and the position within it.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+struct SourceFile {
+    name: String,
+    source: &'static str,
+    offset: usize,
+}
+
+impl SourceMap {
+    pub fn add(&mut self, name: String, source: &'static str) -> usize {
+        // leave a gap after each file so that an end of input span can't be
+        // mistaken for the start of the next file
+        let offset = self
+            .files
+            .last()
+            .map(|file| file.offset + file.source.len() + 1)
+            .unwrap_or(0);
+
+        self.files.push(SourceFile {
+            name,
+            source,
+            offset,
+        });
+
+        offset
+    }
+
+    /// Finds the file a span points into, and the span's range within it
+    pub fn locate(&self, span: Span) -> (String, std::ops::Range<usize>) {
+        let file = self
+            .files
+            .iter()
+            .rev()
+            .find(|file| file.offset <= span.start)
+            .unwrap();
+
+        (
+            file.name.clone(),
+            span.start - file.offset..span.end - file.offset,
+        )
+    }
+
+    /// Whether a span is inside one of the files, which every span the
+    /// program was compiled with is
+    pub fn contains(&self, span: Span) -> bool {
+        self.files.iter().any(|file| {
+            file.offset <= span.start
+                && span.start <= span.end
+                && span.end <= file.offset + file.source.len()
+        })
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = (String, &'static str)> + '_ {
+        self.files
+            .iter()
+            .map(|file| (file.name.clone(), file.source))
+    }
+}
+
+/// Loads a program's main file and, recursively, every file it imports
+pub struct Loader {
+    pub sources: SourceMap,
+    /// Files that have already been loaded, with their index in the source
+    /// map, importing them again is a no-op
+    loaded: HashMap<PathBuf, usize>,
+    /// The chain of imports currently being loaded, used to detect cycles
+    stack: Vec<PathBuf>,
+    pub errors: Vec<Error>,
+}
+
+type Statements = Vec<Spanned<Statement<'static>>>;
+
+impl Loader {
+    pub fn new() -> Self {
+        Self {
+            sources: SourceMap::default(),
+            loaded: HashMap::new(),
+            stack: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Loads the program starting at `path`, returning its statements if it
+    /// could be parsed. Any errors are collected in `self.errors`.
+    pub fn load_main(&mut self, path: &Path) -> std::io::Result<Option<Spanned<Ast<'static>>>> {
+        let path = path.canonicalize()?;
+        let source = std::fs::read_to_string(&path)?;
+
+        Ok(self.load(path, source))
+    }
+
+    fn load(&mut self, path: PathBuf, source: String) -> Option<Spanned<Ast<'static>>> {
+        // sources are kept for the whole run so that the syntax trees of all
+        // files can borrow from them with one shared lifetime
+        let source: &'static str = source.leak();
+
+        let file = self.sources.files.len();
+        let offset = self.sources.add(path.display().to_string(), source);
+        let shift = |span: Span| Span::from(span.start + offset..span.end + offset);
+
+        self.loaded.insert(path.clone(), file);
+
+        let (tokens, lex_errs) = lexer::lexer().parse(source).into_output_errors();
+
+        self.errors.extend(
+            lex_errs
+                .into_iter()
+                .map(|e| e.map_token(|t| t.to_string()))
+                .map(|e| Error::from(e).map_spans(&shift)),
+        );
+
+        let tokens = tokens?
+            .into_iter()
+            .map(|(token, span)| (token, shift(span)))
+            .collect::<Vec<_>>();
+
+        let eoi = shift((source.len()..source.len()).into());
+
+        let (ast, parse_errs) = parser::parser()
+            .parse(tokens.spanned(eoi))
+            .into_output_errors();
+
+        self.errors.extend(
+            parse_errs
+                .into_iter()
+                .map(|e| e.map_token(|t| t.to_string()))
+                .map(Into::into),
+        );
+
+        let mut ast = ast?;
+        let statements = &mut ast.0.statements;
+
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        self.stack.push(path);
+
+        let mut imported_names = Vec::new();
+
+        for stmt in &mut statements.0 {
+            if let Statement::Import {
+                path: import_path,
+                file,
+                statements: imported,
+                ..
+            } = &mut stmt.0
+            {
+                (*file, *imported) = self.load_import(&dir, import_path);
+
+                let names = declared_names(std::slice::from_ref(stmt));
+
+                self.check_collisions(&imported_names, names.clone());
+
+                imported_names.extend(names);
+            }
+        }
+
+        let local_names = statements
+            .0
+            .iter()
+            .filter(|stmt| !matches!(stmt.0, Statement::Import { .. }))
+            .flat_map(|stmt| declared_names(std::slice::from_ref(stmt)))
+            .collect();
+
+        self.check_collisions(&imported_names, local_names);
+
+        self.stack.pop();
+
+        Some(ast)
+    }
+
+    /// Loads an imported file, returning its index in the source map and its
+    /// statements, which are empty if it had already been loaded. The index
+    /// is meaningless if the file couldn't be loaded.
+    fn load_import(&mut self, dir: &Path, import_path: &Spanned<String>) -> (usize, Statements) {
+        let path = match dir.join(&import_path.0).canonicalize() {
+            Ok(path) => path,
+            Err(e) => {
+                self.errors.push(Error::Custom(
+                    import_path.1,
+                    format!("Cannot import '{}': {}", import_path.0, e),
+                ));
+
+                return (0, vec![]);
+            }
+        };
+
+        if let Some(start) = self.stack.iter().position(|loading| *loading == path) {
+            self.errors.push(
+                TypecheckError::CircularImport {
+                    span: import_path.1,
+                    cycle: self.stack[start..]
+                        .iter()
+                        .chain([&path])
+                        .map(|path| path.display().to_string())
+                        .collect(),
+                }
+                .into(),
+            );
+
+            return (0, vec![]);
+        }
+
+        if let Some(&file) = self.loaded.get(&path) {
+            return (file, vec![]);
+        }
+
+        let file = self.sources.files.len();
+
+        match std::fs::read_to_string(&path) {
+            Ok(source) => (
+                file,
+                self.load(path, source)
+                    .map(|(ast, _)| ast.statements.0)
+                    .unwrap_or_default(),
+            ),
+            Err(e) => {
+                self.errors.push(Error::Custom(
+                    import_path.1,
+                    format!("Cannot import '{}': {}", import_path.0, e),
+                ));
+
+                (0, vec![])
+            }
+        }
+    }
+
+    fn check_collisions(&mut self, existing: &[DeclaredName], names: Vec<DeclaredName>) {
+        for (is_type, name, span) in names {
+            if let Some((_, _, existing_span)) = existing
+                .iter()
+                .find(|(other_is_type, other, _)| *other_is_type == is_type && *other == name)
+            {
+                self.errors.push(
+                    TypecheckError::ImportCollision {
+                        name: name.to_string(),
+                        imported: *existing_span,
+                        span,
+                    }
+                    .into(),
+                );
+            }
+        }
+    }
+}
+
+/// A name brought into scope at the top level of a file, flagged by whether
+/// it names a type
+type DeclaredName = (bool, &'static str, Span);
+
+/// The names a list of top level statements brings into scope, including
+/// the ones from files it imports
+fn declared_names(statements: &[Spanned<Statement<'static>>]) -> Vec<DeclaredName> {
+    statements
+        .iter()
+        .flat_map(|stmt| match &stmt.0 {
+            Statement::Let { name, .. }
+            | Statement::Const { name, .. }
+            | Statement::Function { name, .. } => {
+                vec![(false, name.0, name.1)]
+            }
+            Statement::Enum { name, .. }
+            | Statement::Struct { name, .. }
+            | Statement::TypeAlias { name, .. }
+            | Statement::Trait { name, .. } => {
+                vec![(true, name.0, name.1)]
+            }
+            Statement::Import {
+                module: None,
+                statements,
+                ..
+            } => declared_names(statements),
+            // only the types of a module share the importing scope
+            Statement::Import {
+                module: Some(_),
+                statements,
+                ..
+            } => declared_names(statements)
+                .into_iter()
+                .filter(|(is_type, ..)| *is_type)
+                .collect(),
+            _ => vec![],
+        })
+        .collect()
+}
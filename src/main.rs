@@ -1,24 +1,42 @@
-use ariadne::{Label, Report, ReportKind, Source};
-use chumsky::prelude::*;
+use ariadne::{Label, Report, ReportKind};
 use chumsky::span::SimpleSpan;
-use chumsky::Parser as _;
-use clap::Parser;
-use std::fs::read_to_string;
+use clap::{ArgGroup, Parser, Subcommand};
+use error::{Message, Notes, Spans};
+use interpreter::Limits;
+use loader::SourceMap;
 use std::path::{Path, PathBuf};
 
 mod ast;
+mod bytecode;
+mod c;
 mod error;
+mod exhaustiveness;
+mod fold;
+mod fxc;
+mod gc;
 mod interpreter;
+mod json;
 mod lexer;
+mod loader;
+mod mir;
+mod native;
+mod optimize;
 mod parser;
+mod peephole;
+mod prelude;
+mod registers;
+mod ssa;
 mod token;
 mod typecheck;
 mod typed_ast;
+mod verifier;
+mod vm;
+mod wasm;
 
 fn main() {
     let args = Args::parse();
 
-    match run(&args.filename) {
+    match run(&args) {
         Ok(()) => {}
         Err(e) => {
             eprintln!("{}", e);
@@ -28,84 +46,390 @@ fn main() {
 }
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
+// only one backend can run or compile the program
+#[command(group(ArgGroup::new("backend").args(["vm", "registers", "native", "wasm", "c"])))]
 struct Args {
-    filename: PathBuf,
+    #[arg(required = true)]
+    filename: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Print the typed AST as JSON instead of running the program
+    #[arg(long)]
+    json_ast: bool,
+
+    /// Print the type of the innermost expression or binding at this byte
+    /// offset in the main file instead of running the program
+    #[arg(long, value_name = "OFFSET")]
+    type_at: Option<usize>,
+
+    /// Warn when a `let` shadows another binding
+    #[arg(long)]
+    warn_shadowing: bool,
+
+    /// Show how the type of each binding was inferred, as the expressions
+    /// and annotations it was unified with
+    #[arg(long)]
+    explain_types: bool,
+
+    /// Run the program on the bytecode VM instead of the tree-walking interpreter
+    #[arg(long)]
+    vm: bool,
+
+    /// Stop the bytecode VM when this many functions, closures and borrowed
+    /// variables are in use at once, even after collecting the ones that
+    /// can't be used anymore
+    #[arg(long, value_name = "OBJECTS", requires = "vm")]
+    heap_limit: Option<usize>,
+
+    /// Stop the program when a call would make this many calls run at once
+    #[arg(long, value_name = "CALLS", conflicts_with_all = ["registers", "native", "wasm", "c"])]
+    max_depth: Option<usize>,
+
+    /// Stop the program after this many steps, which are instructions on the
+    /// bytecode VM, and statements, expressions and loop iterations in the
+    /// interpreter
+    #[arg(long, value_name = "STEPS", conflicts_with_all = ["registers", "native", "wasm", "c"])]
+    fuel: Option<u64>,
+
+    /// Run the program on the register VM, which compiles the MIR and only
+    /// supports numbers, bools, chars and functions
+    #[arg(long)]
+    registers: bool,
+
+    /// Run the program as x86-64 machine code generated in memory, which is
+    /// experimental and only supports numbers, bools, chars and functions
+    #[arg(long)]
+    native: bool,
+
+    /// How much to optimize the program, from 0, which keeps the bytecode
+    /// and MIR close to the source when debugging, to 3, which inlines bigger
+    /// functions
+    #[arg(
+        short = 'O',
+        value_name = "LEVEL",
+        default_value_t = 2,
+        value_parser = clap::value_parser!(u8).range(..=3)
+    )]
+    opt_level: u8,
+
+    /// Don't fold operations on constants into their result, even at an
+    /// optimization level that does
+    #[arg(long)]
+    no_fold: bool,
+
+    /// Inline calls to functions with at most this many MIR statements and
+    /// blocks when compiling through the MIR, or none if it's 0, instead of
+    /// the optimization level's size
+    #[arg(long, value_name = "SIZE")]
+    inline_threshold: Option<usize>,
+
+    /// Report the variables and values that are removed for never being used
+    /// when compiling through the MIR
+    #[arg(long)]
+    verbose: bool,
+
+    /// Print the compiled bytecode instead of running the program, or the
+    /// register VM's code with `--registers`
+    #[arg(long)]
+    bytecode: bool,
+
+    /// Print the mid-level IR that the WebAssembly, C and native backends
+    /// compile instead of running the program
+    #[arg(long)]
+    mir: bool,
+
+    /// Put the functions in SSA form before printing them with `--mir`, so
+    /// every local is assigned in one place and phis pick between the ones
+    /// that reach the same block
+    #[arg(long, requires = "mir")]
+    ssa: bool,
+
+    /// Compile the program to a WebAssembly module at this path instead of
+    /// running it, which `runtime/foxglove.js` can run
+    #[arg(long, value_name = "PATH")]
+    wasm: Option<PathBuf>,
+
+    /// Translate the program to a C99 source file at this path instead of
+    /// running it
+    #[arg(long, value_name = "PATH")]
+    c: Option<PathBuf>,
 }
 
-fn run<P: AsRef<Path>>(filename: P) -> Result<(), Box<dyn std::error::Error>> {
-    let input = read_to_string(filename)?;
+#[derive(Subcommand)]
+enum Command {
+    /// Compile a program to bytecode and write it to a `.fxc` file, which
+    /// `run` can run without parsing or typechecking it again
+    Build {
+        filename: PathBuf,
+
+        /// Where to write the bytecode, instead of next to the program with
+        /// the extension `.fxc`
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Run a `.fxc` file written by `build` on the bytecode VM
+    Run {
+        filename: PathBuf,
+
+        /// Stop when this many functions, closures and borrowed variables
+        /// are in use at once
+        #[arg(long, value_name = "OBJECTS")]
+        heap_limit: Option<usize>,
 
-    let (tokens, lex_errs) = lexer::lexer().parse(&input).into_output_errors();
+        /// Stop when a call would make this many calls run at once
+        #[arg(long, value_name = "CALLS")]
+        max_depth: Option<usize>,
+
+        /// Stop after running this many instructions
+        #[arg(long, value_name = "STEPS")]
+        fuel: Option<u64>,
+    },
+}
 
-    // dbg!(&tokens);
+fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let filename = match &args.command {
+        Some(Command::Build { filename, .. }) => filename,
+        Some(Command::Run {
+            filename,
+            heap_limit,
+            max_depth,
+            fuel,
+        }) => {
+            let limits = Limits {
+                depth: *max_depth,
+                fuel: *fuel,
+                heap: *heap_limit,
+            };
 
-    let (ast, parse_errs) = if let Some(tokens) = &tokens {
-        parser::parser()
-            .parse(tokens.spanned((input.len()..input.len()).into()))
-            .into_output_errors()
-    } else {
-        (None, vec![])
+            return run_compiled(filename, limits);
+        }
+        None => args.filename.as_ref().unwrap(),
     };
 
+    let mut loader = loader::Loader::new();
+
+    let ast = loader.load_main(filename)?;
+
     // dbg!(&ast);
 
-    let (typed_ast, tc_errs) = if let Some(ast) = ast {
-        match typecheck::typecheck(ast) {
-            Ok(typed_ast) => (Some(typed_ast), vec![]),
-            Err(tc_errs) => (None, vec![tc_errs]),
+    let (typed_ast, tc_warnings, tc_errs) = match ast {
+        Some(ast) if loader.errors.is_empty() => {
+            match typecheck::typecheck(ast, args.warn_shadowing, args.explain_types) {
+                Ok((typed_ast, warnings)) => (Some(typed_ast), warnings, vec![]),
+                Err(tc_errs) => (None, vec![], tc_errs),
+            }
         }
-    } else {
-        (None, vec![])
+        _ => (None, vec![], vec![]),
     };
 
     // dbg!(&typed_ast);
 
-    if let Some(typed_ast) = typed_ast {
-        interpreter::interpret(typed_ast)?;
+    // the optimizer's notes don't repeat what the typechecker already said
+    let unread = tc_warnings
+        .iter()
+        .filter_map(|warning| match warning {
+            error::Warning::UnreadBinding { span, .. } => Some(*span),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    for warning in tc_warnings {
+        let kind = match warning {
+            error::Warning::InferredType { .. } => ReportKind::Advice,
+            _ => ReportKind::Warning,
+        };
+
+        print_report(kind, warning.code(), warning.make_report(), &loader.sources);
     }
 
-    Vec::new()
-        .into_iter()
-        .chain(
-            lex_errs
-                .into_iter()
-                .map(|e| e.map_token(|t| t.to_string()))
-                .map(Into::into),
-        )
-        .chain(
-            parse_errs
-                .into_iter()
-                .map(|e| e.map_token(|t| t.to_string()))
-                .map(Into::into),
-        )
-        .chain(tc_errs)
-        .for_each(|e| {
-            for (msg, spans, notes) in e.make_report() {
-                let mut report =
-                    Report::build(ReportKind::Error, (), spans.first().unwrap().start())
-                        .with_code(e.code())
-                        .with_message(msg);
-
-                for ((msg, col), span) in spans {
-                    report = report.with_label(
-                        Label::new(span.into_range())
-                            .with_message(msg)
-                            .with_color(col),
+    loader.errors.into_iter().chain(tc_errs).for_each(|e| {
+        for report in e.make_report() {
+            print_report(ReportKind::Error, e.code(), report, &loader.sources);
+        }
+    });
+
+    if let Some(typed_ast) = typed_ast {
+        let mut options = optimize::Options::level(args.opt_level);
+
+        options.fold &= !args.no_fold;
+        options.inline_threshold = args.inline_threshold.unwrap_or(options.inline_threshold);
+
+        let fold = |ast| {
+            if options.fold {
+                fold::fold(ast)
+            } else {
+                ast
+            }
+        };
+
+        let compile = |ast| {
+            let mut program = bytecode::compile(fold(ast));
+
+            if options.peephole {
+                peephole::optimize(&mut program);
+            }
+
+            program
+        };
+
+        // the WebAssembly, C and native backends compile the MIR, which is
+        // optimized first
+        let lower = |ast, target| {
+            let mut program = mir::lower(fold(ast), target)?;
+            let unused = optimize::optimize(&mut program, options);
+
+            if args.verbose {
+                for note in unused.into_iter().filter(|note| {
+                    !matches!(note, error::Warning::UnusedVariable { span, .. } if unread.contains(span))
+                }) {
+                    print_report(
+                        ReportKind::Advice,
+                        note.code(),
+                        note.make_report(),
+                        &loader.sources,
                     );
                 }
+            }
+
+            Ok::<_, error::Error>(program)
+        };
+
+        if let Some(Command::Build { output, .. }) = &args.command {
+            let path = output
+                .clone()
+                .unwrap_or_else(|| filename.with_extension("fxc"));
 
-                for note in notes {
-                    report = report.with_note(note);
+            std::fs::write(path, fxc::write(&compile(typed_ast), &loader.sources))?;
+        } else if args.json_ast {
+            println!("{:#}", json::ast_to_json(&typed_ast));
+        } else if let Some(offset) = args.type_at {
+            match typed_ast.0.type_at(offset) {
+                Some((ty, _)) => println!("{}", ty),
+                None => return Err(format!("nothing has a type at offset {}", offset).into()),
+            }
+        } else if args.bytecode && args.registers {
+            match lower(typed_ast, "run on the register VM") {
+                Ok(program) => print!("{}", registers::compile(&program)),
+                Err(e) => exit_with(e, &loader.sources),
+            }
+        } else if args.bytecode {
+            print!("{}", compile(typed_ast));
+        } else if args.mir {
+            match lower(typed_ast, "lowered to MIR") {
+                Ok(mut program) => {
+                    if args.ssa {
+                        for function in &mut program.functions {
+                            ssa::construct(function);
+
+                            if let Err(e) = ssa::verify(function) {
+                                return Err(format!(
+                                    "'{}' isn't in SSA form: {}",
+                                    function.name, e
+                                )
+                                .into());
+                            }
+                        }
+                    }
+
+                    print!("{}", program);
                 }
+                Err(e) => exit_with(e, &loader.sources),
+            }
+        } else if let Some(path) = &args.wasm {
+            match lower(typed_ast, "compiled to WebAssembly") {
+                Ok(program) => std::fs::write(path, wasm::compile(&program))?,
+                Err(e) => exit_with(e, &loader.sources),
+            }
+        } else if let Some(path) = &args.c {
+            match lower(typed_ast, "translated to C") {
+                Ok(program) => std::fs::write(path, c::compile(&program))?,
+                Err(e) => exit_with(e, &loader.sources),
+            }
+        } else {
+            let limits = Limits {
+                depth: args.max_depth,
+                fuel: args.fuel,
+                heap: args.heap_limit,
+            };
 
-                report.finish().eprint(Source::from(&input)).unwrap();
+            let result = if args.vm {
+                vm::run(&compile(typed_ast), limits)
+            } else if args.registers {
+                lower(typed_ast, "run on the register VM")
+                    .and_then(|program| registers::run(&registers::compile(&program)))
+            } else if args.native {
+                let span = typed_ast.1;
+
+                lower(typed_ast, "compiled to native code")
+                    .and_then(|program| native::run(&program, span))
+            } else {
+                interpreter::interpret(fold(typed_ast), limits)
+            };
+
+            if let Err(e) = result {
+                exit_with(e, &loader.sources);
             }
-        });
+        }
+    }
 
     Ok(())
 }
 
+/// Runs a program that `build` compiled, showing runtime errors with the
+/// sources it kept
+fn run_compiled(path: &Path, limits: Limits) -> Result<(), Box<dyn std::error::Error>> {
+    let (program, sources) =
+        fxc::read(&std::fs::read(path)?).map_err(|e| format!("'{}' {}", path.display(), e))?;
+
+    if let Err(e) = vm::run(&program, limits) {
+        exit_with(e, &sources);
+    }
+
+    Ok(())
+}
+
+/// Shows an error that stopped the program and exits
+fn exit_with(e: error::Error, sources: &SourceMap) -> ! {
+    for report in e.make_report() {
+        print_report(ReportKind::Error, e.code(), report, sources);
+    }
+
+    std::process::exit(1);
+}
+
+fn print_report(
+    kind: ReportKind,
+    code: u32,
+    (msg, spans, notes): (Message, Spans, Notes),
+    sources: &SourceMap,
+) {
+    let (file, range) = sources.locate(spans.first().unwrap().1);
+
+    let mut report = Report::build(kind, file, range.start)
+        .with_code(code)
+        .with_message(msg);
+
+    for ((msg, col), span) in spans {
+        report = report.with_label(
+            Label::new(sources.locate(span))
+                .with_message(msg)
+                .with_color(col),
+        );
+    }
+
+    for note in notes {
+        report = report.with_note(note);
+    }
+
+    report
+        .finish()
+        .eprint(ariadne::sources(sources.sources()))
+        .unwrap();
+}
+
 pub type Span = SimpleSpan<usize>;
 
 pub type Spanned<T> = (T, Span);
@@ -0,0 +1,1358 @@
+use crate::error::Error;
+use crate::prelude::Builtin;
+use crate::typed_ast::{
+    self, source_name, BinOp, Expr, ExprKind, FormatSpec, Literal, PrefixOp, Type, TypedAst,
+};
+use crate::{Span, Spanned};
+use std::collections::{HashMap, HashSet};
+
+/// A program lowered to functions made of basic blocks, which the backends
+/// that compile to another language translate. Loops, ifs, labeled blocks
+/// and the short-circuiting operators are all jumps between blocks, and every
+/// operation takes operands that are locals or constants, so the order things
+/// are evaluated in is explicit.
+///
+/// Only ints, floats, bools, chars and unit values are supported, along with
+/// functions that don't capture anything and printing. Unit values don't
+/// exist here at all.
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    /// The first function is the top level code
+    pub functions: Vec<Function>,
+    pub globals: Vec<Global>,
+}
+
+/// A top level variable, which has a flag that's set once it's initialized
+/// as well as its value
+#[derive(Clone, Debug)]
+pub struct Global {
+    pub name: String,
+    pub span: Span,
+    /// `None` if it's a unit value
+    pub ty: Option<Ty>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub name: String,
+    /// How many of the first locals are params
+    pub params: usize,
+    pub locals: Vec<LocalDecl>,
+    pub returns: Option<Ty>,
+    /// The first block is where the function starts
+    pub blocks: Vec<BasicBlock>,
+}
+
+#[derive(Clone, Debug)]
+pub struct LocalDecl {
+    pub ty: Ty,
+    /// The variable it holds, or `None` for a temporary
+    pub name: Option<Spanned<String>>,
+}
+
+pub type Local = usize;
+
+pub type BlockId = usize;
+
+pub type GlobalId = usize;
+
+pub type FunctionId = usize;
+
+#[derive(Clone, Debug)]
+pub struct BasicBlock {
+    /// Always empty unless the function is in SSA form, which the backends
+    /// don't take
+    pub phis: Vec<Phi>,
+    pub statements: Vec<Statement>,
+    pub terminator: Terminator,
+}
+
+/// Assigns a local the value from whichever block jumped to the one the phi
+/// is in, as if it was assigned at the end of that block
+#[derive(Clone, Debug)]
+pub struct Phi {
+    pub local: Local,
+    /// The value for each block that jumps here, once for each jump
+    pub sources: Vec<(BlockId, Operand)>,
+}
+
+/// The types of the values that exist at runtime
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Ty {
+    Int,
+    Float,
+    Bool,
+    Char,
+}
+
+impl Ty {
+    pub fn to_type(self) -> Type {
+        match self {
+            Ty::Int => Type::Int,
+            Ty::Float => Type::Float,
+            Ty::Bool => Type::Bool,
+            Ty::Char => Type::Char,
+        }
+    }
+}
+
+impl std::fmt::Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_type().fmt(f)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Const {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Local(Local),
+    Const(Const),
+}
+
+#[derive(Clone, Debug)]
+pub enum Rvalue {
+    Use(Operand),
+    /// A prefix operator on an operand of the given type
+    Prefix(PrefixOp, Ty, Operand, Span),
+    /// An operator other than the short-circuiting ones, on operands of the
+    /// given type
+    Binary(BinOp, Ty, Operand, Operand, Span),
+    /// A cast between two different types
+    Cast(Ty, Ty, Operand, Span),
+    /// The value of a top level variable
+    Global(GlobalId),
+    Call(FunctionId, Vec<Operand>, Span),
+    /// The counter `tick()` returns, which goes up by one every time
+    Tick,
+}
+
+#[derive(Clone, Debug)]
+pub enum Statement {
+    Assign(Local, Rvalue),
+    /// Evaluates a value that isn't used, for what it does
+    Eval(Rvalue),
+    SetGlobal(GlobalId, Operand),
+    /// Marks a top level variable as initialized
+    Initialize(GlobalId),
+    /// Fails if a top level variable isn't initialized yet
+    CheckInitialized(GlobalId, Span),
+    /// Writes text to stdout
+    Print(Vec<Piece>),
+    /// Shows what's been written without a newline
+    Flush,
+}
+
+/// Part of the text a print writes
+#[derive(Clone, Debug)]
+pub enum Piece {
+    Str(String),
+    /// A value, with its type and how it's shown
+    Value(Operand, Ty, Option<FormatSpec>),
+}
+
+#[derive(Clone, Debug)]
+pub enum Terminator {
+    Goto(BlockId),
+    /// Goes to the first block if the operand is true and to the second one
+    /// otherwise
+    Branch(Operand, BlockId, BlockId),
+    Return(Option<Operand>),
+    /// The end of a function that returns a value, which the typechecker
+    /// makes sure can't be reached
+    Unreachable,
+}
+
+impl Function {
+    /// Removes the blocks that can't be reached from the first one, and puts
+    /// the rest in the order they first appear in `order`
+    pub fn remove_unreachable_blocks(&mut self, order: Vec<BlockId>) {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = vec![0];
+
+        while let Some(block) = stack.pop() {
+            if !std::mem::replace(&mut reachable[block], true) {
+                stack.extend(self.blocks[block].terminator.successors());
+            }
+        }
+
+        let mut ids = vec![None; self.blocks.len()];
+        let mut kept = vec![];
+
+        for block in order {
+            if reachable[block] && ids[block].is_none() {
+                ids[block] = Some(kept.len());
+                kept.push(block);
+            }
+        }
+
+        let mut blocks = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let id = |block: BlockId| ids[block].unwrap();
+
+        self.blocks = kept
+            .into_iter()
+            .map(|block| {
+                let mut block = blocks[block].take().unwrap();
+
+                block.terminator = match block.terminator {
+                    Terminator::Goto(target) => Terminator::Goto(id(target)),
+                    Terminator::Branch(cond, then, else_) => {
+                        Terminator::Branch(cond, id(then), id(else_))
+                    }
+                    terminator => terminator,
+                };
+
+                block
+            })
+            .collect();
+
+        for block in &mut self.blocks {
+            for phi in &mut block.phis {
+                phi.sources.retain(|(source, _)| ids[*source].is_some());
+
+                for (source, _) in &mut phi.sources {
+                    *source = id(*source);
+                }
+            }
+        }
+    }
+
+    /// Works out which locals are read after the start of each block before
+    /// they're assigned again, by going over the blocks until nothing changes.
+    /// What a phi picks from a block counts as read at the end of that block.
+    pub fn live_on_entry(&self) -> Vec<HashSet<Local>> {
+        let mut live_on_entry = vec![HashSet::new(); self.blocks.len()];
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for (index, block) in self.blocks.iter().enumerate().rev() {
+                let mut live = HashSet::new();
+
+                for successor in block.terminator.successors() {
+                    live.extend(&live_on_entry[successor]);
+
+                    for phi in &self.blocks[successor].phis {
+                        for (_, value) in phi.sources.iter().filter(|(from, _)| *from == index) {
+                            if let Operand::Local(local) = value {
+                                live.insert(*local);
+                            }
+                        }
+                    }
+                }
+
+                if let Some(Operand::Local(local)) = block.terminator.operand() {
+                    live.insert(*local);
+                }
+
+                for statement in block.statements.iter().rev() {
+                    if let Statement::Assign(local, _) = statement {
+                        live.remove(local);
+                    }
+
+                    statement.operands(&mut |operand| {
+                        if let Operand::Local(local) = operand {
+                            live.insert(*local);
+                        }
+                    });
+                }
+
+                for phi in &block.phis {
+                    live.remove(&phi.local);
+                }
+
+                // what's live only ever grows, so it's changed if there's more
+                if live.len() != live_on_entry[index].len() {
+                    live_on_entry[index] = live;
+                    changed = true;
+                }
+            }
+        }
+
+        live_on_entry
+    }
+}
+
+impl Rvalue {
+    /// Calls `f` on every operand the value reads
+    pub fn operands(&self, f: &mut impl FnMut(&Operand)) {
+        match self {
+            Rvalue::Use(operand)
+            | Rvalue::Prefix(_, _, operand, _)
+            | Rvalue::Cast(_, _, operand, _) => f(operand),
+            Rvalue::Binary(_, _, lhs, rhs, _) => {
+                f(lhs);
+                f(rhs);
+            }
+            Rvalue::Call(_, args, _) => args.iter().for_each(f),
+            Rvalue::Global(_) | Rvalue::Tick => {}
+        }
+    }
+
+    pub fn operands_mut(&mut self, f: &mut impl FnMut(&mut Operand)) {
+        match self {
+            Rvalue::Use(operand)
+            | Rvalue::Prefix(_, _, operand, _)
+            | Rvalue::Cast(_, _, operand, _) => f(operand),
+            Rvalue::Binary(_, _, lhs, rhs, _) => {
+                f(lhs);
+                f(rhs);
+            }
+            Rvalue::Call(_, args, _) => args.iter_mut().for_each(f),
+            Rvalue::Global(_) | Rvalue::Tick => {}
+        }
+    }
+}
+
+impl Statement {
+    /// Calls `f` on every operand the statement reads
+    pub fn operands(&self, f: &mut impl FnMut(&Operand)) {
+        match self {
+            Statement::Assign(_, value) | Statement::Eval(value) => value.operands(f),
+            Statement::SetGlobal(_, operand) => f(operand),
+            Statement::Print(pieces) => {
+                for piece in pieces {
+                    if let Piece::Value(operand, _, _) = piece {
+                        f(operand);
+                    }
+                }
+            }
+            Statement::Initialize(_) | Statement::CheckInitialized(..) | Statement::Flush => {}
+        }
+    }
+
+    pub fn operands_mut(&mut self, f: &mut impl FnMut(&mut Operand)) {
+        match self {
+            Statement::Assign(_, value) | Statement::Eval(value) => value.operands_mut(f),
+            Statement::SetGlobal(_, operand) => f(operand),
+            Statement::Print(pieces) => {
+                for piece in pieces {
+                    if let Piece::Value(operand, _, _) = piece {
+                        f(operand);
+                    }
+                }
+            }
+            Statement::Initialize(_) | Statement::CheckInitialized(..) | Statement::Flush => {}
+        }
+    }
+}
+
+impl Terminator {
+    /// The operand a branch or return reads
+    pub fn operand(&self) -> Option<&Operand> {
+        match self {
+            Terminator::Branch(operand, _, _) | Terminator::Return(Some(operand)) => Some(operand),
+            Terminator::Goto(_) | Terminator::Return(None) | Terminator::Unreachable => None,
+        }
+    }
+
+    pub fn operand_mut(&mut self) -> Option<&mut Operand> {
+        match self {
+            Terminator::Branch(operand, _, _) | Terminator::Return(Some(operand)) => Some(operand),
+            Terminator::Goto(_) | Terminator::Return(None) | Terminator::Unreachable => None,
+        }
+    }
+
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Goto(target) => vec![*target],
+            Terminator::Branch(_, then, else_) => vec![*then, *else_],
+            Terminator::Return(_) | Terminator::Unreachable => vec![],
+        }
+    }
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, global) in self.globals.iter().enumerate() {
+            match global.ty {
+                Some(ty) => writeln!(f, "global g{} '{}': {}", i, global.name, ty)?,
+                None => writeln!(f, "global g{} '{}'", i, global.name)?,
+            }
+        }
+
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 || !self.globals.is_empty() {
+                writeln!(f)?;
+            }
+
+            write!(
+                f,
+                "function f{} '{}' ({} params)",
+                i, function.name, function.params
+            )?;
+
+            match function.returns {
+                Some(ty) => writeln!(f, ": {}", ty)?,
+                None => writeln!(f, ":")?,
+            }
+
+            for (i, local) in function.locals.iter().enumerate() {
+                match &local.name {
+                    Some(name) => writeln!(f, "    let _{}: {} '{}'", i, local.ty, name.0)?,
+                    None => writeln!(f, "    let _{}: {}", i, local.ty)?,
+                }
+            }
+
+            for (i, block) in function.blocks.iter().enumerate() {
+                writeln!(f, "  bb{}:", i)?;
+
+                for phi in &block.phis {
+                    write!(f, "    _{} = phi(", phi.local)?;
+
+                    for (i, (block, value)) in phi.sources.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+
+                        write!(f, "bb{}: {}", block, value)?;
+                    }
+
+                    writeln!(f, ")")?;
+                }
+
+                for statement in &block.statements {
+                    writeln!(f, "    {}", statement)?;
+                }
+
+                writeln!(f, "    {}", block.terminator)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operand::Local(local) => write!(f, "_{}", local),
+            Operand::Const(Const::Int(n)) => write!(f, "{}", n),
+            Operand::Const(Const::Float(n)) => write!(f, "{:?}", n),
+            Operand::Const(Const::Bool(b)) => write!(f, "{}", b),
+            Operand::Const(Const::Char(c)) => write!(f, "{:?}", c),
+        }
+    }
+}
+
+impl std::fmt::Display for Rvalue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rvalue::Use(operand) => write!(f, "{}", operand),
+            Rvalue::Prefix(op, _, operand, _) => write!(f, "{}{}", op, operand),
+            Rvalue::Binary(op, _, lhs, rhs, _) => write!(f, "{} {} {}", lhs, op, rhs),
+            Rvalue::Cast(_, to, operand, _) => write!(f, "{} as {}", operand, to),
+            Rvalue::Global(global) => write!(f, "g{}", global),
+            Rvalue::Call(function, args, _) => {
+                write!(f, "f{}(", function)?;
+
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}", arg)?;
+                }
+
+                write!(f, ")")
+            }
+            Rvalue::Tick => write!(f, "tick()"),
+        }
+    }
+}
+
+impl std::fmt::Display for Statement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Statement::Assign(local, value) => write!(f, "_{} = {}", local, value),
+            Statement::Eval(value) => write!(f, "{}", value),
+            Statement::SetGlobal(global, value) => write!(f, "g{} = {}", global, value),
+            Statement::Initialize(global) => write!(f, "initialize g{}", global),
+            Statement::CheckInitialized(global, _) => write!(f, "check g{}", global),
+            Statement::Print(pieces) => {
+                write!(f, "print")?;
+
+                for piece in pieces {
+                    match piece {
+                        Piece::Str(text) => write!(f, " {:?}", text)?,
+                        Piece::Value(value, _, None) => write!(f, " {}", value)?,
+                        Piece::Value(value, _, Some(spec)) => write!(f, " {} {}", value, spec)?,
+                    }
+                }
+
+                Ok(())
+            }
+            Statement::Flush => write!(f, "flush"),
+        }
+    }
+}
+
+impl std::fmt::Display for Terminator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Terminator::Goto(target) => write!(f, "goto bb{}", target),
+            Terminator::Branch(cond, then, else_) => {
+                write!(f, "if {} goto bb{} else bb{}", cond, then, else_)
+            }
+            Terminator::Return(Some(value)) => write!(f, "return {}", value),
+            Terminator::Return(None) => write!(f, "return"),
+            Terminator::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+/// Lowers a typechecked program. `target` says what it's being lowered for
+/// in the error for something that isn't supported, like "translated to C".
+pub fn lower(ast: Spanned<TypedAst>, target: &'static str) -> Result<Program, Error> {
+    let mut lowerer = Lowerer {
+        program: Program::default(),
+        target,
+        scopes: vec![HashMap::new()],
+        builders: vec![],
+    };
+
+    let main = lowerer.declare_function("<main>".to_string(), vec![], None);
+
+    lowerer.builders.push(Builder::new(main, vec![]));
+    lowerer.declare_globals(&ast.0.statements.0)?;
+    lowerer.lower_statements(ast.0.statements.0)?;
+    lowerer.terminate(Terminator::Return(None));
+    lowerer.finish_function();
+
+    Ok(lowerer.program)
+}
+
+struct Lowerer<'src> {
+    program: Program,
+    target: &'static str,
+    scopes: Vec<HashMap<&'src str, Binding>>,
+    /// The functions being lowered, innermost last, where the first one is
+    /// the top level code
+    builders: Vec<Builder<'src>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Binding {
+    /// A param or local variable, which has no local if it's a unit value
+    Local(Option<Local>),
+    Global(GlobalId),
+    Function(FunctionId),
+}
+
+struct Builder<'src> {
+    function: FunctionId,
+    locals: Vec<LocalDecl>,
+    blocks: Vec<BasicBlock>,
+    /// The block statements are added to
+    current: BlockId,
+    /// The blocks in the order their code was started in, which is the order
+    /// they end up in
+    order: Vec<BlockId>,
+    /// The enclosing loops and labeled blocks, innermost last
+    labels: Vec<Label<'src>>,
+}
+
+struct Label<'src> {
+    /// The label of a labeled block, or `None` for a loop
+    name: Option<&'src str>,
+    /// The local a break's value is stored in
+    result: Option<Local>,
+    end: BlockId,
+    /// Where `continue` goes, if it's a loop
+    start: Option<BlockId>,
+}
+
+impl Builder<'_> {
+    fn new(function: FunctionId, locals: Vec<LocalDecl>) -> Self {
+        Builder {
+            function,
+            locals,
+            blocks: vec![BasicBlock {
+                phis: vec![],
+                statements: vec![],
+                terminator: Terminator::Unreachable,
+            }],
+            current: 0,
+            order: vec![0],
+            labels: vec![],
+        }
+    }
+}
+
+/// Whether an operator other than the short-circuiting ones can be used on
+/// operands of a type
+fn supported(op: BinOp, ty: Ty) -> bool {
+    matches!(
+        (ty, op),
+        (
+            _,
+            BinOp::Equals
+                | BinOp::NotEquals
+                | BinOp::LessThan
+                | BinOp::LessThanOrEqual
+                | BinOp::GreaterThan
+                | BinOp::GreaterThanOrEqual,
+        ) | (
+            Ty::Int,
+            BinOp::Add
+                | BinOp::Subtract
+                | BinOp::Multiply
+                | BinOp::Divide
+                | BinOp::Modulo
+                | BinOp::Power
+                | BinOp::BitAnd
+                | BinOp::BitOr
+                | BinOp::BitXor
+                | BinOp::ShiftLeft
+                | BinOp::ShiftRight,
+        ) | (
+            Ty::Float,
+            BinOp::Add
+                | BinOp::Subtract
+                | BinOp::Multiply
+                | BinOp::Divide
+                | BinOp::Modulo
+                | BinOp::Power,
+        )
+    )
+}
+
+impl<'src> Lowerer<'src> {
+    fn builder(&mut self) -> &mut Builder<'src> {
+        self.builders.last_mut().unwrap()
+    }
+
+    fn unsupported(&self, span: Span, what: &str) -> Error {
+        Error::Custom(
+            span,
+            format!("Compile error: {} can't be {} yet", what, self.target),
+        )
+    }
+
+    /// The type of the values of a type at runtime, where unit values don't
+    /// exist
+    fn ty(&self, ty: &Type, span: Span) -> Result<Option<Ty>, Error> {
+        match ty {
+            Type::Int => Ok(Some(Ty::Int)),
+            Type::Float => Ok(Some(Ty::Float)),
+            Type::Bool => Ok(Some(Ty::Bool)),
+            Type::Char => Ok(Some(Ty::Char)),
+            Type::Unit => Ok(None),
+            _ => Err(self.unsupported(span, &format!("values of type '{}'", ty))),
+        }
+    }
+
+    fn push(&mut self, statement: Statement) {
+        let builder = self.builder();
+
+        builder.blocks[builder.current].statements.push(statement);
+    }
+
+    fn local(&mut self, ty: Ty, name: Option<Spanned<&str>>) -> Local {
+        let builder = self.builder();
+
+        builder.locals.push(LocalDecl {
+            ty,
+            name: name.map(|name| (source_name(name.0).to_string(), name.1)),
+        });
+
+        builder.locals.len() - 1
+    }
+
+    /// Stores a value in a new local
+    fn temp(&mut self, ty: Ty, value: Rvalue) -> Operand {
+        let local = self.local(ty, None);
+
+        self.push(Statement::Assign(local, value));
+
+        Operand::Local(local)
+    }
+
+    fn assign(&mut self, local: Option<Local>, value: Option<Operand>) {
+        if let (Some(local), Some(value)) = (local, value) {
+            self.push(Statement::Assign(local, Rvalue::Use(value)));
+        }
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        let builder = self.builder();
+
+        builder.blocks.push(BasicBlock {
+            phis: vec![],
+            statements: vec![],
+            terminator: Terminator::Unreachable,
+        });
+
+        builder.blocks.len() - 1
+    }
+
+    /// Ends the current block
+    fn terminate(&mut self, terminator: Terminator) {
+        let builder = self.builder();
+
+        builder.blocks[builder.current].terminator = terminator;
+    }
+
+    /// Adds the statements that come next to a block
+    fn switch_to(&mut self, block: BlockId) {
+        let builder = self.builder();
+
+        builder.current = block;
+        builder.order.push(block);
+    }
+
+    fn goto(&mut self, target: BlockId) {
+        self.terminate(Terminator::Goto(target));
+    }
+
+    /// Ends the current block, and adds anything after it to a block nothing
+    /// jumps to, which `finish_function` removes
+    fn diverge(&mut self, terminator: Terminator) {
+        self.terminate(terminator);
+
+        let next = self.new_block();
+
+        self.switch_to(next);
+    }
+
+    fn declare_function(
+        &mut self,
+        name: String,
+        locals: Vec<LocalDecl>,
+        returns: Option<Ty>,
+    ) -> FunctionId {
+        self.program.functions.push(Function {
+            name,
+            params: locals.len(),
+            locals,
+            returns,
+            blocks: vec![],
+        });
+
+        self.program.functions.len() - 1
+    }
+
+    /// Ends the innermost function, putting its blocks in order and leaving
+    /// out the ones that can't be reached
+    fn finish_function(&mut self) {
+        let builder = self.builders.pop().unwrap();
+        let function = &mut self.program.functions[builder.function];
+
+        function.locals = builder.locals;
+        function.blocks = builder.blocks;
+        function.remove_unreachable_blocks(builder.order);
+    }
+
+    fn top_level(&self) -> bool {
+        self.builders.len() == 1 && self.scopes.len() == 1
+    }
+
+    /// Declares every top level variable ahead of time, since functions can
+    /// use them before they're declared
+    fn declare_globals(
+        &mut self,
+        statements: &[Spanned<typed_ast::Statement<'src>>],
+    ) -> Result<(), Error> {
+        for statement in statements {
+            if let typed_ast::Statement::Let { name, ty, .. } = &statement.0 {
+                let ty = self.ty(&ty.0, ty.1)?;
+
+                self.program.globals.push(Global {
+                    name: source_name(name.0).to_string(),
+                    span: name.1,
+                    ty,
+                });
+
+                self.scopes[0].insert(name.0, Binding::Global(self.program.globals.len() - 1));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve(&self, name: &str) -> Binding {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .copied()
+            .unwrap()
+    }
+
+    /// Lowers the statements of a block, declaring the functions in it first
+    /// so they can be called before they're defined
+    fn lower_statements(
+        &mut self,
+        statements: Vec<Spanned<typed_ast::Statement<'src>>>,
+    ) -> Result<(), Error> {
+        for statement in &statements {
+            if let typed_ast::Statement::Function {
+                name,
+                generics,
+                params,
+                return_ty,
+                captures,
+                ..
+            } = &statement.0
+            {
+                if !generics.is_empty() {
+                    return Err(self.unsupported(name.1, "generic functions"));
+                }
+
+                if let Some(capture) = captures.first() {
+                    return Err(self.unsupported(capture.1, "functions that capture variables"));
+                }
+
+                let mut locals = vec![];
+
+                for (param, ty) in &params.0 {
+                    if let Some(ty) = self.ty(&ty.0, ty.1)? {
+                        locals.push(LocalDecl {
+                            ty,
+                            name: Some((source_name(param.0).to_string(), param.1)),
+                        });
+                    }
+                }
+
+                let returns = self.ty(&return_ty.0, return_ty.1)?;
+                let function =
+                    self.declare_function(source_name(name.0).to_string(), locals, returns);
+
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert(name.0, Binding::Function(function));
+            }
+        }
+
+        for statement in statements {
+            self.lower_statement(statement)?;
+        }
+
+        Ok(())
+    }
+
+    fn lower_statement(
+        &mut self,
+        statement: Spanned<typed_ast::Statement<'src>>,
+    ) -> Result<(), Error> {
+        match statement.0 {
+            typed_ast::Statement::Expr(expr) => {
+                self.lower_expr(expr)?;
+            }
+            typed_ast::Statement::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                self.lower_statements(statements.0)?;
+                self.scopes.pop();
+            }
+            typed_ast::Statement::Let {
+                name, ty, value, ..
+            } => {
+                let value = self.lower_expr(value)?;
+
+                if self.top_level() {
+                    let Binding::Global(global) = self.resolve(name.0) else {
+                        unreachable!()
+                    };
+
+                    if let Some(value) = value {
+                        self.push(Statement::SetGlobal(global, value));
+                    }
+
+                    self.push(Statement::Initialize(global));
+                } else {
+                    let local = self.ty(&ty.0, ty.1)?.map(|ty| self.local(ty, Some(name)));
+
+                    self.assign(local, value);
+
+                    self.scopes
+                        .last_mut()
+                        .unwrap()
+                        .insert(name.0, Binding::Local(local));
+                }
+            }
+            typed_ast::Statement::Function {
+                name, params, body, ..
+            } => {
+                let Binding::Function(function) = self.resolve(name.0) else {
+                    unreachable!()
+                };
+
+                let mut scope = HashMap::new();
+                let mut count = 0;
+
+                for (param, ty) in params.0 {
+                    let local = self.ty(&ty.0, ty.1)?.map(|_| {
+                        count += 1;
+                        count - 1
+                    });
+
+                    scope.insert(param.0, Binding::Local(local));
+                }
+
+                let locals = self.program.functions[function].locals.clone();
+
+                self.builders.push(Builder::new(function, locals));
+                self.scopes.push(scope);
+
+                // the body's statements go straight into the function's scope
+                match *body {
+                    (typed_ast::Statement::Block(statements), _) => {
+                        self.lower_statements(statements.0)?
+                    }
+                    body => self.lower_statement(body)?,
+                }
+
+                // the typechecker makes sure a function that returns a value
+                // can't reach its end
+                if self.program.functions[function].returns.is_some() {
+                    self.terminate(Terminator::Unreachable);
+                } else {
+                    self.terminate(Terminator::Return(None));
+                }
+
+                self.scopes.pop();
+                self.finish_function();
+            }
+            typed_ast::Statement::Assign { name, value } => {
+                let value = self.lower_expr(value)?;
+
+                match (self.resolve(name.0), value) {
+                    (Binding::Local(local), value) => self.assign(local, value),
+                    (Binding::Global(global), Some(value)) => {
+                        self.push(Statement::SetGlobal(global, value))
+                    }
+                    _ => {}
+                }
+            }
+            typed_ast::Statement::Return(expr) => {
+                let value = self.lower_expr(expr)?;
+
+                self.diverge(Terminator::Return(value));
+            }
+            typed_ast::Statement::Break { label, value } => {
+                let value = self.lower_expr(value)?;
+                let name = label.map(|label| label.0);
+
+                let target = self
+                    .builder()
+                    .labels
+                    .iter()
+                    .rev()
+                    .find(|other| other.name == name)
+                    .unwrap();
+                let (result, end) = (target.result, target.end);
+
+                self.assign(result, value);
+                self.diverge(Terminator::Goto(end));
+            }
+            typed_ast::Statement::Continue => {
+                let start = self
+                    .builder()
+                    .labels
+                    .iter()
+                    .rev()
+                    .find_map(|label| label.start)
+                    .unwrap();
+
+                self.diverge(Terminator::Goto(start));
+            }
+            typed_ast::Statement::While { cond, body } => {
+                let start = self.new_block();
+                let body_block = self.new_block();
+                let end = self.new_block();
+
+                // the condition is evaluated again on every iteration, and
+                // `continue` goes back to it
+                self.goto(start);
+                self.switch_to(start);
+
+                let cond = self.lower_expr(cond)?.unwrap();
+
+                self.terminate(Terminator::Branch(cond, body_block, end));
+                self.switch_to(body_block);
+
+                self.builder().labels.push(Label {
+                    name: None,
+                    result: None,
+                    end,
+                    start: Some(start),
+                });
+                self.scopes.push(HashMap::new());
+                self.lower_statements(body.0)?;
+                self.scopes.pop();
+                self.builder().labels.pop();
+
+                self.goto(start);
+                self.switch_to(end);
+            }
+            // declarations that only matter to the typechecker
+            typed_ast::Statement::Const { .. }
+            | typed_ast::Statement::Enum { .. }
+            | typed_ast::Statement::Struct { .. }
+            | typed_ast::Statement::TypeAlias { .. }
+            | typed_ast::Statement::Trait { .. }
+            | typed_ast::Statement::Impl { .. } => {}
+            typed_ast::Statement::SetIndex { .. }
+            | typed_ast::Statement::For { .. }
+            | typed_ast::Statement::Import { .. } => {
+                return Err(self.unsupported(statement.1, "this statement"))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lowers a block, returning its tail's value
+    fn lower_block(&mut self, block: typed_ast::Block<'src>) -> Result<Option<Operand>, Error> {
+        self.scopes.push(HashMap::new());
+        self.lower_statements(block.statements)?;
+
+        let value = match block.tail {
+            Some(tail) => self.lower_expr(*tail)?,
+            None => None,
+        };
+
+        self.scopes.pop();
+
+        Ok(value)
+    }
+
+    /// Lowers an expression, returning an operand holding its value, or
+    /// `None` for a unit value. Variables are copied into a new local when
+    /// they're used, since something evaluated after that could change them.
+    fn lower_expr(&mut self, expr: Spanned<Expr<'src>>) -> Result<Option<Operand>, Error> {
+        let ty = self.ty(&expr.0.ty, expr.1)?;
+
+        let value = match expr.0.expr {
+            ExprKind::Var(name) => match self.resolve(name.0) {
+                Binding::Local(local) => {
+                    local.map(|local| self.temp(ty.unwrap(), Rvalue::Use(Operand::Local(local))))
+                }
+                Binding::Global(global) => {
+                    // top level code can only use a variable after it's declared
+                    if self.builders.len() > 1 {
+                        self.push(Statement::CheckInitialized(global, name.1));
+                    }
+
+                    ty.map(|ty| self.temp(ty, Rvalue::Global(global)))
+                }
+                Binding::Function(_) => {
+                    return Err(self.unsupported(name.1, "functions used as values"))
+                }
+            },
+            ExprKind::Literal((literal, span)) => match literal {
+                Literal::Int(n) => Some(Operand::Const(Const::Int(n))),
+                Literal::Float(n) => Some(Operand::Const(Const::Float(n))),
+                Literal::Char(c) => Some(Operand::Const(Const::Char(c))),
+                Literal::Bool(b) => Some(Operand::Const(Const::Bool(b))),
+                Literal::Unit => None,
+                Literal::Str(_) | Literal::None => {
+                    return Err(self.unsupported(span, &format!("values of type '{}'", expr.0.ty)))
+                }
+            },
+            ExprKind::Prefix { op, expr: operand } => {
+                let operand_ty = self.ty(&operand.0.ty, operand.1)?.unwrap();
+                let operand = self.lower_expr(*operand)?.unwrap();
+
+                Some(self.temp(
+                    ty.unwrap(),
+                    Rvalue::Prefix(op.0, operand_ty, operand, expr.1),
+                ))
+            }
+            ExprKind::Binary {
+                op: (op @ (BinOp::LogicalAnd | BinOp::LogicalOr), _),
+                lhs,
+                rhs,
+            } => {
+                let lhs = self.lower_expr(*lhs)?;
+                let result = self.local(Ty::Bool, None);
+                let rhs_block = self.new_block();
+                let end = self.new_block();
+
+                self.assign(Some(result), lhs);
+
+                // the right side is only evaluated if it decides the result
+                let result_operand = Operand::Local(result);
+
+                if op == BinOp::LogicalAnd {
+                    self.terminate(Terminator::Branch(result_operand, rhs_block, end));
+                } else {
+                    self.terminate(Terminator::Branch(result_operand, end, rhs_block));
+                }
+
+                self.switch_to(rhs_block);
+
+                let rhs = self.lower_expr(*rhs)?;
+
+                self.assign(Some(result), rhs);
+                self.goto(end);
+                self.switch_to(end);
+
+                Some(result_operand)
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                if lhs.0.ty == Type::Str {
+                    return Err(self.unsupported(expr.1, "values of type 'str'"));
+                }
+
+                let operand_ty = lhs.0.ty.clone();
+                let lowered_ty = self.ty(&operand_ty, lhs.1)?;
+
+                let lhs = self.lower_expr(*lhs)?;
+                let rhs = self.lower_expr(*rhs)?;
+
+                match (lowered_ty, lhs, rhs) {
+                    (Some(lowered_ty), Some(lhs), Some(rhs)) if supported(op.0, lowered_ty) => {
+                        Some(self.temp(
+                            ty.unwrap(),
+                            Rvalue::Binary(op.0, lowered_ty, lhs, rhs, expr.1),
+                        ))
+                    }
+                    // unit values are all equal
+                    (None, _, _) if op.0 == BinOp::Equals => {
+                        Some(Operand::Const(Const::Bool(true)))
+                    }
+                    (None, _, _) if op.0 == BinOp::NotEquals => {
+                        Some(Operand::Const(Const::Bool(false)))
+                    }
+                    _ => {
+                        return Err(
+                            self.unsupported(op.1, &format!("'{}' on '{}'", op.0, operand_ty))
+                        )
+                    }
+                }
+            }
+            ExprKind::Call { callee, args } => {
+                let ExprKind::Var(name) = callee.0.expr else {
+                    return Err(self.unsupported(callee.1, "calls to functions used as values"));
+                };
+
+                let Binding::Function(function) = self.resolve(name.0) else {
+                    return Err(self.unsupported(name.1, "calls to functions used as values"));
+                };
+
+                let mut values = vec![];
+
+                for arg in args.0 {
+                    values.extend(self.lower_expr(arg)?);
+                }
+
+                let call = Rvalue::Call(function, values, expr.1);
+
+                match ty {
+                    Some(ty) => Some(self.temp(ty, call)),
+                    None => {
+                        self.push(Statement::Eval(call));
+                        None
+                    }
+                }
+            }
+            ExprKind::BuiltinCall { builtin, args } => match builtin.0 {
+                Builtin::Print | Builtin::Println => {
+                    let mut pieces = vec![];
+
+                    self.pieces(args.0.into_iter().next().unwrap(), &mut pieces)?;
+
+                    if builtin.0 == Builtin::Println {
+                        pieces.push(Piece::Str("\n".to_string()));
+                        self.push(Statement::Print(pieces));
+                    } else {
+                        self.push(Statement::Print(pieces));
+                        self.push(Statement::Flush);
+                    }
+
+                    None
+                }
+                Builtin::Tick => Some(self.temp(Ty::Int, Rvalue::Tick)),
+                _ => return Err(self.unsupported(builtin.1, &format!("'{}'", builtin.0))),
+            },
+            ExprKind::Cast {
+                expr: operand,
+                ty: to,
+            } => {
+                let from = operand.0.ty.clone();
+                let operand = self.lower_expr(*operand)?;
+
+                match (&from, &to.0) {
+                    (from, to) if from == to => operand,
+                    (Type::Int, Type::Float)
+                    | (Type::Bool | Type::Char, Type::Int)
+                    | (Type::Float, Type::Int)
+                    | (Type::Int, Type::Char) => {
+                        let from = self.ty(&from, expr.1)?.unwrap();
+                        let to = ty.unwrap();
+
+                        Some(self.temp(to, Rvalue::Cast(from, to, operand.unwrap(), expr.1)))
+                    }
+                    (from, to) => {
+                        return Err(
+                            self.unsupported(expr.1, &format!("casts from '{}' to '{}'", from, to))
+                        )
+                    }
+                }
+            }
+            ExprKind::LabeledBlock {
+                label,
+                statements,
+                tail,
+            } => {
+                let result = ty.map(|ty| self.local(ty, None));
+                let end = self.new_block();
+
+                self.builder().labels.push(Label {
+                    name: Some(label.0),
+                    result,
+                    end,
+                    start: None,
+                });
+
+                let value = self.lower_block(typed_ast::Block {
+                    statements: statements.0,
+                    tail,
+                })?;
+
+                self.assign(result, value);
+                self.builder().labels.pop();
+
+                self.goto(end);
+                self.switch_to(end);
+
+                result.map(Operand::Local)
+            }
+            ExprKind::If { cond, then, else_ } => {
+                let result = ty.map(|ty| self.local(ty, None));
+                let cond = self.lower_expr(*cond)?.unwrap();
+                let then_block = self.new_block();
+                let else_block = else_.is_some().then(|| self.new_block());
+                let end = self.new_block();
+
+                self.terminate(Terminator::Branch(
+                    cond,
+                    then_block,
+                    else_block.unwrap_or(end),
+                ));
+
+                self.switch_to(then_block);
+
+                let value = self.lower_block(then.0)?;
+
+                self.assign(result, value);
+                self.goto(end);
+
+                if let (Some(else_), Some(else_block)) = (else_, else_block) {
+                    self.switch_to(else_block);
+
+                    let value = self.lower_block(else_.0)?;
+
+                    self.assign(result, value);
+                    self.goto(end);
+                }
+
+                self.switch_to(end);
+
+                result.map(Operand::Local)
+            }
+            ExprKind::Loop { body, .. } => {
+                let result = ty.map(|ty| self.local(ty, None));
+                let start = self.new_block();
+                let end = self.new_block();
+
+                self.builder().labels.push(Label {
+                    name: None,
+                    result,
+                    end,
+                    start: Some(start),
+                });
+
+                self.goto(start);
+                self.switch_to(start);
+                self.scopes.push(HashMap::new());
+                self.lower_statements(body.0)?;
+                self.scopes.pop();
+                self.goto(start);
+
+                self.builder().labels.pop();
+                self.switch_to(end);
+
+                result.map(Operand::Local)
+            }
+            // nothing can jump out of a block without a label except to
+            // somewhere outside of the expression, so its value is its tail's
+            ExprKind::Block(block) => self.lower_block(block)?,
+            _ => return Err(self.unsupported(expr.1, "this expression")),
+        };
+
+        Ok(value)
+    }
+
+    /// Evaluates the arguments of a format string, which is made of literals
+    /// and arguments converted to strings joined with `+`, in order. Every
+    /// argument is evaluated before anything is written, like the
+    /// interpreter does.
+    fn pieces(&mut self, text: Spanned<Expr<'src>>, pieces: &mut Vec<Piece>) -> Result<(), Error> {
+        match text.0.expr {
+            ExprKind::Binary {
+                op: (BinOp::Add, _),
+                lhs,
+                rhs,
+            } => {
+                self.pieces(*lhs, pieces)?;
+                self.pieces(*rhs, pieces)?;
+            }
+            ExprKind::Literal((Literal::Str(text), _)) => pieces.push(Piece::Str(text)),
+            ExprKind::BuiltinCall {
+                builtin: (Builtin::ToStr, _),
+                mut args,
+            } => self.piece(args.0.remove(0), None, pieces)?,
+            ExprKind::BuiltinCall {
+                builtin: (Builtin::ToStrWith(spec), _),
+                mut args,
+            } => self.piece(args.0.remove(0), Some(spec), pieces)?,
+            _ => return Err(self.unsupported(text.1, "values of type 'str'")),
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates a value shown by a placeholder with `spec`
+    fn piece(
+        &mut self,
+        value: Spanned<Expr<'src>>,
+        spec: Option<FormatSpec>,
+        pieces: &mut Vec<Piece>,
+    ) -> Result<(), Error> {
+        let span = value.1;
+
+        match (&value.0.ty, spec) {
+            (Type::Str, None) => self.pieces(value, pieces)?,
+            (Type::Str, Some(FormatSpec::Debug)) => match value.0.expr {
+                ExprKind::Literal((Literal::Str(text), _)) => {
+                    pieces.push(Piece::Str(format!("{:?}", text)))
+                }
+                _ => return Err(self.unsupported(span, "values of type 'str'")),
+            },
+            (Type::Int | Type::Float | Type::Bool | Type::Char | Type::Unit, _) => {
+                let ty = self.ty(&value.0.ty, span)?;
+
+                match (self.lower_expr(value)?, ty) {
+                    (Some(value), Some(ty)) => pieces.push(Piece::Value(value, ty, spec)),
+                    _ => pieces.push(Piece::Str("#".to_string())),
+                }
+            }
+            (ty, _) => return Err(self.unsupported(span, &format!("values of type '{}'", ty))),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,992 @@
+use crate::error::Error;
+use crate::interpreter::{binary, cast, to_str, Value};
+use crate::mir::{self, *};
+use crate::typed_ast::{BinOp, FormatSpec, PrefixOp, Type};
+use crate::Span;
+use std::io::Write;
+
+/// Runs a program as x86-64 machine code, assembled in memory without
+/// going through an external assembler. Only ints, floats, bools, chars and
+/// unit values are supported, along with functions that don't capture
+/// anything and printing. Errors that aren't caused by any one part of the
+/// program point at `span`.
+pub fn run(program: &mir::Program, span: Span) -> Result<(), Error> {
+    let program = compile(program);
+
+    execute(program, span)
+}
+
+/// The machine code of a program, and everything the helpers it calls need
+struct Program {
+    code: Vec<u8>,
+    /// How many 8 byte slots the top level variables and their flags use
+    globals: usize,
+    strings: Vec<String>,
+    formats: Vec<(Type, Option<FormatSpec>)>,
+    /// Where each runtime error the code can stop with happens, with its
+    /// message if a helper doesn't give one
+    failures: Vec<(Span, Option<String>)>,
+}
+
+fn compile(program: &mir::Program) -> Program {
+    let mut compiler = Compiler {
+        asm: Asm::default(),
+        globals: vec![],
+        slots: 0,
+        functions: vec![],
+        strings: vec![],
+        formats: vec![],
+        failures: vec![],
+    };
+
+    for global in &program.globals {
+        let value = global.ty.map(|_| {
+            compiler.slots += 1;
+            8 * (compiler.slots as i32 - 1)
+        });
+
+        compiler.slots += 1;
+        compiler
+            .globals
+            .push((value, 8 * (compiler.slots as i32 - 1)));
+    }
+
+    compiler.functions = program
+        .functions
+        .iter()
+        .map(|_| compiler.asm.label())
+        .collect();
+
+    let exit = compiler.asm.label();
+
+    compiler.entry(compiler.functions[0], exit);
+
+    for (index, function) in program.functions.iter().enumerate() {
+        compiler.asm.bind(compiler.functions[index]);
+        compiler.compile_function(program, function);
+    }
+
+    // every runtime error gives its index to the caller, after dropping
+    // whatever the program had on the stack
+    let failures = std::mem::take(&mut compiler.failures);
+
+    for (index, (label, _, _)) in failures.iter().enumerate() {
+        compiler.asm.bind(*label);
+        compiler.asm.mov_imm(Reg::Rax, index as u64 + 1);
+        compiler.asm.load(Reg::Rsp, Reg::R12, STACK);
+        compiler.asm.jump(exit);
+    }
+
+    Program {
+        code: compiler.asm.finish(),
+        globals: compiler.slots,
+        strings: compiler.strings,
+        formats: compiler.formats,
+        failures: failures
+            .into_iter()
+            .map(|(_, span, message)| (span, message))
+            .collect(),
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+fn execute(program: Program, span: Span) -> Result<(), Error> {
+    let mut state = State {
+        stack: 0,
+        ticks: 0,
+        strings: program.strings,
+        formats: program.formats,
+        error: None,
+    };
+
+    let mut globals = vec![0u64; program.globals];
+    let len = program.code.len();
+
+    let os_error = || {
+        Error::Custom(
+            span,
+            format!("Runtime error: {}", std::io::Error::last_os_error()),
+        )
+    };
+
+    // SAFETY: the code is copied into memory of its own, which is only made
+    // executable once it's no longer writable. It's entered through the
+    // function `Compiler::entry` writes, which follows the C calling
+    // convention, and only touches its own stack, the globals and the state.
+    let failure = unsafe {
+        let memory = libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANON,
+            -1,
+            0,
+        );
+
+        if memory == libc::MAP_FAILED {
+            return Err(os_error());
+        }
+
+        std::ptr::copy_nonoverlapping(program.code.as_ptr(), memory.cast::<u8>(), len);
+
+        if libc::mprotect(memory, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+            let error = os_error();
+
+            libc::munmap(memory, len);
+
+            return Err(error);
+        }
+
+        let entry: extern "C" fn(*mut State, *mut u64) -> u64 = std::mem::transmute(memory);
+        let failure = entry(&mut state, globals.as_mut_ptr());
+
+        libc::munmap(memory, len);
+
+        failure
+    };
+
+    std::io::stdout().flush().unwrap();
+
+    match failure {
+        0 => Ok(()),
+        index => {
+            let (span, message) = program.failures[index as usize - 1].clone();
+            let message = message.or(state.error).unwrap();
+
+            // the machine code doesn't keep track of its calls
+            Err(Error::Runtime {
+                message,
+                span,
+                trace: vec![],
+            })
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", unix)))]
+fn execute(_program: Program, span: Span) -> Result<(), Error> {
+    Err(Error::Custom(
+        span,
+        "Compile error: native code can only be run on x86-64 Unix systems".to_string(),
+    ))
+}
+
+/// Everything the helpers the machine code calls need, which it keeps a
+/// pointer to in `r12`
+#[repr(C)]
+struct State {
+    /// The stack pointer when the program started, which is put back to stop
+    /// it early
+    stack: u64,
+    /// The counter `tick()` returns
+    ticks: u64,
+    strings: Vec<String>,
+    /// How each value passed to `write_value` is shown, by index
+    formats: Vec<(Type, Option<FormatSpec>)>,
+    /// The runtime error a helper failed with
+    error: Option<String>,
+}
+
+const STACK: i32 = 0;
+const TICKS: i32 = 8;
+
+/// How much of the stack the program can use before it's stopped, which
+/// leaves plenty of the default 8 MiB for the helpers
+const STACK_LIMIT: i32 = 4 << 20;
+
+/// The result of a helper that can fail, which comes back in `rax` and `rdx`
+#[repr(C)]
+struct Output {
+    value: u64,
+    failed: u64,
+}
+
+impl Output {
+    fn new(state: &mut State, result: Result<Value, String>) -> Self {
+        match result {
+            Ok(value) => Output {
+                value: bits(value),
+                failed: 0,
+            },
+            Err(message) => {
+                state.error = Some(message);
+
+                Output {
+                    value: 0,
+                    failed: 1,
+                }
+            }
+        }
+    }
+}
+
+/// The 64 bits a value is kept in
+fn bits(value: Value) -> u64 {
+    match value {
+        Value::Int(n) => n as u64,
+        Value::Float(n) => n.to_bits(),
+        Value::Bool(b) => b as u64,
+        Value::Char(c) => c as u64,
+        _ => 0,
+    }
+}
+
+fn value(bits: u64, ty: &Type) -> Value {
+    match ty {
+        Type::Int => Value::Int(bits as i64),
+        Type::Float => Value::Float(f64::from_bits(bits)),
+        Type::Bool => Value::Bool(bits != 0),
+        Type::Char => Value::Char(char::from_u32(bits as u32).unwrap()),
+        _ => Value::Unit,
+    }
+}
+
+extern "C" fn write_str(state: &mut State, index: u64) {
+    print!("{}", state.strings[index as usize]);
+}
+
+extern "C" fn write_value(state: &mut State, bits: u64, index: u64) {
+    let (ty, spec) = &state.formats[index as usize];
+
+    print!("{}", to_str(&value(bits, ty), *spec));
+}
+
+extern "C" fn flush() {
+    std::io::stdout().flush().unwrap();
+}
+
+extern "C" fn pow_int(state: &mut State, a: i64, b: i64) -> Output {
+    let result = binary(BinOp::Power, Value::Int(a), Value::Int(b));
+
+    Output::new(state, result)
+}
+
+extern "C" fn float_to_int(state: &mut State, bits: u64) -> Output {
+    let result = cast(Value::Float(f64::from_bits(bits)), &Type::Int);
+
+    Output::new(state, result)
+}
+
+extern "C" fn int_to_char(state: &mut State, n: i64) -> Output {
+    let result = cast(Value::Int(n), &Type::Char);
+
+    Output::new(state, result)
+}
+
+extern "C" fn pow_float(a: f64, b: f64) -> f64 {
+    a.powf(b)
+}
+
+extern "C" fn rem_float(a: f64, b: f64) -> f64 {
+    a % b
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Reg {
+    Rax = 0,
+    Rcx = 1,
+    Rdx = 2,
+    Rbx = 3,
+    Rsp = 4,
+    Rbp = 5,
+    Rsi = 6,
+    Rdi = 7,
+    R12 = 12,
+    R13 = 13,
+}
+
+/// The condition codes of `jcc` and `setcc`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Cond {
+    Overflow = 0x0,
+    Below = 0x2,
+    AboveOrEqual = 0x3,
+    Equal = 0x4,
+    NotEqual = 0x5,
+    BelowOrEqual = 0x6,
+    Above = 0x7,
+    Parity = 0xa,
+    NoParity = 0xb,
+    Less = 0xc,
+    GreaterOrEqual = 0xd,
+    LessOrEqual = 0xe,
+    Greater = 0xf,
+}
+
+/// The instructions with a register or memory operand and a register
+/// operand, by their opcode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Alu {
+    Add = 0x01,
+    Or = 0x09,
+    And = 0x21,
+    Sub = 0x29,
+    Xor = 0x31,
+    Cmp = 0x39,
+    Test = 0x85,
+}
+
+/// The scalar double instructions, by their opcode after `f2 0f`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Sse {
+    Add = 0x58,
+    Mul = 0x59,
+    Sub = 0x5c,
+    Div = 0x5e,
+}
+
+/// Writes machine code, where jumps and calls go to labels whose addresses
+/// are filled in at the end
+#[derive(Default)]
+struct Asm {
+    code: Vec<u8>,
+    /// Where each label is, once it's been bound
+    labels: Vec<Option<usize>>,
+    /// The 32 bit offsets to fill in with the distance to a label
+    fixups: Vec<(usize, usize)>,
+}
+
+impl Asm {
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn bind(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for (at, label) in self.fixups {
+            let target = self.labels[label].unwrap() as i64;
+            let offset = (target - at as i64 - 4) as i32;
+
+            self.code[at..at + 4].copy_from_slice(&offset.to_le_bytes());
+        }
+
+        self.code
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.code.push(byte);
+    }
+
+    fn imm32(&mut self, imm: i32) {
+        self.code.extend(imm.to_le_bytes());
+    }
+
+    fn rel32(&mut self, label: usize) {
+        self.fixups.push((self.code.len(), label));
+        self.imm32(0);
+    }
+
+    /// Writes the REX prefix for 64 bit operands and registers above `rdi`
+    fn rex(&mut self, reg: u8, rm: u8) {
+        self.byte(0x48 | (reg >> 3) << 2 | rm >> 3);
+    }
+
+    /// An instruction between two registers, or a register and an opcode
+    /// extension in `reg`
+    fn rr(&mut self, opcode: &[u8], reg: u8, rm: u8) {
+        self.rex(reg, rm);
+        self.code.extend(opcode);
+        self.byte(0xc0 | (reg & 7) << 3 | rm & 7);
+    }
+
+    /// An instruction between a register and `[base + disp]`
+    fn mem(&mut self, opcode: &[u8], reg: u8, base: Reg, disp: i32) {
+        let base = base as u8;
+
+        self.rex(reg, base);
+        self.code.extend(opcode);
+        self.byte(0x80 | (reg & 7) << 3 | base & 7);
+
+        // `rsp` and `r12` as a base need a SIB byte
+        if base & 7 == 4 {
+            self.byte(0x24);
+        }
+
+        self.imm32(disp);
+    }
+
+    fn mov(&mut self, dst: Reg, src: Reg) {
+        self.rr(&[0x89], src as u8, dst as u8);
+    }
+
+    fn mov_imm(&mut self, dst: Reg, imm: u64) {
+        self.rex(0, dst as u8);
+        self.byte(0xb8 + (dst as u8 & 7));
+        self.code.extend(imm.to_le_bytes());
+    }
+
+    fn load(&mut self, dst: Reg, base: Reg, disp: i32) {
+        self.mem(&[0x8b], dst as u8, base, disp);
+    }
+
+    fn store(&mut self, base: Reg, disp: i32, src: Reg) {
+        self.mem(&[0x89], src as u8, base, disp);
+    }
+
+    fn store_imm(&mut self, base: Reg, disp: i32, imm: i32) {
+        self.mem(&[0xc7], 0, base, disp);
+        self.imm32(imm);
+    }
+
+    fn alu(&mut self, op: Alu, dst: Reg, src: Reg) {
+        self.rr(&[op as u8], src as u8, dst as u8);
+    }
+
+    /// `add`, `sub` or `cmp` with an immediate, by their opcode extension
+    fn alu_imm(&mut self, ext: u8, dst: Reg, imm: i32) {
+        self.rr(&[0x81], ext, dst as u8);
+        self.imm32(imm);
+    }
+
+    fn add_imm(&mut self, dst: Reg, imm: i32) {
+        self.alu_imm(0, dst, imm);
+    }
+
+    fn sub_imm(&mut self, dst: Reg, imm: i32) {
+        self.alu_imm(5, dst, imm);
+    }
+
+    fn cmp_imm(&mut self, dst: Reg, imm: i32) {
+        self.alu_imm(7, dst, imm);
+    }
+
+    fn imul(&mut self, dst: Reg, src: Reg) {
+        self.rr(&[0x0f, 0xaf], dst as u8, src as u8);
+    }
+
+    fn not(&mut self, reg: Reg) {
+        self.rr(&[0xf7], 2, reg as u8);
+    }
+
+    fn neg(&mut self, reg: Reg) {
+        self.rr(&[0xf7], 3, reg as u8);
+    }
+
+    /// Divides `rdx:rax` by a register, leaving the quotient in `rax` and
+    /// the remainder in `rdx`
+    fn idiv(&mut self, reg: Reg) {
+        self.rr(&[0xf7], 7, reg as u8);
+    }
+
+    /// Sign extends `rax` into `rdx`
+    fn cqo(&mut self) {
+        self.code.extend([0x48, 0x99]);
+    }
+
+    fn shl_cl(&mut self, reg: Reg) {
+        self.rr(&[0xd3], 4, reg as u8);
+    }
+
+    fn sar_cl(&mut self, reg: Reg) {
+        self.rr(&[0xd3], 7, reg as u8);
+    }
+
+    /// Flips the sign bit of a float kept in a general register
+    fn flip_sign(&mut self, reg: Reg) {
+        self.rr(&[0x0f, 0xba], 7, reg as u8);
+        self.byte(63);
+    }
+
+    fn push(&mut self, reg: Reg) {
+        if reg as u8 >= 8 {
+            self.byte(0x41);
+        }
+
+        self.byte(0x50 + (reg as u8 & 7));
+    }
+
+    fn pop(&mut self, reg: Reg) {
+        if reg as u8 >= 8 {
+            self.byte(0x41);
+        }
+
+        self.byte(0x58 + (reg as u8 & 7));
+    }
+
+    /// Sets `rax` to 1 if the condition holds and to 0 otherwise
+    fn set(&mut self, cond: Cond) {
+        self.code.extend([0x0f, 0x90 + cond as u8, 0xc0]);
+        self.code.extend([0x0f, 0xb6, 0xc0]);
+    }
+
+    fn jump(&mut self, label: usize) {
+        self.byte(0xe9);
+        self.rel32(label);
+    }
+
+    fn jump_if(&mut self, cond: Cond, label: usize) {
+        self.code.extend([0x0f, 0x80 + cond as u8]);
+        self.rel32(label);
+    }
+
+    fn call(&mut self, label: usize) {
+        self.byte(0xe8);
+        self.rel32(label);
+    }
+
+    /// Calls a function at an address with the C calling convention, which
+    /// needs the stack to be aligned to 16 bytes. `rbx` keeps the stack
+    /// pointer, since the function has to preserve it.
+    fn call_extern(&mut self, address: u64) {
+        self.mov_imm(Reg::Rax, address);
+        self.mov(Reg::Rbx, Reg::Rsp);
+        self.rr(&[0x83], 4, Reg::Rsp as u8);
+        self.byte(0xf0);
+        self.code.extend([0xff, 0xd0]);
+        self.mov(Reg::Rsp, Reg::Rbx);
+    }
+
+    fn leave(&mut self) {
+        self.byte(0xc9);
+    }
+
+    fn ret(&mut self) {
+        self.byte(0xc3);
+    }
+
+    fn ud2(&mut self) {
+        self.code.extend([0x0f, 0x0b]);
+    }
+
+    /// Moves a float's bits from a general register to `xmm0` or `xmm1`
+    fn xmm_from_reg(&mut self, xmm: u8, reg: Reg) {
+        self.byte(0x66);
+        self.rr(&[0x0f, 0x6e], xmm, reg as u8);
+    }
+
+    fn reg_from_xmm(&mut self, reg: Reg, xmm: u8) {
+        self.byte(0x66);
+        self.rr(&[0x0f, 0x7e], xmm, reg as u8);
+    }
+
+    fn sse(&mut self, op: Sse, dst: u8, src: u8) {
+        self.code
+            .extend([0xf2, 0x0f, op as u8, 0xc0 | dst << 3 | src]);
+    }
+
+    /// Compares two floats, setting the flags like an unsigned comparison
+    /// and the parity flag if either is NaN
+    fn ucomisd(&mut self, a: u8, b: u8) {
+        self.code.extend([0x66, 0x0f, 0x2e, 0xc0 | a << 3 | b]);
+    }
+
+    /// Converts the int in a register to a float in `xmm0`
+    fn cvtsi2sd(&mut self, reg: Reg) {
+        self.byte(0xf2);
+        self.rr(&[0x0f, 0x2a], 0, reg as u8);
+    }
+}
+
+struct Compiler {
+    asm: Asm,
+    /// The slots of each top level variable, by their offset from `r13`,
+    /// with the slot that's set to 1 once it's initialized. A unit value
+    /// only has the second.
+    globals: Vec<(Option<i32>, i32)>,
+    /// How many 8 byte slots the top level variables and their flags use
+    slots: usize,
+    /// The label of each function
+    functions: Vec<usize>,
+    strings: Vec<String>,
+    formats: Vec<(Type, Option<FormatSpec>)>,
+    /// The label each runtime error jumps to, with its span and message
+    failures: Vec<(usize, Span, Option<String>)>,
+}
+
+/// Where a local is, as its offset from `rbp`. The params are pushed in
+/// order, above the return address and the caller's `rbp`, and the other
+/// locals are below `rbp`.
+fn slot(function: &Function, local: Local) -> i32 {
+    let params = function.params as i32;
+    let local = local as i32;
+
+    if local < params {
+        16 + 8 * (params - 1 - local)
+    } else {
+        -8 * (local - params + 1)
+    }
+}
+
+impl Compiler {
+    /// Writes the function the program is entered through, which takes the
+    /// state and the globals and returns the index of the runtime error it
+    /// stopped with plus one, or 0 if it didn't fail
+    fn entry(&mut self, main: usize, exit: usize) {
+        for reg in [Reg::Rbx, Reg::Rbp, Reg::R12, Reg::R13] {
+            self.asm.push(reg);
+        }
+
+        self.asm.mov(Reg::R12, Reg::Rdi);
+        self.asm.mov(Reg::R13, Reg::Rsi);
+        self.asm.store(Reg::R12, STACK, Reg::Rsp);
+        self.asm.call(main);
+        self.asm.alu(Alu::Xor, Reg::Rax, Reg::Rax);
+
+        self.asm.bind(exit);
+
+        for reg in [Reg::R13, Reg::R12, Reg::Rbp, Reg::Rbx] {
+            self.asm.pop(reg);
+        }
+
+        self.asm.ret();
+    }
+
+    /// Jumps to a new runtime error if the condition holds
+    fn fail_if(&mut self, cond: Cond, span: Span, message: Option<&str>) {
+        let label = self.asm.label();
+
+        self.failures
+            .push((label, span, message.map(str::to_string)));
+        self.asm.jump_if(cond, label);
+    }
+
+    /// Calls a helper that can fail with the arguments in `rsi` and `rdx`,
+    /// leaving its result in `rax`
+    fn call_fallible(&mut self, address: u64, span: Span) {
+        self.asm.mov(Reg::Rdi, Reg::R12);
+        self.asm.call_extern(address);
+        self.asm.alu(Alu::Test, Reg::Rdx, Reg::Rdx);
+        self.fail_if(Cond::NotEqual, span, None);
+    }
+
+    fn load(&mut self, function: &Function, reg: Reg, operand: &Operand) {
+        match *operand {
+            Operand::Local(local) => self.asm.load(reg, Reg::Rbp, slot(function, local)),
+            Operand::Const(Const::Int(n)) => self.asm.mov_imm(reg, n as u64),
+            Operand::Const(Const::Float(n)) => self.asm.mov_imm(reg, n.to_bits()),
+            Operand::Const(Const::Bool(b)) => self.asm.mov_imm(reg, b as u64),
+            Operand::Const(Const::Char(c)) => self.asm.mov_imm(reg, c as u64),
+        }
+    }
+
+    fn compile_function(&mut self, program: &mir::Program, function: &Function) {
+        let frame = 8 * (function.locals.len() - function.params) as i32;
+
+        self.asm.push(Reg::Rbp);
+        self.asm.mov(Reg::Rbp, Reg::Rsp);
+        self.asm.sub_imm(Reg::Rsp, frame);
+
+        let blocks = function
+            .blocks
+            .iter()
+            .map(|_| self.asm.label())
+            .collect::<Vec<_>>();
+
+        for (index, block) in function.blocks.iter().enumerate() {
+            self.asm.bind(blocks[index]);
+
+            for statement in &block.statements {
+                self.compile_statement(program, function, statement);
+            }
+
+            // a jump to the next block can fall through to it instead
+            let next = index + 1;
+
+            match &block.terminator {
+                Terminator::Goto(target) if *target == next => {}
+                Terminator::Goto(target) => self.asm.jump(blocks[*target]),
+                Terminator::Branch(cond, then, else_) => {
+                    self.load(function, Reg::Rax, cond);
+                    self.asm.alu(Alu::Test, Reg::Rax, Reg::Rax);
+
+                    if *then == next {
+                        self.asm.jump_if(Cond::Equal, blocks[*else_]);
+                    } else {
+                        self.asm.jump_if(Cond::NotEqual, blocks[*then]);
+
+                        if *else_ != next {
+                            self.asm.jump(blocks[*else_]);
+                        }
+                    }
+                }
+                Terminator::Return(value) => {
+                    if let Some(value) = value {
+                        self.load(function, Reg::Rax, value);
+                    }
+
+                    self.asm.leave();
+                    self.asm.ret();
+                }
+                Terminator::Unreachable => self.asm.ud2(),
+            }
+        }
+    }
+
+    fn compile_statement(
+        &mut self,
+        program: &mir::Program,
+        function: &Function,
+        statement: &Statement,
+    ) {
+        match statement {
+            Statement::Assign(local, value) => {
+                self.compile_rvalue(function, value);
+                self.asm.store(Reg::Rbp, slot(function, *local), Reg::Rax);
+            }
+            Statement::Eval(value) => self.compile_rvalue(function, value),
+            Statement::SetGlobal(global, value) => {
+                self.load(function, Reg::Rax, value);
+                self.asm
+                    .store(Reg::R13, self.globals[*global].0.unwrap(), Reg::Rax);
+            }
+            Statement::Initialize(global) => {
+                self.asm.store_imm(Reg::R13, self.globals[*global].1, 1)
+            }
+            Statement::CheckInitialized(global, span) => {
+                self.asm.load(Reg::Rax, Reg::R13, self.globals[*global].1);
+                self.asm.alu(Alu::Test, Reg::Rax, Reg::Rax);
+                self.fail_if(
+                    Cond::Equal,
+                    *span,
+                    Some(&format!(
+                        "'{}' is used before it's initialized",
+                        program.globals[*global].name
+                    )),
+                );
+            }
+            Statement::Print(pieces) => {
+                for piece in pieces {
+                    match piece {
+                        Piece::Str(text) => self.write_str(text),
+                        Piece::Value(value, ty, spec) => {
+                            self.formats.push((ty.to_type(), *spec));
+
+                            self.asm.mov(Reg::Rdi, Reg::R12);
+                            self.load(function, Reg::Rsi, value);
+                            self.asm.mov_imm(Reg::Rdx, self.formats.len() as u64 - 1);
+                            self.asm.call_extern(write_value as *const () as u64);
+                        }
+                    }
+                }
+            }
+            Statement::Flush => self.asm.call_extern(flush as *const () as u64),
+        }
+    }
+
+    fn write_str(&mut self, text: &str) {
+        self.strings.push(text.to_string());
+
+        self.asm.mov(Reg::Rdi, Reg::R12);
+        self.asm.mov_imm(Reg::Rsi, self.strings.len() as u64 - 1);
+        self.asm.call_extern(write_str as *const () as u64);
+    }
+
+    /// Compiles a value, leaving it in `rax`
+    fn compile_rvalue(&mut self, function: &Function, value: &Rvalue) {
+        match value {
+            Rvalue::Use(value) => self.load(function, Reg::Rax, value),
+            Rvalue::Prefix(op, ty, value, span) => {
+                self.load(function, Reg::Rax, value);
+
+                match (op, ty) {
+                    (PrefixOp::Negate, Ty::Int) => {
+                        self.asm.neg(Reg::Rax);
+                        self.fail_if(Cond::Overflow, *span, Some("integer overflow"));
+                    }
+                    (PrefixOp::Negate, _) => self.asm.flip_sign(Reg::Rax),
+                    (PrefixOp::Not, _) => {
+                        self.asm.mov_imm(Reg::Rcx, 1);
+                        self.asm.alu(Alu::Xor, Reg::Rax, Reg::Rcx);
+                    }
+                    (PrefixOp::BitNot, _) => self.asm.not(Reg::Rax),
+                }
+            }
+            Rvalue::Binary(op, ty, lhs, rhs, span) => {
+                self.load(function, Reg::Rax, lhs);
+                self.load(function, Reg::Rcx, rhs);
+                self.binary(*op, *ty, *span);
+            }
+            Rvalue::Cast(from, to, value, span) => {
+                self.load(function, Reg::Rax, value);
+
+                match (from, to) {
+                    (Ty::Int, Ty::Float) => {
+                        self.asm.cvtsi2sd(Reg::Rax);
+                        self.asm.reg_from_xmm(Reg::Rax, 0);
+                    }
+                    (Ty::Float, Ty::Int) => {
+                        self.asm.mov(Reg::Rsi, Reg::Rax);
+                        self.call_fallible(float_to_int as *const () as u64, *span);
+                    }
+                    (Ty::Int, Ty::Char) => {
+                        self.asm.mov(Reg::Rsi, Reg::Rax);
+                        self.call_fallible(int_to_char as *const () as u64, *span);
+                    }
+                    // bools and chars are already zero extended
+                    _ => {}
+                }
+            }
+            Rvalue::Global(global) => {
+                self.asm
+                    .load(Reg::Rax, Reg::R13, self.globals[*global].0.unwrap())
+            }
+            Rvalue::Call(callee, args, span) => {
+                for arg in args {
+                    self.load(function, Reg::Rax, arg);
+                    self.asm.push(Reg::Rax);
+                }
+
+                // running out of stack would crash the program without a
+                // message
+                self.asm.load(Reg::Rcx, Reg::R12, STACK);
+                self.asm.alu(Alu::Sub, Reg::Rcx, Reg::Rsp);
+                self.asm.cmp_imm(Reg::Rcx, STACK_LIMIT);
+                self.fail_if(Cond::Above, *span, Some("stack overflow"));
+
+                self.asm.call(self.functions[*callee]);
+
+                if !args.is_empty() {
+                    self.asm.add_imm(Reg::Rsp, 8 * args.len() as i32);
+                }
+            }
+            Rvalue::Tick => {
+                self.asm.load(Reg::Rax, Reg::R12, TICKS);
+                self.asm.mov(Reg::Rcx, Reg::Rax);
+                self.asm.add_imm(Reg::Rcx, 1);
+                self.asm.store(Reg::R12, TICKS, Reg::Rcx);
+            }
+        }
+    }
+
+    /// Compiles an operator other than the short-circuiting ones, for
+    /// operands of type `ty` in `rax` and `rcx`
+    fn binary(&mut self, op: BinOp, ty: Ty, span: Span) {
+        let overflow = Some("integer overflow");
+
+        match (ty, op) {
+            (Ty::Int, BinOp::Add | BinOp::Subtract | BinOp::Multiply) => {
+                match op {
+                    BinOp::Add => self.asm.alu(Alu::Add, Reg::Rax, Reg::Rcx),
+                    BinOp::Subtract => self.asm.alu(Alu::Sub, Reg::Rax, Reg::Rcx),
+                    _ => self.asm.imul(Reg::Rax, Reg::Rcx),
+                }
+
+                self.fail_if(Cond::Overflow, span, overflow);
+            }
+            (Ty::Int, BinOp::Divide | BinOp::Modulo) => {
+                let divide = self.asm.label();
+
+                self.asm.alu(Alu::Test, Reg::Rcx, Reg::Rcx);
+                self.fail_if(Cond::Equal, span, Some("division by zero"));
+
+                // i64::MIN / -1 is the one division that overflows
+                self.asm.cmp_imm(Reg::Rcx, -1);
+                self.asm.jump_if(Cond::NotEqual, divide);
+                self.asm.mov_imm(Reg::Rdx, i64::MIN as u64);
+                self.asm.alu(Alu::Cmp, Reg::Rax, Reg::Rdx);
+                self.fail_if(Cond::Equal, span, overflow);
+
+                self.asm.bind(divide);
+                self.asm.cqo();
+                self.asm.idiv(Reg::Rcx);
+
+                if op == BinOp::Modulo {
+                    self.asm.mov(Reg::Rax, Reg::Rdx);
+                }
+            }
+            (Ty::Int, BinOp::Power) => {
+                self.asm.mov(Reg::Rsi, Reg::Rax);
+                self.asm.mov(Reg::Rdx, Reg::Rcx);
+                self.call_fallible(pow_int as *const () as u64, span);
+            }
+            (Ty::Int, BinOp::ShiftLeft | BinOp::ShiftRight) => {
+                // the shift only uses the low 6 bits of cl, so a negative
+                // amount is caught here too by comparing unsigned
+                self.asm.cmp_imm(Reg::Rcx, 63);
+                self.fail_if(Cond::Above, span, overflow);
+
+                if op == BinOp::ShiftLeft {
+                    self.asm.shl_cl(Reg::Rax);
+                } else {
+                    self.asm.sar_cl(Reg::Rax);
+                }
+            }
+            (Ty::Int, BinOp::BitAnd) => self.asm.alu(Alu::And, Reg::Rax, Reg::Rcx),
+            (Ty::Int, BinOp::BitOr) => self.asm.alu(Alu::Or, Reg::Rax, Reg::Rcx),
+            (Ty::Int, BinOp::BitXor) => self.asm.alu(Alu::Xor, Reg::Rax, Reg::Rcx),
+            (Ty::Float, BinOp::Add | BinOp::Subtract | BinOp::Multiply | BinOp::Divide) => {
+                let op = match op {
+                    BinOp::Add => Sse::Add,
+                    BinOp::Subtract => Sse::Sub,
+                    BinOp::Multiply => Sse::Mul,
+                    _ => Sse::Div,
+                };
+
+                self.asm.xmm_from_reg(0, Reg::Rax);
+                self.asm.xmm_from_reg(1, Reg::Rcx);
+                self.asm.sse(op, 0, 1);
+                self.asm.reg_from_xmm(Reg::Rax, 0);
+            }
+            (Ty::Float, BinOp::Modulo | BinOp::Power) => {
+                let helper = if op == BinOp::Modulo {
+                    rem_float as *const ()
+                } else {
+                    pow_float as *const ()
+                };
+
+                self.asm.xmm_from_reg(0, Reg::Rax);
+                self.asm.xmm_from_reg(1, Reg::Rcx);
+                self.asm.call_extern(helper as u64);
+                self.asm.reg_from_xmm(Reg::Rax, 0);
+            }
+            (Ty::Float, BinOp::Equals | BinOp::NotEquals) => {
+                // NaN is unordered, which sets the parity flag
+                let (cond, nan, combine) = if op == BinOp::Equals {
+                    (Cond::Equal, Cond::NoParity, Alu::And)
+                } else {
+                    (Cond::NotEqual, Cond::Parity, Alu::Or)
+                };
+
+                self.asm.xmm_from_reg(0, Reg::Rax);
+                self.asm.xmm_from_reg(1, Reg::Rcx);
+                self.asm.ucomisd(0, 1);
+                self.asm.set(nan);
+                self.asm.mov(Reg::Rcx, Reg::Rax);
+                self.asm.set(cond);
+                self.asm.alu(combine, Reg::Rax, Reg::Rcx);
+            }
+            (Ty::Float, _) => {
+                // comparing the other way around for `<` and `<=` makes every
+                // comparison with NaN false
+                let (a, b, cond) = match op {
+                    BinOp::LessThan => (1, 0, Cond::Above),
+                    BinOp::LessThanOrEqual => (1, 0, Cond::AboveOrEqual),
+                    BinOp::GreaterThan => (0, 1, Cond::Above),
+                    BinOp::GreaterThanOrEqual => (0, 1, Cond::AboveOrEqual),
+                    _ => unreachable!(),
+                };
+
+                self.asm.xmm_from_reg(0, Reg::Rax);
+                self.asm.xmm_from_reg(1, Reg::Rcx);
+                self.asm.ucomisd(a, b);
+                self.asm.set(cond);
+            }
+            (Ty::Int | Ty::Bool | Ty::Char, _) => {
+                // only ints can be negative, bools and chars are compared with
+                // the unsigned conditions
+                let signed = ty == Ty::Int;
+
+                let cond = match op {
+                    BinOp::Equals => Cond::Equal,
+                    BinOp::NotEquals => Cond::NotEqual,
+                    BinOp::LessThan if signed => Cond::Less,
+                    BinOp::LessThan => Cond::Below,
+                    BinOp::LessThanOrEqual if signed => Cond::LessOrEqual,
+                    BinOp::LessThanOrEqual => Cond::BelowOrEqual,
+                    BinOp::GreaterThan if signed => Cond::Greater,
+                    BinOp::GreaterThan => Cond::Above,
+                    BinOp::GreaterThanOrEqual if signed => Cond::GreaterOrEqual,
+                    BinOp::GreaterThanOrEqual => Cond::AboveOrEqual,
+                    _ => unreachable!(),
+                };
+
+                self.asm.alu(Alu::Cmp, Reg::Rax, Reg::Rcx);
+                self.asm.set(cond);
+            }
+        }
+    }
+}
@@ -0,0 +1,1039 @@
+use crate::error::Warning;
+use crate::fold::{fold_binary, fold_prefix};
+use crate::interpreter::{cast, Value};
+use crate::mir::*;
+use crate::typed_ast::{BinOp, Literal, PrefixOp};
+use std::collections::{HashMap, HashSet};
+
+/// Which optimizations are done on the MIR before it's compiled, and on the
+/// bytecode after it's compiled
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    /// Whether operations on constants are replaced with their result
+    pub fold: bool,
+    /// How big a function can be for calls to it to be replaced with its
+    /// body, counting its statements and blocks
+    pub inline_threshold: usize,
+    /// Whether a value that's already been computed is reused instead of
+    /// computing it again
+    pub common_subexpressions: bool,
+    /// Whether assignments, variables and globals that are never read are
+    /// removed, along with jumps between blocks that can be merged
+    pub dead_code: bool,
+    /// Whether short sequences of bytecode instructions are combined
+    pub peephole: bool,
+}
+
+impl Options {
+    /// The optimizations done at a level from 0 to 3. Level 0 does none, so
+    /// the compiled code follows the source one to one, and level 3 inlines
+    /// bigger functions, which lets more of them be folded into constants.
+    pub fn level(level: u8) -> Self {
+        Options {
+            fold: level >= 1,
+            inline_threshold: match level {
+                0 | 1 => 0,
+                2 => 16,
+                _ => 64,
+            },
+            common_subexpressions: level >= 2,
+            dead_code: level >= 1,
+            peephole: level >= 1,
+        }
+    }
+}
+
+/// Optimizes a program, returning notes about the variables and values
+/// that turn out to be unused
+pub fn optimize(program: &mut Program, options: Options) -> Vec<Warning> {
+    // what's unused is found before anything is changed, so that the notes
+    // are about the program as it was written
+    let notes = unused(program);
+
+    inline(program, options.inline_threshold);
+
+    if options.fold {
+        for function in &mut program.functions {
+            fold_constants(function);
+        }
+    }
+
+    if options.common_subexpressions {
+        let pure = pure_functions(program);
+
+        for function in &mut program.functions {
+            eliminate_common_subexpressions(function, &pure);
+
+            // what's the same as something else can be a constant now
+            if options.fold {
+                fold_constants(function);
+            }
+        }
+    }
+
+    if options.dead_code {
+        eliminate_dead_code(program);
+    }
+
+    notes
+}
+
+/// Replaces calls to functions that are at most `threshold` big with
+/// their body. A function has the calls in it inlined before it's inlined
+/// anywhere itself, and recursive functions are never inlined.
+fn inline(program: &mut Program, threshold: usize) {
+    let calls = program.functions.iter().map(callees).collect::<Vec<_>>();
+
+    let mut order = vec![];
+    let mut visited = vec![false; calls.len()];
+
+    for function in 0..calls.len() {
+        post_order(&calls, function, &mut visited, &mut order);
+    }
+
+    let recursive = (0..calls.len())
+        .map(|function| reaches(&calls, function, function))
+        .collect::<Vec<_>>();
+
+    for caller in order {
+        let mut function = program.functions[caller].clone();
+        let mut layout = (0..function.blocks.len()).collect::<Vec<_>>();
+        let mut block = 0;
+
+        while block < function.blocks.len() {
+            let call =
+                function.blocks[block]
+                    .statements
+                    .iter()
+                    .position(|statement| match statement {
+                        Statement::Assign(_, Rvalue::Call(callee, _, _))
+                        | Statement::Eval(Rvalue::Call(callee, _, _)) => {
+                            !recursive[*callee] && size(&program.functions[*callee]) <= threshold
+                        }
+                        _ => false,
+                    });
+
+            match call {
+                // the rest of the block is moved to a new one, so the same
+                // block is looked at again in case it calls anything else
+                Some(index) => inline_call(&mut function, block, index, program, &mut layout),
+                None => block += 1,
+            }
+        }
+
+        function.remove_unreachable_blocks(layout);
+        merge_blocks(&mut function);
+
+        program.functions[caller] = function;
+    }
+}
+
+/// The functions a function calls
+fn callees(function: &Function) -> Vec<FunctionId> {
+    let mut callees = vec![];
+
+    for block in &function.blocks {
+        for statement in &block.statements {
+            if let Statement::Assign(_, Rvalue::Call(callee, _, _))
+            | Statement::Eval(Rvalue::Call(callee, _, _)) = statement
+            {
+                callees.push(*callee);
+            }
+        }
+    }
+
+    callees
+}
+
+fn post_order(
+    calls: &[Vec<FunctionId>],
+    function: FunctionId,
+    visited: &mut [bool],
+    order: &mut Vec<FunctionId>,
+) {
+    if !std::mem::replace(&mut visited[function], true) {
+        for &callee in &calls[function] {
+            post_order(calls, callee, visited, order);
+        }
+
+        order.push(function);
+    }
+}
+
+/// Whether a function ends up calling another one
+fn reaches(calls: &[Vec<FunctionId>], from: FunctionId, to: FunctionId) -> bool {
+    let mut visited = vec![false; calls.len()];
+    let mut stack = calls[from].clone();
+
+    while let Some(function) = stack.pop() {
+        if function == to {
+            return true;
+        }
+
+        if !std::mem::replace(&mut visited[function], true) {
+            stack.extend(&calls[function]);
+        }
+    }
+
+    false
+}
+
+fn size(function: &Function) -> usize {
+    function
+        .blocks
+        .iter()
+        .map(|block| block.statements.len() + 1)
+        .sum()
+}
+
+/// Replaces the call that's the statement at `index` in a block with the
+/// body of the function it calls. The params become locals that the
+/// arguments are assigned to, returning assigns the result and jumps to a
+/// new block with the statements after the call, and `layout` gets the new
+/// blocks right after the one the call was in.
+fn inline_call(
+    function: &mut Function,
+    block: BlockId,
+    index: usize,
+    program: &Program,
+    layout: &mut Vec<BlockId>,
+) {
+    let mut rest = function.blocks[block].statements.split_off(index);
+
+    let (result, callee, args) = match rest.remove(0) {
+        Statement::Assign(local, Rvalue::Call(callee, args, _)) => (Some(local), callee, args),
+        Statement::Eval(Rvalue::Call(callee, args, _)) => (None, callee, args),
+        _ => unreachable!(),
+    };
+
+    let callee = &program.functions[callee];
+    let locals = function.locals.len();
+    let start = function.blocks.len();
+    let after = start + callee.blocks.len();
+
+    function.locals.extend(callee.locals.iter().cloned());
+
+    function.blocks[block].statements.extend(
+        args.into_iter()
+            .enumerate()
+            .map(|(param, arg)| Statement::Assign(locals + param, Rvalue::Use(arg))),
+    );
+
+    let terminator = std::mem::replace(
+        &mut function.blocks[block].terminator,
+        Terminator::Goto(start),
+    );
+
+    for body in &callee.blocks {
+        let mut body = body.clone();
+
+        renumber_locals(&mut body, |local| local + locals);
+
+        body.terminator = match body.terminator {
+            Terminator::Goto(target) => Terminator::Goto(start + target),
+            Terminator::Branch(cond, then, else_) => {
+                Terminator::Branch(cond, start + then, start + else_)
+            }
+            Terminator::Return(value) => {
+                if let (Some(result), Some(value)) = (result, value) {
+                    body.statements
+                        .push(Statement::Assign(result, Rvalue::Use(value)));
+                }
+
+                Terminator::Goto(after)
+            }
+            Terminator::Unreachable => Terminator::Unreachable,
+        };
+
+        function.blocks.push(body);
+    }
+
+    function.blocks.push(BasicBlock {
+        phis: vec![],
+        statements: rest,
+        terminator,
+    });
+
+    let position = layout.iter().position(|other| *other == block).unwrap();
+
+    layout.splice(position + 1..position + 1, start..=after);
+}
+
+/// Moves the code of a block that's only jumped to from one other block,
+/// which doesn't go anywhere else, to the end of that block. This also
+/// removes the blocks that can't be reached.
+fn merge_blocks(function: &mut Function) {
+    // the first block is where the function starts, so it's never merged
+    let mut predecessors = vec![0; function.blocks.len()];
+
+    predecessors[0] = 1;
+
+    for block in &function.blocks {
+        for successor in block.terminator.successors() {
+            predecessors[successor] += 1;
+        }
+    }
+
+    for block in 0..function.blocks.len() {
+        while let Terminator::Goto(target) = function.blocks[block].terminator {
+            if target == block || predecessors[target] != 1 {
+                break;
+            }
+
+            let next = std::mem::replace(
+                &mut function.blocks[target],
+                BasicBlock {
+                    phis: vec![],
+                    statements: vec![],
+                    terminator: Terminator::Unreachable,
+                },
+            );
+
+            function.blocks[block].statements.extend(next.statements);
+            function.blocks[block].terminator = next.terminator;
+        }
+    }
+
+    function.remove_unreachable_blocks((0..function.blocks.len()).collect());
+}
+
+/// The locals that are known to hold a constant at some point in a function
+type Known = HashMap<Local, Const>;
+
+/// Replaces reads of locals that are known to hold a constant with the
+/// constant, operations on constants with their result, and branches on a
+/// constant with a jump. Operations that fail at runtime are left alone, so
+/// the program still stops with the error when it gets there.
+fn fold_constants(function: &mut Function) {
+    let entries = known_on_entry(function);
+
+    for (block, known) in function.blocks.iter_mut().zip(entries) {
+        // the block can't be reached any more
+        let Some(mut known) = known else {
+            continue;
+        };
+
+        for statement in &mut block.statements {
+            fold_statement(statement, &mut known);
+        }
+
+        if let Some(operand) = block.terminator.operand_mut() {
+            *operand = substitute(operand, &known);
+        }
+
+        if let Terminator::Branch(Operand::Const(Const::Bool(cond)), then, else_) = block.terminator
+        {
+            block.terminator = Terminator::Goto(if cond { then } else { else_ });
+        }
+    }
+}
+
+/// Works out which locals hold a constant at the start of each block, by
+/// going over the blocks again whenever what's known at their start changes.
+/// It's `None` for the blocks that can't be reached, which includes the ones
+/// that are only jumped to by a branch on a constant.
+fn known_on_entry(function: &Function) -> Vec<Option<Known>> {
+    let mut entries = vec![None; function.blocks.len()];
+    let mut pending = vec![0];
+
+    entries[0] = Some(Known::new());
+
+    while let Some(block) = pending.pop() {
+        let mut known = entries[block].clone().unwrap();
+
+        for statement in &function.blocks[block].statements {
+            if let Statement::Assign(local, value) = statement {
+                match evaluate(value, &known) {
+                    Some(value) => known.insert(*local, value),
+                    None => known.remove(local),
+                };
+            }
+        }
+
+        let successors = match &function.blocks[block].terminator {
+            Terminator::Branch(cond, then, else_) => match substitute(cond, &known) {
+                Operand::Const(Const::Bool(true)) => vec![*then],
+                Operand::Const(Const::Bool(false)) => vec![*else_],
+                _ => vec![*then, *else_],
+            },
+            terminator => terminator.successors(),
+        };
+
+        for successor in successors {
+            // a local is only known at the start of a block if it holds the
+            // same constant coming from everywhere that jumps there
+            let merged = match &entries[successor] {
+                None => known.clone(),
+                Some(entry) => entry
+                    .iter()
+                    .filter(|(local, value)| known.get(local).is_some_and(|k| same(k, value)))
+                    .map(|(local, value)| (*local, *value))
+                    .collect(),
+            };
+
+            if entries[successor]
+                .as_ref()
+                .is_none_or(|entry| entry.len() != merged.len())
+            {
+                entries[successor] = Some(merged);
+                pending.push(successor);
+            }
+        }
+    }
+
+    entries
+}
+
+fn fold_statement(statement: &mut Statement, known: &mut Known) {
+    statement.operands_mut(&mut |operand| *operand = substitute(operand, known));
+
+    if let Statement::Assign(local, value) = statement {
+        match evaluate(value, known) {
+            Some(constant) => {
+                *value = Rvalue::Use(Operand::Const(constant));
+                known.insert(*local, constant);
+            }
+            None => {
+                known.remove(local);
+            }
+        }
+    }
+}
+
+fn substitute(operand: &Operand, known: &Known) -> Operand {
+    match operand {
+        Operand::Local(local) => match known.get(local) {
+            Some(value) => Operand::Const(*value),
+            None => *operand,
+        },
+        Operand::Const(_) => *operand,
+    }
+}
+
+/// The constant a value always is, if it's known before running the program
+/// and working it out can't fail
+fn evaluate(value: &Rvalue, known: &Known) -> Option<Const> {
+    let constant = |operand: &Operand| match substitute(operand, known) {
+        Operand::Const(value) => Some(value),
+        Operand::Local(_) => None,
+    };
+
+    match value {
+        Rvalue::Use(operand) => constant(operand),
+        Rvalue::Prefix(op, _, operand, _) => {
+            from_literal(fold_prefix(*op, &to_literal(constant(operand)?))?)
+        }
+        Rvalue::Binary(op, _, lhs, rhs, _) => from_literal(fold_binary(
+            *op,
+            &to_literal(constant(lhs)?),
+            &to_literal(constant(rhs)?),
+        )?),
+        Rvalue::Cast(_, to, operand, _) => {
+            let value = match constant(operand)? {
+                Const::Int(n) => Value::Int(n),
+                Const::Float(n) => Value::Float(n),
+                Const::Bool(b) => Value::Bool(b),
+                Const::Char(c) => Value::Char(c),
+            };
+
+            match cast(value, &to.to_type()).ok()? {
+                Value::Int(n) => Some(Const::Int(n)),
+                Value::Float(n) => Some(Const::Float(n)),
+                Value::Bool(b) => Some(Const::Bool(b)),
+                Value::Char(c) => Some(Const::Char(c)),
+                _ => None,
+            }
+        }
+        Rvalue::Global(_) | Rvalue::Call(_, _, _) | Rvalue::Tick => None,
+    }
+}
+
+/// Whether two constants are the same, which for floats means the same bits
+/// so that `0.0` and `-0.0` are told apart
+fn same(a: &Const, b: &Const) -> bool {
+    match (a, b) {
+        (Const::Float(a), Const::Float(b)) => a.to_bits() == b.to_bits(),
+        _ => a == b,
+    }
+}
+
+fn to_literal(value: Const) -> Literal {
+    match value {
+        Const::Int(n) => Literal::Int(n),
+        Const::Float(n) => Literal::Float(n),
+        Const::Bool(b) => Literal::Bool(b),
+        Const::Char(c) => Literal::Char(c),
+    }
+}
+
+fn from_literal(literal: Literal) -> Option<Const> {
+    match literal {
+        Literal::Int(n) => Some(Const::Int(n)),
+        Literal::Float(n) => Some(Const::Float(n)),
+        Literal::Bool(b) => Some(Const::Bool(b)),
+        Literal::Char(c) => Some(Const::Char(c)),
+        Literal::Str(_) | Literal::Unit | Literal::None => None,
+    }
+}
+
+/// Works out which functions don't touch top level variables, print or
+/// call `tick()`, and only call other functions like that, so that calling
+/// one twice with the same arguments gives the same result. A function is
+/// assumed to be pure until something in it or a function it calls says
+/// otherwise, so that recursive functions can be pure too.
+fn pure_functions(program: &Program) -> Vec<bool> {
+    let mut pure = vec![true; program.functions.len()];
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for (index, function) in program.functions.iter().enumerate() {
+            if !pure[index] {
+                continue;
+            }
+
+            let impure = function.blocks.iter().any(|block| {
+                block.statements.iter().any(|statement| match statement {
+                    Statement::Assign(_, value) | Statement::Eval(value) => match value {
+                        Rvalue::Global(_) | Rvalue::Tick => true,
+                        Rvalue::Call(callee, _, _) => !pure[*callee],
+                        _ => false,
+                    },
+                    Statement::SetGlobal(_, _)
+                    | Statement::Initialize(_)
+                    | Statement::CheckInitialized(_, _)
+                    | Statement::Print(_)
+                    | Statement::Flush => true,
+                })
+            });
+
+            if impure {
+                pure[index] = false;
+                changed = true;
+            }
+        }
+    }
+
+    pure
+}
+
+/// Replaces values that have already been worked out earlier in the same
+/// block with the local they were stored in, so `(a + b) * (a + b)` only
+/// adds once. Reads of a local that holds the same value as an earlier one
+/// are replaced too, leaving the copies for dead code elimination.
+fn eliminate_common_subexpressions(function: &mut Function, pure: &[bool]) {
+    for block in &mut function.blocks {
+        let mut numbering = Numbering::default();
+
+        for statement in &mut block.statements {
+            numbering.statement(statement, pure);
+        }
+
+        if let Some(operand) = block.terminator.operand_mut() {
+            numbering.canonical(operand);
+        }
+    }
+}
+
+/// A value in terms of the value numbers of its operands, where the same
+/// key means the same value
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+enum Key {
+    /// A constant's type and bits
+    Const(Ty, u64),
+    Prefix(PrefixOp, Ty, usize),
+    Binary(BinOp, Ty, usize, usize),
+    Cast(Ty, usize),
+    /// A top level variable, which can only be the same value if nothing
+    /// could have changed it in between
+    Global(GlobalId, usize),
+    Call(FunctionId, Vec<usize>),
+}
+
+/// Numbers the values a block works out so that the same number means the
+/// same value
+#[derive(Default)]
+struct Numbering {
+    /// The number of the value each local holds
+    locals: HashMap<Local, usize>,
+    values: HashMap<Key, usize>,
+    /// A local that held each value when it was stored in it, which may have
+    /// been assigned something else since
+    holders: HashMap<usize, Local>,
+    /// Goes up whenever a top level variable might change
+    epoch: usize,
+    next: usize,
+}
+
+impl Numbering {
+    fn fresh(&mut self) -> usize {
+        self.next += 1;
+        self.next - 1
+    }
+
+    fn number(&mut self, operand: &Operand) -> usize {
+        match *operand {
+            Operand::Local(local) => match self.locals.get(&local) {
+                Some(&value) => value,
+                // what it held before the block
+                None => {
+                    let value = self.fresh();
+
+                    self.hold(local, value);
+
+                    value
+                }
+            },
+            Operand::Const(constant) => {
+                let key = match constant {
+                    Const::Int(n) => Key::Const(Ty::Int, n as u64),
+                    Const::Float(n) => Key::Const(Ty::Float, n.to_bits()),
+                    Const::Bool(b) => Key::Const(Ty::Bool, b as u64),
+                    Const::Char(c) => Key::Const(Ty::Char, c as u64),
+                };
+
+                match self.values.get(&key) {
+                    Some(&value) => value,
+                    None => {
+                        let value = self.fresh();
+
+                        self.values.insert(key, value);
+
+                        value
+                    }
+                }
+            }
+        }
+    }
+
+    /// The local that still holds a value, if there is one
+    fn holder(&self, value: usize) -> Option<Local> {
+        self.holders
+            .get(&value)
+            .copied()
+            .filter(|holder| self.locals.get(holder) == Some(&value))
+    }
+
+    /// Records that a local now holds a value
+    fn hold(&mut self, local: Local, value: usize) {
+        self.locals.insert(local, value);
+
+        if self.holder(value).is_none() {
+            self.holders.insert(value, local);
+        }
+    }
+
+    /// Replaces a read of a local with a read of the first local that holds
+    /// the same value
+    fn canonical(&mut self, operand: &mut Operand) {
+        if let Operand::Local(_) = operand {
+            let value = self.number(operand);
+
+            if let Some(holder) = self.holder(value) {
+                *operand = Operand::Local(holder);
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &mut Statement, pure: &[bool]) {
+        statement.operands_mut(&mut |operand| self.canonical(operand));
+
+        match statement {
+            Statement::Assign(local, value) => {
+                // comparing a value with itself, which for floats could be NaN
+                if let Rvalue::Binary(op, ty, lhs, rhs, _) = value {
+                    if *ty != Ty::Float && self.number(lhs) == self.number(rhs) {
+                        let result = match op {
+                            BinOp::Equals | BinOp::LessThanOrEqual | BinOp::GreaterThanOrEqual => {
+                                Some(true)
+                            }
+                            BinOp::NotEquals | BinOp::LessThan | BinOp::GreaterThan => Some(false),
+                            _ => None,
+                        };
+
+                        if let Some(result) = result {
+                            *value = Rvalue::Use(Operand::Const(Const::Bool(result)));
+                        }
+                    }
+                }
+
+                let number = match self.key(value, pure) {
+                    Some(key) => match self.values.get(&key) {
+                        Some(&number) => {
+                            if let Some(holder) = self.holder(number) {
+                                *value = Rvalue::Use(Operand::Local(holder));
+                            }
+
+                            number
+                        }
+                        None => {
+                            let number = self.fresh();
+
+                            self.values.insert(key, number);
+
+                            number
+                        }
+                    },
+                    None => match value {
+                        Rvalue::Use(operand) => self.number(operand),
+                        _ => self.fresh(),
+                    },
+                };
+
+                self.effects(value, pure);
+                self.hold(*local, number);
+            }
+            Statement::Eval(value) => self.effects(value, pure),
+            // reading the variable straight after gives what was stored
+            Statement::SetGlobal(global, value) => {
+                self.epoch += 1;
+
+                let number = self.number(value);
+
+                self.values.insert(Key::Global(*global, self.epoch), number);
+            }
+            Statement::Initialize(_)
+            | Statement::CheckInitialized(_, _)
+            | Statement::Print(_)
+            | Statement::Flush => {}
+        }
+    }
+
+    /// The key of a value that's the same every time its operands are, if
+    /// it's not just a copy of an operand
+    fn key(&mut self, value: &Rvalue, pure: &[bool]) -> Option<Key> {
+        Some(match value {
+            Rvalue::Use(_) | Rvalue::Tick => return None,
+            Rvalue::Prefix(op, ty, operand, _) => Key::Prefix(*op, *ty, self.number(operand)),
+            Rvalue::Binary(op, ty, lhs, rhs, _) => {
+                let mut lhs = self.number(lhs);
+                let mut rhs = self.number(rhs);
+
+                if matches!(
+                    op,
+                    BinOp::Add
+                        | BinOp::Multiply
+                        | BinOp::BitAnd
+                        | BinOp::BitOr
+                        | BinOp::BitXor
+                        | BinOp::Equals
+                        | BinOp::NotEquals
+                ) && rhs < lhs
+                {
+                    std::mem::swap(&mut lhs, &mut rhs);
+                }
+
+                Key::Binary(*op, *ty, lhs, rhs)
+            }
+            Rvalue::Cast(_, to, operand, _) => Key::Cast(*to, self.number(operand)),
+            Rvalue::Global(global) => Key::Global(*global, self.epoch),
+            Rvalue::Call(callee, args, _) if pure[*callee] => {
+                Key::Call(*callee, args.iter().map(|arg| self.number(arg)).collect())
+            }
+            Rvalue::Call(_, _, _) => return None,
+        })
+    }
+
+    /// Notes that a call to a function that isn't pure might change any top
+    /// level variable
+    fn effects(&mut self, value: &Rvalue, pure: &[bool]) {
+        if let Rvalue::Call(callee, _, _) = value {
+            if !pure[*callee] {
+                self.epoch += 1;
+            }
+        }
+    }
+}
+
+/// Removes the blocks that can't be reached, the assignments to locals and
+/// top level variables that are never read afterwards, and the locals that
+/// are left unused. An assigned value that can fail or does something else
+/// is still worked out.
+fn eliminate_dead_code(program: &mut Program) {
+    for function in &mut program.functions {
+        merge_blocks(function);
+    }
+
+    let read = globals_read(program);
+
+    for function in &mut program.functions {
+        for block in &mut function.blocks {
+            block.statements.retain(|statement| match statement {
+                Statement::SetGlobal(global, _) | Statement::Initialize(global) => {
+                    read.contains(global)
+                }
+                _ => true,
+            });
+        }
+
+        while eliminate_dead_assignments(function) {}
+
+        remove_unused_locals(function);
+    }
+}
+
+fn globals_read(program: &Program) -> HashSet<GlobalId> {
+    let mut read = HashSet::new();
+
+    for function in &program.functions {
+        for block in &function.blocks {
+            for statement in &block.statements {
+                match statement {
+                    Statement::Assign(_, Rvalue::Global(global))
+                    | Statement::Eval(Rvalue::Global(global))
+                    | Statement::CheckInitialized(global, _) => {
+                        read.insert(*global);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    read
+}
+
+/// Notes about the variables that are never read, and the values that are
+/// worked out and then thrown away, in the order they're written in
+fn unused(program: &Program) -> Vec<Warning> {
+    let read = globals_read(program);
+    let mut notes = vec![];
+
+    for (global, declaration) in program.globals.iter().enumerate() {
+        if !read.contains(&global) {
+            notes.push(Warning::UnusedVariable {
+                name: declaration.name.clone(),
+                span: declaration.span,
+            });
+        }
+    }
+
+    for function in &program.functions {
+        notes.extend(unused_in(function));
+    }
+
+    notes.sort_by_key(|note| match note {
+        Warning::UnusedVariable { span, .. } | Warning::UnusedValue { span } => span.start,
+        _ => unreachable!(),
+    });
+
+    notes
+}
+
+fn unused_in(function: &Function) -> Vec<Warning> {
+    let mut read = HashSet::new();
+
+    for block in &function.blocks {
+        for statement in &block.statements {
+            statement.operands(&mut |operand| reads(operand, &mut read));
+        }
+
+        if let Some(operand) = block.terminator.operand() {
+            reads(operand, &mut read);
+        }
+    }
+
+    let mut notes = vec![];
+
+    for (local, declaration) in function.locals.iter().enumerate().skip(function.params) {
+        if let (Some(name), false) = (&declaration.name, read.contains(&local)) {
+            notes.push(Warning::UnusedVariable {
+                name: name.0.clone(),
+                span: name.1,
+            });
+        }
+    }
+
+    for block in &function.blocks {
+        for statement in &block.statements {
+            let value = match statement {
+                // a variable that's never used already has a note
+                Statement::Assign(local, value)
+                    if function.locals[*local].name.is_none() && !read.contains(local) =>
+                {
+                    value
+                }
+                Statement::Eval(value) => value,
+                _ => continue,
+            };
+
+            match value {
+                Rvalue::Prefix(_, _, _, span)
+                | Rvalue::Binary(_, _, _, _, span)
+                | Rvalue::Cast(_, _, _, span)
+                    if pure(value) =>
+                {
+                    notes.push(Warning::UnusedValue { span: *span })
+                }
+                _ => {}
+            }
+        }
+    }
+
+    notes
+}
+
+/// Removes the assignments to locals that aren't read before they're
+/// assigned again or the function returns, returning whether anything was
+/// removed
+fn eliminate_dead_assignments(function: &mut Function) -> bool {
+    let live = live_on_exit(function);
+    let mut changed = false;
+
+    for (block, mut live) in function.blocks.iter_mut().zip(live) {
+        if let Some(operand) = block.terminator.operand() {
+            reads(operand, &mut live);
+        }
+
+        let mut statements = vec![];
+
+        for mut statement in std::mem::take(&mut block.statements).into_iter().rev() {
+            match statement {
+                Statement::Assign(local, value) if !live.contains(&local) => {
+                    changed = true;
+
+                    if pure(&value) {
+                        continue;
+                    }
+
+                    statement = Statement::Eval(value);
+                }
+                Statement::Eval(ref value) if pure(value) => {
+                    changed = true;
+
+                    continue;
+                }
+                _ => {}
+            }
+
+            if let Statement::Assign(local, _) = statement {
+                live.remove(&local);
+            }
+
+            statement.operands(&mut |operand| reads(operand, &mut live));
+            statements.push(statement);
+        }
+
+        statements.reverse();
+        block.statements = statements;
+    }
+
+    changed
+}
+
+/// Works out which locals are read after each block before they're
+/// assigned again
+fn live_on_exit(function: &Function) -> Vec<HashSet<Local>> {
+    let live_on_entry = function.live_on_entry();
+
+    function
+        .blocks
+        .iter()
+        .map(|block| {
+            block
+                .terminator
+                .successors()
+                .into_iter()
+                .flat_map(|successor| live_on_entry[successor].iter().copied())
+                .collect()
+        })
+        .collect()
+}
+
+fn reads(operand: &Operand, read: &mut HashSet<Local>) {
+    if let Operand::Local(local) = operand {
+        read.insert(*local);
+    }
+}
+
+/// Removes the locals other than params that nothing reads or assigns to
+/// any more, and renumbers the rest
+fn remove_unused_locals(function: &mut Function) {
+    let mut used = vec![false; function.locals.len()];
+
+    used[..function.params].fill(true);
+
+    for block in &function.blocks {
+        for statement in &block.statements {
+            if let Statement::Assign(local, _) = statement {
+                used[*local] = true;
+            }
+
+            statement.operands(&mut |operand| {
+                if let Operand::Local(local) = operand {
+                    used[*local] = true;
+                }
+            });
+        }
+
+        if let Some(Operand::Local(local)) = block.terminator.operand() {
+            used[*local] = true;
+        }
+    }
+
+    let mut ids = vec![0; function.locals.len()];
+    let mut locals = vec![];
+
+    for (local, declaration) in std::mem::take(&mut function.locals).into_iter().enumerate() {
+        if used[local] {
+            ids[local] = locals.len();
+            locals.push(declaration);
+        }
+    }
+
+    for block in &mut function.blocks {
+        renumber_locals(block, |local| ids[local]);
+    }
+
+    function.locals = locals;
+}
+
+/// Changes every local a block assigns or reads to `f` of it
+fn renumber_locals(block: &mut BasicBlock, f: impl Fn(Local) -> Local) {
+    let mut renumber = |operand: &mut Operand| {
+        if let Operand::Local(local) = operand {
+            *local = f(*local);
+        }
+    };
+
+    for statement in &mut block.statements {
+        if let Statement::Assign(local, _) = statement {
+            *local = f(*local);
+        }
+
+        statement.operands_mut(&mut renumber);
+    }
+
+    if let Some(operand) = block.terminator.operand_mut() {
+        renumber(operand);
+    }
+}
+
+/// Whether working out a value can't fail and doesn't do anything else, so
+/// it can be skipped if it's not used
+fn pure(value: &Rvalue) -> bool {
+    match value {
+        Rvalue::Use(_) | Rvalue::Global(_) => true,
+        Rvalue::Prefix(op, ty, _, _) => !matches!((op, ty), (PrefixOp::Negate, Ty::Int)),
+        // arithmetic on ints can overflow or divide by zero
+        Rvalue::Binary(op, ty, _, _, _) => !matches!(
+            (ty, op),
+            (
+                Ty::Int,
+                BinOp::Add
+                    | BinOp::Subtract
+                    | BinOp::Multiply
+                    | BinOp::Divide
+                    | BinOp::Modulo
+                    | BinOp::Power
+                    | BinOp::ShiftLeft
+                    | BinOp::ShiftRight
+            )
+        ),
+        Rvalue::Cast(from, to, _, _) => {
+            !matches!((from, to), (Ty::Float, Ty::Int) | (Ty::Int, Ty::Char))
+        }
+        Rvalue::Call(_, _, _) | Rvalue::Tick => false,
+    }
+}
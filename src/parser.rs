@@ -23,7 +23,8 @@ fn program_parser<'tokens, 'src: 'tokens>() -> impl Parser<
     Spanned<Vec<Spanned<Statement<'src>>>>,
     extra::Err<Rich<'tokens, Token<'src>, Span>>,
 > {
-    statement_parser()
+    const_parser()
+        .or(statement_parser())
         .repeated()
         .collect()
         .then_ignore(end())
@@ -31,19 +32,69 @@ fn program_parser<'tokens, 'src: 'tokens>() -> impl Parser<
         .boxed()
 }
 
-fn statement_parser<'tokens, 'src: 'tokens>() -> impl Parser<
+fn const_parser<'tokens, 'src: 'tokens>() -> impl Parser<
     'tokens,
     ParserInput<'tokens, 'src>,
     Spanned<Statement<'src>>,
     extra::Err<Rich<'tokens, Token<'src>, Span>>,
 > {
+    just(Token::Keyword(Keyword::Const))
+        .ignore_then(ident_parser())
+        .then(
+            just(Token::Control(Control::Colon))
+                .ignore_then(type_parser())
+                .or_not(),
+        )
+        .then_ignore(just(Token::Control(Control::Equals)))
+        .then(expression_parser(statement_parser()))
+        .then_ignore(just(Token::Control(Control::Semicolon)))
+        .map(|((name, ty), value)| Statement::Const { name, ty, value })
+        .map_with_span(|statement, span| (statement, span))
+        .boxed()
+}
+
+fn statement_parser<'tokens, 'src: 'tokens>() -> impl Parser<
+    'tokens,
+    ParserInput<'tokens, 'src>,
+    Spanned<Statement<'src>>,
+    extra::Err<Rich<'tokens, Token<'src>, Span>>,
+> + Clone {
     recursive(|statement| {
-        let expr = expression_parser()
-            .then_ignore(just(Token::Control(Control::Semicolon)))
-            .map(Statement::Expr)
+        let expression = expression_parser(statement.clone());
+
+        // like `while` and `for`, an expression ending in a block doesn't need
+        // a `;` after it, which is checked after parsing it so that it's only
+        // parsed once. Parentheses aren't kept in the expression, so one that
+        // was in them is told apart by its span ending before the `)`
+        let expr = expression
+            .clone()
+            .map_with_span(|expr, span: Span| (expr, span))
+            .then(just(Token::Control(Control::Semicolon)).or_not())
+            .try_map(|((expr, end), semicolon), _| match (expr, semicolon) {
+                (expr, Some(_)) => Ok(Statement::Expr(expr)),
+                // a block without a value on its own is a block statement
+                (
+                    (
+                        Expr::Block(Block {
+                            statements,
+                            tail: None,
+                        }),
+                        span,
+                    ),
+                    None,
+                ) => Ok(Statement::Block((statements, span))),
+                (expr, None) if expr.1.end == end.end && ends_in_block(&expr.0) => {
+                    Ok(Statement::Expr(expr))
+                }
+                (expr, None) => Err(Rich::custom(
+                    Span::from(expr.1.end..expr.1.end),
+                    "Expected ';'",
+                )),
+            })
             .boxed();
 
         let block = statement
+            .clone()
             .repeated()
             .collect()
             .delimited_by(
@@ -55,16 +106,22 @@ fn statement_parser<'tokens, 'src: 'tokens>() -> impl Parser<
             .boxed();
 
         let let_ = just(Token::Keyword(Keyword::Let))
-            .ignore_then(ident_parser())
+            .ignore_then(just(Token::Keyword(Keyword::Mut)).or_not())
+            .then(ident_parser())
             .then(
                 just(Token::Control(Control::Colon))
                     .ignore_then(type_parser())
                     .or_not(),
             )
             .then_ignore(just(Token::Control(Control::Equals)))
-            .then(expression_parser())
+            .then(expression.clone())
             .then_ignore(just(Token::Control(Control::Semicolon)))
-            .map(|((name, ty), value)| Statement::Let { name, ty, value })
+            .map(|(((mutable, name), ty), value)| Statement::Let {
+                mutable: mutable.is_some(),
+                name,
+                ty,
+                value,
+            })
             .boxed();
 
         let func_args = ident_parser()
@@ -79,8 +136,31 @@ fn statement_parser<'tokens, 'src: 'tokens>() -> impl Parser<
                 just(Token::Control(Control::RightParen)),
             );
 
+        let generics = ident_parser()
+            .then(
+                just(Token::Control(Control::Colon))
+                    .ignore_then(
+                        ident_parser()
+                            .separated_by(just(Token::Operator(Operator::Plus)))
+                            .at_least(1)
+                            .collect(),
+                    )
+                    .or_not()
+                    .map(Option::unwrap_or_default),
+            )
+            .separated_by(just(Token::Control(Control::Comma)))
+            .allow_trailing()
+            .collect()
+            .delimited_by(
+                just(Token::Operator(Operator::LessThan)),
+                just(Token::Operator(Operator::GreaterThan)),
+            )
+            .or_not()
+            .map(Option::unwrap_or_default);
+
         let func = just(Token::Keyword(Keyword::Func))
             .ignore_then(ident_parser())
+            .then(generics)
             .then(func_args)
             .then(
                 just(Token::Control(Control::Colon))
@@ -88,46 +168,345 @@ fn statement_parser<'tokens, 'src: 'tokens>() -> impl Parser<
                     .or_not(),
             )
             .then(block.clone().map_with_span(|block, span| (block, span)))
-            .map(|(((name, params), return_ty), body)| Statement::Function {
-                name,
-                params,
-                return_ty,
-                body: Box::new(body),
-            })
+            .map(
+                |((((name, generics), params), return_ty), body)| Statement::Function {
+                    name,
+                    generics,
+                    params,
+                    return_ty,
+                    body: Box::new(body),
+                },
+            )
             .boxed();
 
         let assign = ident_parser()
             .then_ignore(just(Token::Control(Control::Equals)))
-            .then(expression_parser())
+            .then(expression.clone())
             .then_ignore(just(Token::Control(Control::Semicolon)))
             .map(|(name, value)| Statement::Assign { name, value })
             .boxed();
 
-        let print = just(Token::Keyword(Keyword::Print))
-            .ignore_then(expression_parser().or_not())
+        let set_index = ident_parser()
+            .then(expression.clone().delimited_by(
+                just(Token::Control(Control::LeftSquare)),
+                just(Token::Control(Control::RightSquare)),
+            ))
+            .then_ignore(just(Token::Control(Control::Equals)))
+            .then(expression.clone())
             .then_ignore(just(Token::Control(Control::Semicolon)))
-            .map(Statement::Print)
+            .map(|((name, index), value)| Statement::SetIndex { name, index, value })
+            .boxed();
+
+        // `x += y` is parsed as `x = x + y`
+        let compound_assign = ident_parser()
+            .then(
+                select! {
+                    Token::Operator(Operator::PlusEquals) => BinOp::Add,
+                    Token::Operator(Operator::MinusEquals) => BinOp::Subtract,
+                    Token::Operator(Operator::StarEquals) => BinOp::Multiply,
+                    Token::Operator(Operator::SlashEquals) => BinOp::Divide,
+                }
+                .map_with_span(|op, span| (op, span)),
+            )
+            .then(expression.clone())
+            .then_ignore(just(Token::Control(Control::Semicolon)))
+            .map(|((name, op), rhs)| {
+                let span = Span::from(name.1.start..rhs.1.end);
+
+                Statement::Assign {
+                    name,
+                    value: (
+                        Expr::Binary {
+                            op,
+                            lhs: Box::new((Expr::Var(name), name.1)),
+                            rhs: Box::new(rhs),
+                        },
+                        span,
+                    ),
+                }
+            })
             .boxed();
 
         let return_ = just(Token::Keyword(Keyword::Return))
-            .ignore_then(expression_parser().or_not())
+            .ignore_then(expression.clone().or_not())
             .then_ignore(just(Token::Control(Control::Semicolon)))
             .map(Statement::Return)
             .boxed();
 
-        choice((expr, block, let_, func, assign, print, return_))
-            .map_with_span(|statement, span| (statement, span))
-            .boxed()
+        let break_ = just(Token::Keyword(Keyword::Break))
+            .ignore_then(label_parser().or_not())
+            .then(expression.clone().or_not())
+            .then_ignore(just(Token::Control(Control::Semicolon)))
+            .map(|(label, value)| Statement::Break { label, value })
+            .boxed();
+
+        let continue_ = just(Token::Keyword(Keyword::Continue))
+            .ignore_then(just(Token::Control(Control::Semicolon)))
+            .to(Statement::Continue)
+            .boxed();
+
+        let while_ = just(Token::Keyword(Keyword::While))
+            .ignore_then(expression.clone())
+            .then(
+                statement
+                    .clone()
+                    .repeated()
+                    .collect()
+                    .delimited_by(
+                        just(Token::Control(Control::LeftCurly)),
+                        just(Token::Control(Control::RightCurly)),
+                    )
+                    .map_with_span(|statements, span| (statements, span)),
+            )
+            .map(|(cond, body)| Statement::While { cond, body })
+            .boxed();
+
+        let for_ = just(Token::Keyword(Keyword::For))
+            .ignore_then(ident_parser())
+            .then(
+                just(Token::Control(Control::Comma))
+                    .ignore_then(ident_parser())
+                    .or_not(),
+            )
+            .then_ignore(just(Token::Keyword(Keyword::In)))
+            .then(expression.clone())
+            .then(
+                statement
+                    .clone()
+                    .repeated()
+                    .collect()
+                    .delimited_by(
+                        just(Token::Control(Control::LeftCurly)),
+                        just(Token::Control(Control::RightCurly)),
+                    )
+                    .map_with_span(|statements, span| (statements, span)),
+            )
+            .map(|(((first, second), iter), body)| Statement::For {
+                first,
+                second,
+                iter,
+                body,
+            })
+            .boxed();
+
+        let enum_variant = ident_parser()
+            .then(
+                type_parser()
+                    .separated_by(just(Token::Control(Control::Comma)))
+                    .allow_trailing()
+                    .collect()
+                    .delimited_by(
+                        just(Token::Control(Control::LeftParen)),
+                        just(Token::Control(Control::RightParen)),
+                    )
+                    .or_not()
+                    .map(Option::unwrap_or_default),
+            )
+            .boxed();
+
+        let enum_ = just(Token::Keyword(Keyword::Enum))
+            .ignore_then(ident_parser())
+            .then(
+                enum_variant
+                    .separated_by(just(Token::Control(Control::Comma)))
+                    .allow_trailing()
+                    .collect()
+                    .map_with_span(|variants, span| (variants, span))
+                    .delimited_by(
+                        just(Token::Control(Control::LeftCurly)),
+                        just(Token::Control(Control::RightCurly)),
+                    ),
+            )
+            .map(|(name, variants)| Statement::Enum { name, variants })
+            .boxed();
+
+        let struct_ = just(Token::Keyword(Keyword::Struct))
+            .ignore_then(ident_parser())
+            .then(
+                ident_parser()
+                    .then_ignore(just(Token::Control(Control::Colon)))
+                    .then(type_parser())
+                    .separated_by(just(Token::Control(Control::Comma)))
+                    .allow_trailing()
+                    .collect()
+                    .map_with_span(|fields, span| (fields, span))
+                    .delimited_by(
+                        just(Token::Control(Control::LeftCurly)),
+                        just(Token::Control(Control::RightCurly)),
+                    ),
+            )
+            .map(|(name, fields)| Statement::Struct { name, fields })
+            .boxed();
+
+        let type_alias = just(Token::Keyword(Keyword::Type))
+            .ignore_then(ident_parser())
+            .then_ignore(just(Token::Control(Control::Equals)))
+            .then(type_parser())
+            .then_ignore(just(Token::Control(Control::Semicolon)))
+            .map(|(name, ty)| Statement::TypeAlias { name, ty })
+            .boxed();
+
+        let import = just(Token::Keyword(Keyword::Import))
+            .ignore_then(
+                select! { Token::Str(path) => path }
+                    .map_with_span(|path, span| ((path, span), None))
+                    .or(ident_parser().map(|module: Spanned<&str>| {
+                        ((format!("{}.fox", module.0), module.1), Some(module))
+                    })),
+            )
+            .then_ignore(just(Token::Control(Control::Semicolon)))
+            .map(|(path, module)| Statement::Import {
+                path,
+                module,
+                file: 0,
+                statements: vec![],
+            })
+            .boxed();
+
+        let method_sig = just(Token::Keyword(Keyword::Func))
+            .ignore_then(ident_parser())
+            .then(
+                select! { Token::Ident("self") => () }
+                    .map_with_span(|(), span| span)
+                    .then(
+                        just(Token::Control(Control::Comma))
+                            .ignore_then(
+                                ident_parser()
+                                    .then_ignore(just(Token::Control(Control::Colon)))
+                                    .then(type_parser()),
+                            )
+                            .repeated()
+                            .collect()
+                            .map_with_span(|params, span| (params, span)),
+                    )
+                    .then_ignore(just(Token::Control(Control::Comma)).or_not())
+                    .delimited_by(
+                        just(Token::Control(Control::LeftParen)),
+                        just(Token::Control(Control::RightParen)),
+                    ),
+            )
+            .then(
+                just(Token::Control(Control::Colon))
+                    .ignore_then(type_parser())
+                    .or_not(),
+            )
+            .map_with_span(|((name, (self_, params)), return_ty), span| {
+                (
+                    MethodSig {
+                        name,
+                        self_,
+                        params,
+                        return_ty,
+                    },
+                    span,
+                )
+            })
+            .boxed();
+
+        let trait_ = just(Token::Keyword(Keyword::Trait))
+            .ignore_then(ident_parser())
+            .then(
+                method_sig
+                    .clone()
+                    .then_ignore(just(Token::Control(Control::Semicolon)))
+                    .repeated()
+                    .collect()
+                    .delimited_by(
+                        just(Token::Control(Control::LeftCurly)),
+                        just(Token::Control(Control::RightCurly)),
+                    ),
+            )
+            .map(|(name, methods)| Statement::Trait { name, methods })
+            .boxed();
+
+        let impl_ = just(Token::Keyword(Keyword::Impl))
+            .ignore_then(
+                ident_parser()
+                    .then_ignore(just(Token::Keyword(Keyword::For)))
+                    .or_not(),
+            )
+            .then(type_parser())
+            .then(
+                method_sig
+                    .then(block.clone().map_with_span(|block, span| (block, span)))
+                    .repeated()
+                    .collect()
+                    .delimited_by(
+                        just(Token::Control(Control::LeftCurly)),
+                        just(Token::Control(Control::RightCurly)),
+                    ),
+            )
+            .map(|((trait_, ty), methods)| Statement::Impl {
+                trait_,
+                ty,
+                methods,
+            })
+            .boxed();
+
+        choice((
+            expr,
+            block,
+            let_,
+            func,
+            assign,
+            set_index,
+            compound_assign,
+            return_,
+            break_,
+            continue_,
+            while_,
+            for_,
+            enum_,
+            struct_,
+            type_alias,
+            import,
+            trait_,
+            impl_,
+        ))
+        .map_with_span(|statement, span| (statement, span))
+        .boxed()
     })
 }
 
-fn expression_parser<'tokens, 'src: 'tokens>() -> impl Parser<
+fn expression_parser<'tokens, 'src: 'tokens>(
+    statement: impl Parser<
+            'tokens,
+            ParserInput<'tokens, 'src>,
+            Spanned<Statement<'src>>,
+            extra::Err<Rich<'tokens, Token<'src>, Span>>,
+        > + Clone
+        + 'tokens,
+) -> impl Parser<
     'tokens,
     ParserInput<'tokens, 'src>,
     Spanned<Expr<'src>>,
     extra::Err<Rich<'tokens, Token<'src>, Span>>,
-> {
+> + Clone {
     recursive(|expression| {
+        let variant = ident_parser()
+            .then_ignore(just(Token::Control(Control::DoubleColon)))
+            .then(ident_parser())
+            .then(
+                expression
+                    .clone()
+                    .separated_by(just(Token::Control(Control::Comma)))
+                    .allow_trailing()
+                    .collect()
+                    .delimited_by(
+                        just(Token::Control(Control::LeftParen)),
+                        just(Token::Control(Control::RightParen)),
+                    )
+                    .map_with_span(|args, span| (args, span))
+                    .or_not(),
+            )
+            .map(|((enum_, variant), args)| Expr::Variant {
+                enum_,
+                variant,
+                args,
+            })
+            .map_with_span(|expr, span| (expr, span))
+            .boxed();
+
         let var = ident_parser()
             .map(Expr::Var)
             .map_with_span(|expr, span| (expr, span))
@@ -138,45 +517,560 @@ fn expression_parser<'tokens, 'src: 'tokens>() -> impl Parser<
             .map_with_span(|expr, span| (expr, span))
             .boxed();
 
+        let format_spec = choice((
+            just(Token::Control(Control::Question)).to(FormatSpec::Debug),
+            select! {
+                Token::Ident("x") => FormatSpec::Hex,
+                Token::Ident("b") => FormatSpec::Binary,
+                Token::Ident("o") => FormatSpec::Octal,
+            },
+            just(Token::Control(Control::Dot))
+                .ignore_then(select! { Token::Int(digits) => digits })
+                .try_map(|digits, span| {
+                    usize::try_from(digits)
+                        .map(FormatSpec::Precision)
+                        .map_err(|_| Rich::custom(span, "Precision can't be negative"))
+                }),
+        ))
+        .boxed();
+
+        // an empty hole or one starting with `:` is a placeholder, anything
+        // else is an expression. `{{}}` would be an empty block showing as
+        // `#`, which is never what was meant, so it's reported instead
+        let hole = choice((
+            expression
+                .clone()
+                .validate(|expr: Spanned<Expr>, _, emitter| {
+                    if let Expr::Block(Block {
+                        statements,
+                        tail: None,
+                    }) = &expr.0
+                    {
+                        if statements.is_empty() {
+                            emitter.emit(Rich::custom(
+                                expr.1,
+                                "Empty block in a string, write '\\{' to put a '{' in it",
+                            ));
+                        }
+                    }
+
+                    expr
+                })
+                .map(Ok),
+            just(Token::Control(Control::Colon))
+                .ignore_then(format_spec)
+                .or_not()
+                .map(Err),
+        ))
+        .boxed();
+
+        let interpolated = select! { Token::StrStart(text) => text }
+            .map_with_span(|text, span| (text, span))
+            .then(hole.clone())
+            .then(
+                select! { Token::StrMiddle(text) => text }
+                    .map_with_span(|text, span| (text, span))
+                    .then(hole)
+                    .repeated()
+                    .collect::<Vec<_>>(),
+            )
+            .then(select! { Token::StrEnd(text) => text }.map_with_span(|text, span| (text, span)))
+            .map(|(((start, first), rest), end)| {
+                let mut texts = vec![start];
+                let mut holes = vec![first];
+
+                for (text, hole) in rest {
+                    texts.push(text);
+                    holes.push(hole);
+                }
+
+                texts.push(end);
+
+                let spans = texts.iter().map(|(_, span)| *span).collect::<Vec<_>>();
+                let mut texts = texts.into_iter();
+                let mut parts = vec![];
+
+                for (i, hole) in holes.into_iter().enumerate() {
+                    parts.push(StrPart::Text(texts.next().unwrap()));
+                    parts.push(match hole {
+                        Ok(expr) => StrPart::Expr(expr),
+                        // from the `{` ending the text before it to the `}`
+                        // starting the text after it
+                        Err(spec) => StrPart::Placeholder((
+                            spec,
+                            Span::from(spans[i].end - 1..spans[i + 1].start + 1),
+                        )),
+                    });
+                }
+
+                parts.extend(texts.map(StrPart::Text));
+
+                Expr::Interpolated(parts)
+            })
+            .map_with_span(|expr, span| (expr, span))
+            .boxed();
+
         let parenthesized_expr = expression
+            .clone()
             .delimited_by(
                 just(Token::Control(Control::LeftParen)),
                 just(Token::Control(Control::RightParen)),
             )
             .boxed();
 
-        let atom = choice((var, literal, parenthesized_expr)).boxed();
+        let option_pattern = choice((
+            select! {
+                Token::Keyword(Keyword::Some) => Pattern::Some as fn(_) -> _,
+                Token::Keyword(Keyword::Ok) => Pattern::Ok,
+                Token::Keyword(Keyword::Err) => Pattern::Err,
+            }
+            .then(ident_parser().delimited_by(
+                just(Token::Control(Control::LeftParen)),
+                just(Token::Control(Control::RightParen)),
+            ))
+            .map(|(wrap, binding)| wrap(binding)),
+            just(Token::Keyword(Keyword::None)).to(Pattern::None),
+        ))
+        .map_with_span(|pattern, span| (pattern, span))
+        .boxed();
+
+        let variant_pattern = ident_parser()
+            .then(
+                ident_parser()
+                    .separated_by(just(Token::Control(Control::Comma)))
+                    .allow_trailing()
+                    .collect()
+                    .map_with_span(|bindings, span| (bindings, span))
+                    .delimited_by(
+                        just(Token::Control(Control::LeftParen)),
+                        just(Token::Control(Control::RightParen)),
+                    )
+                    .or_not(),
+            )
+            .map(|(variant, bindings)| match bindings {
+                Some(bindings) => Pattern::Variant { variant, bindings },
+                None => Pattern::Ident(variant),
+            })
+            .map_with_span(|pattern, span| (pattern, span))
+            .boxed();
+
+        let pattern = choice((option_pattern, variant_pattern)).boxed();
 
-        let prefix_op = just(Token::Operator(Operator::Minus))
-            .to(PrefixOp::Negate)
-            .map_with_span(|op, span| (op, span))
+        let match_ = just(Token::Keyword(Keyword::Match))
+            .ignore_then(expression.clone())
+            .then(
+                pattern
+                    .then_ignore(just(Token::Control(Control::FatArrow)))
+                    .then(expression.clone())
+                    .separated_by(just(Token::Control(Control::Comma)))
+                    .allow_trailing()
+                    .collect()
+                    .map_with_span(|arms, span| (arms, span))
+                    .delimited_by(
+                        just(Token::Control(Control::LeftCurly)),
+                        just(Token::Control(Control::RightCurly)),
+                    ),
+            )
+            .map(|(expr, arms)| Expr::Match {
+                expr: Box::new(expr),
+                arms,
+            })
+            .map_with_span(|expr, span| (expr, span))
+            .boxed();
+
+        let some = select! {
+            Token::Keyword(Keyword::Some) => Expr::Some as fn(_) -> _,
+            Token::Keyword(Keyword::Ok) => Expr::Ok,
+            Token::Keyword(Keyword::Err) => Expr::Err,
+        }
+        .then(expression.clone().delimited_by(
+            just(Token::Control(Control::LeftParen)),
+            just(Token::Control(Control::RightParen)),
+        ))
+        .map(|(wrap, expr)| wrap(Box::new(expr)))
+        .map_with_span(|expr, span| (expr, span))
+        .boxed();
+
+        let record = ident_parser()
+            .then_ignore(just(Token::Control(Control::Equals)))
+            .then(expression.clone())
+            .separated_by(just(Token::Control(Control::Comma)))
+            .at_least(1)
+            .allow_trailing()
+            .collect()
+            .delimited_by(
+                just(Token::Control(Control::LeftCurly)),
+                just(Token::Control(Control::RightCurly)),
+            )
+            .map(Expr::Record)
+            .map_with_span(|expr, span| (expr, span))
             .boxed();
 
-        let prefix = prefix_op
+        // needs at least one field so that the block of `if x {}` isn't taken
+        // for the fields of a `x` struct
+        let struct_ = ident_parser()
+            .then(
+                ident_parser()
+                    .then_ignore(just(Token::Control(Control::Equals)))
+                    .then(expression.clone())
+                    .separated_by(just(Token::Control(Control::Comma)))
+                    .at_least(1)
+                    .allow_trailing()
+                    .collect()
+                    .delimited_by(
+                        just(Token::Control(Control::LeftCurly)),
+                        just(Token::Control(Control::RightCurly)),
+                    ),
+            )
+            .map(|(name, fields)| Expr::Struct { name, fields })
+            .map_with_span(|expr, span| (expr, span))
+            .boxed();
+
+        let labeled_block = label_parser()
+            .then_ignore(just(Token::Control(Control::Colon)))
+            .then(
+                statement
+                    .clone()
+                    .repeated()
+                    .collect()
+                    .map_with_span(|statements, span| (statements, span))
+                    .then(expression.clone().map(Box::new).or_not())
+                    .delimited_by(
+                        just(Token::Control(Control::LeftCurly)),
+                        just(Token::Control(Control::RightCurly)),
+                    ),
+            )
+            .map(|(label, (mut statements, tail))| Expr::LabeledBlock {
+                label,
+                tail: take_tail(&mut statements.0, tail),
+                statements,
+            })
+            .map_with_span(|expr, span| (expr, span))
+            .boxed();
+
+        let block = statement
+            .clone()
             .repeated()
-            .foldr(atom, |op, expr| {
-                let span = op.1.start..expr.1.end;
+            .collect()
+            .then(expression.clone().map(Box::new).or_not())
+            .delimited_by(
+                just(Token::Control(Control::LeftCurly)),
+                just(Token::Control(Control::RightCurly)),
+            )
+            .map(|(mut statements, tail)| Block {
+                tail: take_tail(&mut statements, tail),
+                statements,
+            })
+            .map_with_span(|block, span| (block, span))
+            .boxed();
 
-                (
-                    Expr::Prefix {
-                        op,
-                        expr: Box::new(expr),
-                    },
-                    span.into(),
+        let block_expr = block
+            .clone()
+            .map(|(block, span)| (Expr::Block(block), span))
+            .boxed();
+
+        let if_ = recursive(|if_| {
+            just(Token::Keyword(Keyword::If))
+                .ignore_then(expression.clone())
+                .then(block.clone())
+                .then(
+                    just(Token::Keyword(Keyword::Else))
+                        .ignore_then(choice((
+                            block.clone(),
+                            if_.map(|if_: Spanned<Expr<'src>>| {
+                                let span = if_.1;
+
+                                (
+                                    Block {
+                                        statements: vec![],
+                                        tail: Some(Box::new(if_)),
+                                    },
+                                    span,
+                                )
+                            }),
+                        )))
+                        .or_not(),
                 )
+                .map(|((cond, then), else_)| Expr::If {
+                    cond: Box::new(cond),
+                    then,
+                    else_,
+                })
+                .map_with_span(|expr, span| (expr, span))
+        })
+        .boxed();
+
+        let catch = just(Token::Keyword(Keyword::Try))
+            .ignore_then(block.clone())
+            .then_ignore(just(Token::Keyword(Keyword::Catch)))
+            .then(ident_parser())
+            .then(block.clone())
+            .map(|((body, binding), handler)| Expr::Catch {
+                body,
+                binding,
+                handler,
             })
+            .map_with_span(|expr, span| (expr, span))
+            .boxed();
+
+        let loop_ = just(Token::Keyword(Keyword::Loop))
+            .ignore_then(
+                statement
+                    .clone()
+                    .repeated()
+                    .collect()
+                    .delimited_by(
+                        just(Token::Control(Control::LeftCurly)),
+                        just(Token::Control(Control::RightCurly)),
+                    )
+                    .map_with_span(|statements, span| (statements, span)),
+            )
+            .map(|body| Expr::Loop { body })
+            .map_with_span(|expr, span| (expr, span))
+            .boxed();
+
+        let list = expression
+            .clone()
+            .separated_by(just(Token::Control(Control::Comma)))
+            .allow_trailing()
+            .collect()
+            .delimited_by(
+                just(Token::Control(Control::LeftSquare)),
+                just(Token::Control(Control::RightSquare)),
+            )
+            .map_with_span(|elements, span| (elements, span))
+            .map(Expr::List)
+            .map_with_span(|expr, span| (expr, span))
+            .boxed();
+
+        // `{:}` is the empty map, since `{}` is an empty block
+        let map = choice((
+            expression
+                .clone()
+                .then_ignore(just(Token::Control(Control::Colon)))
+                .then(expression.clone())
+                .separated_by(just(Token::Control(Control::Comma)))
+                .at_least(1)
+                .allow_trailing()
+                .collect(),
+            just(Token::Control(Control::Colon)).to(vec![]),
+        ))
+        .delimited_by(
+            just(Token::Control(Control::LeftCurly)),
+            just(Token::Control(Control::RightCurly)),
+        )
+        .map_with_span(|entries, span| (entries, span))
+        .map(Expr::Map)
+        .map_with_span(|expr, span| (expr, span))
+        .boxed();
+
+        let closure_params = ident_parser()
+            .then(
+                just(Token::Control(Control::Colon))
+                    .ignore_then(type_parser())
+                    .or_not(),
+            )
+            .separated_by(just(Token::Control(Control::Comma)))
+            .allow_trailing()
+            .collect()
+            .delimited_by(
+                just(Token::Operator(Operator::Pipe)),
+                just(Token::Operator(Operator::Pipe)),
+            );
+
+        // `||` is lexed as a single token, so a closure without parameters needs
+        // its own case
+        let closure = choice((
+            closure_params,
+            just(Token::Operator(Operator::LogicalOr)).to(vec![]),
+        ))
+        .map_with_span(|params, span| (params, span))
+        .then(expression.clone())
+        .map(|(params, body)| Expr::Closure {
+            params,
+            body: Box::new(body),
+        })
+        .map_with_span(|expr, span| (expr, span))
+        .boxed();
+
+        let atom = choice((
+            labeled_block,
+            if_,
+            catch,
+            loop_,
+            list,
+            match_,
+            variant,
+            some,
+            record,
+            block_expr,
+            // after blocks, so that only a `{` which doesn't start a block is
+            // parsed again as a map
+            map,
+            struct_,
+            closure,
+            var,
+            literal,
+            interpolated,
+            parenthesized_expr,
+        ))
+        .boxed();
+
+        let call_args = expression
+            .clone()
+            .separated_by(just(Token::Control(Control::Comma)))
+            .allow_trailing()
+            .collect()
+            .delimited_by(
+                just(Token::Control(Control::LeftParen)),
+                just(Token::Control(Control::RightParen)),
+            )
+            .map_with_span(|args, span| (args, span))
+            .map(Postfix::Call);
+
+        let field = just(Token::Control(Control::Dot))
+            .ignore_then(ident_parser())
+            .map(Postfix::Field);
+
+        let try_ =
+            just(Token::Control(Control::Question)).map_with_span(|_, span| Postfix::Try(span));
+
+        let index = expression
+            .clone()
+            .delimited_by(
+                just(Token::Control(Control::LeftSquare)),
+                just(Token::Control(Control::RightSquare)),
+            )
+            .map_with_span(Postfix::Index);
+
+        let postfix = atom
+            .foldl(
+                choice((call_args, field, index, try_)).repeated(),
+                |expr, postfix| {
+                    let span = expr.1.start..postfix.span().end;
+
+                    (
+                        match postfix {
+                            Postfix::Call(args) => Expr::Call {
+                                callee: Box::new(expr),
+                                args,
+                            },
+                            Postfix::Field(field) => Expr::FieldAccess {
+                                expr: Box::new(expr),
+                                field,
+                            },
+                            Postfix::Index(index, _) => Expr::Index {
+                                expr: Box::new(expr),
+                                index: Box::new(index),
+                            },
+                            Postfix::Try(_) => Expr::Try(Box::new(expr)),
+                        },
+                        span.into(),
+                    )
+                },
+            )
             .boxed();
 
+        let prefix_op = choice((
+            just(Token::Operator(Operator::Minus)).to(PrefixOp::Negate),
+            just(Token::Operator(Operator::Not)).to(PrefixOp::Not),
+            just(Token::Operator(Operator::Tilde)).to(PrefixOp::BitNot),
+        ))
+        .map_with_span(|op, span| (op, span))
+        .boxed();
+
+        // `**` binds tighter than a prefix operator on its left, so `-2 ** 2`
+        // is `-(2 ** 2)`, and is right associative
+        let prefix = recursive(|prefix: Recursive<dyn Parser<_, Spanned<Expr<'src>>, _>>| {
+            let power = postfix
+                .clone()
+                .then(
+                    just(Token::Operator(Operator::StarStar))
+                        .to(BinOp::Power)
+                        .map_with_span(|op, span| (op, span))
+                        .then(prefix.clone())
+                        .or_not(),
+                )
+                .map(|(lhs, rhs)| match rhs {
+                    Some((op, rhs)) => {
+                        let span = Span::from(lhs.1.start..rhs.1.end);
+
+                        (
+                            Expr::Binary {
+                                lhs: Box::new(lhs),
+                                op,
+                                rhs: Box::new(rhs),
+                            },
+                            span,
+                        )
+                    }
+                    None => lhs,
+                });
+
+            let reference = just(Token::Operator(Operator::Ampersand))
+                .ignore_then(just(Token::Keyword(Keyword::Mut)).or_not())
+                .map_with_span(|mutable, span: Span| (mutable.is_some(), span))
+                .then(prefix)
+                .map(|((mutable, ref_span), expr)| {
+                    let span = ref_span.start..expr.1.end;
+
+                    (
+                        Expr::Ref {
+                            mutable,
+                            expr: Box::new(expr),
+                        },
+                        span.into(),
+                    )
+                });
+
+            let prefix = prefix_op
+                .repeated()
+                .foldr(power, |op, expr: Spanned<Expr<'src>>| {
+                    let span = op.1.start..expr.1.end;
+
+                    (
+                        Expr::Prefix {
+                            op,
+                            expr: Box::new(expr),
+                        },
+                        span.into(),
+                    )
+                });
+
+            choice((reference, prefix))
+        })
+        .boxed();
+
         let factor_op = choice((
             just(Token::Operator(Operator::Star)).to(BinOp::Multiply),
             just(Token::Operator(Operator::Slash)).to(BinOp::Divide),
+            just(Token::Operator(Operator::Percent)).to(BinOp::Modulo),
         ))
         .map_with_span(|op, span| (op, span))
         .boxed();
 
-        let factor = prefix
+        let cast = prefix
+            .foldl(
+                just(Token::Keyword(Keyword::As))
+                    .ignore_then(type_parser())
+                    .repeated(),
+                |expr, ty| {
+                    let span = expr.1.start..ty.1.end;
+
+                    (
+                        Expr::Cast {
+                            expr: Box::new(expr),
+                            ty,
+                        },
+                        span.into(),
+                    )
+                },
+            )
+            .boxed();
+
+        let factor = cast
             .clone()
-            .foldl(factor_op.then(prefix).repeated(), |lhs, (op, rhs)| {
+            .foldl(factor_op.then(cast).repeated(), |lhs, (op, rhs)| {
                 let span = lhs.1.start..rhs.1.end;
 
                 (
@@ -213,6 +1107,105 @@ fn expression_parser<'tokens, 'src: 'tokens>() -> impl Parser<
             })
             .boxed();
 
+        // `>>` is two `>` tokens so that nested type arguments like
+        // `List<List<int>>` still lex, the two just have to be adjacent
+        let shift_right = just(Token::Operator(Operator::GreaterThan))
+            .map_with_span(|_, span: Span| span)
+            .then(just(Token::Operator(Operator::GreaterThan)).map_with_span(|_, span: Span| span))
+            .try_map(|(first, second), span| {
+                if first.end == second.start {
+                    Ok(BinOp::ShiftRight)
+                } else {
+                    Err(Rich::custom(span, "Expected '>>'"))
+                }
+            });
+
+        let shift_op = choice((
+            just(Token::Operator(Operator::ShiftLeft)).to(BinOp::ShiftLeft),
+            shift_right,
+        ))
+        .map_with_span(|op, span| (op, span))
+        .boxed();
+
+        let shift = sum
+            .clone()
+            .foldl(shift_op.then(sum).repeated(), |lhs, (op, rhs)| {
+                let span = lhs.1.start..rhs.1.end;
+
+                (
+                    Expr::Binary {
+                        lhs: Box::new(lhs),
+                        op,
+                        rhs: Box::new(rhs),
+                    },
+                    span.into(),
+                )
+            })
+            .boxed();
+
+        let bit_and_op = just(Token::Operator(Operator::Ampersand))
+            .to(BinOp::BitAnd)
+            .map_with_span(|op, span| (op, span))
+            .boxed();
+
+        let bit_and = shift
+            .clone()
+            .foldl(bit_and_op.then(shift).repeated(), |lhs, (op, rhs)| {
+                let span = lhs.1.start..rhs.1.end;
+
+                (
+                    Expr::Binary {
+                        lhs: Box::new(lhs),
+                        op,
+                        rhs: Box::new(rhs),
+                    },
+                    span.into(),
+                )
+            })
+            .boxed();
+
+        let bit_xor_op = just(Token::Operator(Operator::Caret))
+            .to(BinOp::BitXor)
+            .map_with_span(|op, span| (op, span))
+            .boxed();
+
+        let bit_xor = bit_and
+            .clone()
+            .foldl(bit_xor_op.then(bit_and).repeated(), |lhs, (op, rhs)| {
+                let span = lhs.1.start..rhs.1.end;
+
+                (
+                    Expr::Binary {
+                        lhs: Box::new(lhs),
+                        op,
+                        rhs: Box::new(rhs),
+                    },
+                    span.into(),
+                )
+            })
+            .boxed();
+
+        let bit_or_op = just(Token::Operator(Operator::Pipe))
+            .to(BinOp::BitOr)
+            .map_with_span(|op, span| (op, span))
+            .boxed();
+
+        let bit_or = bit_xor
+            .clone()
+            .foldl(bit_or_op.then(bit_xor).repeated(), |lhs, (op, rhs)| {
+                let span = lhs.1.start..rhs.1.end;
+
+                (
+                    Expr::Binary {
+                        lhs: Box::new(lhs),
+                        op,
+                        rhs: Box::new(rhs),
+                    },
+                    span.into(),
+                )
+            })
+            .boxed();
+
         let relational_op = choice((
             just(Token::Operator(Operator::LessThan)).to(BinOp::LessThan),
             just(Token::Operator(Operator::LessThanOrEqual)).to(BinOp::LessThanOrEqual),
@@ -222,9 +1215,72 @@ fn expression_parser<'tokens, 'src: 'tokens>() -> impl Parser<
         .map_with_span(|op, span| (op, span))
         .boxed();
 
-        let relational = sum
+        let range_op = choice((
+            just(Token::Control(Control::DotDot)).to(false),
+            just(Token::Control(Control::DotDotEquals)).to(true),
+        ))
+        .boxed();
+
+        let range = bit_or
+            .clone()
+            .then(range_op.then(bit_or).or_not())
+            .map(|(start, end)| match end {
+                Some((inclusive, end)) => {
+                    let span = start.1.start..end.1.end;
+
+                    (
+                        Expr::Range {
+                            start: Box::new(start),
+                            end: Box::new(end),
+                            inclusive,
+                        },
+                        span.into(),
+                    )
+                }
+                None => start,
+            })
+            .boxed();
+
+        let is = range
+            .foldl(
+                just(Token::Keyword(Keyword::Is))
+                    .ignore_then(ident_parser())
+                    .repeated(),
+                |expr, variant| {
+                    let span = expr.1.start..variant.1.end;
+
+                    (
+                        Expr::Is {
+                            expr: Box::new(expr),
+                            variant,
+                        },
+                        span.into(),
+                    )
+                },
+            )
+            .boxed();
+
+        let in_ = is
+            .clone()
+            .foldl(
+                just(Token::Keyword(Keyword::In)).ignore_then(is).repeated(),
+                |expr, collection| {
+                    let span = expr.1.start..collection.1.end;
+
+                    (
+                        Expr::In {
+                            expr: Box::new(expr),
+                            collection: Box::new(collection),
+                        },
+                        span.into(),
+                    )
+                },
+            )
+            .boxed();
+
+        let relational = in_
             .clone()
-            .foldl(relational_op.then(sum).repeated(), |lhs, (op, rhs)| {
+            .foldl(relational_op.then(in_).repeated(), |lhs, (op, rhs)| {
                 let span = lhs.1.start..rhs.1.end;
 
                 (
@@ -309,10 +1365,50 @@ fn expression_parser<'tokens, 'src: 'tokens>() -> impl Parser<
             )
             .boxed();
 
-        logical_or
+        let coalesce_op = just(Token::Operator(Operator::Coalesce))
+            .to(BinOp::Coalesce)
+            .map_with_span(|op, span| (op, span))
+            .boxed();
+
+        let coalesce = logical_or
+            .clone()
+            .foldl(coalesce_op.then(logical_or).repeated(), |lhs, (op, rhs)| {
+                let span = lhs.1.start..rhs.1.end;
+
+                (
+                    Expr::Binary {
+                        lhs: Box::new(lhs),
+                        op,
+                        rhs: Box::new(rhs),
+                    },
+                    span.into(),
+                )
+            })
+            .boxed();
+
+        coalesce
     })
 }
 
+enum Postfix<'src> {
+    Call(Spanned<Vec<Spanned<Expr<'src>>>>),
+    Field(Spanned<&'src str>),
+    /// The index, and the span of the brackets around it
+    Index(Spanned<Expr<'src>>, Span),
+    /// The span of the `?`
+    Try(Span),
+}
+
+impl Postfix<'_> {
+    fn span(&self) -> Span {
+        match self {
+            Postfix::Call(args) => args.1,
+            Postfix::Field(field) => field.1,
+            Postfix::Index(_, span) | Postfix::Try(span) => *span,
+        }
+    }
+}
+
 fn literal_parser<'tokens, 'src: 'tokens>() -> impl Parser<
     'tokens,
     ParserInput<'tokens, 'src>,
@@ -320,9 +1416,14 @@ fn literal_parser<'tokens, 'src: 'tokens>() -> impl Parser<
     extra::Err<Rich<'tokens, Token<'src>, Span>>,
 > {
     select! {
-        Token::Num(n) => Literal::Num(n),
+        Token::Int(n) => Literal::Int(n),
+        Token::Float(n) => Literal::Float(n),
+        Token::Str(s) => Literal::Str(s),
+        Token::Char(c) => Literal::Char(c),
+        Token::Unit => Literal::Unit,
         Token::Keyword(Keyword::True) => Literal::Bool(true),
         Token::Keyword(Keyword::False) => Literal::Bool(false),
+        Token::Keyword(Keyword::None) => Literal::None,
     }
     .map_with_span(|literal, span| (literal, span))
     .boxed()
@@ -339,17 +1440,161 @@ fn ident_parser<'tokens, 'src: 'tokens>() -> impl Parser<
         .boxed()
 }
 
+/// Whether an expression ends in a block, which lets it be a statement
+/// without a `;` after it
+fn ends_in_block(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::If { .. }
+            | Expr::Match { .. }
+            | Expr::Loop { .. }
+            | Expr::Block(_)
+            | Expr::LabeledBlock { .. }
+            | Expr::Catch { .. }
+    )
+}
+
+/// The tail of a block that ends in `tail`, or in an expression ending in a
+/// block without a `;` after it, which is taken out of `statements` since it
+/// was parsed as one of them
+fn take_tail<'src>(
+    statements: &mut Vec<Spanned<Statement<'src>>>,
+    tail: Option<Box<Spanned<Expr<'src>>>>,
+) -> Option<Box<Spanned<Expr<'src>>>> {
+    let without_semicolon = matches!(
+        statements.last(),
+        Some((Statement::Expr(expr), span)) if span.end == expr.1.end
+    );
+
+    if tail.is_some() || !without_semicolon {
+        return tail;
+    }
+
+    let Some((Statement::Expr(expr), _)) = statements.pop() else {
+        unreachable!()
+    };
+
+    Some(Box::new(expr))
+}
+
+fn label_parser<'tokens, 'src: 'tokens>() -> impl Parser<
+    'tokens,
+    ParserInput<'tokens, 'src>,
+    Spanned<&'src str>,
+    extra::Err<Rich<'tokens, Token<'src>, Span>>,
+> {
+    select! { Token::Label(label) => label }
+        .map_with_span(|label, span| (label, span))
+        .boxed()
+}
+
 fn type_parser<'tokens, 'src: 'tokens>() -> impl Parser<
     'tokens,
     ParserInput<'tokens, 'src>,
-    Spanned<Type>,
+    Spanned<Type<'src>>,
     extra::Err<Rich<'tokens, Token<'src>, Span>>,
 > {
-    select! {
-        Token::Ident("num") => Type::Num,
-        Token::Ident("bool") => Type::Bool,
-        Token::Unit => Type::Unit,
-    }
-    .map_with_span(|ty, span| (ty, span))
-    .boxed()
+    recursive(|ty| {
+        let record = select! { Token::Ident(ident) => ident }
+            .then_ignore(just(Token::Control(Control::Colon)))
+            .then(ty.clone().map(|(ty, _)| ty))
+            .separated_by(just(Token::Control(Control::Comma)))
+            .allow_trailing()
+            .collect()
+            .delimited_by(
+                just(Token::Control(Control::LeftCurly)),
+                just(Token::Control(Control::RightCurly)),
+            )
+            .map(Type::Record)
+            .boxed();
+
+        let list = select! { Token::Ident("List") => () }
+            .ignore_then(ty.clone().map(|(ty, _)| ty).delimited_by(
+                just(Token::Operator(Operator::LessThan)),
+                just(Token::Operator(Operator::GreaterThan)),
+            ))
+            .map(|ty| Type::List(Box::new(ty)))
+            .boxed();
+
+        let map = select! { Token::Ident("Map") => () }
+            .ignore_then(
+                ty.clone()
+                    .map(|(ty, _)| ty)
+                    .then_ignore(just(Token::Control(Control::Comma)))
+                    .then(ty.clone().map(|(ty, _)| ty))
+                    .delimited_by(
+                        just(Token::Operator(Operator::LessThan)),
+                        just(Token::Operator(Operator::GreaterThan)),
+                    ),
+            )
+            .map(|(key, value)| Type::Map(Box::new(key), Box::new(value)))
+            .boxed();
+
+        let result = select! { Token::Ident("Result") => () }
+            .ignore_then(
+                ty.clone()
+                    .map(|(ty, _)| ty)
+                    .then_ignore(just(Token::Control(Control::Comma)))
+                    .then(ty.clone().map(|(ty, _)| ty))
+                    .delimited_by(
+                        just(Token::Operator(Operator::LessThan)),
+                        just(Token::Operator(Operator::GreaterThan)),
+                    ),
+            )
+            .map(|(ok, err)| Type::Result(Box::new(ok), Box::new(err)))
+            .boxed();
+
+        let func = just(Token::Keyword(Keyword::Func))
+            .ignore_then(
+                ty.clone()
+                    .map(|(ty, _)| ty)
+                    .separated_by(just(Token::Control(Control::Comma)))
+                    .allow_trailing()
+                    .collect()
+                    .delimited_by(
+                        just(Token::Control(Control::LeftParen)),
+                        just(Token::Control(Control::RightParen)),
+                    ),
+            )
+            .then(
+                just(Token::Control(Control::Colon))
+                    .ignore_then(ty.clone().map(|(ty, _)| ty))
+                    .or_not(),
+            )
+            .map(|(params, return_ty)| {
+                Type::Func(params, Box::new(return_ty.unwrap_or(Type::Unit)))
+            })
+            .boxed();
+
+        let reference = just(Token::Operator(Operator::Ampersand))
+            .ignore_then(just(Token::Keyword(Keyword::Mut)).or_not())
+            .then(ty.clone().map(|(ty, _)| ty))
+            .map(|(mutable, ty)| Type::Ref(mutable.is_some(), Box::new(ty)))
+            .boxed();
+
+        choice((
+            reference,
+            list,
+            map,
+            result,
+            func,
+            select! {
+                Token::Ident("int") => Type::Int,
+                Token::Ident("float") => Type::Float,
+                Token::Ident("bool") => Type::Bool,
+                Token::Ident("str") => Type::Str,
+                Token::Ident("char") => Type::Char,
+                Token::Ident("range") => Type::Range,
+                Token::Unit => Type::Unit,
+                Token::Ident(name) => Type::Named(name),
+            },
+            record,
+        ))
+        .foldl(
+            just(Token::Control(Control::Question)).repeated(),
+            |ty, _| Type::Optional(Box::new(ty)),
+        )
+        .map_with_span(|ty, span| (ty, span))
+        .boxed()
+    })
 }
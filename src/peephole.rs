@@ -0,0 +1,154 @@
+use crate::bytecode::{Function, Op, Program};
+use crate::interpreter::Value;
+use crate::typed_ast::{BinOp, PrefixOp};
+
+/// Replaces short sequences of instructions in every function of a compiled
+/// program with fewer ones that do the same, so the VM runs fewer of them
+pub fn optimize(program: &mut Program) {
+    for function in &mut program.functions {
+        while rewrite(function, &program.constants) {}
+    }
+}
+
+/// Goes over a function's code once, returning whether anything changed.
+/// An instruction that's jumped to is only changed along with the ones
+/// after it, so the jump still does the same.
+fn rewrite(function: &mut Function, constants: &[Value]) -> bool {
+    let code = &mut function.code;
+    let len = code.len();
+
+    let mut targets = vec![false; len + 1];
+
+    for op in code.iter() {
+        if let Some(to) = op.target() {
+            targets[to] = true;
+        }
+    }
+
+    let mut removed = vec![false; len];
+    let mut changed = false;
+    let mut at = 0;
+
+    while at < len {
+        // the instructions after this one that can be changed along with it
+        let next = |n: usize| (at + n < len && !targets[at + n]).then(|| code[at + n]);
+
+        // the instructions that replace the ones from here, where `None`
+        // removes one
+        let replacement = match (code[at], next(1), next(2)) {
+            // swapping the operands, so the local is pushed by itself and
+            // the constant goes with the operator
+            (Op::Const(constant), Some(Op::LoadLocal(slot)), Some(Op::Binary(op)))
+                if commutative(op, &constants[constant]) =>
+            {
+                vec![
+                    None,
+                    Some(Op::LoadLocal(slot)),
+                    Some(Op::BinaryConst(op, constant)),
+                ]
+            }
+            (Op::Const(_) | Op::LoadLocal(_) | Op::LoadCapture(_) | Op::Dup, Some(Op::Pop), _) => {
+                vec![None, None]
+            }
+            // a value pushed at the end of a branch that's dropped where the
+            // branches join, like the unit value of an `if` statement
+            (
+                Op::Const(_) | Op::LoadLocal(_) | Op::LoadCapture(_) | Op::Dup,
+                Some(Op::Jump(to)),
+                _,
+            ) if code.get(to) == Some(&Op::Pop) && !removed[to] => {
+                vec![None, Some(Op::Jump(to + 1))]
+            }
+            (Op::Const(constant), Some(Op::Binary(op)), _) => {
+                vec![None, Some(Op::BinaryConst(op, constant))]
+            }
+            (Op::LoadLocal(slot), Some(Op::Binary(op)), _) => {
+                vec![None, Some(Op::BinaryLocal(op, slot))]
+            }
+            (Op::LoadLocal(slot), Some(Op::LoadLocal(other)), _) if slot == other => {
+                vec![Some(Op::LoadLocal(slot)), Some(Op::Dup)]
+            }
+            (Op::Prefix(PrefixOp::Not), Some(Op::JumpIfFalse(to)), _) => {
+                vec![None, Some(Op::JumpIfTrue(to))]
+            }
+            (Op::Prefix(PrefixOp::Not), Some(Op::JumpIfTrue(to)), _) => {
+                vec![None, Some(Op::JumpIfFalse(to))]
+            }
+            (Op::Jump(to), _, _) if to == at + 1 => vec![None],
+            // jumping straight to where a jump goes
+            (mut op, _, _) => match op.target().map(|to| (to, code.get(to))) {
+                Some((to, Some(&Op::Jump(next)))) if next != to && !removed[to] => {
+                    *op.target_mut().unwrap() = next;
+
+                    vec![Some(op)]
+                }
+                _ => vec![],
+            },
+        };
+
+        if replacement.is_empty() {
+            at += 1;
+            continue;
+        }
+
+        for (i, op) in replacement.iter().enumerate() {
+            match op {
+                Some(op) => code[at + i] = *op,
+                None => removed[at + i] = true,
+            }
+        }
+
+        changed = true;
+        at += replacement.len();
+    }
+
+    if changed {
+        remove(function, &removed);
+    }
+
+    changed
+}
+
+/// Removes instructions from a function, pointing the jumps to them at the
+/// next instruction that's kept
+fn remove(function: &mut Function, removed: &[bool]) {
+    let mut position = Vec::with_capacity(removed.len() + 1);
+    let mut kept = 0;
+
+    for removed in removed {
+        position.push(kept);
+
+        if !removed {
+            kept += 1;
+        }
+    }
+
+    position.push(kept);
+
+    let code = std::mem::take(&mut function.code);
+    let spans = std::mem::take(&mut function.spans);
+
+    for ((mut op, span), removed) in code.into_iter().zip(spans).zip(removed) {
+        if *removed {
+            continue;
+        }
+
+        if let Some(to) = op.target_mut() {
+            *to = position[*to];
+        }
+
+        function.code.push(op);
+        function.spans.push(span);
+    }
+}
+
+/// Whether an operator gives the same result with its operands swapped,
+/// when one of them is this constant
+fn commutative(op: BinOp, constant: &Value) -> bool {
+    match op {
+        BinOp::Equals | BinOp::NotEquals => true,
+        BinOp::Add | BinOp::Multiply => matches!(constant, Value::Int(_) | Value::Float(_)),
+        BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor => matches!(constant, Value::Int(_)),
+        _ => false,
+    }
+}
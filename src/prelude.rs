@@ -0,0 +1,83 @@
+use crate::typed_ast::{FormatSpec, Type};
+
+/// Functions that are always in scope and are implemented by the interpreter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Builtin {
+    /// Returns a counter that increases by one on every call, starting at 0
+    Tick,
+    /// Splits a string into its characters
+    Chars,
+    /// The number of characters in a string
+    Len,
+    /// Writes a string to stdout. In source it takes a format string and
+    /// arguments, which the typechecker turns into the string to write.
+    Print,
+    /// Like `Print`, followed by a newline
+    Println,
+    /// Fills in a format string with its arguments. It's replaced with the
+    /// resulting string by the typechecker, so it's never called.
+    Format,
+    /// Converts a value to the text `print` would show for it. It has no
+    /// name in source, the typechecker inserts it for interpolated strings.
+    ToStr,
+    /// Converts a value to text the way a placeholder with this spec shows
+    /// it. It has no name in source either.
+    ToStrWith(FormatSpec),
+}
+
+impl Builtin {
+    pub fn lookup(name: &str) -> Option<Builtin> {
+        match name {
+            "tick" => Some(Builtin::Tick),
+            "chars" => Some(Builtin::Chars),
+            "len" => Some(Builtin::Len),
+            "print" => Some(Builtin::Print),
+            "println" => Some(Builtin::Println),
+            "format" => Some(Builtin::Format),
+            _ => None,
+        }
+    }
+
+    /// Whether the builtin takes a format string followed by its arguments
+    pub fn takes_format(&self) -> bool {
+        matches!(self, Builtin::Print | Builtin::Println | Builtin::Format)
+    }
+
+    pub fn params(&self) -> Vec<Type> {
+        match self {
+            Builtin::Tick => vec![],
+            Builtin::Chars | Builtin::Len => vec![Type::Str],
+            Builtin::Print | Builtin::Println | Builtin::Format => {
+                unreachable!("format strings are checked separately")
+            }
+            Builtin::ToStr | Builtin::ToStrWith(_) => {
+                unreachable!("to_str can't be called by name")
+            }
+        }
+    }
+
+    pub fn return_ty(&self) -> Type {
+        match self {
+            Builtin::Tick => Type::Int,
+            Builtin::Chars => Type::List(Box::new(Type::Char)),
+            Builtin::Len => Type::Int,
+            Builtin::Print | Builtin::Println => Type::Unit,
+            Builtin::Format | Builtin::ToStr | Builtin::ToStrWith(_) => Type::Str,
+        }
+    }
+}
+
+impl std::fmt::Display for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Builtin::Tick => write!(f, "tick"),
+            Builtin::Chars => write!(f, "chars"),
+            Builtin::Len => write!(f, "len"),
+            Builtin::Print => write!(f, "print"),
+            Builtin::Println => write!(f, "println"),
+            Builtin::Format => write!(f, "format"),
+            Builtin::ToStr => write!(f, "to_str"),
+            Builtin::ToStrWith(spec) => write!(f, "to_str {}", spec),
+        }
+    }
+}
@@ -0,0 +1,785 @@
+use crate::error::Error;
+use crate::interpreter::{cast, prefix, to_str, Value};
+use crate::mir::{self, Const, Operand, Rvalue, Statement, Terminator, Ty};
+use crate::typed_ast::{BinOp, FormatSpec, PrefixOp};
+use crate::Span;
+use std::io::Write;
+
+/// A program compiled from the MIR for the register VM, where instructions
+/// read and write a function's registers directly instead of going through
+/// a stack. Registers hold the bits of a value of any of the MIR's types,
+/// like the native backend's slots.
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    /// The first function is the top level code
+    pub functions: Vec<Function>,
+    pub constants: Vec<Const>,
+    /// The names of the top level variables
+    pub globals: Vec<String>,
+    /// The arguments of every call
+    pub args: Vec<Vec<Arg>>,
+    /// What every print writes
+    pub prints: Vec<Vec<Piece>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub name: String,
+    /// The params are in the first registers when it's called
+    pub params: usize,
+    pub registers: usize,
+    pub code: Vec<Instr>,
+    /// Where each instruction that can fail came from, for runtime errors
+    pub spans: Vec<Option<Span>>,
+}
+
+pub type Reg = usize;
+
+/// What an instruction reads
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arg {
+    Reg(Reg),
+    Const(usize),
+}
+
+/// Part of the text a print writes
+#[derive(Clone, Debug)]
+pub enum Piece {
+    Str(String),
+    Value(Arg, Ty, Option<FormatSpec>),
+}
+
+/// An instruction, which writes its result to the register that comes first
+#[derive(Clone, Copy, Debug)]
+pub enum Instr {
+    Move(Reg, Arg),
+    /// A prefix operator on an operand of the given type
+    Prefix(PrefixOp, Ty, Reg, Arg),
+    /// An operator other than the short-circuiting ones, on operands of the
+    /// given type
+    Binary(BinOp, Ty, Reg, Arg, Arg),
+    /// A cast between two different types
+    Cast(Ty, Ty, Reg, Arg),
+    LoadGlobal(Reg, usize),
+    StoreGlobal(usize, Arg),
+    /// Marks a top level variable as initialized
+    Initialize(usize),
+    /// Fails if a top level variable isn't initialized yet
+    CheckInitialized(usize),
+    /// Calls a function with one of the program's argument lists, putting
+    /// the result in the register if it returns a value
+    Call {
+        function: usize,
+        args: usize,
+        result: Option<Reg>,
+    },
+    Tick(Reg),
+    /// Writes one of the program's prints to stdout
+    Print(usize),
+    /// Shows what's been written without a newline
+    Flush,
+    Jump(usize),
+    JumpIfTrue(Arg, usize),
+    JumpIfFalse(Arg, usize),
+    Return(Option<Arg>),
+    /// The end of a function that returns a value, which the typechecker
+    /// makes sure can't be reached
+    Unreachable,
+}
+
+/// Compiles a program lowered to the MIR for the register VM
+pub fn compile(program: &mir::Program) -> Program {
+    let mut compiler = Compiler {
+        program: Program {
+            globals: program
+                .globals
+                .iter()
+                .map(|global| global.name.clone())
+                .collect(),
+            ..Program::default()
+        },
+    };
+
+    for function in &program.functions {
+        let function = compiler.compile_function(function);
+
+        compiler.program.functions.push(function);
+    }
+
+    compiler.program
+}
+
+struct Compiler {
+    program: Program,
+}
+
+impl Compiler {
+    fn compile_function(&mut self, function: &mir::Function) -> Function {
+        let mut function = function.clone();
+
+        coalesce(&mut function);
+
+        let (registers, count) = allocate(&function);
+
+        // where the value of a statement that isn't used goes
+        let scratch = count;
+
+        let mut code = vec![];
+        let mut starts = vec![0; function.blocks.len()];
+        let mut jumps = vec![];
+
+        for (index, block) in function.blocks.iter().enumerate() {
+            starts[index] = code.len();
+
+            for statement in &block.statements {
+                let (result, value) = match statement {
+                    Statement::Assign(local, value) => (registers[*local], value),
+                    Statement::Eval(value) => (scratch, value),
+                    Statement::SetGlobal(global, value) => {
+                        let value = self.arg(value, &registers);
+
+                        code.push((Instr::StoreGlobal(*global, value), None));
+                        continue;
+                    }
+                    Statement::Initialize(global) => {
+                        code.push((Instr::Initialize(*global), None));
+                        continue;
+                    }
+                    Statement::CheckInitialized(global, span) => {
+                        code.push((Instr::CheckInitialized(*global), Some(*span)));
+                        continue;
+                    }
+                    Statement::Print(pieces) => {
+                        let pieces = pieces
+                            .iter()
+                            .map(|piece| match piece {
+                                mir::Piece::Str(text) => Piece::Str(text.clone()),
+                                mir::Piece::Value(value, ty, spec) => {
+                                    Piece::Value(self.arg(value, &registers), *ty, *spec)
+                                }
+                            })
+                            .collect();
+
+                        self.program.prints.push(pieces);
+                        code.push((Instr::Print(self.program.prints.len() - 1), None));
+                        continue;
+                    }
+                    Statement::Flush => {
+                        code.push((Instr::Flush, None));
+                        continue;
+                    }
+                };
+
+                let (instr, span) = match value {
+                    Rvalue::Use(value) => {
+                        let value = self.arg(value, &registers);
+
+                        // the value is already in the register
+                        if value == Arg::Reg(result) {
+                            continue;
+                        }
+
+                        (Instr::Move(result, value), None)
+                    }
+                    Rvalue::Prefix(op, ty, value, span) => (
+                        Instr::Prefix(*op, *ty, result, self.arg(value, &registers)),
+                        Some(*span),
+                    ),
+                    Rvalue::Binary(op, ty, lhs, rhs, span) => (
+                        Instr::Binary(
+                            *op,
+                            *ty,
+                            result,
+                            self.arg(lhs, &registers),
+                            self.arg(rhs, &registers),
+                        ),
+                        Some(*span),
+                    ),
+                    Rvalue::Cast(from, to, value, span) => (
+                        Instr::Cast(*from, *to, result, self.arg(value, &registers)),
+                        Some(*span),
+                    ),
+                    Rvalue::Global(global) => (Instr::LoadGlobal(result, *global), None),
+                    Rvalue::Call(callee, args, span) => {
+                        let args = args.iter().map(|arg| self.arg(arg, &registers)).collect();
+
+                        self.program.args.push(args);
+
+                        (
+                            Instr::Call {
+                                function: *callee,
+                                args: self.program.args.len() - 1,
+                                result: Some(result),
+                            },
+                            Some(*span),
+                        )
+                    }
+                    Rvalue::Tick => (Instr::Tick(result), None),
+                };
+
+                code.push((instr, span));
+            }
+
+            // a jump to the next block is left out, and the targets of the
+            // others are filled in once every block's start is known
+            let next = index + 1;
+
+            match &block.terminator {
+                Terminator::Goto(target) if *target == next => {}
+                Terminator::Goto(target) => {
+                    jumps.push((code.len(), *target));
+                    code.push((Instr::Jump(0), None));
+                }
+                Terminator::Branch(cond, then, else_) => {
+                    let cond = self.arg(cond, &registers);
+
+                    if *then == next {
+                        jumps.push((code.len(), *else_));
+                        code.push((Instr::JumpIfFalse(cond, 0), None));
+                    } else {
+                        jumps.push((code.len(), *then));
+                        code.push((Instr::JumpIfTrue(cond, 0), None));
+
+                        if *else_ != next {
+                            jumps.push((code.len(), *else_));
+                            code.push((Instr::Jump(0), None));
+                        }
+                    }
+                }
+                Terminator::Return(value) => {
+                    let value = value.as_ref().map(|value| self.arg(value, &registers));
+
+                    code.push((Instr::Return(value), None));
+                }
+                Terminator::Unreachable => {
+                    code.push((Instr::Unreachable, None));
+                }
+            }
+        }
+
+        for (at, target) in jumps {
+            let target = starts[target];
+
+            match &mut code[at].0 {
+                Instr::Jump(to) | Instr::JumpIfTrue(_, to) | Instr::JumpIfFalse(_, to) => {
+                    *to = target
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        let (code, spans) = code.into_iter().unzip();
+
+        Function {
+            name: function.name.clone(),
+            params: function.params,
+            registers: count + 1,
+            code,
+            spans,
+        }
+    }
+
+    fn arg(&mut self, operand: &Operand, registers: &[Reg]) -> Arg {
+        match operand {
+            Operand::Local(local) => Arg::Reg(registers[*local]),
+            Operand::Const(constant) => {
+                self.program.constants.push(*constant);
+
+                Arg::Const(self.program.constants.len() - 1)
+            }
+        }
+    }
+}
+
+/// Assigns values straight to the locals they're moved to right after
+/// they're worked out, when the local they were worked out into isn't read
+/// anywhere else, so that the move isn't needed
+fn coalesce(function: &mut mir::Function) {
+    let mut reads = vec![0; function.locals.len()];
+    let mut count = |operand: &Operand| {
+        if let Operand::Local(local) = operand {
+            reads[*local] += 1;
+        }
+    };
+
+    for block in &function.blocks {
+        for statement in &block.statements {
+            statement.operands(&mut count);
+        }
+
+        if let Some(operand) = block.terminator.operand() {
+            count(operand);
+        }
+    }
+
+    for block in &mut function.blocks {
+        let mut i = 1;
+
+        while i < block.statements.len() {
+            if let (
+                Statement::Assign(temp, _),
+                &Statement::Assign(local, Rvalue::Use(Operand::Local(read))),
+            ) = (&block.statements[i - 1], &block.statements[i])
+            {
+                if read == *temp && reads[read] == 1 {
+                    block.statements.remove(i);
+
+                    if let Statement::Assign(temp, _) = &mut block.statements[i - 1] {
+                        *temp = local;
+                    }
+
+                    continue;
+                }
+            }
+
+            i += 1;
+        }
+    }
+}
+
+/// Gives every local of a function a register, returning them and how many
+/// registers there are. Locals share a register if they're never live at
+/// the same time, going by the first and last place in the code that each
+/// one is live, and the params keep the first registers.
+fn allocate(function: &mir::Function) -> (Vec<Reg>, usize) {
+    let live_on_entry = function.live_on_entry();
+
+    // the first and last place each local is live, counting statements and
+    // terminators in the order of the blocks
+    let mut ranges = vec![None::<(usize, usize)>; function.locals.len()];
+    let mut live = |local: mir::Local, at: usize| {
+        let range = ranges[local].get_or_insert((at, at));
+
+        range.0 = range.0.min(at);
+        range.1 = range.1.max(at);
+    };
+
+    let mut at = 0;
+
+    for param in 0..function.params {
+        live(param, 0);
+    }
+
+    for (index, block) in function.blocks.iter().enumerate() {
+        for local in &live_on_entry[index] {
+            live(*local, at);
+        }
+
+        for statement in &block.statements {
+            if let Statement::Assign(local, _) = statement {
+                live(*local, at);
+            }
+
+            statement.operands(&mut |operand| {
+                if let Operand::Local(local) = operand {
+                    live(*local, at);
+                }
+            });
+
+            at += 1;
+        }
+
+        if let Some(Operand::Local(local)) = block.terminator.operand() {
+            live(*local, at);
+        }
+
+        for successor in block.terminator.successors() {
+            for local in &live_on_entry[successor] {
+                live(*local, at);
+            }
+        }
+
+        at += 1;
+    }
+
+    let mut order = (function.params..function.locals.len())
+        .filter(|local| ranges[*local].is_some())
+        .collect::<Vec<_>>();
+
+    order.sort_by_key(|local| ranges[*local].unwrap().0);
+
+    let mut registers = (0..function.locals.len()).collect::<Vec<_>>();
+    let mut count = function.params;
+
+    // the registers in use, with where they're free again
+    let mut active = (0..function.params)
+        .filter_map(|param| ranges[param].map(|range| (range.1, param)))
+        .collect::<Vec<_>>();
+    let mut free = vec![];
+
+    for local in order {
+        let (start, end) = ranges[local].unwrap();
+
+        // a register read for the last time can be written by the same
+        // instruction, since its operands are read first
+        active.retain(|&(until, register)| {
+            if until <= start {
+                free.push(register);
+            }
+
+            until > start
+        });
+
+        let register = free.pop().unwrap_or_else(|| {
+            count += 1;
+            count - 1
+        });
+
+        registers[local] = register;
+        active.push((end, register));
+    }
+
+    (registers, count)
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let arg = |arg: &Arg| match arg {
+            Arg::Reg(register) => format!("r{}", register),
+            Arg::Const(constant) => Operand::Const(self.constants[*constant]).to_string(),
+        };
+
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+
+            writeln!(
+                f,
+                "function {} '{}' ({} params, {} registers):",
+                i, function.name, function.params, function.registers
+            )?;
+
+            for (at, instr) in function.code.iter().enumerate() {
+                write!(f, "{:>5}  ", at)?;
+
+                match instr {
+                    Instr::Move(to, value) => writeln!(f, "r{} = {}", to, arg(value))?,
+                    Instr::Prefix(op, _, to, value) => {
+                        writeln!(f, "r{} = {}{}", to, op, arg(value))?
+                    }
+                    Instr::Binary(op, _, to, lhs, rhs) => {
+                        writeln!(f, "r{} = {} {} {}", to, arg(lhs), op, arg(rhs))?
+                    }
+                    Instr::Cast(_, ty, to, value) => {
+                        writeln!(f, "r{} = {} as {}", to, arg(value), ty)?
+                    }
+                    Instr::LoadGlobal(to, global) => writeln!(f, "r{} = g{}", to, global)?,
+                    Instr::StoreGlobal(global, value) => {
+                        writeln!(f, "g{} = {}", global, arg(value))?
+                    }
+                    Instr::Initialize(global) => writeln!(f, "initialize g{}", global)?,
+                    Instr::CheckInitialized(global) => writeln!(f, "check g{}", global)?,
+                    Instr::Call {
+                        function,
+                        args,
+                        result,
+                    } => {
+                        if let Some(result) = result {
+                            write!(f, "r{} = ", result)?;
+                        }
+
+                        let args = self.args[*args].iter().map(arg).collect::<Vec<_>>();
+
+                        writeln!(f, "call {}({})", function, args.join(", "))?
+                    }
+                    Instr::Tick(to) => writeln!(f, "r{} = tick()", to)?,
+                    Instr::Print(print) => {
+                        write!(f, "print")?;
+
+                        for piece in &self.prints[*print] {
+                            match piece {
+                                Piece::Str(text) => write!(f, " {:?}", text)?,
+                                Piece::Value(value, _, None) => write!(f, " {}", arg(value))?,
+                                Piece::Value(value, _, Some(spec)) => {
+                                    write!(f, " {} {}", arg(value), spec)?
+                                }
+                            }
+                        }
+
+                        writeln!(f)?
+                    }
+                    Instr::Flush => writeln!(f, "flush")?,
+                    Instr::Jump(to) => writeln!(f, "jump {}", to)?,
+                    Instr::JumpIfTrue(cond, to) => writeln!(f, "if {} jump {}", arg(cond), to)?,
+                    Instr::JumpIfFalse(cond, to) => {
+                        writeln!(f, "if not {} jump {}", arg(cond), to)?
+                    }
+                    Instr::Return(Some(value)) => writeln!(f, "return {}", arg(value))?,
+                    Instr::Return(None) => writeln!(f, "return")?,
+                    Instr::Unreachable => writeln!(f, "unreachable")?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs a program compiled for the register VM, stopping at the first
+/// runtime error
+pub fn run(program: &Program) -> Result<(), Error> {
+    let mut vm = Vm {
+        program,
+        constants: program.constants.iter().copied().map(bits).collect(),
+        registers: vec![0; program.functions[0].registers],
+        frames: vec![],
+        globals: vec![0; program.globals.len()],
+        initialized: vec![false; program.globals.len()],
+        ticks: 0,
+    };
+
+    let mut frame = Frame {
+        function: 0,
+        ip: 0,
+        base: 0,
+        result: None,
+    };
+
+    let result = vm.run(&mut frame);
+
+    std::io::stdout().flush().unwrap();
+
+    result.map_err(|(message, span)| {
+        // each caller's next instruction is the one after its call
+        let callees = std::iter::once(&frame).chain(vm.frames.iter().rev());
+        let trace = callees
+            .zip(vm.frames.iter().rev())
+            .map(|(callee, caller)| {
+                (
+                    program.functions[callee.function].name.clone(),
+                    program.functions[caller.function].spans[caller.ip - 1].unwrap(),
+                )
+            })
+            .collect();
+
+        Error::Runtime {
+            message,
+            span,
+            trace,
+        }
+    })
+}
+
+struct Vm<'a> {
+    program: &'a Program,
+    /// The bits of the program's constants
+    constants: Vec<u64>,
+    /// The registers of every function that's running, with the innermost
+    /// one's last
+    registers: Vec<u64>,
+    /// The functions that called the running one, innermost last
+    frames: Vec<Frame>,
+    globals: Vec<u64>,
+    initialized: Vec<bool>,
+    ticks: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Frame {
+    function: usize,
+    /// The next instruction to run
+    ip: usize,
+    /// Where the function's registers start
+    base: usize,
+    /// The caller's register the result goes in
+    result: Option<Reg>,
+}
+
+impl Vm<'_> {
+    /// Runs from `frame` until the program ends, leaving the frame that was
+    /// running in it if there's an error
+    fn run(&mut self, frame: &mut Frame) -> Result<(), (String, Span)> {
+        let program = self.program;
+
+        let mut function = &program.functions[frame.function];
+
+        loop {
+            let at = frame.ip;
+            let instr = function.code[at];
+
+            // errors point at the instruction that failed
+            let error = |message: String| (message, function.spans[at].unwrap());
+
+            frame.ip += 1;
+
+            match instr {
+                Instr::Move(to, value) => {
+                    self.registers[frame.base + to] = self.read(*frame, value);
+                }
+                Instr::Prefix(op, ty, to, value) => {
+                    let value = self.read(*frame, value);
+
+                    self.registers[frame.base + to] = match (op, ty) {
+                        (PrefixOp::Not, Ty::Bool) => value ^ 1,
+                        _ => prefix(op, self::value(value, ty))
+                            .map(from_value)
+                            .map_err(error)?,
+                    };
+                }
+                Instr::Binary(op, ty, to, lhs, rhs) => {
+                    let lhs = self.read(*frame, lhs);
+                    let rhs = self.read(*frame, rhs);
+
+                    self.registers[frame.base + to] = binary(op, ty, lhs, rhs).map_err(error)?;
+                }
+                Instr::Cast(from, to, result, value) => {
+                    let value = self::value(self.read(*frame, value), from);
+
+                    self.registers[frame.base + result] =
+                        cast(value, &to.to_type()).map(from_value).map_err(error)?;
+                }
+                Instr::LoadGlobal(to, global) => {
+                    self.registers[frame.base + to] = self.globals[global];
+                }
+                Instr::StoreGlobal(global, value) => {
+                    self.globals[global] = self.read(*frame, value);
+                }
+                Instr::Initialize(global) => self.initialized[global] = true,
+                Instr::CheckInitialized(global) => {
+                    if !self.initialized[global] {
+                        return Err(error(format!(
+                            "'{}' is used before it's initialized",
+                            program.globals[global]
+                        )));
+                    }
+                }
+                Instr::Call {
+                    function: callee,
+                    args,
+                    result,
+                } => {
+                    let base = self.registers.len();
+
+                    function = &program.functions[callee];
+
+                    self.registers.resize(base + function.registers, 0);
+
+                    for (i, arg) in program.args[args].iter().enumerate() {
+                        self.registers[base + i] = self.read(*frame, *arg);
+                    }
+
+                    self.frames.push(*frame);
+
+                    *frame = Frame {
+                        function: callee,
+                        ip: 0,
+                        base,
+                        result,
+                    };
+                }
+                Instr::Tick(to) => {
+                    self.registers[frame.base + to] = self.ticks;
+                    self.ticks += 1;
+                }
+                Instr::Print(print) => {
+                    for piece in &program.prints[print] {
+                        match piece {
+                            Piece::Str(text) => print!("{}", text),
+                            Piece::Value(value, ty, spec) => {
+                                let value = self::value(self.read(*frame, *value), *ty);
+
+                                print!("{}", to_str(&value, *spec));
+                            }
+                        }
+                    }
+                }
+                Instr::Flush => std::io::stdout().flush().unwrap(),
+                Instr::Jump(to) => frame.ip = to,
+                Instr::JumpIfTrue(cond, to) => {
+                    if self.read(*frame, cond) != 0 {
+                        frame.ip = to;
+                    }
+                }
+                Instr::JumpIfFalse(cond, to) => {
+                    if self.read(*frame, cond) == 0 {
+                        frame.ip = to;
+                    }
+                }
+                Instr::Return(value) => {
+                    let value = value.map(|value| self.read(*frame, value));
+
+                    self.registers.truncate(frame.base);
+
+                    let Some(caller) = self.frames.pop() else {
+                        return Ok(());
+                    };
+
+                    if let (Some(result), Some(value)) = (frame.result, value) {
+                        self.registers[caller.base + result] = value;
+                    }
+
+                    *frame = caller;
+                    function = &program.functions[frame.function];
+                }
+                Instr::Unreachable => unreachable!(),
+            }
+        }
+    }
+
+    fn read(&self, frame: Frame, arg: Arg) -> u64 {
+        match arg {
+            Arg::Reg(register) => self.registers[frame.base + register],
+            Arg::Const(constant) => self.constants[constant],
+        }
+    }
+}
+
+/// Applies a binary operator to the bits of two values of a type. The
+/// common operations are done here, and the rest by the interpreter.
+fn binary(op: BinOp, ty: Ty, a: u64, b: u64) -> Result<u64, String> {
+    let (x, y) = (a as i64, b as i64);
+    let float = |f: fn(f64, f64) -> f64| Ok(f(f64::from_bits(a), f64::from_bits(b)).to_bits());
+
+    let result = match (ty, op) {
+        (Ty::Int, BinOp::Add) => x.checked_add(y),
+        (Ty::Int, BinOp::Subtract) => x.checked_sub(y),
+        (Ty::Int, BinOp::Multiply) => x.checked_mul(y),
+        (Ty::Int, BinOp::LessThan) => Some((x < y) as i64),
+        (Ty::Int, BinOp::LessThanOrEqual) => Some((x <= y) as i64),
+        (Ty::Int, BinOp::GreaterThan) => Some((x > y) as i64),
+        (Ty::Int, BinOp::GreaterThanOrEqual) => Some((x >= y) as i64),
+        (Ty::Int | Ty::Bool | Ty::Char, BinOp::Equals) => Some((a == b) as i64),
+        (Ty::Int | Ty::Bool | Ty::Char, BinOp::NotEquals) => Some((a != b) as i64),
+        (Ty::Float, BinOp::Add) => return float(|a, b| a + b),
+        (Ty::Float, BinOp::Subtract) => return float(|a, b| a - b),
+        (Ty::Float, BinOp::Multiply) => return float(|a, b| a * b),
+        (Ty::Float, BinOp::Divide) => return float(|a, b| a / b),
+        _ => {
+            return crate::interpreter::binary(op, value(a, ty), value(b, ty)).map(from_value);
+        }
+    };
+
+    result
+        .map(|n| n as u64)
+        .ok_or_else(|| "integer overflow".to_string())
+}
+
+/// The bits a constant is kept in a register as
+fn bits(constant: Const) -> u64 {
+    match constant {
+        Const::Int(n) => n as u64,
+        Const::Float(n) => n.to_bits(),
+        Const::Bool(b) => b as u64,
+        Const::Char(c) => c as u64,
+    }
+}
+
+fn value(bits: u64, ty: Ty) -> Value {
+    match ty {
+        Ty::Int => Value::Int(bits as i64),
+        Ty::Float => Value::Float(f64::from_bits(bits)),
+        Ty::Bool => Value::Bool(bits != 0),
+        Ty::Char => Value::Char(char::from_u32(bits as u32).unwrap()),
+    }
+}
+
+fn from_value(value: Value) -> u64 {
+    match value {
+        Value::Int(n) => n as u64,
+        Value::Float(n) => n.to_bits(),
+        Value::Bool(b) => b as u64,
+        Value::Char(c) => c as u64,
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,469 @@
+use crate::mir::*;
+use std::collections::HashSet;
+
+/// Puts a function in SSA form, where every local is assigned in one place
+/// that comes before everywhere it's read. A local that's assigned more than
+/// once gets a new local for every assignment after the first, and where
+/// more than one of them can reach the start of a block that reads it, a phi
+/// there picks the right one. Params count as assigned when the function
+/// starts.
+pub fn construct(function: &mut Function) {
+    function.remove_unreachable_blocks((0..function.blocks.len()).collect());
+    separate_entry(function);
+
+    let predecessors = predecessors(function);
+    let dominators = dominators(function, &predecessors);
+    let frontiers = frontiers(&predecessors, &dominators);
+    let live = function.live_on_entry();
+
+    let mut assigned = vec![vec![]; function.locals.len()];
+
+    for blocks in &mut assigned[..function.params] {
+        blocks.push(0);
+    }
+
+    for (index, block) in function.blocks.iter().enumerate() {
+        for statement in &block.statements {
+            if let Statement::Assign(local, _) = statement {
+                assigned[*local].push(index);
+            }
+        }
+    }
+
+    // the local each phi is for, before it's renamed
+    let mut phis = vec![vec![]; function.blocks.len()];
+
+    for (local, mut work) in assigned.into_iter().enumerate() {
+        let mut placed = HashSet::new();
+
+        while let Some(block) = work.pop() {
+            for &frontier in &frontiers[block] {
+                // a phi is only needed where the local is read afterwards
+                if live[frontier].contains(&local) && placed.insert(frontier) {
+                    phis[frontier].push(local);
+                    function.blocks[frontier].phis.push(Phi {
+                        local,
+                        sources: vec![],
+                    });
+                    work.push(frontier);
+                }
+            }
+        }
+    }
+
+    let mut children = vec![vec![]; function.blocks.len()];
+
+    for (block, dominator) in dominators.iter().enumerate().skip(1) {
+        if let Some(dominator) = dominator {
+            children[*dominator].push(block);
+        }
+    }
+
+    let mut renamer = Renamer {
+        params: function.params,
+        locals: std::mem::take(&mut function.locals),
+        blocks: std::mem::take(&mut function.blocks),
+        phis,
+        children,
+        current: (0..function.params).map(|param| vec![param]).collect(),
+        renamed: vec![],
+    };
+
+    renamer.current.resize(renamer.locals.len(), vec![]);
+    renamer.renamed.resize(renamer.locals.len(), false);
+    renamer.rename(0);
+
+    function.locals = renamer.locals;
+    function.blocks = renamer.blocks;
+}
+
+/// Checks that a function is in SSA form, returning what's wrong if it isn't
+pub fn verify(function: &Function) -> Result<(), String> {
+    let predecessors = predecessors(function);
+    let dominators = dominators(function, &predecessors);
+
+    // where each local is assigned, as its block and the index of the
+    // statement after it, so the phis and params come before index 0
+    let mut assignments = vec![None; function.locals.len()];
+
+    assignments[..function.params].fill(Some((0, 0)));
+
+    let mut assign = |local: Local, at: (BlockId, usize)| {
+        if local >= assignments.len() {
+            return Err(format!("_{} isn't declared", local));
+        }
+
+        match assignments[local].replace(at) {
+            Some(_) if local < function.params => Err(format!("param _{} is assigned", local)),
+            Some(_) => Err(format!("_{} is assigned more than once", local)),
+            None => Ok(()),
+        }
+    };
+
+    for (index, block) in function.blocks.iter().enumerate() {
+        for phi in &block.phis {
+            assign(phi.local, (index, 0))?;
+        }
+
+        for (i, statement) in block.statements.iter().enumerate() {
+            if let Statement::Assign(local, _) = statement {
+                assign(*local, (index, i + 1))?;
+            }
+        }
+    }
+
+    // reads a local at the index of a statement in a block, or at the end
+    // of the block for the terminator and the phis after it
+    let read = |operand: &Operand, block: BlockId, at: usize| match operand {
+        Operand::Local(local) => match assignments.get(*local).copied().flatten() {
+            Some((assigned, after)) if assigned == block && after <= at => Ok(()),
+            Some((assigned, _)) if assigned != block && dominates(&dominators, assigned, block) => {
+                Ok(())
+            }
+            Some(_) => Err(format!(
+                "_{} is read in bb{} where it might not be assigned yet",
+                local, block
+            )),
+            None => Err(format!(
+                "_{} is read in bb{} but never assigned",
+                local, block
+            )),
+        },
+        Operand::Const(_) => Ok(()),
+    };
+
+    for (index, block) in function.blocks.iter().enumerate() {
+        // nothing can be said about where the blocks that can't be reached
+        // read things
+        if index != 0 && dominators[index].is_none() {
+            continue;
+        }
+
+        let mut sources = vec![];
+
+        for phi in &block.phis {
+            sources.clear();
+
+            for (source, value) in &phi.sources {
+                if *source == 0 || dominators.get(*source).is_some_and(Option::is_some) {
+                    read(value, *source, usize::MAX)?;
+                }
+
+                sources.push(*source);
+            }
+
+            sources.sort_unstable();
+
+            if sources != predecessors[index] {
+                return Err(format!(
+                    "the phi for _{} in bb{} doesn't have one value for each jump to it",
+                    phi.local, index
+                ));
+            }
+
+            for (_, value) in &phi.sources {
+                let ty = match value {
+                    Operand::Local(local) => function.locals[*local].ty,
+                    Operand::Const(Const::Int(_)) => Ty::Int,
+                    Operand::Const(Const::Float(_)) => Ty::Float,
+                    Operand::Const(Const::Bool(_)) => Ty::Bool,
+                    Operand::Const(Const::Char(_)) => Ty::Char,
+                };
+
+                if ty != function.locals[phi.local].ty {
+                    return Err(format!(
+                        "the phi for _{} in bb{} picks a {} for a {}",
+                        phi.local, index, ty, function.locals[phi.local].ty
+                    ));
+                }
+            }
+        }
+
+        for (i, statement) in block.statements.iter().enumerate() {
+            let mut result = Ok(());
+
+            statement.operands(&mut |operand| {
+                if result.is_ok() {
+                    result = read(operand, index, i);
+                }
+            });
+
+            result?;
+        }
+
+        if let Some(operand) = block.terminator.operand() {
+            read(operand, index, usize::MAX)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Gives a function a new first block that only jumps to the old one, if
+/// the old one is jumped to from somewhere else, so that the params are
+/// never assigned in a block that has phis
+fn separate_entry(function: &mut Function) {
+    let jumped_to = function
+        .blocks
+        .iter()
+        .any(|block| block.terminator.successors().contains(&0));
+
+    if !jumped_to {
+        return;
+    }
+
+    for block in &mut function.blocks {
+        block.terminator = match block.terminator {
+            Terminator::Goto(target) => Terminator::Goto(target + 1),
+            Terminator::Branch(cond, then, else_) => Terminator::Branch(cond, then + 1, else_ + 1),
+            ref terminator => terminator.clone(),
+        };
+
+        for phi in &mut block.phis {
+            for (source, _) in &mut phi.sources {
+                *source += 1;
+            }
+        }
+    }
+
+    function.blocks.insert(
+        0,
+        BasicBlock {
+            phis: vec![],
+            statements: vec![],
+            terminator: Terminator::Goto(1),
+        },
+    );
+}
+
+/// The blocks that jump to each block, in order and once for every jump
+fn predecessors(function: &Function) -> Vec<Vec<BlockId>> {
+    let mut predecessors = vec![vec![]; function.blocks.len()];
+
+    for (index, block) in function.blocks.iter().enumerate() {
+        for successor in block.terminator.successors() {
+            predecessors[successor].push(index);
+        }
+    }
+
+    predecessors
+}
+
+/// Works out the block each block is immediately dominated by, which is the
+/// closest one that every way of getting to it from the start goes through.
+/// It's `None` for the first block and the blocks that can't be reached.
+fn dominators(function: &Function, predecessors: &[Vec<BlockId>]) -> Vec<Option<BlockId>> {
+    // the blocks in reverse postorder, where every block comes before the
+    // blocks it jumps to except along loops
+    let mut order = vec![];
+    let mut visited = vec![false; function.blocks.len()];
+    let mut stack = vec![(0, 0)];
+
+    visited[0] = true;
+
+    while let Some((block, next)) = stack.pop() {
+        let successors = function.blocks[block].terminator.successors();
+
+        match successors.get(next) {
+            Some(&successor) => {
+                stack.push((block, next + 1));
+
+                if !std::mem::replace(&mut visited[successor], true) {
+                    stack.push((successor, 0));
+                }
+            }
+            None => order.push(block),
+        }
+    }
+
+    order.reverse();
+
+    let mut position = vec![usize::MAX; function.blocks.len()];
+
+    for (i, block) in order.iter().enumerate() {
+        position[*block] = i;
+    }
+
+    // the first block is its own dominator while this works them out
+    let mut dominators = vec![None; function.blocks.len()];
+    let mut changed = true;
+
+    dominators[0] = Some(0);
+
+    while changed {
+        changed = false;
+
+        for &block in &order[1..] {
+            let mut dominator: Option<BlockId> = None;
+
+            for &predecessor in &predecessors[block] {
+                if dominators[predecessor].is_none() {
+                    continue;
+                }
+
+                dominator = Some(match dominator {
+                    None => predecessor,
+                    Some(mut other) => {
+                        let mut predecessor = predecessor;
+
+                        while other != predecessor {
+                            while position[other] > position[predecessor] {
+                                other = dominators[other].unwrap();
+                            }
+
+                            while position[predecessor] > position[other] {
+                                predecessor = dominators[predecessor].unwrap();
+                            }
+                        }
+
+                        other
+                    }
+                });
+            }
+
+            if dominators[block] != dominator {
+                dominators[block] = dominator;
+                changed = true;
+            }
+        }
+    }
+
+    dominators[0] = None;
+    dominators
+}
+
+/// Whether every way of getting to `block` goes through `dominator`
+fn dominates(dominators: &[Option<BlockId>], dominator: BlockId, mut block: BlockId) -> bool {
+    loop {
+        if block == dominator {
+            return true;
+        }
+
+        match dominators[block] {
+            Some(next) => block = next,
+            None => return false,
+        }
+    }
+}
+
+/// Works out the dominance frontier of each block, which is where what's
+/// assigned in it might meet something assigned somewhere else
+fn frontiers(predecessors: &[Vec<BlockId>], dominators: &[Option<BlockId>]) -> Vec<Vec<BlockId>> {
+    let mut frontiers = vec![vec![]; predecessors.len()];
+
+    for (block, predecessors) in predecessors.iter().enumerate() {
+        if predecessors.len() < 2 {
+            continue;
+        }
+
+        for &predecessor in predecessors {
+            let mut runner = predecessor;
+
+            while Some(runner) != dominators[block] {
+                if !frontiers[runner].contains(&block) {
+                    frontiers[runner].push(block);
+                }
+
+                match dominators[runner] {
+                    Some(next) => runner = next,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    frontiers
+}
+
+/// Gives every assignment its own local, going down the dominator tree so
+/// the local each read gets is the one assigned last before it
+struct Renamer {
+    params: usize,
+    locals: Vec<LocalDecl>,
+    blocks: Vec<BasicBlock>,
+    /// The local each phi in each block is for, before it's renamed
+    phis: Vec<Vec<Local>>,
+    /// The blocks each block immediately dominates
+    children: Vec<Vec<BlockId>>,
+    /// The locals each original local has been renamed to in the blocks
+    /// that dominate the current one, latest last
+    current: Vec<Vec<Local>>,
+    /// Whether an original local's first assignment has kept it
+    renamed: Vec<bool>,
+}
+
+impl Renamer {
+    fn rename(&mut self, block: BlockId) {
+        let mut assigned = vec![];
+
+        for i in 0..self.phis[block].len() {
+            let local = self.phis[block][i];
+
+            self.blocks[block].phis[i].local = self.assign(local);
+            assigned.push(local);
+        }
+
+        for i in 0..self.blocks[block].statements.len() {
+            let current = &self.current;
+            let statement = &mut self.blocks[block].statements[i];
+
+            statement.operands_mut(&mut |operand| read(current, operand));
+
+            if let Statement::Assign(local, _) = *statement {
+                let new = self.assign(local);
+
+                if let Statement::Assign(local, _) = &mut self.blocks[block].statements[i] {
+                    *local = new;
+                }
+
+                assigned.push(local);
+            }
+        }
+
+        if let Some(operand) = self.blocks[block].terminator.operand_mut() {
+            read(&self.current, operand);
+        }
+
+        for successor in self.blocks[block].terminator.successors() {
+            for i in 0..self.phis[successor].len() {
+                let mut value = Operand::Local(self.phis[successor][i]);
+
+                read(&self.current, &mut value);
+                self.blocks[successor].phis[i].sources.push((block, value));
+            }
+        }
+
+        for i in 0..self.children[block].len() {
+            self.rename(self.children[block][i]);
+        }
+
+        for local in assigned {
+            self.current[local].pop();
+        }
+    }
+
+    /// Picks the local an assignment to an original local goes to
+    fn assign(&mut self, local: Local) -> Local {
+        let new = if local >= self.params && !std::mem::replace(&mut self.renamed[local], true) {
+            local
+        } else {
+            self.locals.push(self.locals[local].clone());
+            self.locals.len() - 1
+        };
+
+        self.current[local].push(new);
+
+        new
+    }
+}
+
+/// Changes a read of an original local to the local it's currently renamed
+/// to. It's left alone if it's read before anything's assigned to it, which
+/// the typechecker makes sure can't happen.
+fn read(current: &[Vec<Local>], operand: &mut Operand) {
+    if let Operand::Local(local) = operand {
+        if let Some(new) = current[*local].last() {
+            *local = *new;
+        }
+    }
+}
@@ -1,22 +1,39 @@
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum Token<'src> {
-    Num(f64),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Char(char),
+    /// The text of an interpolated string up to its first hole
+    StrStart(String),
+    /// The text of an interpolated string between two holes
+    StrMiddle(String),
+    /// The text of an interpolated string after its last hole
+    StrEnd(String),
     Unit,
     Operator(Operator),
     Control(Control),
     Keyword(Keyword),
     Ident(&'src str),
+    Label(&'src str),
 }
 
 impl std::fmt::Display for Token<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Token::Num(n) => write!(f, "{}", n),
+            Token::Int(n) => write!(f, "{}", n),
+            Token::Float(n) => write!(f, "{:?}", n),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Char(c) => write!(f, "{:?}", c),
+            Token::StrStart(s) => write!(f, "\"{}{{", s),
+            Token::StrMiddle(s) => write!(f, "}}{}{{", s),
+            Token::StrEnd(s) => write!(f, "}}{}\"", s),
             Token::Unit => write!(f, "#"),
             Token::Operator(op) => write!(f, "{}", op),
             Token::Control(ctrl) => write!(f, "{}", ctrl),
             Token::Keyword(kw) => write!(f, "{}", kw),
             Token::Ident(name) => write!(f, "{}", name),
+            Token::Label(name) => write!(f, "'{}", name),
         }
     }
 }
@@ -27,6 +44,8 @@ pub enum Operator {
     Minus,
     Star,
     Slash,
+    Percent,
+    StarStar,
     Equals,
     NotEquals,
     LessThan,
@@ -35,6 +54,17 @@ pub enum Operator {
     GreaterThanOrEqual,
     LogicalAnd,
     LogicalOr,
+    Coalesce,
+    Not,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    ShiftLeft,
+    PlusEquals,
+    MinusEquals,
+    StarEquals,
+    SlashEquals,
 }
 
 impl std::fmt::Display for Operator {
@@ -44,6 +74,8 @@ impl std::fmt::Display for Operator {
             Operator::Minus => write!(f, "-"),
             Operator::Star => write!(f, "*"),
             Operator::Slash => write!(f, "/"),
+            Operator::Percent => write!(f, "%"),
+            Operator::StarStar => write!(f, "**"),
             Operator::Equals => write!(f, "=="),
             Operator::NotEquals => write!(f, "!="),
             Operator::LessThan => write!(f, "<"),
@@ -52,6 +84,17 @@ impl std::fmt::Display for Operator {
             Operator::GreaterThanOrEqual => write!(f, ">="),
             Operator::LogicalAnd => write!(f, "&&"),
             Operator::LogicalOr => write!(f, "||"),
+            Operator::Coalesce => write!(f, "??"),
+            Operator::Not => write!(f, "!"),
+            Operator::Ampersand => write!(f, "&"),
+            Operator::Pipe => write!(f, "|"),
+            Operator::Caret => write!(f, "^"),
+            Operator::Tilde => write!(f, "~"),
+            Operator::ShiftLeft => write!(f, "<<"),
+            Operator::PlusEquals => write!(f, "+="),
+            Operator::MinusEquals => write!(f, "-="),
+            Operator::StarEquals => write!(f, "*="),
+            Operator::SlashEquals => write!(f, "/="),
         }
     }
 }
@@ -65,7 +108,15 @@ pub enum Control {
     RightParen,
     LeftCurly,
     RightCurly,
+    LeftSquare,
+    RightSquare,
     Comma,
+    Dot,
+    DotDot,
+    DotDotEquals,
+    DoubleColon,
+    FatArrow,
+    Question,
 }
 
 impl std::fmt::Display for Control {
@@ -78,30 +129,86 @@ impl std::fmt::Display for Control {
             Control::RightParen => write!(f, ")"),
             Control::LeftCurly => write!(f, "{{"),
             Control::RightCurly => write!(f, "}}"),
+            Control::LeftSquare => write!(f, "["),
+            Control::RightSquare => write!(f, "]"),
             Control::Comma => write!(f, ","),
+            Control::Dot => write!(f, "."),
+            Control::DotDot => write!(f, ".."),
+            Control::DotDotEquals => write!(f, "..="),
+            Control::DoubleColon => write!(f, "::"),
+            Control::FatArrow => write!(f, "=>"),
+            Control::Question => write!(f, "?"),
         }
     }
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Keyword {
-    Print,
     Let,
     True,
     False,
     Func,
     Return,
+    Enum,
+    Match,
+    Type,
+    None,
+    Some,
+    Break,
+    Is,
+    Import,
+    If,
+    Else,
+    Mut,
+    Struct,
+    Ok,
+    Err,
+    Trait,
+    Impl,
+    For,
+    Const,
+    While,
+    Loop,
+    Continue,
+    As,
+    In,
+    Try,
+    Catch,
 }
 
 impl std::fmt::Display for Keyword {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Keyword::Print => write!(f, "print"),
             Keyword::Let => write!(f, "let"),
             Keyword::True => write!(f, "true"),
             Keyword::False => write!(f, "false"),
             Keyword::Func => write!(f, "func"),
             Keyword::Return => write!(f, "return"),
+            Keyword::Enum => write!(f, "enum"),
+            Keyword::Match => write!(f, "match"),
+            Keyword::Type => write!(f, "type"),
+            Keyword::None => write!(f, "none"),
+            Keyword::Some => write!(f, "some"),
+            Keyword::Break => write!(f, "break"),
+            Keyword::Is => write!(f, "is"),
+            Keyword::Import => write!(f, "import"),
+            Keyword::If => write!(f, "if"),
+            Keyword::Else => write!(f, "else"),
+            Keyword::Mut => write!(f, "mut"),
+            Keyword::Struct => write!(f, "struct"),
+            Keyword::Ok => write!(f, "ok"),
+            Keyword::Err => write!(f, "err"),
+            Keyword::Trait => write!(f, "trait"),
+            Keyword::Impl => write!(f, "impl"),
+            Keyword::For => write!(f, "for"),
+            Keyword::Const => write!(f, "const"),
+            Keyword::While => write!(f, "while"),
+            Keyword::Loop => write!(f, "loop"),
+            Keyword::Continue => write!(f, "continue"),
+            Keyword::As => write!(f, "as"),
+            Keyword::In => write!(f, "in"),
+            Keyword::Try => write!(f, "try"),
+            Keyword::Catch => write!(f, "catch"),
         }
     }
 }
@@ -1,382 +1,5085 @@
 use crate::ast::{self, Ast};
-use crate::error::{Error, TypecheckError};
+use crate::error::{Error, TypecheckError, Warning};
+use crate::exhaustiveness::{self, Pat};
+use crate::prelude::Builtin;
 use crate::typed_ast::*;
-use crate::Spanned;
-use std::collections::HashMap;
+use crate::{Span, Spanned};
+use std::borrow::Borrow;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 
-pub fn typecheck(ast: Spanned<Ast>) -> Result<Spanned<TypedAst>, Error> {
-    let mut checker = Typechecker::new();
+/// Typechecks a program, returning every error found if there are any. A
+/// statement that fails is left out and checking goes on after it, with what
+/// it would have bound given a type that can't cause more errors.
+pub fn typecheck(
+    ast: Spanned<Ast>,
+    warn_shadowing: bool,
+    explain_types: bool,
+) -> Result<(Spanned<TypedAst>, Vec<Warning>), Vec<Error>> {
+    let mut checker = Typechecker::new(warn_shadowing, explain_types);
 
-    checker.typecheck_ast(ast)
+    let typed_ast = checker.typecheck_ast(ast);
+
+    checker.explain_types();
+
+    if checker.errors.is_empty() {
+        Ok((typed_ast, checker.warnings))
+    } else {
+        Err(checker.errors)
+    }
 }
 
-struct Typechecker<'a> {
+struct Typechecker<'src> {
     engine: Engine,
-    bindings: Scopes<&'a str, TypeId>,
+    bindings: Scopes<&'src str, Binding<'src>>,
+    types: Scopes<&'src str, TypeDef<'src>>,
+    /// The variants of every enum and the fields of every struct, by the
+    /// name their types have, see [`Typechecker::nominal_name`]
+    enums: HashMap<&'src str, Variants>,
+    structs: HashMap<&'src str, Fields>,
+    /// Enclosing labeled blocks and loops (without a label), innermost last,
+    /// with the type their breaks must have, which `while` and `for` loops
+    /// don't have since they can't be broken out of with a value
+    labels: Vec<(Option<&'src str>, Option<TypeId>)>,
+    /// The types of the labels and loops in `labels` that have been broken out of
+    broken: HashSet<TypeId>,
+    /// The return type of the function being checked, if there is one
+    return_ty: Option<TypeId>,
+    /// Enclosing closures, innermost last
+    closures: Vec<Closure<'src>>,
+    traits: Scopes<&'src str, Trait>,
+    /// Every trait implementation seen so far, as the trait and the type it's for
+    impls: Vec<(String, Spanned<Type>)>,
+    /// The methods of impls without a trait, as the type they're for, their
+    /// name, and their type leaving out `self` with where they're declared
+    methods: Vec<(Type, &'src str, Spanned<Type>)>,
+    /// Types that have to implement a trait but weren't known yet when that was
+    /// required, see [`Typechecker::check_obligations`]
+    obligations: Vec<Obligation>,
+    /// Modules imported with `import name;`, by the index of their file
+    imports: Scopes<&'src str, usize>,
+    /// The top level bindings of every module checked so far, by file
+    modules: HashMap<usize, Scopes<&'src str, Binding<'src>>>,
+    /// The types that top level functions and variables were declared with
+    /// before their bodies or values were checked, by the offset their name
+    /// starts at
+    declared: HashMap<usize, TypeId>,
+    globals: Globals<'src>,
+    use_site: UseSite<'src>,
+    /// How many `let`s, enums and structs got a name of their own, see
+    /// [`Typechecker::shadow`] and [`Typechecker::nominal_name`]
+    shadowed: usize,
+    /// Whether to warn about bindings shadowing others
+    warn_shadowing: bool,
+    /// The type of every expression and binding checked so far, by where it
+    /// is, for [`TypedAst::type_at`]
+    located: Vec<Spanned<Type>>,
+    /// Every binding and its type, for `--explain-types`, which collects them
+    explained: Option<Vec<(Spanned<&'src str>, TypeId)>>,
+    warnings: Vec<Warning>,
+    /// The errors of the statements that failed so far
+    errors: Vec<Error>,
+}
+
+/// What a statement can leave changed if checking it fails partway through,
+/// to be put back before checking the next one
+struct Checkpoint<'src> {
+    scopes: [usize; 4],
+    labels: Vec<(Option<&'src str>, Option<TypeId>)>,
+    return_ty: Option<TypeId>,
+    closures: usize,
+    use_site: UseSite<'src>,
+}
+
+#[derive(Clone, Debug)]
+struct Trait {
+    /// The type of each method, leaving out `self`, with `Self` standing for
+    /// the type implementing the trait, and where the method is declared,
+    /// which the built-in traits have nowhere
+    methods: Vec<(String, Type, Option<Span>)>,
+}
+
+struct Obligation {
+    ty: Type,
+    trait_: String,
+    span: Span,
+    /// How many impls had been seen when this was required, only those count
+    /// since the ones after it haven't been run yet at that point
+    impls: usize,
+}
+
+/// A closure or function whose body is being checked, collecting the
+/// variables it captures
+struct Closure<'src> {
+    /// How many scopes were open outside the closure, variables bound in
+    /// these are captured
+    depth: usize,
+    span: Span,
+    captures: Vec<Spanned<&'src str>>,
+    /// Whether this is a function, which sees the top level variables of its
+    /// file when it's called instead of capturing them
+    function: bool,
+}
+
+/// The scope that the top level bindings of a file are in, as in the
+/// interpreter
+const TOP_LEVEL: usize = 1;
+
+/// The top level variables of the file being checked and the uses of top level
+/// bindings, to find variables that are used before they're initialized, see
+/// [`Typechecker::check_init_order`]
+#[derive(Default)]
+struct Globals<'src> {
+    /// Where each variable is declared
+    variables: HashMap<&'src str, Span>,
+    /// The variables whose `let` has been checked so far
+    initialized: HashSet<&'src str>,
+    /// What the top level statements do with the variables, in the order
+    /// they run
+    events: Vec<GlobalEvent<'src>>,
+    /// The uses in the body of each top level function
+    functions: HashMap<&'src str, Vec<Spanned<&'src str>>>,
+}
+
+enum GlobalEvent<'src> {
+    Use(Spanned<&'src str>),
+    Init(&'src str),
+}
+
+/// What the uses of top level bindings being checked are part of
+#[derive(Clone, Copy)]
+enum UseSite<'src> {
+    /// The top level statements, including the closures and functions inside
+    /// them, which are taken to run where they're created
+    TopLevel,
+    /// The body of a top level function, which runs when it's called
+    Function(&'src str),
+    /// A method, whose calls can't be followed
+    Method,
+}
+
+#[derive(Clone, Debug)]
+struct Binding<'src> {
+    /// The name the interpreter knows the variable by, which is only different
+    /// from its own for a `let` that shadows a binding in the same scope, see
+    /// [`Typechecker::shadow`]
+    name: &'src str,
+    ty: TypeId,
+    /// Where the variable was declared
+    span: Span,
+    mutable: bool,
+    /// The type parameters of a generic function with the traits they have to
+    /// implement, they're replaced with new type variables every time the
+    /// function is used
+    generics: Vec<(String, Vec<String>)>,
+    /// The value of a constant, which its uses are replaced with
+    value: Option<Literal>,
+    /// Whether anything has read the binding, which it's warned about if not
+    read: bool,
+}
+
+#[derive(Clone, Debug)]
+enum TypeDef<'src> {
+    /// An enum or struct, by the name its type has, which its variants or
+    /// fields are kept under
+    Enum(&'src str),
+    Struct(&'src str),
+    Alias(Type),
+    /// A type parameter of the generic function being checked
+    Param {
+        bounds: Vec<String>,
+    },
 }
 
-impl<'a> Typechecker<'a> {
-    fn new() -> Self {
+type Variants = Vec<(String, Vec<Spanned<Type>>)>;
+
+type Fields = Vec<(String, Spanned<Type>)>;
+
+impl<'src> Typechecker<'src> {
+    fn new(warn_shadowing: bool, explain_types: bool) -> Self {
+        let mut traits = Scopes::new();
+
+        // each method takes and returns values of the implementing type
+        for op in [BinOp::Add, BinOp::Subtract, BinOp::Multiply, BinOp::Divide] {
+            let (trait_, method) = op.trait_().unwrap();
+            let self_ty = Type::Param("Self".to_string());
+
+            traits.insert(
+                trait_,
+                Trait {
+                    methods: vec![(
+                        method.to_string(),
+                        Type::Func(vec![self_ty.clone()], Box::new(self_ty)),
+                        None,
+                    )],
+                },
+            );
+        }
+
         Self {
-            engine: Engine::new(),
+            engine: Engine::new(explain_types),
             bindings: Scopes::new(),
+            types: Scopes::new(),
+            enums: HashMap::new(),
+            structs: HashMap::new(),
+            labels: Vec::new(),
+            broken: HashSet::new(),
+            return_ty: None,
+            closures: Vec::new(),
+            traits,
+            impls: Vec::new(),
+            methods: Vec::new(),
+            obligations: Vec::new(),
+            imports: Scopes::new(),
+            modules: HashMap::new(),
+            declared: HashMap::new(),
+            globals: Globals::default(),
+            use_site: UseSite::TopLevel,
+            shadowed: 0,
+            warn_shadowing,
+            located: Vec::new(),
+            explained: explain_types.then(Vec::new),
+            warnings: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// The name the type of an enum or struct declared as `name` should
+    /// have. Like a shadowing `let`, one declared with the name of another
+    /// gets a new name of its own, so that values of the two types, which
+    /// can both be around when one is declared in a block, aren't mixed up.
+    fn nominal_name(&mut self, name: &'src str) -> &'src str {
+        if !self.enums.contains_key(name) && !self.structs.contains_key(name) {
+            return name;
+        }
+
+        self.shadowed += 1;
+
+        String::leak(format!("{}#{}", name, self.shadowed))
+    }
+
+    fn bind(&mut self, name: Spanned<&'src str>, ty: TypeId, mutable: bool) {
+        // binding a name again in the same scope hides the old binding for
+        // good, unless it's the binding of the same declaration that was made
+        // ahead of time, which may already have been read
+        let read = match self.bindings.get_in(self.bindings.depth() - 1, name.0) {
+            Some(previous) if previous.span == name.1 => previous.read,
+            Some(previous) => {
+                if !previous.read {
+                    let span = previous.span;
+
+                    self.unread(name.0, span);
+                }
+
+                false
+            }
+            None => false,
+        };
+
+        self.located.push((Type::Var(ty), name.1));
+
+        if let Some(explained) = &mut self.explained {
+            match explained.iter_mut().find(|(other, _)| other.1 == name.1) {
+                Some(explanation) => explanation.1 = ty,
+                None => explained.push((name, ty)),
+            }
+        }
+
+        self.bindings.insert(
+            name.0,
+            Binding {
+                name: name.0,
+                ty,
+                span: name.1,
+                mutable,
+                generics: Vec::new(),
+                value: None,
+                read,
+            },
+        );
+    }
+
+    /// Tells how the type of each binding was inferred, if that was asked for
+    fn explain_types(&mut self) {
+        let mut explained = self.explained.take().unwrap_or_default();
+
+        explained.sort_by_key(|(name, _)| name.1.start);
+
+        for (name, ty) in explained {
+            self.warnings.push(Warning::InferredType {
+                name: name.0.to_string(),
+                span: name.1,
+                ty: self.engine.reconstruct(ty).0,
+                steps: self
+                    .engine
+                    .explain(ty)
+                    .into_iter()
+                    .map(|chain| {
+                        chain
+                            .into_iter()
+                            .filter(|span| *span != name.1)
+                            .collect::<Vec<_>>()
+                    })
+                    .filter(|chain| !chain.is_empty())
+                    .collect(),
+            });
+        }
+    }
+
+    /// Warns about a binding that's never read, unless its name says that's
+    /// on purpose
+    fn unread(&mut self, name: &str, span: Span) {
+        if !name.starts_with('_') {
+            self.warnings.push(Warning::UnreadBinding {
+                name: name.to_string(),
+                span,
+            });
         }
     }
 
-    fn typecheck_ast<'src: 'a>(
+    /// Checks a `let` of `name` against the binding it shadows, if there is
+    /// one, and returns the name the interpreter should know it by. A `let`
+    /// in the same scope as another binding of the same name gets a new name
+    /// of its own, so that the functions using the old binding, which look it
+    /// up when they're called, keep seeing that one.
+    fn shadow(&mut self, name: Spanned<&'src str>) -> &'src str {
+        let Some(previous) = self.bindings.get(name.0) else {
+            return name.0;
+        };
+
+        if self.warn_shadowing {
+            self.warnings.push(Warning::Shadowing {
+                name: name.0.to_string(),
+                span: name.1,
+                previous: previous.span,
+            });
+        }
+
+        if self
+            .bindings
+            .get_in(self.bindings.depth() - 1, name.0)
+            .is_none()
+        {
+            return name.0;
+        }
+
+        self.shadowed += 1;
+
+        // `#` can't be part of a name in the source, so this can't clash
+        // with another variable
+        String::leak(format!("{}#{}", name.0, self.shadowed))
+    }
+
+    /// Binds a function, which is generic over `generics` if there are any
+    fn bind_function(
         &mut self,
-        ast: Spanned<Ast<'src>>,
-    ) -> Result<Spanned<TypedAst<'src>>, Error> {
+        name: Spanned<&'src str>,
+        ty: TypeId,
+        generics: &[(Spanned<&'src str>, Vec<Spanned<&'src str>>)],
+    ) {
+        self.bind(name, ty, false);
+
+        if let Some(binding) = self.bindings.get_mut(name.0) {
+            binding.generics = generics
+                .iter()
+                .map(|(generic, bounds)| {
+                    (
+                        generic.0.to_string(),
+                        bounds.iter().map(|bound| bound.0.to_string()).collect(),
+                    )
+                })
+                .collect();
+        }
+    }
+
+    /// Binds the top level functions of a file before any of its statements
+    /// are checked, so that they can call each other whatever order they're
+    /// declared in
+    fn declare_functions(&mut self, statements: &[Spanned<ast::Statement<'src>>]) {
+        for stmt in statements {
+            let ast::Statement::Function {
+                name,
+                generics,
+                params,
+                return_ty,
+                ..
+            } = &stmt.0
+            else {
+                continue;
+            };
+
+            self.types.push_scope();
+
+            for (generic, bounds) in generics {
+                self.types.insert(
+                    generic.0,
+                    TypeDef::Param {
+                        bounds: bounds.iter().map(|bound| bound.0.to_string()).collect(),
+                    },
+                );
+            }
+
+            let param_tys = params
+                .0
+                .iter()
+                .map(|(_, ty)| self.lower_type(ty.clone()))
+                .collect::<Result<Vec<_>, _>>();
+
+            let return_ty = return_ty.clone().map(|ty| self.lower_type(ty)).transpose();
+
+            self.types.pop_scope();
+
+            // a signature with a type that's declared further down can't be
+            // lowered yet, that function is only bound once it's reached, which
+            // is also where any error in the signature is reported
+            let (Ok(param_tys), Ok(return_ty)) = (param_tys, return_ty) else {
+                continue;
+            };
+
+            let param_ids = param_tys
+                .into_iter()
+                .map(|ty| self.engine.insert_type(ty))
+                .collect();
+
+            let return_id = match return_ty {
+                Some(ty) => self.engine.insert_type(ty),
+                None => self
+                    .engine
+                    .insert((TypeInfo::Unknown, (params.1.end..params.1.end).into())),
+            };
+
+            let func_ty = self
+                .engine
+                .insert((TypeInfo::Func(param_ids, return_id), name.1));
+
+            self.bind_function(*name, func_ty, generics);
+            self.declared.insert(name.1.start, func_ty);
+        }
+    }
+
+    /// Binds the top level variables of a file before any of its statements
+    /// are checked, so that functions can use them wherever they're declared.
+    /// Only the first `let` of each name is bound, the others shadow it.
+    fn declare_globals(&mut self, statements: &[Spanned<ast::Statement<'src>>]) {
+        for stmt in statements {
+            let ast::Statement::Let {
+                mutable,
+                name,
+                ty,
+                value,
+            } = &stmt.0
+            else {
+                continue;
+            };
+
+            if self.bindings.get_in(TOP_LEVEL, name.0).is_some() {
+                continue;
+            }
+
+            // a type that's declared further down can't be lowered yet, the
+            // annotation is checked again once the `let` is reached. without
+            // one, the type of a literal is already known.
+            let id = match (ty.clone().map(|ty| self.lower_type(ty)), value) {
+                (Some(Ok(ty)), _) => self.engine.insert_type(ty),
+                (None, (ast::Expr::Literal(literal), span)) => {
+                    let literal = self.lower_literal(literal.clone());
+                    let ty = self.literal_type(&literal);
+
+                    self.engine.insert_type((ty, *span))
+                }
+                _ => self.engine.insert((TypeInfo::Unknown, name.1)),
+            };
+
+            self.bind(*name, id, *mutable);
+            self.declared.insert(name.1.start, id);
+            self.globals.variables.insert(name.0, name.1);
+        }
+    }
+
+    /// Records a use of a top level binding, see [`Typechecker::check_init_order`].
+    /// A variable used directly by a top level statement is checked right away,
+    /// since those are checked in the order they run.
+    fn use_global(&mut self, name: Spanned<&'src str>) -> Result<(), Error> {
+        if self.bindings.depth_of(name.0) != Some(TOP_LEVEL) {
+            return Ok(());
+        }
+
+        let name = (self.bindings.get(name.0).unwrap().name, name.1);
+
+        match self.use_site {
+            UseSite::TopLevel => {
+                if let Some(declared) = self.globals.variables.get(name.0) {
+                    if !self.globals.initialized.contains(name.0) {
+                        return Err(TypecheckError::UsedBeforeInit {
+                            name: name.0.to_string(),
+                            span: name.1,
+                            declared: *declared,
+                            call: None,
+                        }
+                        .into());
+                    }
+                }
+
+                self.globals.events.push(GlobalEvent::Use(name));
+            }
+            UseSite::Function(function) => self
+                .globals
+                .functions
+                .entry(function)
+                .or_default()
+                .push(name),
+            UseSite::Method => {}
+        }
+
+        Ok(())
+    }
+
+    /// Checks that no top level statement calls a function that uses a variable
+    /// before its `let` has run. A function that's only mentioned is taken to
+    /// be called, since it could be.
+    fn check_init_order(&self) -> Result<(), Error> {
+        let mut initialized = HashSet::new();
+        let mut checked = HashSet::new();
+
+        for event in &self.globals.events {
+            match event {
+                GlobalEvent::Init(name) => {
+                    initialized.insert(*name);
+                }
+                GlobalEvent::Use(name) => {
+                    self.check_global_use(*name, None, &initialized, &mut checked)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks a use of a top level binding by a statement, or a function called
+    /// by it at `call`. A function only has to be checked once, since more
+    /// variables are initialized every time it's reached again.
+    fn check_global_use(
+        &self,
+        name: Spanned<&'src str>,
+        call: Option<Span>,
+        initialized: &HashSet<&'src str>,
+        checked: &mut HashSet<&'src str>,
+    ) -> Result<(), Error> {
+        if let Some(declared) = self.globals.variables.get(name.0) {
+            if !initialized.contains(name.0) {
+                return Err(TypecheckError::UsedBeforeInit {
+                    name: name.0.to_string(),
+                    span: name.1,
+                    declared: *declared,
+                    call,
+                }
+                .into());
+            }
+        } else if let Some(uses) = self.globals.functions.get(name.0) {
+            if checked.insert(name.0) {
+                for inner in uses {
+                    self.check_global_use(
+                        *inner,
+                        Some(call.unwrap_or(name.1)),
+                        initialized,
+                        checked,
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records that a variable is used, capturing it in every enclosing
+    /// closure it was bound outside of
+    fn capture(&mut self, name: Spanned<&'src str>) {
+        let Some(depth) = self.bindings.depth_of(name.0) else {
+            return;
+        };
+
+        let name = (self.bindings.get(name.0).unwrap().name, name.1);
+
+        for closure in self.closures.iter_mut().rev() {
+            if depth >= closure.depth {
+                break;
+            }
+
+            if closure.function && depth == TOP_LEVEL {
+                continue;
+            }
+
+            if !closure.captures.iter().any(|capture| capture.0 == name.0) {
+                closure.captures.push(name);
+            }
+        }
+    }
+
+    fn push_scope(&mut self) {
         self.bindings.push_scope();
+        self.types.push_scope();
+        self.traits.push_scope();
+        self.imports.push_scope();
+    }
 
-        let statements = ast
-            .0
-            .statements
-            .0
+    fn pop_scope(&mut self) {
+        let mut unread = self
+            .bindings
+            .pop_scope()
             .into_iter()
-            .map(|stmt| self.typecheck_statement(stmt))
-            .collect::<Result<Vec<_>, _>>()?;
+            .filter(|(_, binding)| !binding.read)
+            .map(|(name, binding)| (name, binding.span))
+            .collect::<Vec<_>>();
 
-        self.bindings.pop_scope();
+        unread.sort_by_key(|(_, span)| span.start);
 
-        Ok((
+        for (name, span) in unread {
+            self.unread(name, span);
+        }
+
+        self.types.pop_scope();
+        self.traits.pop_scope();
+        self.imports.pop_scope();
+    }
+
+    /// Typechecks the whole program, recording the errors in `errors`. The
+    /// typed AST is only complete if there aren't any.
+    fn typecheck_ast(&mut self, ast: Spanned<Ast<'src>>) -> Spanned<TypedAst<'src>> {
+        self.push_scope();
+
+        self.declare_functions(&ast.0.statements.0);
+        self.declare_globals(&ast.0.statements.0);
+
+        let mut statements = self.typecheck_statements(ast.0.statements.0);
+
+        let result = self
+            .check_obligations()
+            .and_then(|()| self.check_init_order());
+        self.record(result);
+
+        self.pop_scope();
+
+        // the types of statements after a failed one can depend on it, so
+        // they're only resolved once everything has checked
+        if self.errors.is_empty() {
+            for stmt in &mut statements {
+                let result = self.resolve_statement(stmt);
+                self.record(result);
+            }
+        }
+
+        let located = std::mem::take(&mut self.located)
+            .into_iter()
+            .map(|(ty, span)| (self.engine.substitute(&ty), span))
+            .collect();
+
+        (
             TypedAst {
                 statements: (statements, ast.0.statements.1),
+                types: TypeIndex::new(located),
             },
             ast.1,
-        ))
+        )
+    }
+
+    /// Typechecks a list of statements, leaving out the ones that fail after
+    /// recording their errors
+    fn typecheck_statements(
+        &mut self,
+        statements: Vec<Spanned<ast::Statement<'src>>>,
+    ) -> Vec<Spanned<Statement<'src>>> {
+        let statements = statements
+            .into_iter()
+            .filter_map(|stmt| self.recover(stmt))
+            .collect::<Vec<_>>();
+
+        // only the first statement that's never run is warned about, the rest
+        // after it go without saying
+        if let Some(at) = statements.iter().position(diverges) {
+            if let Some(stmt) = statements[at + 1..].iter().find(|stmt| runs(stmt)) {
+                self.warnings.push(Warning::UnreachableCode {
+                    span: stmt.1,
+                    cause: statements[at].1,
+                });
+            }
+        }
+
+        statements
+    }
+
+    /// Typechecks a statement, or records its error and goes back to where
+    /// the typechecker was before it. A variable or function it would have
+    /// bound gets a placeholder type instead, so its uses don't fail too.
+    fn recover(&mut self, stmt: Spanned<ast::Statement<'src>>) -> Option<Spanned<Statement<'src>>> {
+        let bound = match &stmt.0 {
+            ast::Statement::Let { name, mutable, .. } => Some((*name, *mutable)),
+            ast::Statement::Const { name, .. } => Some((*name, false)),
+            // a function declared ahead of time keeps the type in its signature
+            ast::Statement::Function { name, .. }
+                if self
+                    .bindings
+                    .get_in(self.bindings.depth() - 1, name.0)
+                    .is_none() =>
+            {
+                Some((*name, false))
+            }
+            _ => None,
+        };
+
+        let checkpoint = self.checkpoint();
+
+        let error = match self.typecheck_statement(stmt) {
+            Ok(stmt) => return Some(stmt),
+            Err(error) => error,
+        };
+
+        self.restore(checkpoint);
+        self.record(Err(error));
+
+        if let Some((name, mutable)) = bound {
+            let placeholder = self.engine.insert((TypeInfo::Error, name.1));
+
+            // a top level variable counts as initialized, since its `let`
+            // was reached
+            if let Some(declared) = self.declared.remove(&name.1.start) {
+                let result = self.engine.unify(placeholder, declared);
+                self.record(result);
+            }
+
+            if self.globals.variables.get(name.0) == Some(&name.1) {
+                self.globals.initialized.insert(name.0);
+                self.globals.events.push(GlobalEvent::Init(name.0));
+            }
+
+            self.bind(name, placeholder, mutable);
+        }
+
+        None
+    }
+
+    fn checkpoint(&self) -> Checkpoint<'src> {
+        Checkpoint {
+            scopes: [
+                self.bindings.depth(),
+                self.types.depth(),
+                self.traits.depth(),
+                self.imports.depth(),
+            ],
+            labels: self.labels.clone(),
+            return_ty: self.return_ty,
+            closures: self.closures.len(),
+            use_site: self.use_site,
+        }
+    }
+
+    fn restore(&mut self, checkpoint: Checkpoint<'src>) {
+        let [bindings, types, traits, imports] = checkpoint.scopes;
+
+        self.bindings.split_off(bindings);
+        self.types.split_off(types);
+        self.traits.split_off(traits);
+        self.imports.split_off(imports);
+
+        self.labels = checkpoint.labels;
+        self.return_ty = checkpoint.return_ty;
+        self.closures.truncate(checkpoint.closures);
+        self.use_site = checkpoint.use_site;
+    }
+
+    /// Keeps the error of a check that failed, unless it's one that's only
+    /// caused by an earlier error, see [`Typechecker::cannot_infer`]
+    fn record(&mut self, result: Result<(), Error>) {
+        match result {
+            Ok(()) => {}
+            Err(Error::Many(errors)) => self.errors.extend(errors),
+            Err(error) => self.errors.push(error),
+        }
+    }
+
+    /// The error for a type that has to be known by now but isn't. If it's
+    /// unknown because of a statement that failed, that error has already been
+    /// recorded, so this is an empty [`Error::Many`] that isn't shown.
+    fn cannot_infer(&self, ty: &Type, span: Span) -> Error {
+        if self.engine.has_placeholder(ty) {
+            Error::Many(vec![])
+        } else {
+            TypecheckError::CannotInferType { span }.into()
+        }
     }
 
-    fn typecheck_statement<'src: 'a>(
+    fn typecheck_statement(
         &mut self,
         stmt: Spanned<ast::Statement<'src>>,
     ) -> Result<Spanned<Statement<'src>>, Error> {
         Ok((
             match stmt.0 {
                 ast::Statement::Expr(expr) => {
-                    let expr = self.typecheck_expr(expr)?;
+                    let expr = self.typecheck_expr(expr);
 
                     Statement::Expr(expr)
                 }
                 ast::Statement::Block(statements) => {
-                    self.bindings.push_scope();
+                    self.push_scope();
 
-                    let statements = statements
-                        .0
-                        .into_iter()
-                        .map(|stmt| self.typecheck_statement(stmt))
-                        .collect::<Result<Vec<_>, _>>()?;
+                    let statements = self.typecheck_statements(statements.0);
 
-                    self.bindings.pop_scope();
+                    self.pop_scope();
 
                     Statement::Block((statements, stmt.1))
                 }
-                ast::Statement::Let { name, ty, value } => {
-                    let value = self.typecheck_expr(value)?;
-                    let value_ty = self.engine.insert(type_to_typeinfo((value.0.ty, value.1)));
-
-                    let ty = ty.map(|ty| self.lower_type(ty));
+                ast::Statement::Let {
+                    mutable,
+                    name,
+                    ty,
+                    value,
+                } => {
+                    let ty = ty.map(|ty| self.lower_type(ty)).transpose()?;
 
-                    let ty = match ty {
+                    let (value, ty) = match ty {
                         Some(ty) => {
-                            let ty = self.engine.insert(type_to_typeinfo(ty));
+                            let ty = self.engine.insert_type(ty);
+
+                            (self.check_expr(value, ty), ty)
+                        }
+                        None => {
+                            let value = self.typecheck_expr(value);
+                            let value_ty = self.engine.insert_type((value.0.ty.clone(), value.1));
+
+                            (value, value_ty)
+                        }
+                    };
 
-                            self.engine.unify(value_ty, ty)?;
+                    // a top level variable was bound ahead of time, and isn't
+                    // shadowing anything
+                    let declared = self.declared.remove(&name.1.start);
+
+                    let runtime_name = match declared {
+                        Some(declared) => {
+                            self.engine.unify(ty, declared)?;
+                            self.globals.initialized.insert(name.0);
+                            self.globals.events.push(GlobalEvent::Init(name.0));
+
+                            name.0
+                        }
+                        None => self.shadow(name),
+                    };
 
-                            ty
+                    // a closure that can't be changed can be used at a different
+                    // type every time, like a generic function
+                    let generics = match value.0.expr {
+                        ExprKind::Closure { .. } if !mutable => {
+                            self.generalize(ty, declared, value.1)?
                         }
-                        None => value_ty,
+                        _ => vec![],
                     };
 
-                    self.bindings.insert(name.0, ty);
+                    self.bind(name, ty, mutable);
+
+                    if let Some(binding) = self.bindings.get_mut(name.0) {
+                        binding.name = runtime_name;
+                        binding.generics = generics;
+                    }
 
                     Statement::Let {
+                        mutable,
+                        name: (runtime_name, name.1),
+                        ty: self.engine.reconstruct(ty),
+                        value,
+                    }
+                }
+                ast::Statement::Const { name, ty, value } => {
+                    let (value, ty) = match ty {
+                        Some(ty) => {
+                            let ty = self.lower_type(ty)?;
+                            let ty = self.engine.insert_type(ty);
+
+                            (self.check_expr(value, ty), ty)
+                        }
+                        None => {
+                            let value = self.typecheck_expr(value);
+                            let value_ty = self.engine.insert_type((value.0.ty.clone(), value.1));
+
+                            (value, value_ty)
+                        }
+                    };
+
+                    let value_span = value.1;
+
+                    let value =
+                        crate::fold::fold_constant(value).ok_or(TypecheckError::NotConstant {
+                            name: name.0.to_string(),
+                            span: value_span,
+                        })?;
+
+                    self.bind(name, ty, false);
+                    self.bindings.get_mut(name.0).unwrap().value = Some(value.0.clone());
+
+                    Statement::Const {
                         name,
-                        ty: self.engine.reconstruct(ty)?,
+                        ty: self.engine.reconstruct(ty),
                         value,
                     }
                 }
                 ast::Statement::Function {
                     name,
+                    generics,
                     params,
                     return_ty,
                     body,
                 } => {
-                    todo!()
-                }
-                ast::Statement::Assign { name, value } => {
-                    let value = self.typecheck_expr(value)?;
-                    let value_ty = self.engine.insert(type_to_typeinfo((value.0.ty, value.1)));
+                    // type parameters are only in scope in the function's signature and body
+                    self.types.push_scope();
 
-                    let ty =
-                        self.bindings
-                            .get(&name.0)
-                            .ok_or(TypecheckError::UndefinedVariable {
-                                name: name.0.to_string(),
-                                span: name.1,
-                            })?;
+                    for (i, (generic, bounds)) in generics.iter().enumerate() {
+                        if let Some((other, _)) =
+                            generics[..i].iter().find(|(other, _)| other.0 == generic.0)
+                        {
+                            return Err(TypecheckError::DuplicateTypeParam {
+                                name: generic.0.to_string(),
+                                span1: other.1,
+                                span2: generic.1,
+                            }
+                            .into());
+                        }
 
-                    self.engine.unify(value_ty, *ty)?;
+                        for bound in bounds {
+                            if self.traits.get(bound.0).is_none() {
+                                return Err(TypecheckError::UndefinedTrait {
+                                    name: bound.0.to_string(),
+                                    span: bound.1,
+                                }
+                                .into());
+                            }
+                        }
 
-                    Statement::Assign { name, value }
-                }
-                ast::Statement::Print(expr) => {
-                    let expr = match expr {
-                        Some(expr) => expr,
-                        None => (
-                            ast::Expr::Literal((
-                                ast::Literal::Unit,
-                                (stmt.1.end..stmt.1.end).into(),
-                            )),
-                            (stmt.1.end..stmt.1.end).into(),
-                        ),
-                    };
+                        self.types.insert(
+                            generic.0,
+                            TypeDef::Param {
+                                bounds: bounds.iter().map(|bound| bound.0.to_string()).collect(),
+                            },
+                        );
+                    }
 
-                    let expr = self.typecheck_expr(expr)?;
+                    let params = (
+                        params
+                            .0
+                            .into_iter()
+                            .map(|(param, ty)| Ok((param, self.lower_type(ty)?)))
+                            .collect::<Result<Vec<_>, Error>>()?,
+                        params.1,
+                    );
 
-                    Statement::Print(expr)
-                }
-                ast::Statement::Return(expr) => {
-                    let expr = match expr {
-                        Some(expr) => expr,
-                        None => (
-                            ast::Expr::Literal((
-                                ast::Literal::Unit,
-                                (stmt.1.end..stmt.1.end).into(),
-                            )),
-                            (stmt.1.end..stmt.1.end).into(),
-                        ),
+                    // without an annotation the return type is inferred from the
+                    // function's return statements
+                    let return_id = match return_ty {
+                        Some(ty) => {
+                            let ty = self.lower_type(ty)?;
+
+                            self.engine.insert_type(ty)
+                        }
+                        None => self
+                            .engine
+                            .insert((TypeInfo::Unknown, (params.1.end..params.1.end).into())),
                     };
 
-                    let expr = self.typecheck_expr(expr)?;
+                    let param_ids = params
+                        .0
+                        .iter()
+                        .map(|(_, ty)| self.engine.insert_type(ty.clone()))
+                        .collect::<Vec<_>>();
+
+                    let func_ty = self
+                        .engine
+                        .insert((TypeInfo::Func(param_ids.clone(), return_id), name.1));
+
+                    // uses before this point were checked against the type it was
+                    // declared with
+                    if let Some(declared) = self.declared.remove(&name.1.start) {
+                        self.engine.unify(func_ty, declared)?;
+                    }
+
+                    // bound before checking the body so that the function can call itself
+                    self.bind_function(name, func_ty, &generics);
+
+                    // labels outside the function can't be broken out of from inside it
+                    let labels = std::mem::take(&mut self.labels);
+                    let outer_return_ty = self.return_ty.replace(return_id);
+
+                    self.closures.push(Closure {
+                        depth: self.bindings.depth(),
+                        span: name.1,
+                        captures: Vec::new(),
+                        function: true,
+                    });
+
+                    // only the body of a function at the top level runs when it's called,
+                    // a function inside a statement is taken to run with the statement
+                    let outer_use_site = self.use_site;
+
+                    if self.bindings.depth() == TOP_LEVEL + 1
+                        && matches!(self.use_site, UseSite::TopLevel)
+                    {
+                        self.use_site = UseSite::Function(name.0);
+                    }
+
+                    self.push_scope();
+
+                    for ((param, _), id) in params.0.iter().zip(param_ids) {
+                        self.bind_param(*param, id);
+                    }
+
+                    let errors = self.errors.len();
+                    let body = self.typecheck_statement(*body)?;
+                    let failed = self.errors.len() > errors;
+
+                    self.pop_scope();
+
+                    // the function is bound in the scope around it, but it's
+                    // there by the time it's called
+                    let mut captures = self.closures.pop().unwrap().captures;
+                    captures.retain(|capture| capture.0 != name.0);
+
+                    self.use_site = outer_use_site;
+
+                    self.labels = labels;
+                    self.return_ty = outer_return_ty;
+
+                    let return_ty = self.engine.reconstruct(return_id);
+
+                    match return_ty.0 {
+                        // its return statements could have been among the
+                        // ones that failed
+                        Type::Var(_) if failed => {
+                            let placeholder = self.engine.insert((TypeInfo::Error, return_ty.1));
+
+                            self.engine.unify(placeholder, return_id)?;
+                        }
+                        _ if failed => {}
+                        // a function with no return statements returns unit
+                        Type::Var(_) => {
+                            let unit = self.engine.insert((TypeInfo::Unit, return_ty.1));
+
+                            self.engine.unify(unit, return_id)?;
+                        }
+                        Type::Unit => {}
+                        ty if !always_returns(&body) => {
+                            return Err(TypecheckError::MissingReturn { span: name.1, ty }.into())
+                        }
+                        _ => {}
+                    }
+
+                    let return_ty = self.engine.reconstruct(return_id);
+
+                    // uses of the type parameters have to be checked while they're in scope
+                    if !generics.is_empty() {
+                        self.check_obligations()?;
+                    }
+
+                    self.types.pop_scope();
+
+                    Statement::Function {
+                        name,
+                        generics,
+                        params,
+                        return_ty,
+                        captures,
+                        body: Box::new(body),
+                    }
+                }
+                ast::Statement::Assign { name, value } => {
+                    let binding = self.assignable_binding(name)?;
+                    let value = self.check_expr(value, binding.ty);
+
+                    self.use_global(name)?;
+
+                    Statement::Assign {
+                        name: (binding.name, name.1),
+                        value,
+                    }
+                }
+                ast::Statement::SetIndex { name, index, value } => {
+                    let binding = self.assignable_binding(name)?;
+
+                    self.use_global(name)?;
+
+                    let index = self.typecheck_expr(index);
+                    let index_ty = self.engine.insert_type((index.0.ty.clone(), index.1));
+
+                    let value = self.typecheck_expr(value);
+                    let value_ty = self.engine.insert_type((value.0.ty.clone(), value.1));
+
+                    match self.engine.reconstruct(binding.ty).0 {
+                        Type::Map(_, _) => {
+                            let key = self.engine.insert((TypeInfo::Unknown, name.1));
+                            let element = self.engine.insert((TypeInfo::Unknown, name.1));
+                            let map = self.engine.insert((TypeInfo::Map(key, element), name.1));
+
+                            self.engine.unify(binding.ty, map)?;
+                            self.coerce(index_ty, key)?;
+                            self.coerce(value_ty, element)?;
+                        }
+                        Type::List(_) | Type::Var(_) => {
+                            let element = self.engine.insert((TypeInfo::Unknown, name.1));
+                            let list = self.engine.insert((TypeInfo::List(element), name.1));
+                            let int = self.engine.insert((TypeInfo::Int, index.1));
+
+                            self.engine.unify(binding.ty, list)?;
+                            self.engine.unify(index_ty, int)?;
+                            self.coerce(value_ty, element)?;
+                        }
+                        ty => {
+                            return Err(TypecheckError::NotAList { span: name.1, ty }.into());
+                        }
+                    }
+
+                    Statement::SetIndex {
+                        name: (binding.name, name.1),
+                        index,
+                        value,
+                    }
+                }
+                ast::Statement::Return(expr) => {
+                    let expr = match expr {
+                        Some(expr) => expr,
+                        None => (
+                            ast::Expr::Literal((
+                                ast::Literal::Unit,
+                                (stmt.1.end..stmt.1.end).into(),
+                            )),
+                            (stmt.1.end..stmt.1.end).into(),
+                        ),
+                    };
+
+                    let return_ty = self
+                        .return_ty
+                        .ok_or(TypecheckError::ReturnOutsideFunction { span: stmt.1 })?;
+
+                    let expr = self.check_expr(expr, return_ty);
+
+                    Statement::Return(expr)
+                }
+                ast::Statement::Import {
+                    path,
+                    module: None,
+                    file,
+                    statements,
+                } => {
+                    // imported statements share the importing scope so that
+                    // their top level bindings stay visible
+                    self.declare_functions(&statements);
+                    self.declare_globals(&statements);
+
+                    let statements = self.typecheck_statements(statements);
+
+                    Statement::Import {
+                        path,
+                        module: None,
+                        file,
+                        statements,
+                    }
+                }
+                ast::Statement::Import {
+                    path,
+                    module: Some(module),
+                    file,
+                    statements,
+                } => {
+                    // a module can't see the variables of the file importing it, and
+                    // its own are kept apart to be reached through its name. types are
+                    // still shared, so values of them can be passed between the two.
+                    let outer_bindings = std::mem::replace(&mut self.bindings, Scopes::new());
+                    self.bindings.push_scope();
+                    let outer_imports = std::mem::replace(&mut self.imports, Scopes::new());
+                    let outer_globals = std::mem::take(&mut self.globals);
+
+                    self.declare_functions(&statements);
+                    self.declare_globals(&statements);
+
+                    let statements = self.typecheck_statements(statements);
+
+                    // the importing file's bindings have to be put back, so
+                    // this doesn't fail the import
+                    let result = self.check_init_order();
+                    self.record(result);
+
+                    let bindings = std::mem::replace(&mut self.bindings, outer_bindings);
+                    self.imports = outer_imports;
+                    self.globals = outer_globals;
+
+                    // a module that was already imported elsewhere has no statements
+                    // this time, and keeps the bindings from then
+                    self.modules.entry(file).or_insert(bindings);
+                    self.imports.insert(module.0, file);
+
+                    Statement::Import {
+                        path,
+                        module: Some(module),
+                        file,
+                        statements,
+                    }
+                }
+                ast::Statement::Trait { name, methods } => {
+                    // `Self` is only in scope in the trait's method signatures
+                    self.types.push_scope();
+
+                    self.types.insert(
+                        "Self",
+                        TypeDef::Param {
+                            bounds: vec![name.0.to_string()],
+                        },
+                    );
+
+                    let methods = methods
+                        .into_iter()
+                        .map(|(sig, span)| {
+                            let params = sig
+                                .params
+                                .0
+                                .into_iter()
+                                .map(|(_, ty)| Ok(self.lower_type(ty)?.0))
+                                .collect::<Result<Vec<_>, Error>>()?;
+
+                            let return_ty = match sig.return_ty {
+                                Some(ty) => self.lower_type(ty)?.0,
+                                None => Type::Unit,
+                            };
+
+                            Ok((sig.name, (Type::Func(params, Box::new(return_ty)), span)))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+
+                    self.types.pop_scope();
+
+                    for (i, (method, _)) in methods.iter().enumerate() {
+                        if let Some((first, _)) =
+                            methods[..i].iter().find(|(other, _)| other.0 == method.0)
+                        {
+                            return Err(TypecheckError::DuplicateMethod {
+                                name: method.0.to_string(),
+                                span1: first.1,
+                                span2: method.1,
+                            }
+                            .into());
+                        }
+                    }
+
+                    self.traits.insert(
+                        name.0,
+                        Trait {
+                            methods: methods
+                                .iter()
+                                .map(|(method, ty)| {
+                                    (method.0.to_string(), ty.0.clone(), Some(ty.1))
+                                })
+                                .collect(),
+                        },
+                    );
+
+                    Statement::Trait { name, methods }
+                }
+                ast::Statement::Impl {
+                    trait_,
+                    ty,
+                    methods,
+                } => {
+                    let trait_def = match trait_ {
+                        Some(trait_) => Some(self.traits.get(trait_.0).cloned().ok_or(
+                            TypecheckError::UndefinedTrait {
+                                name: trait_.0.to_string(),
+                                span: trait_.1,
+                            },
+                        )?),
+                        None => None,
+                    };
+
+                    let self_ty = ty.0.clone();
+                    let ty = self.lower_type(ty)?;
+
+                    if !matches!(
+                        ty.0,
+                        Type::Int
+                            | Type::Float
+                            | Type::Bool
+                            | Type::Str
+                            | Type::Char
+                            | Type::Unit
+                            | Type::Enum(_)
+                            | Type::Struct(_)
+                    ) {
+                        return Err(TypecheckError::CannotImplement {
+                            ty: ty.0,
+                            span: ty.1,
+                        }
+                        .into());
+                    }
+
+                    // `Self` stands for the type in the methods
+                    self.types.push_scope();
+                    self.types.insert("Self", TypeDef::Alias(ty.0.clone()));
+
+                    match trait_ {
+                        Some(trait_) => {
+                            if let Some((_, other)) =
+                                self.impls.iter().find(|(other_trait, other)| {
+                                    other_trait == trait_.0 && other.0 == ty.0
+                                })
+                            {
+                                return Err(TypecheckError::DuplicateImpl {
+                                    trait_: trait_.0.to_string(),
+                                    ty: ty.0,
+                                    span1: other.1,
+                                    span2: ty.1,
+                                }
+                                .into());
+                            }
+
+                            // recorded before checking the methods so that they can call each other
+                            self.impls.push((trait_.0.to_string(), ty.clone()));
+                        }
+                        None => self.declare_methods(&ty.0, &methods)?,
+                    }
+
+                    let mut implemented: Vec<Spanned<&str>> = Vec::new();
+                    let mut typed_methods = Vec::new();
+
+                    let outer_use_site = std::mem::replace(&mut self.use_site, UseSite::Method);
+
+                    for (sig, body) in methods {
+                        let name = sig.0.name;
+
+                        if let Some(first) = implemented.iter().find(|other| other.0 == name.0) {
+                            return Err(TypecheckError::DuplicateMethod {
+                                name: name.0.to_string(),
+                                span1: first.1,
+                                span2: name.1,
+                            }
+                            .into());
+                        }
+
+                        let expected = match (trait_, &trait_def) {
+                            (Some(trait_), Some(trait_def)) => trait_def
+                                .methods
+                                .iter()
+                                .find(|(method, ..)| method == name.0)
+                                .map(|(_, method_ty, declared)| {
+                                    (
+                                        method_ty.instantiate(&HashMap::from([(
+                                            "Self".to_string(),
+                                            ty.0.clone(),
+                                        )])),
+                                        declared.unwrap_or(sig.1),
+                                    )
+                                })
+                                .ok_or(TypecheckError::NotATraitMethod {
+                                    trait_: trait_.0.to_string(),
+                                    method: name.0.to_string(),
+                                    span: name.1,
+                                })?,
+                            _ => self.inherent_method(&ty.0, name.0).unwrap().clone(),
+                        };
+
+                        let mut params =
+                            vec![(("self", sig.0.self_), (self_ty.clone(), sig.0.self_))];
+
+                        params.extend(sig.0.params.0);
+
+                        // checked like a function in a scope of its own, so that methods
+                        // aren't visible as variables outside of the impl
+                        self.push_scope();
+
+                        let method = self.typecheck_statement((
+                            ast::Statement::Function {
+                                name,
+                                generics: vec![],
+                                params: (params, sig.0.params.1),
+                                return_ty: sig.0.return_ty,
+                                body: Box::new(body),
+                            },
+                            sig.1,
+                        ))?;
+
+                        // it's called on values of the type rather than by its name
+                        if let Some(binding) = self.bindings.get_mut(name.0) {
+                            binding.read = true;
+                        }
+
+                        self.pop_scope();
+
+                        let Statement::Function {
+                            params, return_ty, ..
+                        } = &method.0
+                        else {
+                            unreachable!()
+                        };
+
+                        let found = Type::Func(
+                            params.0[1..].iter().map(|(_, ty)| ty.0.clone()).collect(),
+                            Box::new(return_ty.0.clone()),
+                        );
+
+                        let found = self.engine.insert_type((found, sig.1));
+                        let expected = self.engine.insert_type(expected);
+
+                        self.engine.unify(found, expected)?;
+
+                        implemented.push(name);
+                        typed_methods.push(method);
+                    }
+
+                    self.use_site = outer_use_site;
+                    self.types.pop_scope();
+
+                    if let (Some(trait_), Some(trait_def)) = (trait_, &trait_def) {
+                        if let Some((method, _, declared)) = trait_def
+                            .methods
+                            .iter()
+                            .find(|(method, ..)| !implemented.iter().any(|other| other.0 == method))
+                        {
+                            return Err(TypecheckError::MissingTraitMethod {
+                                trait_: trait_.0.to_string(),
+                                method: method.clone(),
+                                span: trait_.1,
+                                declared: *declared,
+                            }
+                            .into());
+                        }
+                    }
+
+                    Statement::Impl {
+                        trait_,
+                        ty,
+                        methods: typed_methods,
+                    }
+                }
+                ast::Statement::Break { label, value } => {
+                    let label_ty = self
+                        .labels
+                        .iter()
+                        .rev()
+                        .find(|(name, _)| *name == label.map(|label| label.0))
+                        .map(|(_, ty)| *ty)
+                        .ok_or_else(|| match label {
+                            Some(label) => TypecheckError::UndefinedLabel {
+                                label: label.0.to_string(),
+                                span: label.1,
+                            },
+                            None => TypecheckError::BreakOutsideLoop { span: stmt.1 },
+                        })?;
+
+                    let value_span = label.map_or(stmt.1, |label| label.1);
+
+                    let value = match (value, label_ty) {
+                        (Some(value), None) => {
+                            return Err(TypecheckError::BreakValueOutOfLoop { span: value.1 }.into())
+                        }
+                        (Some(value), Some(_)) => value,
+                        (None, _) => (
+                            ast::Expr::Literal((ast::Literal::Unit, value_span)),
+                            value_span,
+                        ),
+                    };
+
+                    let value = self.typecheck_expr(value);
+
+                    if let Some(label_ty) = label_ty {
+                        let value_ty = self.engine.insert_type((value.0.ty.clone(), value.1));
+
+                        self.engine.unify(value_ty, label_ty)?;
+                        self.broken.insert(label_ty);
+                    }
+
+                    Statement::Break { label, value }
+                }
+                ast::Statement::Continue => {
+                    if !self.labels.iter().any(|(name, _)| name.is_none()) {
+                        return Err(TypecheckError::ContinueOutsideLoop { span: stmt.1 }.into());
+                    }
+
+                    Statement::Continue
+                }
+                ast::Statement::While { cond, body } => {
+                    let cond = self.typecheck_expr(cond);
+                    let cond_ty = self.engine.insert_type((cond.0.ty.clone(), cond.1));
+                    let bool_ty = self.engine.insert((TypeInfo::Bool, cond.1));
+
+                    self.coerce(cond_ty, bool_ty)?;
+
+                    self.labels.push((None, None));
+                    self.push_scope();
+
+                    let statements = self.typecheck_statements(body.0);
+
+                    self.pop_scope();
+                    self.labels.pop();
+
+                    Statement::While {
+                        cond,
+                        body: (statements, body.1),
+                    }
+                }
+                ast::Statement::For {
+                    first,
+                    second,
+                    iter,
+                    body,
+                } => {
+                    let iter = self.typecheck_expr(iter);
+                    let iter_id = self.engine.insert_type((iter.0.ty.clone(), iter.1));
+
+                    // a list gives its elements, a map its keys, a str its
+                    // chars and a range its ints, along with the index or value
+                    // when there's a second name
+                    let (first_ty, second_ty) = match self.engine.reconstruct(iter_id).0 {
+                        Type::Map(_, _) => {
+                            let key = self.engine.insert((TypeInfo::Unknown, iter.1));
+                            let value = self.engine.insert((TypeInfo::Unknown, iter.1));
+                            let map = self.engine.insert((TypeInfo::Map(key, value), iter.1));
+
+                            self.engine.unify(iter_id, map)?;
+
+                            (key, value)
+                        }
+                        Type::Str => (
+                            self.engine.insert((TypeInfo::Int, iter.1)),
+                            self.engine.insert((TypeInfo::Char, iter.1)),
+                        ),
+                        Type::Range => (
+                            self.engine.insert((TypeInfo::Int, iter.1)),
+                            self.engine.insert((TypeInfo::Int, iter.1)),
+                        ),
+                        Type::List(_) | Type::Var(_) => {
+                            let element = self.engine.insert((TypeInfo::Unknown, iter.1));
+                            let list = self.engine.insert((TypeInfo::List(element), iter.1));
+
+                            self.engine.unify(iter_id, list)?;
+
+                            (self.engine.insert((TypeInfo::Int, iter.1)), element)
+                        }
+                        ty => {
+                            return Err(TypecheckError::NotIterable { span: iter.1, ty }.into());
+                        }
+                    };
+
+                    let is_map = matches!(self.engine.reconstruct(iter_id).0, Type::Map(_, _));
+
+                    self.labels.push((None, None));
+                    self.push_scope();
+
+                    match second {
+                        Some(second) => {
+                            self.bind(first, first_ty, false);
+                            self.bind(second, second_ty, false);
+                        }
+                        None if is_map => self.bind(first, first_ty, false),
+                        None => self.bind(first, second_ty, false),
+                    }
+
+                    let statements = self.typecheck_statements(body.0);
+
+                    self.pop_scope();
+                    self.labels.pop();
+
+                    Statement::For {
+                        first,
+                        second,
+                        iter,
+                        body: (statements, body.1),
+                    }
+                }
+                ast::Statement::Enum { name, variants } => {
+                    let unique = self.nominal_name(name.0);
+
+                    // registered before lowering the payloads so that they can refer to the
+                    // enum itself
+                    self.types.insert(name.0, TypeDef::Enum(unique));
+                    self.enums.insert(unique, vec![]);
+
+                    let variants = (
+                        variants
+                            .0
+                            .into_iter()
+                            .map(|(variant, fields)| {
+                                Ok((
+                                    variant,
+                                    fields
+                                        .into_iter()
+                                        .map(|field| self.lower_type(field))
+                                        .collect::<Result<Vec<_>, _>>()?,
+                                ))
+                            })
+                            .collect::<Result<Vec<_>, Error>>()?,
+                        variants.1,
+                    );
+
+                    for (i, (variant, _)) in variants.0.iter().enumerate() {
+                        if let Some((first, _)) = variants.0[..i]
+                            .iter()
+                            .find(|(other, _)| other.0 == variant.0)
+                        {
+                            return Err(TypecheckError::DuplicateVariant {
+                                name: variant.0.to_string(),
+                                span1: first.1,
+                                span2: variant.1,
+                            }
+                            .into());
+                        }
+                    }
+
+                    self.enums.insert(
+                        unique,
+                        variants
+                            .0
+                            .iter()
+                            .map(|(variant, fields)| (variant.0.to_string(), fields.clone()))
+                            .collect(),
+                    );
+
+                    Statement::Enum {
+                        name: (unique, name.1),
+                        variants,
+                    }
+                }
+                ast::Statement::Struct { name, fields } => {
+                    let unique = self.nominal_name(name.0);
+
+                    // registered before lowering the fields so that they can refer to the
+                    // struct itself
+                    self.types.insert(name.0, TypeDef::Struct(unique));
+                    self.structs.insert(unique, vec![]);
+
+                    let fields = (
+                        fields
+                            .0
+                            .into_iter()
+                            .map(|(field, ty)| Ok((field, self.lower_type(ty)?)))
+                            .collect::<Result<Vec<_>, Error>>()?,
+                        fields.1,
+                    );
+
+                    for (i, (field, _)) in fields.0.iter().enumerate() {
+                        if let Some((first, _)) =
+                            fields.0[..i].iter().find(|(other, _)| other.0 == field.0)
+                        {
+                            return Err(TypecheckError::DuplicateField {
+                                name: field.0.to_string(),
+                                span1: first.1,
+                                span2: field.1,
+                            }
+                            .into());
+                        }
+                    }
+
+                    self.structs.insert(
+                        unique,
+                        fields
+                            .0
+                            .iter()
+                            .map(|(field, ty)| (field.0.to_string(), ty.clone()))
+                            .collect(),
+                    );
+
+                    Statement::Struct {
+                        name: (unique, name.1),
+                        fields,
+                    }
+                }
+                ast::Statement::TypeAlias { name, ty } => {
+                    if ty.0 == ast::Type::Named(name.0) {
+                        return Err(TypecheckError::RecursiveTypeAlias {
+                            name: name.0.to_string(),
+                            span: ty.1,
+                        }
+                        .into());
+                    }
+
+                    let ty = self.lower_type(ty)?;
+
+                    self.types.insert(name.0, TypeDef::Alias(ty.0.clone()));
+
+                    Statement::TypeAlias { name, ty }
+                }
+            },
+            stmt.1,
+        ))
+    }
+
+    /// Gives unit to a block-like expression that never produces a value
+    /// because every path through it returns, so that it can still be resolved.
+    /// One that does produce a value is left alone, since its value can have
+    /// the type of a variable that's solved later, like a closure's parameter.
+    fn default_to_unit(&mut self, ty: TypeId) -> Result<(), Error> {
+        let (ty_now, span) = self.engine.reconstruct(ty);
+
+        if let Type::Var(_) = ty_now {
+            let unit = self.engine.insert((TypeInfo::Unit, span));
+
+            self.engine.unify(unit, ty)?;
+        }
+
+        Ok(())
+    }
+
+    /// Typechecks one part of an interpolated string as a string expression
+    fn lower_str_part(&mut self, part: ast::StrPart<'src>) -> Result<Spanned<Expr<'src>>, Error> {
+        match part {
+            ast::StrPart::Placeholder((_, span)) => {
+                Err(TypecheckError::PlaceholderOutsideFormat { span }.into())
+            }
+            ast::StrPart::Text((text, span)) => Ok((
+                Expr {
+                    expr: ExprKind::Literal((Literal::Str(text), span)),
+                    ty: Type::Str,
+                },
+                span,
+            )),
+            ast::StrPart::Expr(expr) => {
+                let expr = self.typecheck_expr(expr);
+                let expr_id = self.engine.insert_type((expr.0.ty.clone(), expr.1));
+
+                match self.engine.reconstruct(expr_id).0 {
+                    Type::Str => Ok(expr),
+                    ty if !ty.is_known() => Err(self.cannot_infer(&ty, expr.1)),
+                    _ => {
+                        let span = expr.1;
+
+                        Ok((
+                            Expr {
+                                expr: ExprKind::BuiltinCall {
+                                    builtin: (Builtin::ToStr, span),
+                                    args: (vec![expr], span),
+                                },
+                                ty: Type::Str,
+                            },
+                            span,
+                        ))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Typechecks the format string and arguments of `print`, `println` or
+    /// `format`, giving the string they make. Each placeholder is filled in by
+    /// the next argument.
+    fn typecheck_format(
+        &mut self,
+        builtin: Spanned<Builtin>,
+        args: Spanned<Vec<Spanned<ast::Expr<'src>>>>,
+    ) -> Result<Spanned<Expr<'src>>, Error> {
+        let arg_count = args.0.len().saturating_sub(1);
+        let mut args_iter = args.0.into_iter();
+
+        let (parts, format_span) = match args_iter.next() {
+            // `println()` writes an empty line
+            None => (vec![], args.1),
+            Some((ast::Expr::Interpolated(parts), span)) => (parts, span),
+            Some((ast::Expr::Literal((ast::Literal::Str(text), text_span)), span)) => {
+                (vec![ast::StrPart::Text((text, text_span))], span)
+            }
+            Some((_, span)) => {
+                return Err(TypecheckError::NotAFormatString {
+                    builtin: builtin.0.to_string(),
+                    span,
+                }
+                .into())
+            }
+        };
+
+        let mut placeholders = 0;
+        let mut lowered = vec![];
+
+        for part in parts {
+            let ast::StrPart::Placeholder((spec, span)) = part else {
+                lowered.push(self.lower_str_part(part)?);
+                continue;
+            };
+
+            placeholders += 1;
+
+            let arg = args_iter.next().ok_or(TypecheckError::MissingFormatArg {
+                span,
+                number: placeholders,
+                args: arg_count,
+            })?;
+
+            let arg = self.typecheck_expr(arg);
+            let arg_id = self.engine.insert_type((arg.0.ty.clone(), arg.1));
+            let arg_ty = self.engine.reconstruct(arg_id).0;
+            let spec = spec.map(|spec| self.lower_format_spec(spec));
+
+            match spec.and_then(|spec| Some((spec, spec.arg_type()?))) {
+                Some((_, expected)) if !arg_ty.is_known() => {
+                    let expected = self.engine.insert_type((expected, span));
+
+                    self.engine.unify(arg_id, expected)?;
+                }
+                Some((spec, expected)) if arg_ty != expected => {
+                    return Err(TypecheckError::FormatArgMismatch {
+                        spec,
+                        placeholder: span,
+                        span: arg.1,
+                        ty: arg_ty,
+                    }
+                    .into());
+                }
+                // the arguments after it can still be checked
+                None if !arg_ty.is_known() => {
+                    let error = self.cannot_infer(&arg_ty, arg.1);
+                    self.record(Err(error));
+                }
+                _ => {}
+            }
+
+            let arg_span = arg.1;
+
+            lowered.push((
+                Expr {
+                    expr: ExprKind::BuiltinCall {
+                        builtin: (spec.map_or(Builtin::ToStr, Builtin::ToStrWith), span),
+                        args: (vec![arg], arg_span),
+                    },
+                    ty: Type::Str,
+                },
+                span,
+            ));
+        }
+
+        if let Some(extra) = args_iter.next() {
+            return Err(TypecheckError::ExtraFormatArg {
+                span: extra.1,
+                placeholders,
+                format: format_span,
+            }
+            .into());
+        }
+
+        Ok(concat_strs(lowered, format_span))
+    }
+
+    /// Typechecks the arguments of a call, coercing each one to the type of
+    /// its parameter. `span` is where the parameter types come from.
+    fn typecheck_args(
+        &mut self,
+        args: Spanned<Vec<Spanned<ast::Expr<'src>>>>,
+        params: Vec<Type>,
+        span: Span,
+    ) -> Result<Spanned<Vec<Spanned<Expr<'src>>>>, Error> {
+        if args.0.len() != params.len() {
+            return Err(TypecheckError::WrongNumberOfArguments {
+                span: args.1,
+                expected: params.len(),
+                found: args.0.len(),
+            }
+            .into());
+        }
+
+        let mut typed_args = Vec::with_capacity(args.0.len());
+
+        for (arg, param) in args.0.into_iter().zip(params) {
+            let param_id = self.engine.insert_type((param, span));
+
+            let arg = match arg {
+                (ast::Expr::Ref { mutable, expr }, span) => {
+                    self.typecheck_ref(mutable, *expr, span, param_id)?
+                }
+                arg => self.check_expr(arg, param_id),
+            };
+
+            typed_args.push(arg);
+        }
+
+        Ok((typed_args, args.1))
+    }
+
+    /// Typechecks a reference passed as an argument for the parameter `param`,
+    /// which has to take one. A mutable reference can be passed where an
+    /// immutable one is expected, and is then passed as an immutable one.
+    fn typecheck_ref(
+        &mut self,
+        mutable: bool,
+        expr: Spanned<ast::Expr<'src>>,
+        span: Span,
+        param: TypeId,
+    ) -> Result<Spanned<Expr<'src>>, Error> {
+        let Type::Ref(param_mutable, _) = self.engine.reconstruct(param).0 else {
+            return Err(TypecheckError::UnexpectedRef {
+                span,
+                param: self.engine.reconstruct(param).0,
+            }
+            .into());
+        };
+
+        if mutable {
+            let ast::Expr::Var(name) = expr.0 else {
+                return Err(TypecheckError::NotBorrowable { span: expr.1 }.into());
+            };
+
+            self.borrowable_binding(name)?;
+        }
+
+        let expr = self.typecheck_expr(expr);
+        let mutable = mutable && param_mutable;
+        let ty = Type::Ref(mutable, Box::new(expr.0.ty.clone()));
+
+        Ok((
+            Expr {
+                expr: ExprKind::Ref {
+                    mutable,
+                    expr: Box::new(expr),
+                },
+                ty,
+            },
+            span,
+        ))
+    }
+
+    /// Typechecks the statements and tail of a block in a new scope, unifying
+    /// the value the block produces with `ty`
+    fn typecheck_block(
+        &mut self,
+        statements: Spanned<Vec<Spanned<ast::Statement<'src>>>>,
+        tail: Option<Box<Spanned<ast::Expr<'src>>>>,
+        ty: TypeId,
+    ) -> Result<Block<'src>, Error> {
+        self.push_scope();
+
+        let errors = self.errors.len();
+        let typed_statements = self.typecheck_statements(statements.0);
+
+        let typed_tail = tail.map(|tail| self.check_expr(*tail, ty));
+
+        self.pop_scope();
+
+        // a statement that's never run before the tail was already warned about
+        if let (Some(tail), Some(at)) = (&typed_tail, typed_statements.iter().position(diverges)) {
+            if !typed_statements[at + 1..].iter().any(runs) {
+                self.warnings.push(Warning::UnreachableCode {
+                    span: tail.1,
+                    cause: typed_statements[at].1,
+                });
+            }
+        }
+
+        // falling off the end of the block produces unit, unless the last
+        // statement always breaks out of it, which a statement that failed
+        // could have done
+        if typed_tail.is_none()
+            && self.errors.len() == errors
+            && !matches!(
+                typed_statements.last(),
+                Some((
+                    Statement::Break { .. } | Statement::Continue | Statement::Return(_),
+                    _
+                ))
+            )
+        {
+            let unit = self.engine.insert((TypeInfo::Unit, statements.1));
+
+            self.engine.unify(unit, ty)?;
+        }
+
+        Ok(Block {
+            statements: typed_statements,
+            tail: typed_tail.map(Box::new),
+        })
+    }
+
+    /// Turns the type variables in `ty` that nothing else in scope has into
+    /// type parameters, named `'a`, `'b` and so on, and returns them as the
+    /// generics of a binding. `own` is the type the binding was declared with
+    /// ahead of time, if it was, which doesn't count as something else.
+    /// Variables that still have to implement a trait are left alone, since
+    /// there's no way to know which types that will be.
+    fn generalize(
+        &mut self,
+        ty: TypeId,
+        own: Option<TypeId>,
+        span: Span,
+    ) -> Result<Vec<(String, Vec<String>)>, Error> {
+        let mut vars = vec![];
+        self.engine
+            .unknowns(&self.engine.reconstruct(ty).0, &mut vars);
+
+        if vars.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut outside = vec![];
+
+        for binding in self.bindings.values() {
+            if Some(binding.ty) != own {
+                self.engine
+                    .unknowns(&self.engine.reconstruct(binding.ty).0, &mut outside);
+            }
+        }
+
+        let others = self
+            .labels
+            .iter()
+            .filter_map(|(_, ty)| *ty)
+            .chain(self.return_ty);
+
+        for other in others {
+            self.engine
+                .unknowns(&self.engine.reconstruct(other).0, &mut outside);
+        }
+
+        for obligation in &self.obligations {
+            self.engine
+                .unknowns(&self.engine.substitute(&obligation.ty), &mut outside);
+        }
+
+        vars.retain(|var| !outside.contains(var));
+
+        let mut generics = vec![];
+
+        for (i, var) in vars.into_iter().enumerate() {
+            let letter = (b'a' + (i % 26) as u8) as char;
+            let name = match i / 26 {
+                0 => format!("'{}", letter),
+                n => format!("'{}{}", letter, n),
+            };
+
+            let param = self.engine.insert((TypeInfo::Param(name.clone()), span));
+            self.engine.unify(var, param)?;
+
+            generics.push((name, vec![]));
+        }
+
+        Ok(generics)
+    }
+
+    /// The type of a use of a variable. Each use of a generic function gets
+    /// its own copy of its type, with new type variables in place of its type
+    /// parameters.
+    fn binding_type(&mut self, binding: &Binding<'src>, span: Span) -> Type {
+        let ty = self.engine.reconstruct(binding.ty).0;
+
+        if binding.generics.is_empty() {
+            return ty;
+        }
+
+        let vars = binding
+            .generics
+            .clone()
+            .into_iter()
+            .map(|(generic, bounds)| {
+                let var = Type::Var(self.engine.insert((TypeInfo::Unknown, span)));
+
+                for trait_ in bounds {
+                    self.obligations.push(Obligation {
+                        ty: var.clone(),
+                        trait_,
+                        span,
+                        impls: self.impls.len(),
+                    });
+                }
+
+                (generic, var)
+            })
+            .collect::<HashMap<_, _>>();
+
+        ty.instantiate(&vars)
+    }
+
+    fn typecheck_call(
+        &mut self,
+        callee: Spanned<Expr<'src>>,
+        args: Spanned<Vec<Spanned<ast::Expr<'src>>>>,
+    ) -> Result<Expr<'src>, Error> {
+        let callee_id = self.engine.insert_type((callee.0.ty.clone(), callee.1));
+
+        let (params, return_ty) = match self.engine.reconstruct(callee_id).0 {
+            Type::Func(params, return_ty) => (params, *return_ty),
+            ty @ Type::Var(_) => return Err(self.cannot_infer(&ty, callee.1)),
+            ty => return Err(TypecheckError::NotCallable { span: callee.1, ty }.into()),
+        };
+
+        let args = self.typecheck_args(args, params, callee.1)?;
+
+        Ok(Expr {
+            expr: ExprKind::Call {
+                callee: Box::new(callee),
+                args,
+            },
+            ty: return_ty,
+        })
+    }
+
+    /// Typechecks an expression on its own, working out its type from what's
+    /// inside it
+    fn typecheck_expr(&mut self, expr: Spanned<ast::Expr<'src>>) -> Spanned<Expr<'src>> {
+        self.recover_expr(expr, None)
+    }
+
+    /// Typechecks an expression where a value of type `expected` is required.
+    /// What's known about `expected` is passed down into the expression, so
+    /// the parameters of a closure can take their types from it, and a value
+    /// that doesn't fit is reported at the part of the expression it's in.
+    fn check_expr(
+        &mut self,
+        expr: Spanned<ast::Expr<'src>>,
+        expected: TypeId,
+    ) -> Spanned<Expr<'src>> {
+        let expr = self.recover_expr(expr, Some(expected));
+
+        // an expression that never produces a value fits anywhere
+        if !expr_always_returns(&expr) {
+            let ty = self.engine.insert_type((expr.0.ty.clone(), expr.1));
+
+            let result = self.coerce(ty, expected);
+            self.record(result);
+        }
+
+        expr
+    }
+
+    /// Infers the type of an expression, or records its error and goes back
+    /// to where the typechecker was before it, like [`Typechecker::recover`]
+    /// does for statements. The expression gets a placeholder type instead,
+    /// which unifies with anything, so what it's part of is still checked
+    /// without failing because of it.
+    fn recover_expr(
+        &mut self,
+        expr: Spanned<ast::Expr<'src>>,
+        expected: Option<TypeId>,
+    ) -> Spanned<Expr<'src>> {
+        let span = expr.1;
+        let checkpoint = self.checkpoint();
+
+        let expr = match self.infer_expr(expr, expected) {
+            Ok(expr) => expr,
+            Err(error) => {
+                self.restore(checkpoint);
+                self.record(Err(error));
+
+                // never compiled, since there's an error
+                (
+                    Expr {
+                        expr: ExprKind::Literal((Literal::Unit, span)),
+                        ty: Type::Var(self.engine.insert((TypeInfo::Error, span))),
+                    },
+                    span,
+                )
+            }
+        };
+
+        self.located.push((expr.0.ty.clone(), expr.1));
+
+        expr
+    }
+
+    /// Checks the expression against `expected` if there is one, returning
+    /// it along with the type it has where it's used
+    fn check_or_infer(
+        &mut self,
+        expr: Spanned<ast::Expr<'src>>,
+        expected: Option<TypeId>,
+    ) -> (Spanned<Expr<'src>>, Type) {
+        match expected {
+            Some(expected) => (self.check_expr(expr, expected), Type::Var(expected)),
+            None => {
+                let expr = self.typecheck_expr(expr);
+                let ty = expr.0.ty.clone();
+
+                (expr, ty)
+            }
+        }
+    }
+
+    /// What's known about the expected type of an expression, if it has one
+    fn expected_type(&self, expected: Option<TypeId>) -> Option<Spanned<Type>> {
+        expected.map(|expected| self.engine.reconstruct(expected))
+    }
+
+    /// Typechecks an expression, passing `expected` down into the parts of it
+    /// that produce its value. The caller still has to check the expression's
+    /// own type against it, which `check_expr` does.
+    fn infer_expr(
+        &mut self,
+        expr: Spanned<ast::Expr<'src>>,
+        expected: Option<TypeId>,
+    ) -> Result<Spanned<Expr<'src>>, Error> {
+        Ok((
+            match expr.0 {
+                ast::Expr::Var(name) => {
+                    let binding = self.bindings.get_mut(&name.0).ok_or(
+                        TypecheckError::UndefinedVariable {
+                            name: name.0.to_string(),
+                            span: name.1,
+                        },
+                    )?;
+
+                    binding.read = true;
+
+                    let binding = binding.clone();
+
+                    let ty = self.binding_type(&binding, name.1);
+
+                    if let Some(value) = binding.value {
+                        return Ok((
+                            Expr {
+                                expr: ExprKind::Literal((value, name.1)),
+                                ty,
+                            },
+                            expr.1,
+                        ));
+                    }
+
+                    self.use_global(name)?;
+                    self.capture(name);
+
+                    Expr {
+                        expr: ExprKind::Var((binding.name, name.1)),
+                        ty,
+                    }
+                }
+                ast::Expr::Literal(literal) => {
+                    let literal = self.lower_literal(literal);
+
+                    Expr {
+                        ty: self.literal_type(&literal),
+                        expr: ExprKind::Literal(literal),
+                    }
+                }
+                ast::Expr::Prefix { op, expr } => {
+                    let op = self.lower_prefix_operator(op);
+
+                    let expr = self.typecheck_expr(*expr);
+                    let expr_id = self.engine.insert_type((expr.0.ty.clone(), expr.1));
+                    let expr_ty = self.engine.reconstruct(expr_id);
+
+                    self.check_operand(&expr_ty.0, expr.1)?;
+
+                    let ty = expr_ty.0.get_prefix_type(op)?;
+
+                    Expr {
+                        expr: ExprKind::Prefix {
+                            op,
+                            expr: Box::new(expr),
+                        },
+                        ty,
+                    }
+                }
+                ast::Expr::Binary { op, lhs, rhs } => {
+                    let op = self.lower_binary_operator(op);
+
+                    let mut lhs = self.typecheck_expr(*lhs);
+                    let mut lhs_id = self.engine.insert_type((lhs.0.ty.clone(), lhs.1));
+
+                    let mut rhs = self.typecheck_expr(*rhs);
+                    let mut rhs_id = self.engine.insert_type((rhs.0.ty.clone(), rhs.1));
+
+                    let ty = if op.0 == BinOp::Coalesce {
+                        let optional_rhs = self.engine.insert((TypeInfo::Optional(rhs_id), rhs.1));
+
+                        self.engine.unify(lhs_id, optional_rhs)?;
+
+                        self.engine.reconstruct(rhs_id).0
+                    } else {
+                        // an operand whose type isn't known yet, like a closure parameter
+                        // without an annotation, takes the type of the other one
+                        if matches!(self.engine.reconstruct(lhs_id).0, Type::Var(_))
+                            != matches!(self.engine.reconstruct(rhs_id).0, Type::Var(_))
+                        {
+                            self.engine.unify(lhs_id, rhs_id)?;
+                        }
+
+                        self.check_operand(&self.engine.reconstruct(lhs_id).0, lhs.1)?;
+                        self.check_operand(&self.engine.reconstruct(rhs_id).0, rhs.1)?;
+
+                        // an int used with a float is converted to a float first
+                        if op.0.takes_floats() {
+                            match (
+                                self.engine.reconstruct(lhs_id).0,
+                                self.engine.reconstruct(rhs_id).0,
+                            ) {
+                                (Type::Int, Type::Float) => {
+                                    lhs = to_float(lhs);
+                                    lhs_id = self.engine.insert((TypeInfo::Float, lhs.1));
+                                }
+                                (Type::Float, Type::Int) => {
+                                    rhs = to_float(rhs);
+                                    rhs_id = self.engine.insert((TypeInfo::Float, rhs.1));
+                                }
+                                _ => {}
+                            }
+                        }
+
+                        self.engine.unify(lhs_id, rhs_id)?;
+
+                        let lhs_ty = self.engine.reconstruct(lhs_id);
+                        let rhs_ty = self.engine.reconstruct(rhs_id);
+
+                        // an operator on a type implementing its trait calls the
+                        // trait's method, which returns the same type
+                        if let Some((trait_, method)) = op.0.trait_() {
+                            if matches!(lhs_ty.0, Type::Enum(_) | Type::Struct(_) | Type::Param(_))
+                                && self.implements(&lhs_ty.0, trait_, self.impls.len())
+                            {
+                                let rhs_span = rhs.1;
+                                let args = (vec![rhs], rhs_span);
+
+                                return Ok((
+                                    Expr {
+                                        expr: ExprKind::MethodCall {
+                                            receiver: Box::new(lhs),
+                                            trait_: Some(trait_.to_string()),
+                                            method: (method, op.1),
+                                            args,
+                                        },
+                                        ty: lhs_ty.0,
+                                    },
+                                    expr.1,
+                                ));
+                            }
+                        }
+
+                        lhs_ty.0.get_binary_type(op, &rhs_ty.0)?
+                    };
+
+                    Expr {
+                        expr: ExprKind::Binary {
+                            op,
+                            lhs: Box::new(lhs),
+                            rhs: Box::new(rhs),
+                        },
+                        ty,
+                    }
+                }
+                ast::Expr::LabeledBlock {
+                    label,
+                    statements,
+                    tail,
+                } => {
+                    let ty = self.engine.insert((TypeInfo::Unknown, expr.1));
+
+                    if let Some(expected) = expected {
+                        self.engine.unify(ty, expected)?;
+                    }
+
+                    let statements_span = statements.1;
+
+                    self.labels.push((Some(label.0), Some(ty)));
+
+                    let block = self.typecheck_block(statements, tail, ty)?;
+
+                    self.labels.pop();
+
+                    if !self.broken.contains(&ty) && block_always_returns(&block) {
+                        self.default_to_unit(ty)?;
+                    }
+
+                    Expr {
+                        expr: ExprKind::LabeledBlock {
+                            label,
+                            statements: (block.statements, statements_span),
+                            tail: block.tail,
+                        },
+                        ty: Type::Var(ty),
+                    }
+                }
+                ast::Expr::Loop { body } => {
+                    let ty = self.engine.insert((TypeInfo::Unknown, expr.1));
+
+                    self.labels.push((None, Some(ty)));
+                    self.push_scope();
+
+                    let statements = self.typecheck_statements(body.0);
+
+                    self.pop_scope();
+                    self.labels.pop();
+
+                    if !self.broken.contains(&ty) {
+                        self.default_to_unit(ty)?;
+                    }
+
+                    Expr {
+                        expr: ExprKind::Loop {
+                            body: (statements, body.1),
+                            breaks: self.broken.contains(&ty),
+                        },
+                        ty: Type::Var(ty),
+                    }
+                }
+                ast::Expr::If { cond, then, else_ } => {
+                    let cond = self.typecheck_expr(*cond);
+                    let cond_ty = self.engine.insert_type((cond.0.ty.clone(), cond.1));
+                    let bool_ty = self.engine.insert((TypeInfo::Bool, cond.1));
+
+                    self.engine.unify(cond_ty, bool_ty)?;
+
+                    let ty = self.engine.insert((TypeInfo::Unknown, expr.1));
+
+                    if let Some(expected) = expected {
+                        self.engine.unify(ty, expected)?;
+                    }
+
+                    let joined = else_.is_some() && !self.engine.reconstruct(ty).0.is_known();
+                    let then_ty = self.branch_type(ty, joined, then.1);
+
+                    let then = (
+                        self.typecheck_block((then.0.statements, then.1), then.0.tail, then_ty)?,
+                        then.1,
+                    );
+
+                    let else_ = match else_ {
+                        Some(else_) => {
+                            let else_ty = self.branch_type(ty, joined, else_.1);
+
+                            let block = self.typecheck_block(
+                                (else_.0.statements, else_.1),
+                                else_.0.tail,
+                                else_ty,
+                            )?;
+
+                            if joined {
+                                let branches = self.join(then_ty, else_ty)?;
+
+                                self.engine.unify(ty, branches)?;
+                            }
+
+                            Some((block, else_.1))
+                        }
+                        // without an else branch nothing is produced when the condition
+                        // is false, so the if has to be unit
+                        None => {
+                            let unit = self.engine.insert((TypeInfo::Unit, expr.1));
+
+                            self.engine.unify(unit, ty)?;
+
+                            None
+                        }
+                    };
+
+                    if else_.as_ref().is_some_and(|else_| {
+                        block_always_returns(&then.0) && block_always_returns(&else_.0)
+                    }) {
+                        self.default_to_unit(ty)?;
+                    }
+
+                    Expr {
+                        expr: ExprKind::If {
+                            cond: Box::new(cond),
+                            then,
+                            else_,
+                        },
+                        ty: Type::Var(ty),
+                    }
+                }
+                ast::Expr::Block(block) => {
+                    let ty = self.engine.insert((TypeInfo::Unknown, expr.1));
+
+                    if let Some(expected) = expected {
+                        self.engine.unify(ty, expected)?;
+                    }
+
+                    let block = self.typecheck_block((block.statements, expr.1), block.tail, ty)?;
+
+                    if block_always_returns(&block) {
+                        self.default_to_unit(ty)?;
+                    }
+
+                    Expr {
+                        expr: ExprKind::Block(block),
+                        ty: Type::Var(ty),
+                    }
+                }
+                ast::Expr::Catch {
+                    body,
+                    binding,
+                    handler,
+                } => {
+                    let ty = self.engine.insert((TypeInfo::Unknown, expr.1));
+
+                    if let Some(expected) = expected {
+                        self.engine.unify(ty, expected)?;
+                    }
+
+                    let body = (
+                        self.typecheck_block((body.0.statements, body.1), body.0.tail, ty)?,
+                        body.1,
+                    );
+
+                    // the handler gets the error's message
+                    self.push_scope();
+
+                    let str_ty = self.engine.insert((TypeInfo::Str, binding.1));
+
+                    self.bind(binding, str_ty, false);
+
+                    let handler = (
+                        self.typecheck_block(
+                            (handler.0.statements, handler.1),
+                            handler.0.tail,
+                            ty,
+                        )?,
+                        handler.1,
+                    );
+
+                    self.pop_scope();
+
+                    if block_always_returns(&body.0) && block_always_returns(&handler.0) {
+                        self.default_to_unit(ty)?;
+                    }
+
+                    Expr {
+                        expr: ExprKind::Catch {
+                            body,
+                            binding,
+                            handler,
+                        },
+                        ty: Type::Var(ty),
+                    }
+                }
+                ast::Expr::Interpolated(parts) => {
+                    let parts = parts
+                        .into_iter()
+                        .map(|part| self.lower_str_part(part))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    concat_strs(parts, expr.1).0
+                }
+                ast::Expr::Call { callee, args } => {
+                    let builtin = match callee.0 {
+                        ast::Expr::Var(name) if self.bindings.get(name.0).is_none() => {
+                            Builtin::lookup(name.0).map(|builtin| (builtin, name.1))
+                        }
+                        _ => None,
+                    };
+
+                    if let Some(builtin) = builtin.filter(|builtin| builtin.0.takes_format()) {
+                        let text = self.typecheck_format(builtin, args)?;
+
+                        match builtin.0 {
+                            Builtin::Format => text.0,
+                            _ => Expr {
+                                expr: ExprKind::BuiltinCall {
+                                    builtin,
+                                    args: (vec![text], expr.1),
+                                },
+                                ty: Type::Unit,
+                            },
+                        }
+                    } else if let Some(builtin) = builtin {
+                        let args = self.typecheck_args(args, builtin.0.params(), builtin.1)?;
+
+                        Expr {
+                            expr: ExprKind::BuiltinCall { builtin, args },
+                            ty: builtin.0.return_ty(),
+                        }
+                    } else {
+                        let callee = match *callee {
+                            (
+                                ast::Expr::FieldAccess {
+                                    expr: receiver,
+                                    field,
+                                },
+                                span,
+                            ) => {
+                                let receiver = self.typecheck_expr(*receiver);
+
+                                if let Some((trait_, Type::Func(params, return_ty))) =
+                                    self.find_method(&receiver, field)?
+                                {
+                                    let args = self.typecheck_args(args, params, field.1)?;
+
+                                    return Ok((
+                                        Expr {
+                                            expr: ExprKind::MethodCall {
+                                                receiver: Box::new(receiver),
+                                                trait_,
+                                                method: field,
+                                                args,
+                                            },
+                                            ty: *return_ty,
+                                        },
+                                        expr.1,
+                                    ));
+                                }
+
+                                (self.typecheck_field_access(receiver, field)?, span)
+                            }
+                            callee => self.typecheck_expr(callee),
+                        };
+
+                        self.typecheck_call(callee, args)?
+                    }
+                }
+                ast::Expr::Variant {
+                    enum_,
+                    variant,
+                    args,
+                } => {
+                    if self.types.get(enum_.0).is_none() {
+                        if let Some(&file) = self.imports.get(enum_.0) {
+                            let binding = self
+                                .modules
+                                .get(&file)
+                                .and_then(|bindings| bindings.get(variant.0))
+                                .cloned()
+                                .ok_or(TypecheckError::UndefinedVariable {
+                                    name: format!("{}::{}", enum_.0, variant.0),
+                                    span: variant.1,
+                                })?;
+
+                            let var = (
+                                Expr {
+                                    ty: self.binding_type(&binding, variant.1),
+                                    expr: match binding.value {
+                                        Some(value) => ExprKind::Literal((value, variant.1)),
+                                        None => ExprKind::ModuleVar {
+                                            module: enum_,
+                                            file,
+                                            name: (binding.name, variant.1),
+                                        },
+                                    },
+                                },
+                                Span::from(enum_.1.start..variant.1.end),
+                            );
+
+                            return Ok(match args {
+                                Some(args) => (self.typecheck_call(var, args)?, expr.1),
+                                None => var,
+                            });
+                        }
+                    }
+
+                    let args =
+                        args.unwrap_or_else(|| (vec![], Span::from(variant.1.end..variant.1.end)));
+
+                    // the enum can also be named through an alias
+                    let enum_name = match self.types.get(&enum_.0) {
+                        Some(TypeDef::Enum(name)) => name.to_string(),
+                        Some(TypeDef::Alias(Type::Enum(name))) => name.clone(),
+                        Some(TypeDef::Alias(ty)) => {
+                            return Err(TypecheckError::NotAnEnum {
+                                span: enum_.1,
+                                ty: ty.clone(),
+                            }
+                            .into())
+                        }
+                        Some(TypeDef::Struct(name)) => {
+                            return Err(TypecheckError::NotAnEnum {
+                                span: enum_.1,
+                                ty: Type::Struct(name.to_string()),
+                            }
+                            .into())
+                        }
+                        Some(TypeDef::Param { .. }) => {
+                            return Err(TypecheckError::NotAnEnum {
+                                span: enum_.1,
+                                ty: Type::Param(enum_.0.to_string()),
+                            }
+                            .into())
+                        }
+                        None => {
+                            return Err(TypecheckError::UndefinedType {
+                                name: enum_.0.to_string(),
+                                span: enum_.1,
+                            }
+                            .into())
+                        }
+                    };
+
+                    let fields = self.enums[enum_name.as_str()]
+                        .iter()
+                        .find(|(name, _)| name == variant.0)
+                        .map(|(_, fields)| fields.clone())
+                        .ok_or(TypecheckError::NoSuchVariant {
+                            enum_name: enum_name.clone(),
+                            variant: variant.0.to_string(),
+                            span: variant.1,
+                        })?;
+
+                    if args.0.len() != fields.len() {
+                        return Err(TypecheckError::WrongNumberOfArguments {
+                            span: args.1,
+                            expected: fields.len(),
+                            found: args.0.len(),
+                        }
+                        .into());
+                    }
+
+                    let typed_args = args
+                        .0
+                        .into_iter()
+                        .zip(fields)
+                        .map(|(arg, field)| {
+                            let arg = self.typecheck_expr(arg);
+                            let arg_id = self.engine.insert_type((arg.0.ty.clone(), arg.1));
+                            let field_id = self.engine.insert_type(field);
+
+                            self.coerce(arg_id, field_id)?;
+
+                            Ok(arg)
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+
+                    Expr {
+                        expr: ExprKind::Variant {
+                            enum_,
+                            variant,
+                            args: (typed_args, args.1),
+                        },
+                        ty: Type::Enum(enum_name),
+                    }
+                }
+                ast::Expr::Is { expr, variant } => {
+                    let expr = self.typecheck_expr(*expr);
+                    let expr_id = self.engine.insert_type((expr.0.ty.clone(), expr.1));
+
+                    let enum_name = match self.engine.reconstruct(expr_id).0 {
+                        Type::Enum(name) => name,
+                        ty @ Type::Var(_) => return Err(self.cannot_infer(&ty, expr.1)),
+                        ty => return Err(TypecheckError::NotAnEnum { span: expr.1, ty }.into()),
+                    };
+
+                    let exists = self.enums[enum_name.as_str()]
+                        .iter()
+                        .any(|(name, _)| name == variant.0);
+
+                    if !exists {
+                        return Err(TypecheckError::NoSuchVariant {
+                            enum_name,
+                            variant: variant.0.to_string(),
+                            span: variant.1,
+                        }
+                        .into());
+                    }
+
+                    Expr {
+                        expr: ExprKind::Is {
+                            expr: Box::new(expr),
+                            variant,
+                        },
+                        ty: Type::Bool,
+                    }
+                }
+                // references are handled by `typecheck_args`
+                ast::Expr::Ref { .. } => {
+                    return Err(TypecheckError::RefOutsideCall { span: expr.1 }.into())
+                }
+                ast::Expr::In { expr, collection } => {
+                    let expr = self.typecheck_expr(*expr);
+                    let expr_id = self.engine.insert_type((expr.0.ty.clone(), expr.1));
+
+                    let collection = self.typecheck_expr(*collection);
+                    let collection_id = self
+                        .engine
+                        .insert_type((collection.0.ty.clone(), collection.1));
+
+                    // the type of what the collection holds, which `expr` is compared with
+                    let element = match self.engine.reconstruct(collection_id).0 {
+                        Type::Map(_, _) => {
+                            let key = self.engine.insert((TypeInfo::Unknown, collection.1));
+                            let value = self.engine.insert((TypeInfo::Unknown, collection.1));
+                            let map = self
+                                .engine
+                                .insert((TypeInfo::Map(key, value), collection.1));
+
+                            self.engine.unify(collection_id, map)?;
+
+                            key
+                        }
+                        Type::Str => self.engine.insert((TypeInfo::Char, collection.1)),
+                        Type::Range => self.engine.insert((TypeInfo::Int, collection.1)),
+                        Type::List(_) | Type::Var(_) => {
+                            let element = self.engine.insert((TypeInfo::Unknown, collection.1));
+                            let list = self.engine.insert((TypeInfo::List(element), collection.1));
+
+                            self.engine.unify(collection_id, list)?;
+
+                            element
+                        }
+                        ty => {
+                            return Err(TypecheckError::NotIterable {
+                                span: collection.1,
+                                ty,
+                            }
+                            .into());
+                        }
+                    };
+
+                    self.engine.unify(expr_id, element)?;
+
+                    Expr {
+                        expr: ExprKind::In {
+                            expr: Box::new(expr),
+                            collection: Box::new(collection),
+                        },
+                        ty: Type::Bool,
+                    }
+                }
+                ast::Expr::Range {
+                    start,
+                    end,
+                    inclusive,
+                } => {
+                    let start = self.typecheck_expr(*start);
+                    let end = self.typecheck_expr(*end);
+
+                    for bound in [&start, &end] {
+                        let bound_id = self.engine.insert_type((bound.0.ty.clone(), bound.1));
+                        let int = self.engine.insert((TypeInfo::Int, expr.1));
+
+                        self.engine.unify(bound_id, int)?;
+                    }
+
+                    Expr {
+                        expr: ExprKind::Range {
+                            start: Box::new(start),
+                            end: Box::new(end),
+                            inclusive,
+                        },
+                        ty: Type::Range,
+                    }
+                }
+                ast::Expr::Cast { expr, ty } => {
+                    let expr = self.typecheck_expr(*expr);
+                    let expr_id = self.engine.insert_type((expr.0.ty.clone(), expr.1));
+                    let ty = self.lower_type(ty)?;
+
+                    let from = match self.engine.reconstruct(expr_id).0 {
+                        ty @ Type::Var(_) => return Err(self.cannot_infer(&ty, expr.1)),
+                        from => from,
+                    };
+
+                    if !can_cast(&from, &ty.0) {
+                        return Err(TypecheckError::CannotCast {
+                            from,
+                            to: ty.0,
+                            span: expr.1,
+                        }
+                        .into());
+                    }
+
+                    Expr {
+                        ty: ty.0.clone(),
+                        expr: ExprKind::Cast {
+                            expr: Box::new(expr),
+                            ty,
+                        },
+                    }
+                }
+                ast::Expr::List(elements) => {
+                    let expected = match self.expected_type(expected) {
+                        Some((Type::List(element), span)) => {
+                            Some(self.engine.insert_type((*element, span)))
+                        }
+                        _ => None,
+                    };
+
+                    let mut element_ty = expected
+                        .unwrap_or_else(|| self.engine.insert((TypeInfo::Unknown, elements.1)));
+
+                    let mut first_span = None;
+                    let mut typed_elements = Vec::with_capacity(elements.0.len());
+
+                    for element in elements.0 {
+                        // each element is checked against the expected element
+                        // type on its own, rather than against the first one
+                        if expected.is_some() {
+                            typed_elements.push(self.check_expr(element, element_ty));
+
+                            continue;
+                        }
+
+                        let element = self.typecheck_expr(element);
+                        let id = self.engine.insert_type((element.0.ty.clone(), element.1));
+
+                        if let Some(first_span) = first_span {
+                            let expected = self.engine.reconstruct(element_ty).0;
+
+                            element_ty = self.join(element_ty, id).map_err(|_| {
+                                TypecheckError::ListElementMismatch {
+                                    span: element.1,
+                                    ty: self.engine.reconstruct(id).0,
+                                    expected,
+                                    expected_span: first_span,
+                                }
+                            })?;
+                        } else {
+                            self.engine.unify(id, element_ty)?;
+
+                            first_span = Some(element.1);
+                        }
+
+                        typed_elements.push(element);
+                    }
+
+                    Expr {
+                        expr: ExprKind::List((typed_elements, elements.1)),
+                        ty: Type::List(Box::new(Type::Var(element_ty))),
+                    }
+                }
+                ast::Expr::Map(entries) => {
+                    let key_ty = self.engine.insert((TypeInfo::Unknown, entries.1));
+                    let value_ty = self.engine.insert((TypeInfo::Unknown, entries.1));
+
+                    let mut first_spans = None;
+                    let mut typed_entries = Vec::with_capacity(entries.0.len());
+
+                    for (key, value) in entries.0 {
+                        let key = self.typecheck_expr(key);
+                        let key_id = self.engine.insert_type((key.0.ty.clone(), key.1));
+
+                        let value = self.typecheck_expr(value);
+                        let value_id = self.engine.insert_type((value.0.ty.clone(), value.1));
+
+                        if let Some((first_key, first_value)) = first_spans {
+                            for (is_key, id, ty, span, expected_span) in [
+                                (true, key_id, key_ty, key.1, first_key),
+                                (false, value_id, value_ty, value.1, first_value),
+                            ] {
+                                let expected = self.engine.reconstruct(ty).0;
+
+                                self.engine.unify(id, ty).map_err(|_| {
+                                    TypecheckError::MapEntryMismatch {
+                                        is_key,
+                                        span,
+                                        ty: self.engine.reconstruct(id).0,
+                                        expected,
+                                        expected_span,
+                                    }
+                                })?;
+                            }
+                        } else {
+                            self.engine.unify(key_id, key_ty)?;
+                            self.engine.unify(value_id, value_ty)?;
+
+                            first_spans = Some((key.1, value.1));
+                        }
+
+                        typed_entries.push((key, value));
+                    }
+
+                    Expr {
+                        expr: ExprKind::Map((typed_entries, entries.1)),
+                        ty: Type::Map(Box::new(Type::Var(key_ty)), Box::new(Type::Var(value_ty))),
+                    }
+                }
+                ast::Expr::Match {
+                    expr: scrutinee,
+                    arms,
+                } => {
+                    let scrutinee = self.typecheck_expr(*scrutinee);
+
+                    let variants = match &scrutinee.0.ty {
+                        Type::Enum(name) => Some(self.enums[name.as_str()].clone()),
+                        _ => None,
+                    };
+
+                    let ty = self.engine.insert((TypeInfo::Unknown, expr.1));
+
+                    if let Some(expected) = expected {
+                        self.engine.unify(ty, expected)?;
+                    }
+
+                    let joined = !self.engine.reconstruct(ty).0.is_known();
+                    let mut arms_ty = None;
+                    let mut typed_arms = Vec::new();
+
+                    for (pattern, body) in arms.0 {
+                        self.push_scope();
+
+                        let pattern =
+                            self.typecheck_pattern(pattern, &scrutinee, variants.as_ref())?;
+                        let body_ty = self.branch_type(ty, joined, body.1);
+                        let body = self.check_expr(body, body_ty);
+
+                        self.pop_scope();
+
+                        if joined {
+                            arms_ty = Some(match arms_ty {
+                                Some(arms_ty) => self.join(arms_ty, body_ty)?,
+                                None => body_ty,
+                            });
+                        }
+
+                        typed_arms.push((pattern, body));
+                    }
+
+                    if let Some(arms_ty) = arms_ty {
+                        self.engine.unify(ty, arms_ty)?;
+                    }
+
+                    // the patterns can have made the type of the scrutinee known
+                    let scrutinee_ty = self.engine.substitute(&scrutinee.0.ty);
+                    let patterns = typed_arms
+                        .iter()
+                        .map(|(pattern, _)| Pat::from_pattern(&pattern.0))
+                        .collect::<Vec<_>>();
+
+                    let report = exhaustiveness::check(&patterns, &scrutinee_ty, &|ty| {
+                        self.constructors(ty)
+                    });
+
+                    for ((pattern, _), reachable) in typed_arms.iter().zip(report.reachable) {
+                        if !reachable {
+                            self.warnings
+                                .push(Warning::UnreachableMatchArm { span: pattern.1 });
+                        }
+                    }
+
+                    if !report.missing.is_empty() {
+                        return Err(TypecheckError::NonExhaustiveMatch {
+                            span: scrutinee.1,
+                            missing: report.missing.iter().map(ToString::to_string).collect(),
+                        }
+                        .into());
+                    }
+
+                    Expr {
+                        expr: ExprKind::Match {
+                            expr: Box::new(scrutinee),
+                            arms: (typed_arms, arms.1),
+                        },
+                        ty: self.engine.reconstruct(ty).0,
+                    }
+                }
+                ast::Expr::Some(inner) => {
+                    let expected = match self.expected_type(expected) {
+                        Some((Type::Optional(inner), span)) => {
+                            Some(self.engine.insert_type((*inner, span)))
+                        }
+                        _ => None,
+                    };
+
+                    let (inner, inner_ty) = self.check_or_infer(*inner, expected);
+                    let ty = Type::Optional(Box::new(inner_ty));
+
+                    Expr {
+                        expr: ExprKind::Some(Box::new(inner)),
+                        ty,
+                    }
+                }
+                ast::Expr::Ok(inner) => {
+                    let expected = match self.expected_type(expected) {
+                        Some((Type::Result(ok, _), span)) => {
+                            Some(self.engine.insert_type((*ok, span)))
+                        }
+                        _ => None,
+                    };
+
+                    let (inner, inner_ty) = self.check_or_infer(*inner, expected);
+                    let err = self.engine.insert((TypeInfo::Unknown, expr.1));
+                    let ty = Type::Result(Box::new(inner_ty), Box::new(Type::Var(err)));
+
+                    Expr {
+                        expr: ExprKind::Ok(Box::new(inner)),
+                        ty,
+                    }
+                }
+                ast::Expr::Err(inner) => {
+                    let expected = match self.expected_type(expected) {
+                        Some((Type::Result(_, err), span)) => {
+                            Some(self.engine.insert_type((*err, span)))
+                        }
+                        _ => None,
+                    };
+
+                    let (inner, inner_ty) = self.check_or_infer(*inner, expected);
+                    let ok = self.engine.insert((TypeInfo::Unknown, expr.1));
+                    let ty = Type::Result(Box::new(Type::Var(ok)), Box::new(inner_ty));
+
+                    Expr {
+                        expr: ExprKind::Err(Box::new(inner)),
+                        ty,
+                    }
+                }
+                ast::Expr::Try(inner) => {
+                    let return_ty = self
+                        .return_ty
+                        .ok_or(TypecheckError::ReturnOutsideFunction { span: expr.1 })?;
+
+                    let inner = self.typecheck_expr(*inner);
+                    let (ok, err) = self.result_parts(&inner, inner.1)?;
+
+                    // the function has to return a result with the same error type,
+                    // whatever its value type is
+                    let any_ok = self.engine.insert((TypeInfo::Unknown, expr.1));
+                    let propagated = self.engine.insert((TypeInfo::Result(any_ok, err), expr.1));
+
+                    self.engine.unify(propagated, return_ty)?;
+
+                    Expr {
+                        expr: ExprKind::Try(Box::new(inner)),
+                        ty: Type::Var(ok),
+                    }
+                }
+                ast::Expr::Closure { params, body } => {
+                    // the parameters without a type take theirs from the
+                    // function type that's expected, if there is one
+                    let (expected_params, expected_return) = match self.expected_type(expected) {
+                        Some((Type::Func(expected_params, return_ty), span))
+                            if expected_params.len() == params.0.len() =>
+                        {
+                            (
+                                expected_params.into_iter().map(Some).collect(),
+                                Some(self.engine.insert_type((*return_ty, span))),
+                            )
+                        }
+                        _ => (vec![None; params.0.len()], None),
+                    };
+
+                    let params = (
+                        params
+                            .0
+                            .into_iter()
+                            .zip(expected_params)
+                            .map(|((param, ty), expected)| {
+                                Ok((
+                                    param,
+                                    match (ty, expected) {
+                                        (Some(ty), _) => self.lower_type(ty)?,
+                                        (None, Some(expected)) => (expected, param.1),
+                                        (None, None) => (
+                                            Type::Var(
+                                                self.engine.insert((TypeInfo::Unknown, param.1)),
+                                            ),
+                                            param.1,
+                                        ),
+                                    },
+                                ))
+                            })
+                            .collect::<Result<Vec<_>, Error>>()?,
+                        params.1,
+                    );
+
+                    let param_ids = params
+                        .0
+                        .iter()
+                        .map(|(_, ty)| self.engine.insert_type(ty.clone()))
+                        .collect::<Vec<_>>();
+
+                    let return_id = expected_return
+                        .unwrap_or_else(|| self.engine.insert((TypeInfo::Unknown, body.1)));
+
+                    // like a function, a closure can't break out of the labels around it,
+                    // and returns from the closure itself
+                    let labels = std::mem::take(&mut self.labels);
+                    let outer_return_ty = self.return_ty.replace(return_id);
+
+                    self.closures.push(Closure {
+                        depth: self.bindings.depth(),
+                        span: expr.1,
+                        captures: Vec::new(),
+                        function: false,
+                    });
+
+                    self.push_scope();
+
+                    for ((param, _), id) in params.0.iter().zip(&param_ids) {
+                        self.bind_param(*param, *id);
+                    }
+
+                    let body = self.check_expr(*body, return_id);
+
+                    self.pop_scope();
+
+                    let captures = self.closures.pop().unwrap().captures;
+
+                    self.labels = labels;
+                    self.return_ty = outer_return_ty;
+
+                    let ty = Type::Func(
+                        params.0.iter().map(|(_, ty)| ty.0.clone()).collect(),
+                        Box::new(Type::Var(return_id)),
+                    );
+
+                    Expr {
+                        expr: ExprKind::Closure {
+                            params,
+                            captures,
+                            body: Box::new(body),
+                        },
+                        ty,
+                    }
+                }
+                ast::Expr::Record(fields) => {
+                    let mut typed_fields: Vec<(Spanned<&str>, Spanned<Expr>)> = Vec::new();
+
+                    for (name, value) in fields {
+                        if let Some((first, _)) =
+                            typed_fields.iter().find(|(other, _)| other.0 == name.0)
+                        {
+                            return Err(TypecheckError::DuplicateField {
+                                name: name.0.to_string(),
+                                span1: first.1,
+                                span2: name.1,
+                            }
+                            .into());
+                        }
+
+                        let value = self.typecheck_expr(value);
+
+                        typed_fields.push((name, value));
+                    }
+
+                    let mut ty = typed_fields
+                        .iter()
+                        .map(|(name, value)| (name.0.to_string(), value.0.ty.clone()))
+                        .collect::<Vec<_>>();
+
+                    ty.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+                    Expr {
+                        expr: ExprKind::Record(typed_fields),
+                        ty: Type::Record(ty),
+                    }
+                }
+                ast::Expr::Struct { name, fields } => {
+                    let (unique, declared) = match self.types.get(name.0) {
+                        Some(&TypeDef::Struct(unique)) => (unique, self.structs[unique].clone()),
+                        Some(_) => {
+                            return Err(TypecheckError::NotAStruct {
+                                name: name.0.to_string(),
+                                span: name.1,
+                            }
+                            .into())
+                        }
+                        None => {
+                            return Err(TypecheckError::UndefinedType {
+                                name: name.0.to_string(),
+                                span: name.1,
+                            }
+                            .into())
+                        }
+                    };
+
+                    let ty = Type::Struct(unique.to_string());
+
+                    let mut typed_fields: Vec<(Spanned<&str>, Spanned<Expr>)> = Vec::new();
+
+                    for (field, value) in fields {
+                        if let Some((first, _)) =
+                            typed_fields.iter().find(|(other, _)| other.0 == field.0)
+                        {
+                            return Err(TypecheckError::DuplicateField {
+                                name: field.0.to_string(),
+                                span1: first.1,
+                                span2: field.1,
+                            }
+                            .into());
+                        }
+
+                        let field_ty = declared
+                            .iter()
+                            .find(|(name, _)| name == field.0)
+                            .map(|(_, ty)| ty.clone())
+                            .ok_or_else(|| TypecheckError::NoSuchField {
+                                span: field.1,
+                                field: field.0.to_string(),
+                                ty: ty.clone(),
+                            })?;
+
+                        let value = self.typecheck_expr(value);
+                        let value_id = self.engine.insert_type((value.0.ty.clone(), value.1));
+                        let field_id = self.engine.insert_type(field_ty);
+
+                        self.coerce(value_id, field_id)?;
+
+                        typed_fields.push((field, value));
+                    }
+
+                    let mut ordered = Vec::with_capacity(declared.len());
+
+                    for (field, field_ty) in &declared {
+                        let index = typed_fields
+                            .iter()
+                            .position(|(name, _)| name.0 == field)
+                            .ok_or_else(|| TypecheckError::MissingStructField {
+                                name: name.0.to_string(),
+                                field: field.clone(),
+                                span: name.1,
+                                declared: field_ty.1,
+                            })?;
+
+                        ordered.push(typed_fields.remove(index));
+                    }
+
+                    Expr {
+                        expr: ExprKind::Struct {
+                            name: (unique, name.1),
+                            fields: ordered,
+                        },
+                        ty,
+                    }
+                }
+                ast::Expr::FieldAccess { expr, field } => {
+                    let expr = self.typecheck_expr(*expr);
+
+                    self.typecheck_field_access(expr, field)?
+                }
+                ast::Expr::Index { expr, index } => {
+                    let expr = self.typecheck_expr(*expr);
+                    let expr_id = self.engine.insert_type((expr.0.ty.clone(), expr.1));
+                    let expr_ty = self.engine.reconstruct(expr_id);
+
+                    let index = self.typecheck_expr(*index);
+                    let index_id = self.engine.insert_type((index.0.ty.clone(), index.1));
+                    let is_slice = self.engine.reconstruct(index_id).0 == Type::Range;
+
+                    let ty = match expr_ty.0 {
+                        // indexing a map gives an optional, which is empty when
+                        // the key isn't in it
+                        Type::Map(_, _) => {
+                            let key = self.engine.insert((TypeInfo::Unknown, expr.1));
+                            let value = self.engine.insert((TypeInfo::Unknown, expr.1));
+                            let map = self.engine.insert((TypeInfo::Map(key, value), expr.1));
+
+                            self.engine.unify(expr_id, map)?;
+                            self.coerce(index_id, key)?;
+
+                            Type::Optional(Box::new(Type::Var(value)))
+                        }
+                        // indexing with a range gives the part of the list or
+                        // str that it covers
+                        Type::Str if is_slice => Type::Str,
+                        Type::List(_) | Type::Var(_) if is_slice => {
+                            let element = self.engine.insert((TypeInfo::Unknown, expr.1));
+                            let list = self.engine.insert((TypeInfo::List(element), expr.1));
+
+                            self.engine.unify(expr_id, list)?;
+
+                            Type::List(Box::new(Type::Var(element)))
+                        }
+                        Type::List(_) | Type::Var(_) => {
+                            let element = self.engine.insert((TypeInfo::Unknown, expr.1));
+                            let list = self.engine.insert((TypeInfo::List(element), expr.1));
+                            let int = self.engine.insert((TypeInfo::Int, index.1));
+
+                            self.engine.unify(expr_id, list)?;
+                            self.engine.unify(index_id, int)?;
+
+                            Type::Var(element)
+                        }
+                        ty => {
+                            return Err(TypecheckError::NotAList { span: expr.1, ty }.into());
+                        }
+                    };
+
+                    Expr {
+                        expr: ExprKind::Index {
+                            expr: Box::new(expr),
+                            index: Box::new(index),
+                        },
+                        ty,
+                    }
+                }
+            },
+            expr.1,
+        ))
+    }
+
+    /// Checks that a variable can be borrowed mutably, which is the same as
+    /// being able to assign to it, since the function it's passed to can
+    fn borrowable_binding(&self, name: Spanned<&'src str>) -> Result<(), Error> {
+        match self.assignable_binding(name) {
+            Err(Error::Typecheck(TypecheckError::AssignToImmutable {
+                name,
+                span,
+                declared,
+            })) => Err(TypecheckError::BorrowImmutable {
+                name,
+                span,
+                declared,
+            }
+            .into()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// Binds a parameter of a function or a closure. A parameter taking a
+    /// reference is bound to the type it refers to, and can be assigned to if
+    /// the reference is mutable, which the caller sees even if the parameter
+    /// is never read.
+    fn bind_param(&mut self, param: Spanned<&'src str>, ty: TypeId) {
+        match self.engine.reconstruct(ty).0 {
+            Type::Ref(mutable, inner) => {
+                let inner = self.engine.insert_type((*inner, param.1));
+
+                self.bind(param, inner, mutable);
+
+                if let (true, Some(binding)) = (mutable, self.bindings.get_mut(param.0)) {
+                    binding.read = true;
+                }
+            }
+            _ => self.bind(param, ty, false),
+        }
+    }
+
+    /// The binding of a variable being assigned to, which has to be mutable
+    fn assignable_binding(&self, name: Spanned<&'src str>) -> Result<Binding<'src>, Error> {
+        let binding = self
+            .bindings
+            .get(&name.0)
+            .ok_or(TypecheckError::UndefinedVariable {
+                name: name.0.to_string(),
+                span: name.1,
+            })?
+            .clone();
+
+        if binding.value.is_some() {
+            return Err(TypecheckError::AssignToConst {
+                name: name.0.to_string(),
+                span: name.1,
+                declared: binding.span,
+            }
+            .into());
+        }
+
+        if !binding.mutable {
+            return Err(TypecheckError::AssignToImmutable {
+                name: name.0.to_string(),
+                span: name.1,
+                declared: binding.span,
+            }
+            .into());
+        }
+
+        // a closure or function only has a copy of the variables it captures,
+        // so assigning to one wouldn't be seen outside of it
+        if let Some(closure) = self.closures.last() {
+            if self.bindings.depth_of(name.0).is_some_and(|depth| {
+                depth < closure.depth && !(closure.function && depth == TOP_LEVEL)
+            }) {
+                return Err(TypecheckError::AssignToCapture {
+                    name: name.0.to_string(),
+                    span: name.1,
+                    closure: closure.span,
+                    function: closure.function,
+                }
+                .into());
+            }
+        }
+
+        Ok(binding)
+    }
+
+    fn typecheck_field_access(
+        &mut self,
+        expr: Spanned<Expr<'src>>,
+        field: Spanned<&'src str>,
+    ) -> Result<Expr<'src>, Error> {
+        let expr_id = self.engine.insert_type((expr.0.ty.clone(), expr.1));
+        let expr_ty = self.engine.reconstruct(expr_id);
+
+        let ty = match &expr_ty.0 {
+            Type::Record(fields) => fields
+                .iter()
+                .find(|(name, _)| name == field.0)
+                .map(|(_, ty)| ty.clone())
+                .ok_or_else(|| TypecheckError::NoSuchField {
+                    span: field.1,
+                    field: field.0.to_string(),
+                    ty: expr_ty.0.clone(),
+                })?,
+            Type::Struct(name) => self.structs[name.as_str()]
+                .iter()
+                .find(|(name, _)| name == field.0)
+                .map(|(_, ty)| ty.0.clone())
+                .ok_or_else(|| TypecheckError::NoSuchField {
+                    span: field.1,
+                    field: field.0.to_string(),
+                    ty: expr_ty.0.clone(),
+                })?,
+            ty @ Type::Var(_) => return Err(self.cannot_infer(ty, expr.1)),
+            _ => {
+                return Err(TypecheckError::NotARecord {
+                    span: expr.1,
+                    field: field.0.to_string(),
+                    ty: expr_ty.0,
+                }
+                .into())
+            }
+        };
+
+        Ok(Expr {
+            expr: ExprKind::FieldAccess {
+                expr: Box::new(expr),
+                field,
+            },
+            ty,
+        })
+    }
+
+    /// Finds the method `receiver.method` calls, if there is one, along with
+    /// the trait it's from (`None` for the type's own impl) and its type
+    fn find_method(
+        &self,
+        receiver: &Spanned<Expr>,
+        method: Spanned<&str>,
+    ) -> Result<Option<(Option<String>, Type)>, Error> {
+        let ty = self.engine.substitute(&receiver.0.ty);
+
+        // the type's own methods come before the ones of its traits
+        if let Some((found, _)) = self.inherent_method(&ty, method.0) {
+            return Ok(Some((None, found.clone())));
+        }
+
+        for trait_ in self.traits_of(&ty) {
+            if let Some(found) = self.traits.get(trait_.as_str()).and_then(|def| {
+                def.methods
+                    .iter()
+                    .find(|(name, ..)| name == method.0)
+                    .map(|(_, found, _)| {
+                        found.instantiate(&HashMap::from([("Self".to_string(), ty.clone())]))
+                    })
+            }) {
+                return Ok(Some((Some(trait_), found)));
+            }
+        }
+
+        let has_field = match &ty {
+            Type::Record(fields) => fields.iter().any(|(name, _)| name == method.0),
+            Type::Struct(name) => self.structs[name.as_str()]
+                .iter()
+                .any(|(field, _)| field == method.0),
+            _ => false,
+        };
+
+        match ty {
+            // a field can hold a function to call instead
+            _ if has_field => Ok(None),
+            Type::Var(_) => Ok(None),
+            ty => Err(TypecheckError::NoSuchMethod {
+                method: method.0.to_string(),
+                available: self.method_names(&ty),
+                ty,
+                span: method.1,
+            }
+            .into()),
+        }
+    }
+
+    /// The traits whose methods values of type `ty` have, which for a type
+    /// parameter are only the ones it's bound by
+    fn traits_of(&self, ty: &Type) -> Vec<String> {
+        match ty {
+            Type::Param(name) => match self.types.get(name.as_str()) {
+                Some(TypeDef::Param { bounds }) => bounds.clone(),
+                _ => vec![],
+            },
+            ty => self
+                .impls
+                .iter()
+                .filter(|(_, impl_ty)| impl_ty.0 == *ty)
+                .map(|(trait_, _)| trait_.clone())
+                .collect(),
+        }
+    }
+
+    /// The names of the methods values of type `ty` have, its own first
+    fn method_names(&self, ty: &Type) -> Vec<String> {
+        let own = self
+            .methods
+            .iter()
+            .filter(|(other, ..)| other == ty)
+            .map(|(_, name, _)| name.to_string());
+
+        let from_traits = self.traits_of(ty).into_iter().flat_map(|trait_| {
+            self.traits
+                .get(trait_.as_str())
+                .map(|def| {
+                    def.methods
+                        .iter()
+                        .map(|(name, ..)| name.clone())
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        });
+
+        own.chain(from_traits).collect()
+    }
+
+    /// Records the signatures of the methods in an impl without a trait before
+    /// their bodies are checked, so that they can call each other
+    fn declare_methods(
+        &mut self,
+        ty: &Type,
+        methods: &[(Spanned<ast::MethodSig<'src>>, Spanned<ast::Statement<'src>>)],
+    ) -> Result<(), Error> {
+        for (sig, _) in methods {
+            let name = sig.0.name;
+
+            if let Some((_, declared)) = self.inherent_method(ty, name.0) {
+                return Err(TypecheckError::DuplicateMethod {
+                    name: name.0.to_string(),
+                    span1: *declared,
+                    span2: name.1,
+                }
+                .into());
+            }
+
+            let params = sig
+                .0
+                .params
+                .0
+                .iter()
+                .map(|(_, ty)| Ok(self.lower_type(ty.clone())?.0))
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            // without an annotation the return type is inferred from the body
+            let return_ty = match &sig.0.return_ty {
+                Some(ty) => self.lower_type(ty.clone())?.0,
+                None => Type::Var(self.engine.insert((
+                    TypeInfo::Unknown,
+                    (sig.0.params.1.end..sig.0.params.1.end).into(),
+                ))),
+            };
+
+            self.methods.push((
+                ty.clone(),
+                name.0,
+                (Type::Func(params, Box::new(return_ty)), name.1),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The type of a method of `ty` from an impl without a trait, and where
+    /// it's declared
+    fn inherent_method(&self, ty: &Type, method: &str) -> Option<&Spanned<Type>> {
+        self.methods
+            .iter()
+            .find(|(other_ty, other, _)| other_ty == ty && *other == method)
+            .map(|(_, _, found)| found)
+    }
+
+    /// Whether `ty` implements `trait_`, only counting the first `impls` impls
+    fn implements(&self, ty: &Type, trait_: &str, impls: usize) -> bool {
+        match ty {
+            Type::Param(name) => matches!(
+                self.types.get(name.as_str()),
+                Some(TypeDef::Param { bounds }) if bounds.iter().any(|bound| bound == trait_)
+            ),
+            ty => self.impls[..impls]
+                .iter()
+                .any(|(other, impl_ty)| other == trait_ && impl_ty.0 == *ty),
+        }
+    }
+
+    /// Checks that the types required to implement a trait do, leaving the
+    /// ones that aren't known yet for later
+    fn check_obligations(&mut self) -> Result<(), Error> {
+        for obligation in std::mem::take(&mut self.obligations) {
+            let ty = self.engine.substitute(&obligation.ty);
+
+            if matches!(ty, Type::Var(_)) {
+                self.obligations.push(obligation);
+            } else if !self.implements(&ty, &obligation.trait_, obligation.impls) {
+                return Err(TypecheckError::TraitNotImplemented {
+                    trait_: obligation.trait_,
+                    ty,
+                    span: obligation.span,
+                }
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn typecheck_pattern(
+        &mut self,
+        pattern: Spanned<ast::Pattern<'src>>,
+        scrutinee: &Spanned<Expr<'src>>,
+        variants: Option<&Variants>,
+    ) -> Result<Spanned<Pattern<'src>>, Error> {
+        let (variant, bindings) = match pattern.0 {
+            ast::Pattern::Ident(name) => {
+                let is_variant = variants
+                    .map(|variants| variants.iter().any(|(variant, _)| variant == name.0))
+                    .unwrap_or(false);
+
+                // a capitalized name matching an enum is almost certainly a
+                // misspelled variant, which would otherwise match everything
+                let looks_like_variant =
+                    variants.is_some() && name.0.starts_with(|c: char| c.is_ascii_uppercase());
+
+                if !is_variant && !looks_like_variant {
+                    let ty = self.engine.insert_type((scrutinee.0.ty.clone(), name.1));
+
+                    self.bind(name, ty, false);
+
+                    return Ok((Pattern::Binding(name), pattern.1));
+                }
+
+                (name, (vec![], name.1))
+            }
+            ast::Pattern::Variant { variant, bindings } => (variant, bindings),
+            ast::Pattern::Some(binding) => {
+                let inner = self.optional_inner(scrutinee, pattern.1)?;
+
+                self.bind(binding, inner, false);
+
+                return Ok((Pattern::Some(binding), pattern.1));
+            }
+            ast::Pattern::None => {
+                self.optional_inner(scrutinee, pattern.1)?;
+
+                return Ok((Pattern::None, pattern.1));
+            }
+            ast::Pattern::Ok(binding) => {
+                let (ok, _) = self.result_parts(scrutinee, pattern.1)?;
+
+                self.bind(binding, ok, false);
+
+                return Ok((Pattern::Ok(binding), pattern.1));
+            }
+            ast::Pattern::Err(binding) => {
+                let (_, err) = self.result_parts(scrutinee, pattern.1)?;
+
+                self.bind(binding, err, false);
+
+                return Ok((Pattern::Err(binding), pattern.1));
+            }
+        };
+
+        let variants = variants.ok_or(TypecheckError::NotAnEnum {
+            span: scrutinee.1,
+            ty: scrutinee.0.ty.clone(),
+        })?;
+
+        let fields = variants
+            .iter()
+            .find(|(name, _)| name == variant.0)
+            .map(|(_, fields)| fields)
+            .ok_or_else(|| TypecheckError::NoSuchVariant {
+                enum_name: match &scrutinee.0.ty {
+                    Type::Enum(name) => name.clone(),
+                    _ => unreachable!(),
+                },
+                variant: variant.0.to_string(),
+                span: variant.1,
+            })?;
+
+        if bindings.0.len() != fields.len() {
+            return Err(TypecheckError::WrongNumberOfArguments {
+                span: bindings.1,
+                expected: fields.len(),
+                found: bindings.0.len(),
+            }
+            .into());
+        }
+
+        for (binding, field) in bindings.0.iter().zip(fields) {
+            let ty = self.engine.insert_type((field.0.clone(), binding.1));
+
+            self.bind(*binding, ty, false);
+        }
+
+        Ok((Pattern::Variant { variant, bindings }, pattern.1))
+    }
+
+    /// The constructors that patterns can match values of type `ty` with,
+    /// which optionals and results have two of, like enums with two variants
+    fn constructors(&self, ty: &Type) -> Option<Vec<(String, Vec<Type>)>> {
+        match ty {
+            Type::Enum(name) => Some(
+                self.enums[name.as_str()]
+                    .iter()
+                    .map(|(name, fields)| {
+                        (
+                            name.clone(),
+                            fields.iter().map(|field| field.0.clone()).collect(),
+                        )
+                    })
+                    .collect(),
+            ),
+            Type::Optional(inner) => Some(vec![
+                ("some".to_string(), vec![(**inner).clone()]),
+                ("none".to_string(), vec![]),
+            ]),
+            Type::Result(ok, err) => Some(vec![
+                ("ok".to_string(), vec![(**ok).clone()]),
+                ("err".to_string(), vec![(**err).clone()]),
+            ]),
+            _ => None,
+        }
+    }
+
+    /// Checks that a `some` or `none` pattern is matched against an optional,
+    /// returning the type of the value it holds
+    fn optional_inner(&mut self, scrutinee: &Spanned<Expr>, span: Span) -> Result<TypeId, Error> {
+        let scrutinee_ty = self
+            .engine
+            .insert_type((scrutinee.0.ty.clone(), scrutinee.1));
+
+        let inner = self.engine.insert((TypeInfo::Unknown, span));
+        let optional = self.engine.insert((TypeInfo::Optional(inner), span));
+
+        self.engine.unify(scrutinee_ty, optional)?;
+
+        Ok(inner)
+    }
+
+    /// Checks that an expression is a result, returning the types of its
+    /// value and its error
+    fn result_parts(
+        &mut self,
+        expr: &Spanned<Expr>,
+        span: Span,
+    ) -> Result<(TypeId, TypeId), Error> {
+        let expr_ty = self.engine.insert_type((expr.0.ty.clone(), expr.1));
+
+        let ok = self.engine.insert((TypeInfo::Unknown, span));
+        let err = self.engine.insert((TypeInfo::Unknown, span));
+        let result = self.engine.insert((TypeInfo::Result(ok, err), span));
+
+        self.engine.unify(expr_ty, result)?;
+
+        Ok((ok, err))
+    }
+
+    fn literal_type(&mut self, literal: &Spanned<Literal>) -> Type {
+        match literal.0 {
+            Literal::Int(_) => Type::Int,
+            Literal::Float(_) => Type::Float,
+            Literal::Str(_) => Type::Str,
+            Literal::Char(_) => Type::Char,
+            Literal::Bool(_) => Type::Bool,
+            Literal::Unit => Type::Unit,
+            Literal::None => Type::Optional(Box::new(Type::Var(
+                self.engine.insert((TypeInfo::Unknown, literal.1)),
+            ))),
+        }
+    }
+
+    /// Checks that a value of type `value` can be used where a value of type
+    /// `expected` is required.
+    ///
+    /// Fully known record types may be widened to a record with fewer fields,
+    /// anything else (including records that still contain type variables) has
+    /// to unify exactly so that inference never picks a wider type than needed.
+    fn coerce(&mut self, value: TypeId, expected: TypeId) -> Result<(), Error> {
+        let (value_ty, span) = self.engine.reconstruct(value);
+        let expected_ty = self.engine.reconstruct(expected).0;
+
+        match (&value_ty, &expected_ty) {
+            (Type::Record(value_fields), Type::Record(expected_fields))
+                if value_ty.is_known() && expected_ty.is_known() =>
+            {
+                if value_ty.is_assignable_to(&expected_ty) {
+                    return Ok(());
+                }
+
+                match expected_fields
+                    .iter()
+                    .find(|(name, _)| !value_fields.iter().any(|(other, _)| other == name))
+                {
+                    Some((field, _)) => Err(TypecheckError::MissingField {
+                        span,
+                        field: field.clone(),
+                        ty: value_ty,
+                    }
+                    .into()),
+                    None => Err(self.engine.mismatch(value, expected)),
+                }
+            }
+            _ => self.engine.unify(value, expected),
+        }
+    }
+
+    /// The type a branch of an `if` or `match` is checked against, which is
+    /// the type of the whole expression, unless the branches are `joined`,
+    /// when it's a new variable for that branch alone
+    fn branch_type(&mut self, ty: TypeId, joined: bool, span: Span) -> TypeId {
+        if joined {
+            self.engine.insert((TypeInfo::Unknown, span))
+        } else {
+            ty
+        }
+    }
+
+    /// The type that values of type `a` and of type `b` can both be used as,
+    /// for branches and list elements. That's the fields both have for fully
+    /// known records, like [`Typechecker::coerce`] allows, so which of them
+    /// comes first doesn't matter, and anything else has to unify exactly.
+    fn join(&mut self, a: TypeId, b: TypeId) -> Result<TypeId, Error> {
+        let (a_ty, span) = self.engine.reconstruct(a);
+        let b_ty = self.engine.reconstruct(b).0;
+
+        if !a_ty.is_known() || !b_ty.is_known() || a_ty == b_ty {
+            self.engine.unify(a, b)?;
+
+            return Ok(a);
+        }
+
+        match a_ty.join(&b_ty) {
+            Some(joined) => Ok(self.engine.insert_type((joined, span))),
+            None => Err(self.engine.mismatch(a, b)),
+        }
+    }
+
+    /// Checks that the type of an operand is known and isn't an optional that
+    /// still needs unwrapping
+    fn check_operand(&self, ty: &Type, span: Span) -> Result<(), Error> {
+        match ty {
+            Type::Var(_) => Err(self.cannot_infer(ty, span)),
+            Type::Optional(_) => Err(TypecheckError::OptionalNotUnwrapped {
+                span,
+                ty: ty.clone(),
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn resolve_statement(&self, stmt: &mut Spanned<Statement>) -> Result<(), Error> {
+        match &mut stmt.0 {
+            Statement::Expr(expr)
+            | Statement::Return(expr)
+            | Statement::Break { value: expr, .. } => self.resolve_expr(expr),
+            Statement::While { cond: expr, body }
+            | Statement::For {
+                iter: expr, body, ..
+            } => {
+                self.resolve_expr(expr)?;
+
+                body.0
+                    .iter_mut()
+                    .try_for_each(|stmt| self.resolve_statement(stmt))
+            }
+            Statement::Block((statements, _))
+            | Statement::Import { statements, .. }
+            | Statement::Impl {
+                methods: statements,
+                ..
+            } => statements
+                .iter_mut()
+                .try_for_each(|stmt| self.resolve_statement(stmt)),
+            Statement::Const { name, ty, .. } => {
+                ty.0 = self.engine.resolve(&ty.0).map_err(|_| {
+                    TypecheckError::CannotInferBindingType {
+                        name: name.0.to_string(),
+                        span: name.1,
+                        ty: self.engine.substitute(&ty.0),
+                    }
+                })?;
+
+                Ok(())
+            }
+            Statement::Let {
+                name, ty, value, ..
+            } => {
+                ty.0 = self.engine.resolve(&ty.0).map_err(|_| {
+                    TypecheckError::CannotInferBindingType {
+                        name: source_name(name.0).to_string(),
+                        span: name.1,
+                        ty: self.engine.substitute(&ty.0),
+                    }
+                })?;
+
+                self.resolve_expr(value)
+            }
+            Statement::Function {
+                return_ty, body, ..
+            } => {
+                self.resolve_statement(body)?;
+
+                return_ty.0 = self.engine.resolve(&return_ty.0)?;
+
+                Ok(())
+            }
+            Statement::Assign { value, .. } => self.resolve_expr(value),
+            Statement::SetIndex { index, value, .. } => {
+                self.resolve_expr(index)?;
+                self.resolve_expr(value)
+            }
+            Statement::Enum { .. }
+            | Statement::Struct { .. }
+            | Statement::TypeAlias { .. }
+            | Statement::Trait { .. }
+            | Statement::Continue => Ok(()),
+        }
+    }
+
+    fn resolve_expr(&self, expr: &mut Spanned<Expr>) -> Result<(), Error> {
+        match &mut expr.0.expr {
+            ExprKind::Var(_) | ExprKind::ModuleVar { .. } | ExprKind::Literal(_) => {}
+            ExprKind::Prefix { expr, .. }
+            | ExprKind::Some(expr)
+            | ExprKind::Ok(expr)
+            | ExprKind::Err(expr)
+            | ExprKind::Try(expr)
+            | ExprKind::FieldAccess { expr, .. }
+            | ExprKind::Is { expr, .. }
+            | ExprKind::Ref { expr, .. }
+            | ExprKind::Cast { expr, .. } => self.resolve_expr(expr)?,
+            ExprKind::Binary { lhs, rhs, .. }
+            | ExprKind::Index {
+                expr: lhs,
+                index: rhs,
+            }
+            | ExprKind::In {
+                expr: lhs,
+                collection: rhs,
+            }
+            | ExprKind::Range {
+                start: lhs,
+                end: rhs,
+                ..
+            } => {
+                self.resolve_expr(lhs)?;
+                self.resolve_expr(rhs)?;
+            }
+            ExprKind::Call { callee, args } => {
+                self.resolve_expr(callee)?;
+
+                for arg in &mut args.0 {
+                    self.resolve_expr(arg)?;
+                }
+            }
+            ExprKind::LabeledBlock {
+                statements, tail, ..
+            } => {
+                for stmt in &mut statements.0 {
+                    self.resolve_statement(stmt)?;
+                }
+
+                if let Some(tail) = tail {
+                    self.resolve_expr(tail)?;
+                }
+            }
+            ExprKind::Loop { body, .. } => {
+                for stmt in &mut body.0 {
+                    self.resolve_statement(stmt)?;
+                }
+            }
+            ExprKind::If { cond, then, else_ } => {
+                self.resolve_expr(cond)?;
+
+                for block in std::iter::once(then).chain(else_) {
+                    self.resolve_block(&mut block.0)?;
+                }
+            }
+            ExprKind::Block(block) => self.resolve_block(block)?,
+            ExprKind::Catch { body, handler, .. } => {
+                self.resolve_block(&mut body.0)?;
+                self.resolve_block(&mut handler.0)?;
+            }
+            ExprKind::MethodCall { receiver, args, .. } => {
+                self.resolve_expr(receiver)?;
+
+                for arg in &mut args.0 {
+                    self.resolve_expr(arg)?;
+                }
+            }
+            ExprKind::Closure { params, body, .. } => {
+                for (_, ty) in &mut params.0 {
+                    ty.0 = self.engine.resolve(&ty.0)?;
+                }
+
+                self.resolve_expr(body)?;
+            }
+            ExprKind::BuiltinCall { args, .. } => {
+                for arg in &mut args.0 {
+                    self.resolve_expr(arg)?;
+                }
+            }
+            ExprKind::Variant { args, .. } => {
+                for arg in &mut args.0 {
+                    self.resolve_expr(arg)?;
+                }
+            }
+            ExprKind::Record(fields) | ExprKind::Struct { fields, .. } => {
+                for (_, value) in fields {
+                    self.resolve_expr(value)?;
+                }
+            }
+            ExprKind::List(elements) => {
+                for element in &mut elements.0 {
+                    self.resolve_expr(element)?;
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in &mut entries.0 {
+                    self.resolve_expr(key)?;
+                    self.resolve_expr(value)?;
+                }
+            }
+            ExprKind::Match { expr, arms } => {
+                self.resolve_expr(expr)?;
+
+                for (_, body) in &mut arms.0 {
+                    self.resolve_expr(body)?;
+                }
+            }
+        }
+
+        expr.0.ty = self.engine.resolve(&expr.0.ty)?;
+
+        Ok(())
+    }
+
+    fn resolve_block(&self, block: &mut Block) -> Result<(), Error> {
+        for stmt in &mut block.statements {
+            self.resolve_statement(stmt)?;
+        }
+
+        if let Some(tail) = &mut block.tail {
+            self.resolve_expr(tail)?;
+        }
+
+        Ok(())
+    }
+
+    fn lower_literal(&self, literal: Spanned<ast::Literal>) -> Spanned<Literal> {
+        (
+            match literal.0 {
+                ast::Literal::Int(n) => Literal::Int(n),
+                ast::Literal::Float(n) => Literal::Float(n),
+                ast::Literal::Str(s) => Literal::Str(s),
+                ast::Literal::Char(c) => Literal::Char(c),
+                ast::Literal::Bool(b) => Literal::Bool(b),
+                ast::Literal::Unit => Literal::Unit,
+                ast::Literal::None => Literal::None,
+            },
+            literal.1,
+        )
+    }
+
+    fn lower_format_spec(&self, spec: ast::FormatSpec) -> FormatSpec {
+        match spec {
+            ast::FormatSpec::Debug => FormatSpec::Debug,
+            ast::FormatSpec::Hex => FormatSpec::Hex,
+            ast::FormatSpec::Binary => FormatSpec::Binary,
+            ast::FormatSpec::Octal => FormatSpec::Octal,
+            ast::FormatSpec::Precision(digits) => FormatSpec::Precision(digits),
+        }
+    }
+
+    fn lower_prefix_operator(&self, op: Spanned<ast::PrefixOp>) -> Spanned<PrefixOp> {
+        (
+            match op.0 {
+                ast::PrefixOp::Negate => PrefixOp::Negate,
+                ast::PrefixOp::Not => PrefixOp::Not,
+                ast::PrefixOp::BitNot => PrefixOp::BitNot,
+            },
+            op.1,
+        )
+    }
+
+    fn lower_binary_operator(&self, op: Spanned<ast::BinOp>) -> Spanned<BinOp> {
+        (
+            match op.0 {
+                ast::BinOp::Add => BinOp::Add,
+                ast::BinOp::Subtract => BinOp::Subtract,
+                ast::BinOp::Multiply => BinOp::Multiply,
+                ast::BinOp::Divide => BinOp::Divide,
+                ast::BinOp::Modulo => BinOp::Modulo,
+                ast::BinOp::Power => BinOp::Power,
+                ast::BinOp::BitAnd => BinOp::BitAnd,
+                ast::BinOp::BitOr => BinOp::BitOr,
+                ast::BinOp::BitXor => BinOp::BitXor,
+                ast::BinOp::ShiftLeft => BinOp::ShiftLeft,
+                ast::BinOp::ShiftRight => BinOp::ShiftRight,
+                ast::BinOp::Equals => BinOp::Equals,
+                ast::BinOp::NotEquals => BinOp::NotEquals,
+                ast::BinOp::LessThan => BinOp::LessThan,
+                ast::BinOp::LessThanOrEqual => BinOp::LessThanOrEqual,
+                ast::BinOp::GreaterThan => BinOp::GreaterThan,
+                ast::BinOp::GreaterThanOrEqual => BinOp::GreaterThanOrEqual,
+                ast::BinOp::LogicalAnd => BinOp::LogicalAnd,
+                ast::BinOp::LogicalOr => BinOp::LogicalOr,
+                ast::BinOp::Coalesce => BinOp::Coalesce,
+            },
+            op.1,
+        )
+    }
+
+    fn lower_type(&self, ty: Spanned<ast::Type>) -> Result<Spanned<Type>, Error> {
+        Ok((
+            match ty.0 {
+                ast::Type::Int => Type::Int,
+                ast::Type::Float => Type::Float,
+                ast::Type::Bool => Type::Bool,
+                ast::Type::Str => Type::Str,
+                ast::Type::Char => Type::Char,
+                ast::Type::Range => Type::Range,
+                ast::Type::Unit => Type::Unit,
+                ast::Type::Optional(inner) => {
+                    Type::Optional(Box::new(self.lower_type((*inner, ty.1))?.0))
+                }
+                ast::Type::List(inner) => Type::List(Box::new(self.lower_type((*inner, ty.1))?.0)),
+                ast::Type::Ref(mutable, inner) => {
+                    Type::Ref(mutable, Box::new(self.lower_type((*inner, ty.1))?.0))
+                }
+                ast::Type::Map(key, value) => Type::Map(
+                    Box::new(self.lower_type((*key, ty.1))?.0),
+                    Box::new(self.lower_type((*value, ty.1))?.0),
+                ),
+                ast::Type::Result(ok, err) => Type::Result(
+                    Box::new(self.lower_type((*ok, ty.1))?.0),
+                    Box::new(self.lower_type((*err, ty.1))?.0),
+                ),
+                ast::Type::Func(params, return_ty) => Type::Func(
+                    params
+                        .into_iter()
+                        .map(|param| Ok(self.lower_type((param, ty.1))?.0))
+                        .collect::<Result<_, Error>>()?,
+                    Box::new(self.lower_type((*return_ty, ty.1))?.0),
+                ),
+                ast::Type::Record(fields) => {
+                    let mut fields = fields
+                        .into_iter()
+                        .map(|(name, field)| {
+                            Ok((name.to_string(), self.lower_type((field, ty.1))?.0))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+
+                    fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+                    if let Some(pair) = fields.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+                        return Err(TypecheckError::DuplicateField {
+                            name: pair[0].0.clone(),
+                            span1: ty.1,
+                            span2: ty.1,
+                        }
+                        .into());
+                    }
+
+                    Type::Record(fields)
+                }
+                ast::Type::Named(name) => match self.types.get(name) {
+                    Some(TypeDef::Enum(name)) => Type::Enum(name.to_string()),
+                    Some(TypeDef::Struct(name)) => Type::Struct(name.to_string()),
+                    Some(TypeDef::Alias(ty)) => ty.clone(),
+                    Some(TypeDef::Param { .. }) => Type::Param(name.to_string()),
+                    None => {
+                        return Err(TypecheckError::UndefinedType {
+                            name: name.to_string(),
+                            span: ty.1,
+                        }
+                        .into())
+                    }
+                },
+            },
+            ty.1,
+        ))
+    }
+}
+
+/// The type variables, kept as a union-find forest where the variables that
+/// have been unified with each other are in the same tree, and what's known
+/// about the type they all have is kept at its root
+struct Engine {
+    /// The variable each one was unified with, or itself for a root. Paths
+    /// are shortened while finding roots, so this is a `Cell` to allow that
+    /// through a shared reference.
+    parents: Vec<Cell<TypeId>>,
+    /// An upper bound on the height of the tree below each root
+    ranks: Vec<u8>,
+    /// What's known about each variable, which is only up to date at roots
+    infos: Vec<TypeInfo>,
+    spans: Vec<Span>,
+    /// Which variables were unified with which, kept for `--explain-types`
+    trail: Option<Trail>,
+}
+
+/// The unifications that joined the trees of an [`Engine`], which the
+/// tree itself forgets as its paths are shortened
+struct Trail {
+    /// Where each variable was made, before solving it moved its span
+    sites: Vec<Span>,
+    /// The variables in the type each one was made with, or `None` for the
+    /// ones that were made unknown
+    parts: Vec<Option<Vec<TypeId>>>,
+    /// The variables each one was unified with
+    edges: Vec<Vec<TypeId>>,
+}
+
+impl Trail {
+    /// The variables from `start` to the closest one made with a type, found
+    /// by a breadth-first search, or just `start` if there isn't one
+    fn path(&self, start: TypeId) -> Vec<TypeId> {
+        // remembering the way each variable was reached by
+        let mut from = HashMap::from([(start, start)]);
+        let mut queue = std::collections::VecDeque::from([start]);
+
+        while let Some(var) = queue.pop_front() {
+            if self.parts[var].is_some() {
+                let mut path = vec![var];
+
+                while path.last() != Some(&start) {
+                    path.push(from[path.last().unwrap()]);
+                }
+
+                path.reverse();
+
+                return path;
+            }
+
+            for &next in &self.edges[var] {
+                if let std::collections::hash_map::Entry::Vacant(entry) = from.entry(next) {
+                    entry.insert(var);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        vec![start]
+    }
+}
+
+impl Engine {
+    fn new(explain: bool) -> Self {
+        Self {
+            parents: vec![],
+            ranks: vec![],
+            infos: vec![],
+            spans: vec![],
+            trail: explain.then(|| Trail {
+                sites: vec![],
+                parts: vec![],
+                edges: vec![],
+            }),
+        }
+    }
+
+    fn insert(&mut self, info: Spanned<TypeInfo>) -> TypeId {
+        let id = self.infos.len();
+
+        if let Some(trail) = &mut self.trail {
+            trail.sites.push(info.1);
+            trail
+                .parts
+                .push((info.0 != TypeInfo::Unknown).then(|| info.0.parts()));
+            trail.edges.push(vec![]);
+        }
+
+        self.parents.push(Cell::new(id));
+        self.ranks.push(0);
+        self.infos.push(info.0);
+        self.spans.push(info.1);
+        id
+    }
+
+    /// Records that `a` and `b` were unified, if that's being kept
+    fn record(&mut self, a: TypeId, b: TypeId) {
+        if let Some(trail) = &mut self.trail {
+            trail.edges[a].push(b);
+            trail.edges[b].push(a);
+        }
+    }
+
+    /// Where the type of `id` came from: the sites of the variables it was
+    /// unified with, one after another, up to the one that was made with
+    /// its type, by the shortest way there. That one can have had parts of
+    /// its type unknown, like the element type of `[]`, so each chain after
+    /// the first is the same for such a part that's known now. Only the site
+    /// of `id` itself is known if unifications weren't kept, or if its type
+    /// is still unknown.
+    fn explain(&self, id: TypeId) -> Vec<Vec<Span>> {
+        let Some(trail) = &self.trail else {
+            return vec![vec![self.spans[id]]];
+        };
+
+        // variables made for the same expression share its site, which is
+        // only shown once
+        let mut shown = Vec::new();
+        let mut chains = Vec::new();
+        let mut queue = std::collections::VecDeque::from([id]);
+        let mut followed = HashSet::from([id]);
+
+        while let Some(start) = queue.pop_front() {
+            let path = trail.path(start);
+            let mut chain = Vec::new();
+
+            for &var in &path {
+                if !shown.contains(&trail.sites[var]) {
+                    shown.push(trail.sites[var]);
+                    chain.push(trail.sites[var]);
+                }
+            }
+
+            chains.push(chain);
+
+            // the parts that were made unknown can be nested in ones that weren't
+            let mut parts = match path.last().map(|&var| &trail.parts[var]) {
+                Some(Some(parts)) => parts.clone(),
+                _ => continue,
+            };
+
+            while let Some(part) = parts.pop() {
+                if !followed.insert(part) {
+                    continue;
+                }
+
+                match &trail.parts[part] {
+                    Some(inner) => parts.extend(inner),
+                    None if self.infos[self.find(part)] != TypeInfo::Unknown => {
+                        queue.push_back(part);
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        chains
+    }
+
+    /// The root of the tree `id` is in, which stands for every variable in it
+    fn find(&self, id: TypeId) -> TypeId {
+        let mut root = id;
+
+        while self.parents[root].get() != root {
+            root = self.parents[root].get();
+        }
+
+        // point everything on the way straight at the root, so finding it
+        // again is quick
+        let mut id = id;
+
+        while id != root {
+            id = self.parents[id].replace(root);
+        }
+
+        root
+    }
+
+    /// What's known about the type of `id`
+    fn info(&self, id: TypeId) -> &TypeInfo {
+        &self.infos[self.find(id)]
+    }
+
+    /// Joins the trees of the roots `a` and `b`, hanging the lower one from
+    /// the higher one, and keeps `info` at the new root
+    fn link(&mut self, a: TypeId, b: TypeId, info: TypeInfo) {
+        let (child, root) = if self.ranks[a] > self.ranks[b] {
+            (b, a)
+        } else {
+            (a, b)
+        };
+
+        if self.ranks[a] == self.ranks[b] {
+            self.ranks[root] += 1;
+        }
+
+        self.parents[child].set(root);
+        self.infos[root] = info;
+    }
+
+    fn insert_type(&mut self, ty: Spanned<Type>) -> TypeId {
+        let info = match ty.0 {
+            Type::Int => TypeInfo::Int,
+            Type::Float => TypeInfo::Float,
+            Type::Bool => TypeInfo::Bool,
+            Type::Str => TypeInfo::Str,
+            Type::Char => TypeInfo::Char,
+            Type::Range => TypeInfo::Range,
+            Type::Unit => TypeInfo::Unit,
+            Type::Enum(name) => TypeInfo::Enum(name),
+            Type::Struct(name) => TypeInfo::Struct(name),
+            Type::Param(name) => TypeInfo::Param(name),
+            Type::Optional(inner) => TypeInfo::Optional(self.insert_type((*inner, ty.1))),
+            Type::List(inner) => TypeInfo::List(self.insert_type((*inner, ty.1))),
+            Type::Map(key, value) => TypeInfo::Map(
+                self.insert_type((*key, ty.1)),
+                self.insert_type((*value, ty.1)),
+            ),
+            Type::Result(ok, err) => TypeInfo::Result(
+                self.insert_type((*ok, ty.1)),
+                self.insert_type((*err, ty.1)),
+            ),
+            Type::Record(fields) => TypeInfo::Record(
+                fields
+                    .into_iter()
+                    .map(|(name, field)| (name, self.insert_type((field, ty.1))))
+                    .collect(),
+            ),
+            Type::Func(params, return_ty) => TypeInfo::Func(
+                params
+                    .into_iter()
+                    .map(|param| self.insert_type((param, ty.1)))
+                    .collect(),
+                self.insert_type((*return_ty, ty.1)),
+            ),
+            Type::Ref(mutable, inner) => {
+                TypeInfo::Reference(mutable, self.insert_type((*inner, ty.1)))
+            }
+            // a new variable in the tree of `id`, which keeps its own span
+            Type::Var(id) => {
+                let var = self.insert((TypeInfo::Unknown, ty.1));
+                let root = self.find(id);
+                self.parents[var].set(root);
+                self.record(var, root);
+                return var;
+            }
+        };
+
+        self.insert((info, ty.1))
+    }
+
+    fn unify(&mut self, a: TypeId, b: TypeId) -> Result<(), Error> {
+        let a = self.find(a);
+        let b = self.find(b);
+
+        if a == b {
+            return Ok(());
+        }
+
+        // the pairs of types inside `a` and `b` that have to be the same for
+        // them to be
+        let inner = match (&self.infos[a], &self.infos[b]) {
+            // a variable can't be solved with a type that has it inside, which
+            // would make the type infinite
+            (TypeInfo::Unknown, _) if self.occurs(a, b) => return Err(self.infinite(a, b)),
+            (_, TypeInfo::Unknown) if self.occurs(b, a) => return Err(self.infinite(b, a)),
+
+            (TypeInfo::Unknown, _) => {
+                self.solve(a, b);
+                return Ok(());
+            }
+            (_, TypeInfo::Unknown) => {
+                self.solve(b, a);
+                return Ok(());
+            }
+
+            (TypeInfo::Error, _) | (_, TypeInfo::Error) => return Ok(()),
 
-                    Statement::Return(expr)
-                }
-            },
-            stmt.1,
-        ))
-    }
+            (TypeInfo::Int, TypeInfo::Int)
+            | (TypeInfo::Float, TypeInfo::Float)
+            | (TypeInfo::Bool, TypeInfo::Bool)
+            | (TypeInfo::Str, TypeInfo::Str)
+            | (TypeInfo::Char, TypeInfo::Char)
+            | (TypeInfo::Range, TypeInfo::Range)
+            | (TypeInfo::Unit, TypeInfo::Unit) => return Ok(()),
 
-    fn typecheck_expr<'src>(
-        &mut self,
-        expr: Spanned<ast::Expr<'src>>,
-    ) -> Result<Spanned<Expr<'src>>, Error> {
-        Ok((
-            match expr.0 {
-                ast::Expr::Var(name) => {
-                    let ty =
-                        self.bindings
-                            .get(&name.0)
-                            .ok_or(TypecheckError::UndefinedVariable {
-                                name: name.0.to_string(),
-                                span: name.1,
-                            })?;
+            (TypeInfo::Enum(name_a), TypeInfo::Enum(name_b))
+            | (TypeInfo::Struct(name_a), TypeInfo::Struct(name_b))
+            | (TypeInfo::Param(name_a), TypeInfo::Param(name_b))
+                if name_a == name_b =>
+            {
+                return Ok(())
+            }
 
-                    Expr {
-                        expr: ExprKind::Var(name),
-                        ty: self.engine.reconstruct(*ty)?.0,
-                    }
-                }
-                ast::Expr::Literal(literal) => {
-                    let literal = self.lower_literal(literal);
+            (TypeInfo::Reference(mutable_a, inner_a), TypeInfo::Reference(mutable_b, inner_b))
+                if mutable_a == mutable_b =>
+            {
+                vec![(*inner_a, *inner_b)]
+            }
 
-                    Expr {
-                        expr: ExprKind::Literal(literal),
-                        ty: literal.0.ty(),
-                    }
-                }
-                ast::Expr::Prefix { op, expr } => {
-                    let op = self.lower_prefix_operator(op);
+            (TypeInfo::Optional(inner_a), TypeInfo::Optional(inner_b))
+            | (TypeInfo::List(inner_a), TypeInfo::List(inner_b)) => vec![(*inner_a, *inner_b)],
 
-                    let expr = self.typecheck_expr(*expr)?;
-                    let expr_id = self.engine.insert(type_to_typeinfo((expr.0.ty, expr.1)));
-                    let expr_ty = self.engine.reconstruct(expr_id)?;
+            (TypeInfo::Result(ok_a, err_a), TypeInfo::Result(ok_b, err_b))
+            | (TypeInfo::Map(ok_a, err_a), TypeInfo::Map(ok_b, err_b)) => {
+                vec![(*ok_a, *ok_b), (*err_a, *err_b)]
+            }
 
-                    let ty = expr_ty.0.get_prefix_type(op)?;
+            (TypeInfo::Func(params_a, return_a), TypeInfo::Func(params_b, return_b))
+                if params_a.len() == params_b.len() =>
+            {
+                params_a
+                    .iter()
+                    .copied()
+                    .zip(params_b.iter().copied())
+                    .chain([(*return_a, *return_b)])
+                    .collect()
+            }
 
-                    Expr {
-                        expr: ExprKind::Prefix {
-                            op,
-                            expr: Box::new(expr),
-                        },
-                        ty,
-                    }
-                }
-                ast::Expr::Binary { op, lhs, rhs } => {
-                    let op = self.lower_binary_operator(op);
+            (TypeInfo::Record(fields_a), TypeInfo::Record(fields_b))
+                if fields_a.len() == fields_b.len()
+                    && fields_a
+                        .iter()
+                        .zip(fields_b)
+                        .all(|((name_a, _), (name_b, _))| name_a == name_b) =>
+            {
+                fields_a
+                    .iter()
+                    .zip(fields_b)
+                    .map(|((_, field_a), (_, field_b))| (*field_a, *field_b))
+                    .collect::<Vec<_>>()
+            }
 
-                    let lhs = self.typecheck_expr(*lhs)?;
-                    let lhs_id = self.engine.insert(type_to_typeinfo((lhs.0.ty, lhs.1)));
+            _ => return Err(self.mismatch(a, b)),
+        };
 
-                    let rhs = self.typecheck_expr(*rhs)?;
-                    let rhs_id = self.engine.insert(type_to_typeinfo((rhs.0.ty, rhs.1)));
+        inner
+            .into_iter()
+            .try_for_each(|(inner_a, inner_b)| self.unify(inner_a, inner_b))
+            .map_err(|error| self.outer_mismatch(error, a, b))
+    }
 
-                    self.engine.unify(lhs_id, rhs_id)?;
+    /// Solves the unknown root `var` with the type of the root `ty`, which
+    /// also takes its span
+    fn solve(&mut self, var: TypeId, ty: TypeId) {
+        let info = std::mem::replace(&mut self.infos[ty], TypeInfo::Unknown);
+        self.spans[var] = self.spans[ty];
+        self.record(var, ty);
+        self.link(var, ty, info);
+    }
 
-                    let lhs_ty = self.engine.reconstruct(lhs_id)?;
-                    let rhs_ty = self.engine.reconstruct(rhs_id)?;
+    fn mismatch(&self, a: TypeId, b: TypeId) -> Error {
+        TypecheckError::TypeMismatch {
+            span1: self.spans[a],
+            span2: self.spans[b],
+            ty1: self.reconstruct(a).0,
+            ty2: self.reconstruct(b).0,
+        }
+        .into()
+    }
 
-                    let ty = lhs_ty.0.get_binary_type(op, &rhs_ty.0)?;
+    /// Turns an error from unifying the types inside `a` and `b` into a
+    /// mismatch of `a` and `b` themselves, which shows more of where they
+    /// differ, unless it's about an infinite type
+    fn outer_mismatch(&self, error: Error, a: TypeId, b: TypeId) -> Error {
+        match error {
+            Error::Typecheck(TypecheckError::InfiniteType { .. }) => error,
+            _ => self.mismatch(a, b),
+        }
+    }
 
-                    Expr {
-                        expr: ExprKind::Binary {
-                            op,
-                            lhs: Box::new(lhs),
-                            rhs: Box::new(rhs),
-                        },
-                        ty,
-                    }
-                }
-                ast::Expr::Call { callee, args } => {
-                    todo!()
-                }
-            },
-            expr.1,
-        ))
+    fn infinite(&self, var: TypeId, ty: TypeId) -> Error {
+        TypecheckError::InfiniteType {
+            span1: self.spans[var],
+            span2: self.spans[ty],
+            ty: self.reconstruct(ty).0,
+        }
+        .into()
     }
 
-    fn lower_literal(&self, literal: Spanned<ast::Literal>) -> Spanned<Literal> {
+    /// Whether the root `var` is part of the type `ty`
+    fn occurs(&self, var: TypeId, ty: TypeId) -> bool {
+        let ty = self.find(ty);
+
+        if var == ty {
+            return true;
+        }
+
+        match &self.infos[ty] {
+            TypeInfo::Optional(inner) | TypeInfo::List(inner) | TypeInfo::Reference(_, inner) => {
+                self.occurs(var, *inner)
+            }
+            TypeInfo::Map(a, b) | TypeInfo::Result(a, b) => {
+                self.occurs(var, *a) || self.occurs(var, *b)
+            }
+            TypeInfo::Record(fields) => fields.iter().any(|(_, field)| self.occurs(var, *field)),
+            TypeInfo::Func(params, return_ty) => {
+                params.iter().any(|param| self.occurs(var, *param)) || self.occurs(var, *return_ty)
+            }
+            TypeInfo::Unknown
+            | TypeInfo::Error
+            | TypeInfo::Int
+            | TypeInfo::Float
+            | TypeInfo::Bool
+            | TypeInfo::Str
+            | TypeInfo::Char
+            | TypeInfo::Unit
+            | TypeInfo::Range
+            | TypeInfo::Enum(_)
+            | TypeInfo::Struct(_)
+            | TypeInfo::Param(_) => false,
+        }
+    }
+
+    /// Reconstructs the type of `id` as far as it is currently known, leaving
+    /// unsolved parts as `Type::Var`
+    fn reconstruct(&self, id: TypeId) -> Spanned<Type> {
+        let root = self.find(id);
+
         (
-            match literal.0 {
-                ast::Literal::Num(n) => Literal::Num(n),
-                ast::Literal::Bool(b) => Literal::Bool(b),
-                ast::Literal::Unit => Literal::Unit,
+            match &self.infos[root] {
+                TypeInfo::Unknown | TypeInfo::Error => Type::Var(root),
+                TypeInfo::Int => Type::Int,
+                TypeInfo::Float => Type::Float,
+                TypeInfo::Bool => Type::Bool,
+                TypeInfo::Str => Type::Str,
+                TypeInfo::Char => Type::Char,
+                TypeInfo::Range => Type::Range,
+                TypeInfo::Unit => Type::Unit,
+                TypeInfo::Enum(name) => Type::Enum(name.clone()),
+                TypeInfo::Struct(name) => Type::Struct(name.clone()),
+                TypeInfo::Param(name) => Type::Param(name.clone()),
+                TypeInfo::Optional(inner) => Type::Optional(Box::new(self.reconstruct(*inner).0)),
+                TypeInfo::List(inner) => Type::List(Box::new(self.reconstruct(*inner).0)),
+                TypeInfo::Reference(mutable, inner) => {
+                    Type::Ref(*mutable, Box::new(self.reconstruct(*inner).0))
+                }
+                TypeInfo::Map(key, value) => Type::Map(
+                    Box::new(self.reconstruct(*key).0),
+                    Box::new(self.reconstruct(*value).0),
+                ),
+                TypeInfo::Result(ok, err) => Type::Result(
+                    Box::new(self.reconstruct(*ok).0),
+                    Box::new(self.reconstruct(*err).0),
+                ),
+                TypeInfo::Record(fields) => Type::Record(
+                    fields
+                        .iter()
+                        .map(|(name, field)| (name.clone(), self.reconstruct(*field).0))
+                        .collect(),
+                ),
+                TypeInfo::Func(params, return_ty) => Type::Func(
+                    params
+                        .iter()
+                        .map(|param| self.reconstruct(*param).0)
+                        .collect(),
+                    Box::new(self.reconstruct(*return_ty).0),
+                ),
             },
-            literal.1,
+            self.spans[id],
         )
     }
 
-    fn lower_prefix_operator(&self, op: Spanned<ast::PrefixOp>) -> Spanned<PrefixOp> {
-        (
-            match op.0 {
-                ast::PrefixOp::Negate => PrefixOp::Negate,
+    /// Adds the unsolved type variables in `ty` that aren't already in `vars`
+    /// to it, in the order they appear
+    fn unknowns(&self, ty: &Type, vars: &mut Vec<TypeId>) {
+        match ty {
+            Type::Var(id) => {
+                let root = self.find(*id);
+
+                if matches!(self.infos[root], TypeInfo::Unknown) && !vars.contains(&root) {
+                    vars.push(root);
+                }
+            }
+            Type::Optional(inner) | Type::List(inner) | Type::Ref(_, inner) => {
+                self.unknowns(inner, vars)
+            }
+            Type::Result(ok, err) | Type::Map(ok, err) => {
+                self.unknowns(ok, vars);
+                self.unknowns(err, vars);
+            }
+            Type::Record(fields) => {
+                for (_, field) in fields {
+                    self.unknowns(field, vars);
+                }
+            }
+            Type::Func(params, return_ty) => {
+                for param in params {
+                    self.unknowns(param, vars);
+                }
+
+                self.unknowns(return_ty, vars);
+            }
+            Type::Int
+            | Type::Float
+            | Type::Bool
+            | Type::Str
+            | Type::Char
+            | Type::Range
+            | Type::Unit
+            | Type::Enum(_)
+            | Type::Struct(_)
+            | Type::Param(_) => {}
+        }
+    }
+
+    /// Whether `ty` has the type of something that failed to typecheck in it
+    fn has_placeholder(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Var(id) => matches!(self.info(*id), TypeInfo::Error),
+            Type::Optional(inner) | Type::List(inner) | Type::Ref(_, inner) => {
+                self.has_placeholder(inner)
+            }
+            Type::Result(ok, err) | Type::Map(ok, err) => {
+                self.has_placeholder(ok) || self.has_placeholder(err)
+            }
+            Type::Record(fields) => fields.iter().any(|(_, field)| self.has_placeholder(field)),
+            Type::Func(params, return_ty) => {
+                params.iter().any(|param| self.has_placeholder(param))
+                    || self.has_placeholder(return_ty)
+            }
+            Type::Int
+            | Type::Float
+            | Type::Bool
+            | Type::Str
+            | Type::Char
+            | Type::Range
+            | Type::Unit
+            | Type::Enum(_)
+            | Type::Struct(_)
+            | Type::Param(_) => false,
+        }
+    }
+
+    /// Replaces every solved `Type::Var` in `ty` with its solution
+    fn substitute(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(id) => match self.reconstruct(*id).0 {
+                Type::Var(id) => Type::Var(id),
+                ty => self.substitute(&ty),
             },
-            op.1,
-        )
+            Type::Optional(inner) => Type::Optional(Box::new(self.substitute(inner))),
+            Type::List(inner) => Type::List(Box::new(self.substitute(inner))),
+            Type::Ref(mutable, inner) => Type::Ref(*mutable, Box::new(self.substitute(inner))),
+            Type::Map(key, value) => Type::Map(
+                Box::new(self.substitute(key)),
+                Box::new(self.substitute(value)),
+            ),
+            Type::Result(ok, err) => Type::Result(
+                Box::new(self.substitute(ok)),
+                Box::new(self.substitute(err)),
+            ),
+            Type::Record(fields) => Type::Record(
+                fields
+                    .iter()
+                    .map(|(name, field)| (name.clone(), self.substitute(field)))
+                    .collect(),
+            ),
+            Type::Func(params, return_ty) => Type::Func(
+                params.iter().map(|param| self.substitute(param)).collect(),
+                Box::new(self.substitute(return_ty)),
+            ),
+            ty => ty.clone(),
+        }
     }
 
-    fn lower_binary_operator(&self, op: Spanned<ast::BinOp>) -> Spanned<BinOp> {
-        (
-            match op.0 {
-                ast::BinOp::Add => BinOp::Add,
-                ast::BinOp::Subtract => BinOp::Subtract,
-                ast::BinOp::Multiply => BinOp::Multiply,
-                ast::BinOp::Divide => BinOp::Divide,
-                ast::BinOp::Equals => BinOp::Equals,
-                ast::BinOp::NotEquals => BinOp::NotEquals,
-                ast::BinOp::LessThan => BinOp::LessThan,
-                ast::BinOp::LessThanOrEqual => BinOp::LessThanOrEqual,
-                ast::BinOp::GreaterThan => BinOp::GreaterThan,
-                ast::BinOp::GreaterThanOrEqual => BinOp::GreaterThanOrEqual,
-                ast::BinOp::LogicalAnd => BinOp::LogicalAnd,
-                ast::BinOp::LogicalOr => BinOp::LogicalOr,
+    /// Resolves every `Type::Var` in `ty`, failing if any of them is still unsolved
+    fn resolve(&self, ty: &Type) -> Result<Type, Error> {
+        Ok(match ty {
+            Type::Var(id) => match self.reconstruct(*id) {
+                (Type::Var(_), span) => return Err(TypecheckError::CannotInferType { span }.into()),
+                (ty, _) => self.resolve(&ty)?,
             },
-            op.1,
-        )
+            Type::Optional(inner) => Type::Optional(Box::new(self.resolve(inner)?)),
+            Type::List(inner) => Type::List(Box::new(self.resolve(inner)?)),
+            Type::Ref(mutable, inner) => Type::Ref(*mutable, Box::new(self.resolve(inner)?)),
+            Type::Map(key, value) => {
+                Type::Map(Box::new(self.resolve(key)?), Box::new(self.resolve(value)?))
+            }
+            Type::Result(ok, err) => {
+                Type::Result(Box::new(self.resolve(ok)?), Box::new(self.resolve(err)?))
+            }
+            Type::Record(fields) => Type::Record(
+                fields
+                    .iter()
+                    .map(|(name, field)| Ok((name.clone(), self.resolve(field)?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+            Type::Func(params, return_ty) => Type::Func(
+                params
+                    .iter()
+                    .map(|param| self.resolve(param))
+                    .collect::<Result<_, Error>>()?,
+                Box::new(self.resolve(return_ty)?),
+            ),
+            ty => ty.clone(),
+        })
     }
+}
 
-    fn lower_type(&self, ty: Spanned<ast::Type>) -> Spanned<Type> {
-        (
-            match ty.0 {
-                ast::Type::Num => Type::Num,
-                ast::Type::Bool => Type::Bool,
-                ast::Type::Unit => Type::Unit,
+/// Joins string expressions into one with a chain of concatenations, leaving
+/// out empty text
+fn concat_strs<'src>(parts: Vec<Spanned<Expr<'src>>>, span: Span) -> Spanned<Expr<'src>> {
+    parts
+        .into_iter()
+        .filter(|part| {
+            !matches!(&part.0.expr, ExprKind::Literal((Literal::Str(text), _)) if text.is_empty())
+        })
+        .reduce(|lhs, rhs| {
+            let span = Span::from(lhs.1.start..rhs.1.end);
+
+            (
+                Expr {
+                    expr: ExprKind::Binary {
+                        op: (BinOp::Add, rhs.1),
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(rhs),
+                    },
+                    ty: Type::Str,
+                },
+                span,
+            )
+        })
+        .unwrap_or((
+            Expr {
+                expr: ExprKind::Literal((Literal::Str(String::new()), span)),
+                ty: Type::Str,
             },
-            ty.1,
+            span,
+        ))
+}
+
+/// Whether `expr as to` is allowed for an expression of type `from`
+fn can_cast(from: &Type, to: &Type) -> bool {
+    from == to
+        || matches!(
+            (from, to),
+            (Type::Int | Type::Float | Type::Bool | Type::Char, Type::Str)
+                | (Type::Str | Type::Float | Type::Bool | Type::Char, Type::Int)
+                | (Type::Str | Type::Int, Type::Float)
+                | (Type::Int, Type::Char)
         )
+}
+
+/// Converts an int expression to a float, the way `expr as float` would
+fn to_float(expr: Spanned<Expr>) -> Spanned<Expr> {
+    let span = expr.1;
+
+    (
+        Expr {
+            expr: ExprKind::Cast {
+                expr: Box::new(expr),
+                ty: (Type::Float, span),
+            },
+            ty: Type::Float,
+        },
+        span,
+    )
+}
+
+/// Whether running a statement always ends in a return, so that nothing
+/// after it is reached
+fn always_returns(stmt: &Spanned<Statement>) -> bool {
+    match &stmt.0 {
+        Statement::Return(_) => true,
+        Statement::Block((statements, _)) => statements.iter().any(always_returns),
+        Statement::Expr(expr)
+        | Statement::Let { value: expr, .. }
+        | Statement::Assign { value: expr, .. }
+        | Statement::SetIndex { value: expr, .. } => expr_always_returns(expr),
+        Statement::Function { .. }
+        | Statement::Enum { .. }
+        | Statement::Struct { .. }
+        | Statement::TypeAlias { .. }
+        | Statement::Break { .. }
+        | Statement::Continue
+        | Statement::While { .. }
+        | Statement::For { .. }
+        | Statement::Import { .. }
+        | Statement::Trait { .. }
+        | Statement::Impl { .. }
+        | Statement::Const { .. } => false,
     }
 }
 
-struct Engine {
-    id_counter: usize,
-    vars: HashMap<TypeId, Spanned<TypeInfo>>,
+fn block_always_returns(block: &Block) -> bool {
+    block.statements.iter().any(always_returns)
+        || block.tail.as_deref().is_some_and(expr_always_returns)
 }
 
-impl Engine {
-    fn new() -> Self {
-        Self {
-            id_counter: 0,
-            vars: HashMap::new(),
+fn expr_always_returns(expr: &Spanned<Expr>) -> bool {
+    match &expr.0.expr {
+        ExprKind::If {
+            then,
+            else_: Some(else_),
+            ..
+        } => block_always_returns(&then.0) && block_always_returns(&else_.0),
+        ExprKind::Block(block) => block_always_returns(block),
+        // the body can fail before it returns
+        ExprKind::Catch { body, handler, .. } => {
+            block_always_returns(&body.0) && block_always_returns(&handler.0)
         }
+        // a loop without breaks never ends, unless it returns
+        ExprKind::Loop { breaks, .. } => !breaks,
+        ExprKind::Match { arms, .. } => arms.0.iter().all(|(_, body)| expr_always_returns(body)),
+        // labeled blocks can be broken out of before reaching a return
+        _ => false,
     }
+}
 
-    fn insert(&mut self, info: Spanned<TypeInfo>) -> TypeId {
-        self.id_counter += 1;
-        let id = self.id_counter;
-        self.vars.insert(id, info);
-        id
+/// Whether running a statement never gets to the statement after it,
+/// because it always returns or breaks out of a loop
+fn diverges(stmt: &Spanned<Statement>) -> bool {
+    match &stmt.0 {
+        Statement::Break { .. } | Statement::Continue => true,
+        Statement::Block((statements, _)) => statements.iter().any(diverges),
+        Statement::Expr(expr)
+        | Statement::Let { value: expr, .. }
+        | Statement::Assign { value: expr, .. }
+        | Statement::SetIndex { value: expr, .. } => expr_diverges(expr),
+        _ => always_returns(stmt),
     }
+}
 
-    fn unify(&mut self, a: TypeId, b: TypeId) -> Result<(), Error> {
-        let var_a = self.vars[&a];
-        let var_b = self.vars[&b];
-
-        match (var_a.0, var_b.0) {
-            (TypeInfo::Ref(a), _) => self.unify(a, b),
-            (_, TypeInfo::Ref(b)) => self.unify(a, b),
-
-            (TypeInfo::Unknown, _) => {
-                self.vars.insert(a, (TypeInfo::Ref(b), var_b.1));
-                Ok(())
-            }
-            (_, TypeInfo::Unknown) => {
-                self.vars.insert(b, (TypeInfo::Ref(a), var_a.1));
-                Ok(())
-            }
-
-            (TypeInfo::Num, TypeInfo::Num) => Ok(()),
-
-            (TypeInfo::Bool, TypeInfo::Bool) => Ok(()),
+fn block_diverges(block: &Block) -> bool {
+    block.statements.iter().any(diverges) || block.tail.as_deref().is_some_and(expr_diverges)
+}
 
-            (a, b) => Err(TypecheckError::TypeMismatch {
-                span1: var_a.1,
-                span2: var_b.1,
-                ty1: a,
-                ty2: b,
-            }
-            .into()),
+fn expr_diverges(expr: &Spanned<Expr>) -> bool {
+    match &expr.0.expr {
+        ExprKind::If {
+            then,
+            else_: Some(else_),
+            ..
+        } => block_diverges(&then.0) && block_diverges(&else_.0),
+        ExprKind::Block(block) => block_diverges(block),
+        ExprKind::Catch { body, handler, .. } => {
+            block_diverges(&body.0) && block_diverges(&handler.0)
         }
+        ExprKind::Match { arms, .. } => arms.0.iter().all(|(_, body)| expr_diverges(body)),
+        // a break inside a loop or a labeled block only ends that
+        _ => expr_always_returns(expr),
     }
+}
 
-    fn reconstruct(&mut self, id: TypeId) -> Result<Spanned<Type>, Error> {
-        let var = self.vars[&id];
-
-        Ok((
-            match var.0 {
-                TypeInfo::Unknown => {
-                    return Err(TypecheckError::CannotInferType { span: var.1 }.into())
-                }
-                TypeInfo::Ref(id) => self.reconstruct(id)?.0,
-                TypeInfo::Num => Type::Num,
-                TypeInfo::Bool => Type::Bool,
-                TypeInfo::Unit => Type::Unit,
-            },
-            var.1,
-        ))
-    }
+/// Whether a statement does anything when it's reached, unlike declarations,
+/// which are made before anything runs
+fn runs(stmt: &Spanned<Statement>) -> bool {
+    !matches!(
+        stmt.0,
+        Statement::Function { .. }
+            | Statement::Enum { .. }
+            | Statement::Struct { .. }
+            | Statement::TypeAlias { .. }
+            | Statement::Import { .. }
+            | Statement::Trait { .. }
+            | Statement::Impl { .. }
+            | Statement::Const { .. }
+    )
 }
 
 type TypeId = usize;
 
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum TypeInfo {
     Unknown,
-    Ref(TypeId),
-    Num,
+    /// The type of something that failed to typecheck, which unifies with
+    /// anything without taking its type, so it doesn't cause more errors
+    Error,
+    Int,
+    Float,
     Bool,
+    Str,
+    Char,
     Unit,
+    Range,
+    Enum(String),
+    Struct(String),
+    Param(String),
+    Optional(TypeId),
+    List(TypeId),
+    Map(TypeId, TypeId),
+    /// A reference, mutable if the bool is set
+    Reference(bool, TypeId),
+    Result(TypeId, TypeId),
+    Record(Vec<(String, TypeId)>),
+    Func(Vec<TypeId>, TypeId),
 }
 
-fn type_to_typeinfo(ty: Spanned<Type>) -> Spanned<TypeInfo> {
-    (
-        match ty.0 {
-            Type::Num => TypeInfo::Num,
-            Type::Bool => TypeInfo::Bool,
-            Type::Unit => TypeInfo::Unit,
-        },
-        ty.1,
-    )
+impl TypeInfo {
+    /// The variables this type is made of
+    fn parts(&self) -> Vec<TypeId> {
+        match self {
+            TypeInfo::Optional(inner) | TypeInfo::List(inner) | TypeInfo::Reference(_, inner) => {
+                vec![*inner]
+            }
+            TypeInfo::Map(a, b) | TypeInfo::Result(a, b) => vec![*a, *b],
+            TypeInfo::Record(fields) => fields.iter().map(|(_, field)| *field).collect(),
+            TypeInfo::Func(params, return_ty) => {
+                params.iter().copied().chain([*return_ty]).collect()
+            }
+            TypeInfo::Unknown
+            | TypeInfo::Error
+            | TypeInfo::Int
+            | TypeInfo::Float
+            | TypeInfo::Bool
+            | TypeInfo::Str
+            | TypeInfo::Char
+            | TypeInfo::Unit
+            | TypeInfo::Range
+            | TypeInfo::Enum(_)
+            | TypeInfo::Struct(_)
+            | TypeInfo::Param(_) => vec![],
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -391,15 +5094,63 @@ impl<K: Eq + Hash, V> Scopes<K, V> {
         self.0.push(HashMap::new());
     }
 
-    pub fn pop_scope(&mut self) {
-        self.0.pop();
+    /// Removes the innermost scope, returning what was in it
+    pub fn pop_scope(&mut self) -> HashMap<K, V> {
+        self.0.pop().unwrap()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Removes every scope above the first `depth`, returning them so that
+    /// they can be put back with [`Scopes::extend`]
+    pub fn split_off(&mut self, depth: usize) -> Scopes<K, V> {
+        Scopes(self.0.split_off(depth))
+    }
+
+    pub fn extend(&mut self, scopes: Scopes<K, V>) {
+        self.0.extend(scopes.0);
     }
 
     pub fn insert(&mut self, k: K, v: V) {
         self.0.last_mut().unwrap().insert(k, v);
     }
 
-    pub fn get(&self, k: &K) -> Option<&V> {
+    /// Every value in every scope, including the ones that are shadowed
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.0.iter().flat_map(HashMap::values)
+    }
+
+    /// Puts `scope` in place of the scope with `depth` scopes below it,
+    /// returning the one that was there
+    pub fn replace_scope(&mut self, depth: usize, scope: HashMap<K, V>) -> HashMap<K, V> {
+        std::mem::replace(&mut self.0[depth], scope)
+    }
+
+    /// Looks `k` up only in the scope with `depth` scopes below it
+    pub fn get_in<Q>(&self, depth: usize, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0[depth].get(k)
+    }
+
+    /// How many scopes are below the one `k` is bound in
+    pub fn depth_of<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.iter().rposition(|scope| scope.contains_key(k))
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
         for scope in self.0.iter().rev() {
             if let Some(v) = scope.get(k) {
                 return Some(v);
@@ -409,7 +5160,11 @@ impl<K: Eq + Hash, V> Scopes<K, V> {
         None
     }
 
-    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
         for scope in self.0.iter_mut().rev() {
             if let Some(v) = scope.get_mut(k) {
                 return Some(v);
@@ -421,23 +5176,138 @@ impl<K: Eq + Hash, V> Scopes<K, V> {
 }
 
 impl Type {
+    /// Replaces the type parameters in `self` that are in `vars`
+    fn instantiate(&self, vars: &HashMap<String, Type>) -> Type {
+        match self {
+            Type::Param(name) => vars.get(name).cloned().unwrap_or_else(|| self.clone()),
+            Type::Optional(inner) => Type::Optional(Box::new(inner.instantiate(vars))),
+            Type::List(inner) => Type::List(Box::new(inner.instantiate(vars))),
+            Type::Ref(mutable, inner) => Type::Ref(*mutable, Box::new(inner.instantiate(vars))),
+            Type::Map(key, value) => Type::Map(
+                Box::new(key.instantiate(vars)),
+                Box::new(value.instantiate(vars)),
+            ),
+            Type::Result(ok, err) => Type::Result(
+                Box::new(ok.instantiate(vars)),
+                Box::new(err.instantiate(vars)),
+            ),
+            Type::Record(fields) => Type::Record(
+                fields
+                    .iter()
+                    .map(|(name, field)| (name.clone(), field.instantiate(vars)))
+                    .collect(),
+            ),
+            Type::Func(params, return_ty) => Type::Func(
+                params.iter().map(|param| param.instantiate(vars)).collect(),
+                Box::new(return_ty.instantiate(vars)),
+            ),
+            Type::Int
+            | Type::Float
+            | Type::Bool
+            | Type::Str
+            | Type::Char
+            | Type::Range
+            | Type::Unit
+            | Type::Enum(_)
+            | Type::Struct(_)
+            | Type::Var(_) => self.clone(),
+        }
+    }
+
     fn get_prefix_type(&self, op: Spanned<PrefixOp>) -> Result<Type, Error> {
         match self {
-            Type::Num => match op.0 {
-                PrefixOp::Negate => Ok(Type::Num),
-            },
-            Type::Bool => Err(TypecheckError::CannotApplyUnaryOperator {
+            Type::Int | Type::Float if op.0 == PrefixOp::Negate => Ok(self.clone()),
+            Type::Bool if op.0 == PrefixOp::Not => Ok(Type::Bool),
+            Type::Int if op.0 == PrefixOp::BitNot => Ok(Type::Int),
+            Type::Int
+            | Type::Float
+            | Type::Bool
+            | Type::Str
+            | Type::Char
+            | Type::Range
+            | Type::Unit
+            | Type::Enum(_)
+            | Type::Struct(_)
+            | Type::Param(_)
+            | Type::Optional(_)
+            | Type::List(_)
+            | Type::Map(_, _)
+            | Type::Result(_, _)
+            | Type::Record(_)
+            | Type::Func(_, _)
+            | Type::Ref(_, _)
+            | Type::Var(_) => Err(TypecheckError::CannotApplyUnaryOperator {
                 span: op.1,
                 op: op.0,
-                ty: *self,
+                ty: self.clone(),
             }
             .into()),
-            Type::Unit => Err(TypecheckError::CannotApplyUnaryOperator {
-                span: op.1,
-                op: op.0,
-                ty: *self,
+        }
+    }
+
+    /// Whether a value of this type can be used where `target` is expected,
+    /// allowing records with extra fields to stand in for records with fewer
+    fn is_assignable_to(&self, target: &Type) -> bool {
+        match (self, target) {
+            (Type::Record(fields), Type::Record(target_fields)) => {
+                target_fields.iter().all(|(name, target_field)| {
+                    fields
+                        .iter()
+                        .any(|(other, field)| other == name && field.is_assignable_to(target_field))
+                })
             }
-            .into()),
+            (Type::Optional(inner), Type::Optional(target_inner)) => {
+                inner.is_assignable_to(target_inner)
+            }
+            (ty, target) => ty == target,
+        }
+    }
+
+    /// The type that values of this type and of `other` can both be used as,
+    /// see [`Type::is_assignable_to`], which is the fields two records have
+    /// in common, or `None` if there is none
+    fn join(&self, other: &Type) -> Option<Type> {
+        match (self, other) {
+            (Type::Record(fields), Type::Record(other_fields)) => {
+                let common = fields
+                    .iter()
+                    .filter_map(|(name, field)| {
+                        let (_, other_field) =
+                            other_fields.iter().find(|(other, _)| other == name)?;
+
+                        Some((name.clone(), field.join(other_field)?))
+                    })
+                    .collect::<Vec<_>>();
+
+                (!common.is_empty()).then_some(Type::Record(common))
+            }
+            (Type::Optional(inner), Type::Optional(other_inner)) => {
+                Some(Type::Optional(Box::new(inner.join(other_inner)?)))
+            }
+            (ty, other) => (ty == other).then(|| ty.clone()),
+        }
+    }
+
+    /// Whether this type contains no unsolved type variables
+    fn is_known(&self) -> bool {
+        match self {
+            Type::Var(_) => false,
+            Type::Optional(inner) | Type::List(inner) | Type::Ref(_, inner) => inner.is_known(),
+            Type::Result(ok, err) | Type::Map(ok, err) => ok.is_known() && err.is_known(),
+            Type::Record(fields) => fields.iter().all(|(_, field)| field.is_known()),
+            Type::Func(params, return_ty) => {
+                params.iter().all(Type::is_known) && return_ty.is_known()
+            }
+            Type::Int
+            | Type::Float
+            | Type::Bool
+            | Type::Str
+            | Type::Char
+            | Type::Range
+            | Type::Unit
+            | Type::Enum(_)
+            | Type::Struct(_)
+            | Type::Param(_) => true,
         }
     }
 
@@ -445,34 +5315,62 @@ impl Type {
         let lhs = self;
 
         match (lhs, rhs) {
-            (Type::Num, Type::Num) => match op.0 {
-                BinOp::Add | BinOp::Subtract | BinOp::Multiply | BinOp::Divide => Ok(Type::Num),
+            (Type::Int, Type::Int) | (Type::Float, Type::Float) => match op.0 {
+                BinOp::Add
+                | BinOp::Subtract
+                | BinOp::Multiply
+                | BinOp::Divide
+                | BinOp::Modulo
+                | BinOp::Power => Ok(lhs.clone()),
                 BinOp::Equals
                 | BinOp::NotEquals
                 | BinOp::LessThan
                 | BinOp::LessThanOrEqual
                 | BinOp::GreaterThan
                 | BinOp::GreaterThanOrEqual => Ok(Type::Bool),
-                BinOp::LogicalAnd | BinOp::LogicalOr => {
-                    Err(TypecheckError::CannotApplyBinaryOperator {
-                        span: op.1,
-                        op: op.0,
-                        ty1: *lhs,
-                        ty2: *rhs,
-                    }
-                    .into())
+                BinOp::BitAnd
+                | BinOp::BitOr
+                | BinOp::BitXor
+                | BinOp::ShiftLeft
+                | BinOp::ShiftRight
+                    if *lhs == Type::Int =>
+                {
+                    Ok(Type::Int)
+                }
+                BinOp::BitAnd
+                | BinOp::BitOr
+                | BinOp::BitXor
+                | BinOp::ShiftLeft
+                | BinOp::ShiftRight
+                | BinOp::LogicalAnd
+                | BinOp::LogicalOr
+                | BinOp::Coalesce => Err(TypecheckError::CannotApplyBinaryOperator {
+                    span: op.1,
+                    op: op.0,
+                    ty1: lhs.clone(),
+                    ty2: rhs.clone(),
                 }
+                .into()),
             },
             (Type::Bool, Type::Bool) => match op.0 {
-                BinOp::Add | BinOp::Subtract | BinOp::Multiply | BinOp::Divide => {
-                    Err(TypecheckError::CannotApplyBinaryOperator {
-                        span: op.1,
-                        op: op.0,
-                        ty1: *lhs,
-                        ty2: *rhs,
-                    }
-                    .into())
+                BinOp::Add
+                | BinOp::Subtract
+                | BinOp::Multiply
+                | BinOp::Divide
+                | BinOp::Modulo
+                | BinOp::Power
+                | BinOp::BitAnd
+                | BinOp::BitOr
+                | BinOp::BitXor
+                | BinOp::ShiftLeft
+                | BinOp::ShiftRight
+                | BinOp::Coalesce => Err(TypecheckError::CannotApplyBinaryOperator {
+                    span: op.1,
+                    op: op.0,
+                    ty1: lhs.clone(),
+                    ty2: rhs.clone(),
                 }
+                .into()),
                 BinOp::Equals
                 | BinOp::NotEquals
                 | BinOp::LessThan
@@ -482,23 +5380,76 @@ impl Type {
                 | BinOp::LogicalAnd
                 | BinOp::LogicalOr => Ok(Type::Bool),
             },
+            (Type::Str, Type::Str) => match op.0 {
+                BinOp::Add => Ok(Type::Str),
+                BinOp::Equals | BinOp::NotEquals => Ok(Type::Bool),
+                BinOp::Subtract
+                | BinOp::Multiply
+                | BinOp::Divide
+                | BinOp::Modulo
+                | BinOp::Power
+                | BinOp::BitAnd
+                | BinOp::BitOr
+                | BinOp::BitXor
+                | BinOp::ShiftLeft
+                | BinOp::ShiftRight
+                | BinOp::LessThan
+                | BinOp::LessThanOrEqual
+                | BinOp::GreaterThan
+                | BinOp::GreaterThanOrEqual
+                | BinOp::LogicalAnd
+                | BinOp::LogicalOr
+                | BinOp::Coalesce => Err(TypecheckError::CannotApplyBinaryOperator {
+                    span: op.1,
+                    op: op.0,
+                    ty1: lhs.clone(),
+                    ty2: rhs.clone(),
+                }
+                .into()),
+            },
+            (Type::Char, Type::Char) => match op.0 {
+                BinOp::Equals
+                | BinOp::NotEquals
+                | BinOp::LessThan
+                | BinOp::LessThanOrEqual
+                | BinOp::GreaterThan
+                | BinOp::GreaterThanOrEqual => Ok(Type::Bool),
+                _ => Err(TypecheckError::CannotApplyBinaryOperator {
+                    span: op.1,
+                    op: op.0,
+                    ty1: lhs.clone(),
+                    ty2: rhs.clone(),
+                }
+                .into()),
+            },
+            (Type::Range, Type::Range) => match op.0 {
+                BinOp::Equals | BinOp::NotEquals => Ok(Type::Bool),
+                _ => Err(TypecheckError::CannotApplyBinaryOperator {
+                    span: op.1,
+                    op: op.0,
+                    ty1: lhs.clone(),
+                    ty2: rhs.clone(),
+                }
+                .into()),
+            },
+            // there's only one unit value, but comparing them keeps generic code simple
+            (Type::Unit, Type::Unit) => match op.0 {
+                BinOp::Equals | BinOp::NotEquals => Ok(Type::Bool),
+                _ => Err(TypecheckError::CannotApplyBinaryOperator {
+                    span: op.1,
+                    op: op.0,
+                    ty1: lhs.clone(),
+                    ty2: rhs.clone(),
+                }
+                .into()),
+            },
             (lhs, rhs) => Err(TypecheckError::CannotApplyBinaryOperator {
                 span: op.1,
                 op: op.0,
-                ty1: *lhs,
-                ty2: *rhs,
+                ty1: lhs.clone(),
+                ty2: rhs.clone(),
             }
             .into()),
         }
     }
 }
-
-impl Literal {
-    fn ty(&self) -> Type {
-        match self {
-            Literal::Num(_) => Type::Num,
-            Literal::Bool(_) => Type::Bool,
-            Literal::Unit => Type::Unit,
-        }
-    }
-}
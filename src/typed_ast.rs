@@ -1,8 +1,71 @@
+use crate::prelude::Builtin;
 use crate::Spanned;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct TypedAst<'src> {
     pub statements: s!(Vec<s!(Statement<'src>)>),
+    /// The types of the expressions and bindings, by where they are
+    pub types: TypeIndex,
+}
+
+impl TypedAst<'_> {
+    /// The type of the innermost expression or binding at `offset`, along
+    /// with its span, if there is one there
+    pub fn type_at(&self, offset: usize) -> Option<Spanned<Type>> {
+        self.types.at(offset)
+    }
+}
+
+/// Types by the spans they're at, which are sorted by where they start, and
+/// longest first when they start at the same place. The spans of a program's
+/// expressions nest, so each one also has the span before it that contains
+/// it, and the innermost span at an offset is found by going out from the
+/// last span that starts before it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TypeIndex {
+    types: Vec<Spanned<Type>>,
+    parents: Vec<Option<usize>>,
+}
+
+impl TypeIndex {
+    pub fn new(mut types: Vec<Spanned<Type>>) -> Self {
+        types.sort_by_key(|(_, span)| (span.start, std::cmp::Reverse(span.end)));
+
+        let mut parents = Vec::with_capacity(types.len());
+        let mut open: Vec<usize> = vec![];
+
+        for (i, (_, span)) in types.iter().enumerate() {
+            while let Some(&last) = open.last() {
+                if types[last].1.end >= span.end {
+                    break;
+                }
+
+                open.pop();
+            }
+
+            parents.push(open.last().copied());
+            open.push(i);
+        }
+
+        Self { types, parents }
+    }
+
+    fn at(&self, offset: usize) -> Option<Spanned<Type>> {
+        let mut i = self
+            .types
+            .partition_point(|(_, span)| span.start <= offset)
+            .checked_sub(1)?;
+
+        loop {
+            let (ty, span) = &self.types[i];
+
+            if offset < span.end {
+                return Some((ty.clone(), *span));
+            }
+
+            i = self.parents[i]?;
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -10,29 +73,184 @@ pub enum Statement<'src> {
     Expr(s!(Expr<'src>)),
     Block(s!(Vec<s!(Statement<'src>)>)),
     Let {
+        mutable: bool,
         name: s!(&'src str),
         ty: s!(Type),
         value: s!(Expr<'src>),
     },
+    /// Uses of a constant are replaced with its value by the typechecker
+    Const {
+        name: s!(&'src str),
+        ty: s!(Type),
+        value: s!(Literal),
+    },
     Function {
         name: s!(&'src str),
+        /// Type parameters, with the traits each one has to implement
+        generics: Vec<(s!(&'src str), Vec<s!(&'src str)>)>,
         params: s!(Vec<(s!(&'src str), s!(Type))>),
         return_ty: s!(Type),
+        /// Variables from the functions and blocks around this one that its body
+        /// uses, in the order they're first used, which are copied into it when
+        /// it's defined. Top level variables aren't captured, and neither is
+        /// the function itself.
+        captures: Vec<s!(&'src str)>,
         body: Box<s!(Statement<'src>)>,
     },
     Assign {
         name: s!(&'src str),
         value: s!(Expr<'src>),
     },
-    Print(s!(Expr<'src>)),
+    SetIndex {
+        name: s!(&'src str),
+        index: s!(Expr<'src>),
+        value: s!(Expr<'src>),
+    },
     Return(s!(Expr<'src>)),
+    Enum {
+        name: s!(&'src str),
+        variants: s!(Vec<(s!(&'src str), Vec<s!(Type)>)>),
+    },
+    Struct {
+        name: s!(&'src str),
+        fields: s!(Vec<(s!(&'src str), s!(Type))>),
+    },
+    TypeAlias {
+        name: s!(&'src str),
+        ty: s!(Type),
+    },
+    /// Breaks out of the innermost loop if there's no label
+    Break {
+        label: Option<s!(&'src str)>,
+        value: s!(Expr<'src>),
+    },
+    Continue,
+    While {
+        cond: s!(Expr<'src>),
+        body: s!(Vec<s!(Statement<'src>)>),
+    },
+    For {
+        first: s!(&'src str),
+        second: Option<s!(&'src str)>,
+        iter: s!(Expr<'src>),
+        body: s!(Vec<s!(Statement<'src>)>),
+    },
+    Import {
+        path: s!(String),
+        /// The name of a module imported with `import name;`
+        module: Option<s!(&'src str)>,
+        /// The index of the imported file in the source map
+        file: usize,
+        statements: Vec<s!(Statement<'src>)>,
+    },
+    /// Each method's type leaves out `self`
+    Trait {
+        name: s!(&'src str),
+        methods: Vec<(s!(&'src str), s!(Type))>,
+    },
+    /// The methods are functions that take `self` as their first parameter,
+    /// and `trait_` is `None` for the type's own methods
+    Impl {
+        trait_: Option<s!(&'src str)>,
+        ty: s!(Type),
+        methods: Vec<s!(Statement<'src>)>,
+    },
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Type {
-    Num,
+    Int,
+    Float,
     Bool,
+    Str,
+    Char,
     Unit,
+    /// A range of ints
+    Range,
+    Enum(String),
+    Struct(String),
+    /// A type parameter of a generic function, which stands for any type
+    /// inside the function
+    Param(String),
+    Optional(Box<Type>),
+    List(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    Result(Box<Type>, Box<Type>),
+    /// Fields are kept sorted by name
+    Record(Vec<(String, Type)>),
+    Func(Vec<Type>, Box<Type>),
+    /// `&T`, or `&mut T` if it's mutable
+    Ref(bool, Box<Type>),
+    /// An unsolved type variable, only present while typechecking
+    Var(usize),
+}
+
+impl Type {
+    /// The name values of this type go by when running, which their methods
+    /// are found by. Unlike how the type is shown, this keeps enums and
+    /// structs that were declared with the same name apart.
+    pub fn type_name(&self) -> String {
+        match self {
+            Type::Enum(name) | Type::Struct(name) => name.clone(),
+            ty => ty.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "str"),
+            Type::Char => write!(f, "char"),
+            Type::Unit => write!(f, "#"),
+            Type::Range => write!(f, "range"),
+            Type::Enum(name) | Type::Struct(name) => write!(f, "{}", source_name(name)),
+            Type::Param(name) => write!(f, "{}", name),
+            Type::Optional(inner) => write!(f, "{}?", inner),
+            Type::List(inner) => write!(f, "List<{}>", inner),
+            Type::Map(key, value) => write!(f, "Map<{}, {}>", key, value),
+            Type::Result(ok, err) => write!(f, "Result<{}, {}>", ok, err),
+            Type::Record(fields) => {
+                write!(f, "{{ ")?;
+
+                for (i, (name, ty)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}: {}", name, ty)?;
+                }
+
+                write!(f, " }}")
+            }
+            Type::Func(params, return_ty) => {
+                write!(f, "func(")?;
+
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}", param)?;
+                }
+
+                write!(f, "): {}", return_ty)
+            }
+            Type::Ref(true, inner) => write!(f, "&mut {}", inner),
+            Type::Ref(false, inner) => write!(f, "&{}", inner),
+            Type::Var(_) => write!(f, "_"),
+        }
+    }
+}
+
+/// The name a variable, enum or struct was written with. A `let` shadowing a
+/// binding in the same scope, and an enum or struct declared with the name of
+/// another, is given a name of its own by adding a `#` and a number.
+pub fn source_name(name: &str) -> &str {
+    name.split('#').next().unwrap()
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -44,6 +262,12 @@ pub struct Expr<'src> {
 #[derive(Clone, Debug, PartialEq)]
 pub enum ExprKind<'src> {
     Var(s!(&'src str)),
+    /// `module::name`, a top level binding of the module in `file`
+    ModuleVar {
+        module: s!(&'src str),
+        file: usize,
+        name: s!(&'src str),
+    },
     Literal(s!(Literal)),
     Prefix {
         op: s!(PrefixOp),
@@ -58,24 +282,188 @@ pub enum ExprKind<'src> {
         callee: Box<s!(Expr<'src>)>,
         args: s!(Vec<s!(Expr<'src>)>),
     },
+    /// `receiver.method(args)`, calling a method of `trait_`, or of the
+    /// receiver's type's own impl if it's `None`
+    MethodCall {
+        receiver: Box<s!(Expr<'src>)>,
+        trait_: Option<String>,
+        method: s!(&'src str),
+        args: s!(Vec<s!(Expr<'src>)>),
+    },
+    BuiltinCall {
+        builtin: s!(Builtin),
+        args: s!(Vec<s!(Expr<'src>)>),
+    },
+    Variant {
+        enum_: s!(&'src str),
+        variant: s!(&'src str),
+        args: s!(Vec<s!(Expr<'src>)>),
+    },
+    Match {
+        expr: Box<s!(Expr<'src>)>,
+        arms: s!(Vec<(s!(Pattern<'src>), s!(Expr<'src>))>),
+    },
+    Some(Box<s!(Expr<'src>)>),
+    Ok(Box<s!(Expr<'src>)>),
+    Err(Box<s!(Expr<'src>)>),
+    /// `expr?`, returning the error of a result from the enclosing function
+    Try(Box<s!(Expr<'src>)>),
+    Is {
+        expr: Box<s!(Expr<'src>)>,
+        variant: s!(&'src str),
+    },
+    /// A reference passed as an argument, which is always to a variable
+    /// when it's mutable
+    Ref {
+        mutable: bool,
+        expr: Box<s!(Expr<'src>)>,
+    },
+    /// `expr in collection`
+    In {
+        expr: Box<s!(Expr<'src>)>,
+        collection: Box<s!(Expr<'src>)>,
+    },
+    /// `start..end`, or `start..=end` which includes `end`
+    Range {
+        start: Box<s!(Expr<'src>)>,
+        end: Box<s!(Expr<'src>)>,
+        inclusive: bool,
+    },
+    /// `expr as ty`, converting between base types
+    Cast {
+        expr: Box<s!(Expr<'src>)>,
+        ty: s!(Type),
+    },
+    Record(Vec<(s!(&'src str), s!(Expr<'src>))>),
+    /// Fields are in the order they're declared in
+    Struct {
+        name: s!(&'src str),
+        fields: Vec<(s!(&'src str), s!(Expr<'src>))>,
+    },
+    List(s!(Vec<s!(Expr<'src>)>)),
+    Map(s!(Vec<(s!(Expr<'src>), s!(Expr<'src>))>)),
+    FieldAccess {
+        expr: Box<s!(Expr<'src>)>,
+        field: s!(&'src str),
+    },
+    Index {
+        expr: Box<s!(Expr<'src>)>,
+        index: Box<s!(Expr<'src>)>,
+    },
+    LabeledBlock {
+        label: s!(&'src str),
+        statements: s!(Vec<s!(Statement<'src>)>),
+        tail: Option<Box<s!(Expr<'src>)>>,
+    },
+    If {
+        cond: Box<s!(Expr<'src>)>,
+        then: s!(Block<'src>),
+        /// `else if` is an else block holding only the nested `if`
+        else_: Option<s!(Block<'src>)>,
+    },
+    Loop {
+        body: s!(Vec<s!(Statement<'src>)>),
+        /// Whether any break ends the loop, without one it can only be left by
+        /// returning
+        breaks: bool,
+    },
+    Block(Block<'src>),
+    /// `try { ... } catch e { ... }`, where `e` is the str message of the
+    /// runtime error
+    Catch {
+        body: s!(Block<'src>),
+        binding: s!(&'src str),
+        handler: s!(Block<'src>),
+    },
+    Closure {
+        params: s!(Vec<(s!(&'src str), s!(Type))>),
+        /// Variables from outside the closure that its body uses, in the order
+        /// they're first used, which are copied into the closure when it's created
+        captures: Vec<s!(&'src str)>,
+        body: Box<s!(Expr<'src>)>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block<'src> {
+    pub statements: Vec<s!(Statement<'src>)>,
+    pub tail: Option<Box<s!(Expr<'src>)>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern<'src> {
+    Binding(s!(&'src str)),
+    Variant {
+        variant: s!(&'src str),
+        bindings: s!(Vec<s!(&'src str)>),
+    },
+    /// `some(x)`, matching an optional that holds a value
+    Some(s!(&'src str)),
+    /// `none`, matching an empty optional
+    None,
+    /// `ok(x)`, matching a successful result
+    Ok(s!(&'src str)),
+    /// `err(e)`, matching a failed result
+    Err(s!(&'src str)),
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Literal {
-    Num(f64),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Char(char),
     Bool(bool),
     Unit,
+    None,
+}
+
+/// How a placeholder of a format string shows its argument
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FormatSpec {
+    Debug,
+    Hex,
+    Binary,
+    Octal,
+    Precision(usize),
+}
+
+impl FormatSpec {
+    /// The type an argument has to have to be shown this way, if it's limited to one
+    pub fn arg_type(self) -> Option<Type> {
+        match self {
+            FormatSpec::Debug => None,
+            FormatSpec::Hex | FormatSpec::Binary | FormatSpec::Octal => Some(Type::Int),
+            FormatSpec::Precision(_) => Some(Type::Float),
+        }
+    }
+}
+
+impl std::fmt::Display for FormatSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatSpec::Debug => write!(f, "{{:?}}"),
+            FormatSpec::Hex => write!(f, "{{:x}}"),
+            FormatSpec::Binary => write!(f, "{{:b}}"),
+            FormatSpec::Octal => write!(f, "{{:o}}"),
+            FormatSpec::Precision(digits) => write!(f, "{{:.{}}}", digits),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PrefixOp {
     Negate,
+    Not,
+    BitNot,
 }
 
 impl std::fmt::Display for PrefixOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             PrefixOp::Negate => write!(f, "-"),
+            PrefixOp::Not => write!(f, "!"),
+            PrefixOp::BitNot => write!(f, "~"),
         }
     }
 }
@@ -86,6 +474,13 @@ pub enum BinOp {
     Subtract,
     Multiply,
     Divide,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
     Equals,
     NotEquals,
     LessThan,
@@ -94,6 +489,40 @@ pub enum BinOp {
     GreaterThanOrEqual,
     LogicalAnd,
     LogicalOr,
+    Coalesce,
+}
+
+impl BinOp {
+    /// The built-in trait that lets other types be used with this operator,
+    /// and the name of its method
+    pub fn trait_(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            BinOp::Add => Some(("Add", "add")),
+            BinOp::Subtract => Some(("Sub", "sub")),
+            BinOp::Multiply => Some(("Mul", "mul")),
+            BinOp::Divide => Some(("Div", "div")),
+            _ => None,
+        }
+    }
+
+    /// Whether the operator works on floats as well as ints
+    pub fn takes_floats(self) -> bool {
+        matches!(
+            self,
+            BinOp::Add
+                | BinOp::Subtract
+                | BinOp::Multiply
+                | BinOp::Divide
+                | BinOp::Modulo
+                | BinOp::Power
+                | BinOp::Equals
+                | BinOp::NotEquals
+                | BinOp::LessThan
+                | BinOp::LessThanOrEqual
+                | BinOp::GreaterThan
+                | BinOp::GreaterThanOrEqual
+        )
+    }
 }
 
 impl std::fmt::Display for BinOp {
@@ -103,6 +532,13 @@ impl std::fmt::Display for BinOp {
             BinOp::Subtract => write!(f, "-"),
             BinOp::Multiply => write!(f, "*"),
             BinOp::Divide => write!(f, "/"),
+            BinOp::Modulo => write!(f, "%"),
+            BinOp::Power => write!(f, "**"),
+            BinOp::BitAnd => write!(f, "&"),
+            BinOp::BitOr => write!(f, "|"),
+            BinOp::BitXor => write!(f, "^"),
+            BinOp::ShiftLeft => write!(f, "<<"),
+            BinOp::ShiftRight => write!(f, ">>"),
             BinOp::Equals => write!(f, "=="),
             BinOp::NotEquals => write!(f, "!="),
             BinOp::LessThan => write!(f, "<"),
@@ -111,6 +547,7 @@ impl std::fmt::Display for BinOp {
             BinOp::GreaterThanOrEqual => write!(f, ">="),
             BinOp::LogicalAnd => write!(f, "&&"),
             BinOp::LogicalOr => write!(f, "||"),
+            BinOp::Coalesce => write!(f, "??"),
         }
     }
 }
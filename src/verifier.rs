@@ -0,0 +1,331 @@
+use crate::bytecode::{Function, Op, Program, Unpack};
+use crate::interpreter::Value;
+
+/// Checks that bytecode that wasn't compiled in this run, like a `.fxc`
+/// file, can be run by the VM: every index into the program's tables and a
+/// function's locals is in range, every jump lands inside its function, the
+/// stack never has fewer values than an instruction takes, every way of
+/// reaching an instruction leaves the stack equally high, and no function
+/// runs past its end.
+///
+/// Whether values have the types the instructions expect can't be known
+/// without the types the typechecker found, which aren't in the file, so the
+/// VM checks that as it runs them instead, stopping with a runtime error on
+/// a value an instruction can't be used with.
+pub fn verify(program: &Program) -> Result<(), String> {
+    let functions = program.functions.len();
+
+    if program.main >= functions {
+        return Err(format!("the main function {} doesn't exist", program.main));
+    }
+
+    for constant in &program.constants {
+        check_constant(constant, functions)?;
+    }
+
+    for function in program.methods.values() {
+        if *function >= functions {
+            return Err(format!("the method function {} doesn't exist", function));
+        }
+    }
+
+    let captures = captures(program)?;
+
+    for (id, function) in program.functions.iter().enumerate() {
+        check_function(program, function, captures[id])
+            .map_err(|e| format!("in function {} '{}', {}", id, function.name, e))?;
+    }
+
+    Ok(())
+}
+
+/// The only values that refer to something else are functions, which have
+/// to be the program's own
+fn check_constant(value: &Value, functions: usize) -> Result<(), String> {
+    match value {
+        Value::Func { id, .. } if id.index() >= functions => Err(format!(
+            "a constant refers to function {}, which doesn't exist",
+            id.index()
+        )),
+        Value::Variant { fields, .. } | Value::List(fields) => fields
+            .iter()
+            .try_for_each(|field| check_constant(field, functions)),
+        Value::Optional(Some(value)) | Value::Result(Ok(value) | Err(value)) => {
+            check_constant(value, functions)
+        }
+        Value::Record(fields) | Value::Struct { fields, .. } => fields
+            .iter()
+            .try_for_each(|(_, field)| check_constant(field, functions)),
+        Value::Map(entries) => entries.iter().try_for_each(|(key, value)| {
+            check_constant(key, functions)?;
+            check_constant(value, functions)
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// How many captures each function can load, which is how many it's created
+/// with, plus itself for a nested function. A function that's also used
+/// without being created, like a top level function or a method, has none.
+fn captures(program: &Program) -> Result<Vec<usize>, String> {
+    let mut captures = vec![None; program.functions.len()];
+
+    let mut set = |function: usize, count: usize| match captures[function] {
+        Some(other) if other != count => Err(format!(
+            "function {} '{}' is used with both {} and {} captures",
+            function, program.functions[function].name, other, count
+        )),
+        _ => {
+            captures[function] = Some(count);
+            Ok(())
+        }
+    };
+
+    set(program.main, 0)?;
+
+    for function in program.methods.values() {
+        set(*function, 0)?;
+    }
+
+    for constant in &program.constants {
+        if let Value::Func { id, .. } = constant {
+            set(id.index(), 0)?;
+        }
+    }
+
+    for function in &program.functions {
+        for op in &function.code {
+            match *op {
+                Op::MakeFunction { function, captures } if function < program.functions.len() => {
+                    set(function, captures + 1)?
+                }
+                Op::MakeClosure { function, captures } if function < program.functions.len() => {
+                    set(function, captures)?
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(captures
+        .into_iter()
+        .map(|count| count.unwrap_or(0))
+        .collect())
+}
+
+fn check_function(program: &Program, function: &Function, captures: usize) -> Result<(), String> {
+    let len = function.code.len();
+
+    if function.params > function.locals {
+        return Err(format!(
+            "there are {} params but only {} locals",
+            function.params, function.locals
+        ));
+    }
+
+    // every local other than a param is set by an instruction, which keeps
+    // a damaged count from making the VM allocate a huge stack
+    if function.locals > function.params + len {
+        return Err(format!(
+            "there are {} locals but only {} instructions to set them",
+            function.locals, len
+        ));
+    }
+
+    for (at, op) in function.code.iter().enumerate() {
+        check_indices(program, function, captures, *op).map_err(|e| format!("at {}, {}", at, e))?;
+    }
+
+    // the stack height before each instruction, found from the start by
+    // following every way it can go
+    let mut heights: Vec<Option<usize>> = vec![None; len];
+    let mut work = vec![(0, 0)];
+
+    while let Some((at, height)) = work.pop() {
+        if at == len {
+            return Err("the code can run past the end of the function".to_string());
+        }
+
+        match heights[at] {
+            Some(other) if other != height => {
+                return Err(format!(
+                    "{} is reached with {} and with {} values on the stack",
+                    at, other, height
+                ))
+            }
+            Some(_) => continue,
+            None => heights[at] = Some(height),
+        }
+
+        let op = function.code[at];
+        let error = |message: &str| Err(format!("at {}, {:?} {}", at, op, message));
+
+        if height < takes(program, op) {
+            return error(&format!("takes more values than the {} there are", height));
+        }
+
+        let Some(next) = height.checked_add_signed(program.stack_effect(op)) else {
+            return error(&format!("takes more values than the {} there are", height));
+        };
+
+        match op {
+            Op::Return => {}
+            Op::Jump(to) => work.push((to, height)),
+            Op::JumpIfFalse(to) | Op::JumpIfTrue(to) | Op::Unpack(_, to) => {
+                work.push((to, height - 1));
+                work.push((at + 1, next));
+            }
+            // the unwrapped value is kept when it jumps
+            Op::Coalesce(to) => {
+                work.push((to, height));
+                work.push((at + 1, next));
+            }
+            Op::Next { done, .. } => {
+                work.push((done, height));
+                work.push((at + 1, next));
+            }
+            // the handler gets the error's message on top
+            Op::Catch(to) => {
+                work.push((to, height + 1));
+                work.push((at + 1, next));
+            }
+            Op::Truncate { height: to, keep } => {
+                if height < to + keep as usize {
+                    return error(&format!("keeps more values than the {} there are", height));
+                }
+
+                work.push((at + 1, to + keep as usize));
+            }
+            _ => work.push((at + 1, next)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks everything an instruction refers to by index, including where it
+/// jumps
+fn check_indices(
+    program: &Program,
+    function: &Function,
+    captures: usize,
+    op: Op,
+) -> Result<(), String> {
+    let check = |index: usize, len: usize, what: &str| {
+        if index < len {
+            Ok(())
+        } else {
+            Err(format!(
+                "{:?} refers to {} {}, but there are only {}",
+                op, what, index, len
+            ))
+        }
+    };
+
+    let locals = function.locals;
+    let names = program.names.len();
+    let globals = program.globals.len();
+
+    if let Some(to) = op.target() {
+        check(to, function.code.len(), "instruction")?;
+    }
+
+    match op {
+        Op::Const(constant) | Op::BinaryConst(_, constant) => {
+            check(constant, program.constants.len(), "constant")
+        }
+        Op::LoadLocal(slot)
+        | Op::InitLocal(slot)
+        | Op::StoreLocal(slot)
+        | Op::RefLocal(slot)
+        | Op::BinaryLocal(_, slot)
+        | Op::SetIndexLocal(slot) => check(slot, locals, "local"),
+        Op::LoadGlobal(slot)
+        | Op::InitGlobal(slot)
+        | Op::StoreGlobal(slot)
+        | Op::RefGlobal(slot)
+        | Op::SetIndexGlobal(slot) => check(slot, globals, "global"),
+        Op::LoadCapture(i) => check(i, captures, "capture"),
+        Op::CallMethod { trait_, method, .. } => {
+            if let Some(trait_) = trait_ {
+                check(trait_, names, "name")?;
+            }
+
+            check(method, names, "name")
+        }
+        Op::MakeFunction { function, .. } | Op::MakeClosure { function, .. } => {
+            check(function, program.functions.len(), "function")
+        }
+        Op::MakeRecord { shape } => check(shape, program.shapes.len(), "shape"),
+        Op::MakeStruct { name, shape } => {
+            check(name, names, "name")?;
+            check(shape, program.shapes.len(), "shape")
+        }
+        Op::MakeVariant { enum_, variant, .. } => {
+            check(enum_, names, "name")?;
+            check(variant, names, "name")
+        }
+        Op::GetField(name)
+        | Op::Is(name)
+        | Op::Unpack(Unpack::Variant { variant: name, .. }, _) => check(name, names, "name"),
+        Op::Cast(ty) => check(ty, program.types.len(), "type"),
+        Op::Next {
+            collection,
+            counter,
+            ..
+        } => {
+            check(collection, locals, "local")?;
+            check(counter, locals, "local")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// How many values an instruction needs on the stack, which is how many it
+/// pops, or 1 for `Dup`
+fn takes(program: &Program, op: Op) -> usize {
+    match op {
+        Op::Const(_)
+        | Op::LoadLocal(_)
+        | Op::LoadGlobal(_)
+        | Op::LoadCapture(_)
+        | Op::RefLocal(_)
+        | Op::RefGlobal(_)
+        | Op::Jump(_)
+        | Op::Next { .. }
+        | Op::Truncate { .. }
+        | Op::Catch(_)
+        | Op::EndCatch => 0,
+        Op::Dup
+        | Op::Pop
+        | Op::InitLocal(_)
+        | Op::StoreLocal(_)
+        | Op::InitGlobal(_)
+        | Op::StoreGlobal(_)
+        | Op::JumpIfFalse(_)
+        | Op::JumpIfTrue(_)
+        | Op::Coalesce(_)
+        | Op::Return
+        | Op::Prefix(_)
+        | Op::BinaryConst(..)
+        | Op::BinaryLocal(..)
+        | Op::WrapSome
+        | Op::WrapOk
+        | Op::WrapErr
+        | Op::GetField(_)
+        | Op::Is(_)
+        | Op::Cast(_)
+        | Op::Try
+        | Op::Unpack(..) => 1,
+        Op::Binary(_) | Op::MakeRange { .. } | Op::Index | Op::In => 2,
+        Op::SetIndexLocal(_) | Op::SetIndexGlobal(_) => 2,
+        // the function or the receiver is below the arguments
+        Op::Call { args } | Op::CallMethod { args, .. } => args + 1,
+        Op::CallBuiltin { args, .. } => args,
+        Op::MakeFunction { captures, .. } | Op::MakeClosure { captures, .. } => captures,
+        Op::MakeList(elements) => elements,
+        Op::MakeMap(entries) => 2 * entries,
+        Op::MakeRecord { shape } | Op::MakeStruct { shape, .. } => program.shapes[shape].len(),
+        Op::MakeVariant { fields, .. } => fields,
+    }
+}
@@ -0,0 +1,699 @@
+use crate::bytecode::{Bindings, Op, Program, Unpack};
+use crate::error::{Error, Resource};
+use crate::gc::{Handle, Heap, Instance};
+use crate::interpreter::{
+    binary, call_builtin, cast, contains, get_index, prefix, range, set_index, wrong_type, Limits,
+    Value,
+};
+use crate::typed_ast::source_name;
+use crate::Spanned;
+
+/// Runs a compiled program, stopping at the first runtime error that isn't
+/// caught or once it reaches one of its limits
+pub fn run(program: &Program, limits: Limits) -> Result<(), Error> {
+    let mut vm = Vm {
+        program,
+        stack: Vec::new(),
+        frames: Vec::new(),
+        globals: vec![None; program.globals.len()],
+        heap: Heap::new(program.functions.len(), limits.heap),
+        handlers: Vec::new(),
+        ticks: 0,
+        limits,
+        steps: 0,
+    };
+
+    let mut frame = Frame {
+        function: program.main,
+        instance: Handle::permanent(program.main),
+        ip: 0,
+        base: 0,
+        ret: 0,
+    };
+
+    vm.stack
+        .resize(program.functions[program.main].locals, Value::Unit);
+
+    // an error inside a `try` body goes on at its handler
+    let result = loop {
+        match vm.run(&mut frame) {
+            Err((Stop::Error(message), _)) if !vm.handlers.is_empty() => {
+                vm.catch(&mut frame, message)
+            }
+            result => break result,
+        }
+    };
+
+    // anything still in use that the program can't reach anymore is only
+    // kept by a cycle, which reference counting can't free
+    #[cfg(all(feature = "rc", debug_assertions))]
+    if result.is_ok() {
+        let cycles = vm
+            .heap
+            .cycles(vm.stack.iter().chain(vm.globals.iter().flatten()));
+
+        if cycles > 0 {
+            eprintln!(
+                "warning: {} closures and borrowed variables were leaked by reference cycles",
+                cycles
+            );
+        }
+    }
+
+    result.map_err(|(stop, span)| {
+        // each caller's next instruction is the one after its call
+        let callees = std::iter::once(&frame).chain(vm.frames.iter().rev());
+        let trace = callees
+            .zip(vm.frames.iter().rev())
+            .map(|(callee, caller)| {
+                (
+                    program.functions[callee.function].name.clone(),
+                    program.functions[caller.function].spans[caller.ip - 1],
+                )
+            })
+            .collect();
+
+        match stop {
+            Stop::Error(message) => Error::Runtime {
+                message,
+                span,
+                trace,
+            },
+            Stop::Exhausted(resource) => Error::Exhausted {
+                resource,
+                span,
+                trace,
+            },
+        }
+    })
+}
+
+/// Why the VM stopped before the program ended
+enum Stop {
+    /// A runtime error, which a `try` can catch
+    Error(String),
+    /// A limit being reached, which can't be caught
+    Exhausted(Resource),
+}
+
+struct Vm<'a> {
+    program: &'a Program,
+    stack: Vec<Value>,
+    /// The functions that called the running one, innermost last
+    frames: Vec<Frame>,
+    /// Top level variables, which are `None` until they're initialized
+    globals: Vec<Option<Value>>,
+    /// The functions and closures that exist, indexed by `Value::Func` and
+    /// `Value::Closure`, and the values of variables that have been borrowed
+    /// mutably, indexed by `Value::Ref`, which is left in the variable and the
+    /// parameter. The first instances are the program's functions without any
+    /// captures, in the same order.
+    heap: Heap,
+    /// Where runtime errors go, for the `try` bodies that are running,
+    /// innermost last
+    handlers: Vec<Handler>,
+    ticks: u64,
+    limits: Limits,
+    /// How many instructions have been run
+    steps: u64,
+}
+
+struct Handler {
+    /// The number of frames below the one running the `try` body
+    frames: usize,
+    /// The stack height to go back to
+    height: usize,
+    /// Where the handler starts
+    to: usize,
+}
+
+struct Frame {
+    function: usize,
+    /// Keeps the function or closure from being freed while it runs
+    instance: Handle,
+    /// The next instruction to run
+    ip: usize,
+    /// Where the function's locals start on the stack
+    base: usize,
+    /// The stack height to go back to when it returns, before pushing the result
+    ret: usize,
+}
+
+impl Vm<'_> {
+    /// Runs from `frame` until the program ends, leaving the frame that was
+    /// running in it if there's an error
+    fn run(&mut self, frame: &mut Frame) -> Result<(), Spanned<Stop>> {
+        let program = self.program;
+
+        loop {
+            let function = &program.functions[frame.function];
+            let op = function.code[frame.ip];
+
+            // errors point at the instruction that failed
+            let span = function.spans[frame.ip];
+            let error = |message: String| (Stop::Error(message), span);
+            let exhausted = |resource: Resource| (Stop::Exhausted(resource), span);
+            let uninitialized = |slot: usize| {
+                error(format!(
+                    "'{}' is used before it's initialized",
+                    source_name(&program.globals[slot])
+                ))
+            };
+
+            self.steps += 1;
+
+            if let Some(fuel) = self.limits.fuel.filter(|&fuel| self.steps > fuel) {
+                return Err(exhausted(Resource::Fuel(fuel)));
+            }
+
+            frame.ip += 1;
+
+            match op {
+                Op::Const(constant) => self.stack.push(program.constants[constant].clone()),
+                Op::Pop => {
+                    self.stack.pop();
+                }
+                Op::Dup => self.stack.push(self.stack.last().unwrap().clone()),
+                Op::LoadLocal(slot) => {
+                    let value = self.deref(&self.stack[frame.base + slot]);
+
+                    self.stack.push(value);
+                }
+                Op::InitLocal(slot) => {
+                    let value = self.stack.pop().unwrap();
+
+                    self.stack[frame.base + slot] = value;
+                }
+                Op::StoreLocal(slot) => {
+                    let value = self.stack.pop().unwrap();
+
+                    match self.stack[frame.base + slot] {
+                        Value::Ref(ref id) => self.heap.cells[id.index()] = value,
+                        ref mut local => *local = value,
+                    }
+                }
+                Op::LoadGlobal(slot) => match &self.globals[slot] {
+                    Some(value) => self.stack.push(self.deref(value)),
+                    None => return Err(uninitialized(slot)),
+                },
+                Op::InitGlobal(slot) => self.globals[slot] = self.stack.pop(),
+                Op::StoreGlobal(slot) => {
+                    let value = self.stack.pop().unwrap();
+
+                    match self.globals[slot] {
+                        Some(Value::Ref(ref id)) => self.heap.cells[id.index()] = value,
+                        ref mut global => *global = Some(value),
+                    }
+                }
+                Op::LoadCapture(i) => {
+                    let captures = &self.heap.instances[frame.instance.index()].captures;
+
+                    // a nested function gets itself after its captures, which
+                    // isn't kept with them so it doesn't hold on to itself
+                    let value = match captures.get(i) {
+                        Some(capture) => capture.clone(),
+                        None => Value::Func {
+                            name: function.name.clone(),
+                            id: frame.instance.clone(),
+                        },
+                    };
+
+                    self.stack.push(value);
+                }
+                Op::RefLocal(slot) => {
+                    self.reserve(frame).map_err(exhausted)?;
+
+                    let id = self.borrow(frame.base + slot);
+
+                    self.stack.push(Value::Ref(id));
+                }
+                Op::RefGlobal(slot) => {
+                    self.reserve(frame).map_err(exhausted)?;
+
+                    let global = self.globals[slot]
+                        .take()
+                        .ok_or_else(|| uninitialized(slot))?;
+
+                    // a variable that is already borrowed, like a parameter
+                    // taking a mutable reference, shares its cell
+                    let id = match global {
+                        Value::Ref(id) => id,
+                        value => self.heap.cell(value),
+                    };
+
+                    self.globals[slot] = Some(Value::Ref(id.clone()));
+                    self.stack.push(Value::Ref(id));
+                }
+                Op::Prefix(op) => {
+                    let value = self.stack.pop().unwrap();
+
+                    self.stack.push(prefix(op, value).map_err(error)?);
+                }
+                Op::Binary(op) => {
+                    let rhs = self.stack.pop().unwrap();
+                    let lhs = self.stack.pop().unwrap();
+
+                    self.stack.push(binary(op, lhs, rhs).map_err(error)?);
+                }
+                Op::BinaryConst(op, constant) => {
+                    let rhs = program.constants[constant].clone();
+                    let lhs = self.stack.pop().unwrap();
+
+                    self.stack.push(binary(op, lhs, rhs).map_err(error)?);
+                }
+                Op::BinaryLocal(op, slot) => {
+                    let rhs = self.deref(&self.stack[frame.base + slot]);
+                    let lhs = self.stack.pop().unwrap();
+
+                    self.stack.push(binary(op, lhs, rhs).map_err(error)?);
+                }
+                Op::Jump(to) => frame.ip = to,
+                Op::JumpIfFalse(to) => {
+                    if let Some(Value::Bool(false)) = self.stack.pop() {
+                        frame.ip = to;
+                    }
+                }
+                Op::JumpIfTrue(to) => {
+                    if let Some(Value::Bool(true)) = self.stack.pop() {
+                        frame.ip = to;
+                    }
+                }
+                Op::Coalesce(to) => {
+                    if let Some(Value::Optional(Some(value))) = self.stack.pop() {
+                        self.stack.push(*value);
+
+                        frame.ip = to;
+                    }
+                }
+                Op::Call { args } => {
+                    let callee = self.stack.len() - args - 1;
+
+                    let (Value::Func { id, .. } | Value::Closure { id }) = &self.stack[callee]
+                    else {
+                        return Err(error(wrong_type()));
+                    };
+
+                    self.call(frame, id.clone(), callee + 1, callee)
+                        .map_err(exhausted)?;
+                }
+                Op::CallMethod {
+                    trait_,
+                    method,
+                    args,
+                } => {
+                    let receiver = self.stack.len() - args - 1;
+
+                    // found from the value, since a type parameter can stand for any type
+                    let key = (
+                        trait_.map(|trait_| program.names[trait_].clone()),
+                        program.names[method].clone(),
+                        self.stack[receiver].type_name(),
+                    );
+
+                    let instance = Handle::permanent(
+                        *program
+                            .methods
+                            .get(&key)
+                            .ok_or_else(|| error(wrong_type()))?,
+                    );
+
+                    self.call(frame, instance, receiver, receiver)
+                        .map_err(exhausted)?;
+                }
+                Op::CallBuiltin { builtin, args } => {
+                    let args = self.stack.split_off(self.stack.len() - args);
+
+                    self.stack
+                        .push(call_builtin(builtin, args, &mut self.ticks).map_err(error)?);
+                }
+                Op::Return => {
+                    let value = self.stack.pop().unwrap();
+
+                    self.stack.truncate(frame.ret);
+                    self.stack.push(value);
+                    self.end_catches();
+
+                    match self.frames.pop() {
+                        Some(caller) => *frame = caller,
+                        None => return Ok(()),
+                    }
+                }
+                Op::MakeFunction { function, captures } => {
+                    // before the captures are popped, so they're kept
+                    self.reserve(frame).map_err(exhausted)?;
+
+                    let captures = self.stack.split_off(self.stack.len() - captures);
+                    let id = self.heap.instance(Instance { function, captures });
+
+                    self.stack.push(Value::Func {
+                        name: program.functions[function].name.clone(),
+                        id,
+                    });
+                }
+                Op::MakeClosure { function, captures } => {
+                    self.reserve(frame).map_err(exhausted)?;
+
+                    let captures = self.stack.split_off(self.stack.len() - captures);
+                    let id = self.heap.instance(Instance { function, captures });
+
+                    self.stack.push(Value::Closure { id });
+                }
+                Op::MakeList(elements) => {
+                    let elements = self.stack.split_off(self.stack.len() - elements);
+
+                    self.stack.push(Value::List(elements));
+                }
+                Op::MakeMap(entries) => {
+                    let values = self.stack.split_off(self.stack.len() - 2 * entries);
+                    let mut map: Vec<(Value, Value)> = Vec::with_capacity(entries);
+
+                    // a key written twice keeps its first position and its last value
+                    for entry in values.chunks(2) {
+                        let (key, value) = (entry[0].clone(), entry[1].clone());
+
+                        match map.iter_mut().find(|(other, _)| *other == key) {
+                            Some((_, existing)) => *existing = value,
+                            None => map.push((key, value)),
+                        }
+                    }
+
+                    self.stack.push(Value::Map(map));
+                }
+                Op::MakeRecord { shape } => {
+                    let fields = self.fields(shape);
+
+                    self.stack.push(Value::Record(fields));
+                }
+                Op::MakeStruct { name, shape } => {
+                    let fields = self.fields(shape);
+
+                    self.stack.push(Value::Struct {
+                        name: program.names[name].clone(),
+                        fields,
+                    });
+                }
+                Op::MakeVariant {
+                    enum_,
+                    variant,
+                    fields,
+                } => {
+                    let fields = self.stack.split_off(self.stack.len() - fields);
+
+                    self.stack.push(Value::Variant {
+                        enum_: program.names[enum_].clone(),
+                        variant: program.names[variant].clone(),
+                        fields,
+                    });
+                }
+                Op::MakeRange { inclusive } => {
+                    let end = self.stack.pop().unwrap();
+                    let start = self.stack.pop().unwrap();
+
+                    self.stack
+                        .push(range(start, end, inclusive).map_err(error)?);
+                }
+                Op::WrapSome => {
+                    let value = self.stack.pop().unwrap();
+
+                    self.stack.push(Value::Optional(Some(Box::new(value))));
+                }
+                Op::WrapOk => {
+                    let value = self.stack.pop().unwrap();
+
+                    self.stack.push(Value::Result(Ok(Box::new(value))));
+                }
+                Op::WrapErr => {
+                    let value = self.stack.pop().unwrap();
+
+                    self.stack.push(Value::Result(Err(Box::new(value))));
+                }
+                Op::GetField(field) => {
+                    let value = match self.stack.pop() {
+                        Some(Value::Record(fields) | Value::Struct { fields, .. }) => fields
+                            .into_iter()
+                            .find(|(name, _)| *name == program.names[field]),
+                        _ => None,
+                    };
+
+                    let (_, value) = value.ok_or_else(|| error(wrong_type()))?;
+
+                    self.stack.push(value);
+                }
+                Op::Index => {
+                    let index = self.stack.pop().unwrap();
+                    let collection = self.stack.pop().unwrap();
+
+                    self.stack
+                        .push(get_index(collection, index).map_err(error)?);
+                }
+                Op::SetIndexLocal(slot) => {
+                    let value = self.stack.pop().unwrap();
+                    let index = self.stack.pop().unwrap();
+
+                    let target = match self.stack[frame.base + slot] {
+                        Value::Ref(ref id) => &mut self.heap.cells[id.index()],
+                        ref mut local => local,
+                    };
+
+                    set_index(target, index, value).map_err(error)?;
+                }
+                Op::SetIndexGlobal(slot) => {
+                    let value = self.stack.pop().unwrap();
+                    let index = self.stack.pop().unwrap();
+
+                    let global = self.globals[slot].as_mut();
+                    let target = match global.ok_or_else(|| uninitialized(slot))? {
+                        Value::Ref(id) => &mut self.heap.cells[id.index()],
+                        global => global,
+                    };
+
+                    set_index(target, index, value).map_err(error)?;
+                }
+                Op::In => {
+                    let collection = self.stack.pop().unwrap();
+                    let value = self.stack.pop().unwrap();
+
+                    self.stack
+                        .push(Value::Bool(contains(&collection, &value).map_err(error)?));
+                }
+                Op::Is(variant) => match self.stack.pop() {
+                    Some(Value::Variant {
+                        variant: value_variant,
+                        ..
+                    }) => self
+                        .stack
+                        .push(Value::Bool(value_variant == program.names[variant])),
+                    _ => return Err(error(wrong_type())),
+                },
+                Op::Cast(ty) => {
+                    let value = self.stack.pop().unwrap();
+
+                    self.stack
+                        .push(cast(value, &program.types[ty]).map_err(error)?);
+                }
+                Op::Try => match self.stack.pop() {
+                    Some(Value::Result(Ok(value))) => self.stack.push(*value),
+                    Some(error @ Value::Result(Err(_))) => {
+                        self.stack.truncate(frame.ret);
+                        self.stack.push(error);
+                        self.end_catches();
+
+                        match self.frames.pop() {
+                            Some(caller) => *frame = caller,
+                            None => return Ok(()),
+                        }
+                    }
+                    _ => return Err(error(wrong_type())),
+                },
+                Op::Unpack(unpack, to) => {
+                    let value = self.stack.pop().unwrap();
+
+                    match (unpack, value) {
+                        (
+                            Unpack::Variant { variant, .. },
+                            Value::Variant {
+                                variant: value_variant,
+                                fields,
+                                ..
+                            },
+                        ) if value_variant == program.names[variant] => {
+                            self.stack.extend(fields);
+                        }
+                        (Unpack::Some, Value::Optional(Some(value)))
+                        | (Unpack::Ok, Value::Result(Ok(value)))
+                        | (Unpack::Err, Value::Result(Err(value))) => self.stack.push(*value),
+                        (Unpack::None, Value::Optional(None)) => {}
+                        _ => frame.ip = to,
+                    }
+                }
+                Op::Next {
+                    collection,
+                    counter,
+                    bindings,
+                    done,
+                } => {
+                    let Value::Int(n) = self.stack[frame.base + counter] else {
+                        return Err(error(wrong_type()));
+                    };
+
+                    let item = match &self.stack[frame.base + collection] {
+                        Value::List(elements) => elements
+                            .get(n as usize)
+                            .map(|element| (Value::Int(n), element.clone())),
+                        Value::Map(entries) => entries.get(n as usize).cloned(),
+                        // ranges are iterated lazily, since they can be much
+                        // larger than anything that's been stored
+                        Value::Range { start, end } => start
+                            .checked_add(n)
+                            .filter(|i| i < end)
+                            .map(|i| (Value::Int(n), Value::Int(i))),
+                        _ => return Err(error(wrong_type())),
+                    };
+
+                    match item {
+                        Some((first, second)) => {
+                            self.stack[frame.base + counter] = Value::Int(n + 1);
+
+                            match bindings {
+                                Bindings::Both => self.stack.extend([first, second]),
+                                Bindings::First => self.stack.push(first),
+                                Bindings::Second => self.stack.push(second),
+                            }
+                        }
+                        None => frame.ip = done,
+                    }
+                }
+                Op::Truncate { height, keep } => {
+                    let top = keep.then(|| self.stack.pop().unwrap());
+
+                    self.stack.truncate(frame.base + function.locals + height);
+                    self.stack.extend(top);
+                }
+                Op::Catch(to) => self.handlers.push(Handler {
+                    frames: self.frames.len(),
+                    height: self.stack.len(),
+                    to,
+                }),
+                Op::EndCatch => {
+                    self.handlers.pop();
+                }
+            }
+        }
+    }
+
+    /// Goes on at the innermost handler with an error's message, leaving the
+    /// functions called since it started catching
+    fn catch(&mut self, frame: &mut Frame, message: String) {
+        let handler = self.handlers.pop().unwrap();
+
+        if self.frames.len() > handler.frames {
+            self.frames.truncate(handler.frames + 1);
+            *frame = self.frames.pop().unwrap();
+        }
+
+        self.stack.truncate(handler.height);
+        self.stack.push(Value::Str(message));
+
+        frame.ip = handler.to;
+    }
+
+    /// Drops the handlers of the running function when it returns, since it
+    /// can return from inside a `try` body
+    fn end_catches(&mut self) {
+        while self
+            .handlers
+            .last()
+            .is_some_and(|handler| handler.frames == self.frames.len())
+        {
+            self.handlers.pop();
+        }
+    }
+
+    /// Starts running the function or closure `instance`, whose arguments
+    /// start at `base`, in `frame`. The caller's frame is kept to go back to
+    /// when it returns, unless that would run more calls at once than the
+    /// program is allowed to.
+    fn call(
+        &mut self,
+        frame: &mut Frame,
+        instance: Handle,
+        base: usize,
+        ret: usize,
+    ) -> Result<(), Resource> {
+        if let Some(depth) = self
+            .limits
+            .depth
+            .filter(|&depth| self.frames.len() >= depth)
+        {
+            return Err(Resource::Depth(depth));
+        }
+
+        let function = self.heap.instances[instance.index()].function;
+
+        // the params are already in their slots
+        self.stack
+            .resize(base + self.program.functions[function].locals, Value::Unit);
+
+        let caller = std::mem::replace(
+            frame,
+            Frame {
+                function,
+                instance,
+                ip: 0,
+                base,
+                ret,
+            },
+        );
+
+        self.frames.push(caller);
+
+        Ok(())
+    }
+
+    /// Moves the value in a stack slot into a cell, leaving a reference to it,
+    /// and returns the cell
+    fn borrow(&mut self, slot: usize) -> Handle {
+        // a variable that is already borrowed, like a parameter taking a
+        // mutable reference, shares its cell
+        if let Value::Ref(id) = &self.stack[slot] {
+            return id.clone();
+        }
+
+        let value = std::mem::replace(&mut self.stack[slot], Value::Unit);
+        let id = self.heap.cell(value);
+
+        self.stack[slot] = Value::Ref(id.clone());
+
+        id
+    }
+
+    /// Makes room on the heap for one more object, which can collect garbage,
+    /// so every value that's still needed has to be on the stack, in a global
+    /// or in a frame
+    fn reserve(&mut self, frame: &Frame) -> Result<(), Resource> {
+        let roots = self.stack.iter().chain(self.globals.iter().flatten());
+        let running = self
+            .frames
+            .iter()
+            .chain([frame])
+            .map(|frame| frame.instance.index());
+
+        self.heap.reserve(roots, running)
+    }
+
+    fn deref(&self, value: &Value) -> Value {
+        match value {
+            Value::Ref(id) => self.heap.cells[id.index()].clone(),
+            value => value.clone(),
+        }
+    }
+
+    /// Pops the values of a record's or struct's fields, pairing them with
+    /// their names
+    fn fields(&mut self, shape: usize) -> Vec<(String, Value)> {
+        let names = &self.program.shapes[shape];
+        let values = self.stack.split_off(self.stack.len() - names.len());
+
+        names.iter().cloned().zip(values).collect()
+    }
+}
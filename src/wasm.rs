@@ -0,0 +1,1197 @@
+use crate::mir::*;
+use crate::typed_ast::{BinOp, FormatSpec, PrefixOp};
+use std::collections::HashMap;
+
+/// Compiles a program to a WebAssembly module, which needs the functions of
+/// `runtime/foxglove.js` to run. Only ints, floats, bools, chars and unit
+/// values are supported, along with functions that don't capture anything
+/// and printing.
+pub fn compile(program: &Program) -> Vec<u8> {
+    let mut compiler = Compiler {
+        types: vec![],
+        functions: vec![],
+        globals: vec![ValType::I64],
+        slots: vec![],
+        data: vec![],
+        strings: HashMap::new(),
+        code: vec![],
+    };
+
+    // every top level variable has a global for its value unless it's a unit
+    // value, and one that's set to 1 once it's initialized
+    for global in &program.globals {
+        let value = global.ty.map(|ty| {
+            compiler.globals.push(val_type(ty));
+            compiler.globals.len() as u32 - 1
+        });
+
+        compiler.globals.push(ValType::I32);
+        compiler
+            .slots
+            .push((value, compiler.globals.len() as u32 - 1));
+    }
+
+    for function in &program.functions {
+        let params = function.locals[..function.params]
+            .iter()
+            .map(|local| val_type(local.ty))
+            .collect();
+        let ty = compiler.type_index(params, function.returns.map(val_type));
+
+        compiler.functions.push((ty, None));
+    }
+
+    for (index, function) in program.functions.iter().enumerate() {
+        let body = compiler.compile_function(program, function);
+
+        compiler.functions[index].1 = Some(body);
+    }
+
+    compiler.module(function_index(0))
+}
+
+/// The functions the module imports from the runtime, in the order of their
+/// indices
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Import {
+    /// Writes the UTF-8 text at a pointer and length to stdout
+    WriteStr,
+    /// Writes an int in the given base
+    WriteInt,
+    WriteFloat,
+    /// Writes a float with the given number of digits after the point
+    WriteFixed,
+    /// Writes a char, quoted and escaped if the second argument isn't 0
+    WriteChar,
+    PowFloat,
+    RemFloat,
+    /// Stops the program with the runtime error at a pointer and length
+    Fail,
+    /// Stops the program because a float couldn't be cast to an int
+    FailToInt,
+    /// Stops the program because an int couldn't be cast to a char
+    FailToChar,
+}
+
+const IMPORTS: [(Import, &str, &[ValType], Option<ValType>); 10] = [
+    (
+        Import::WriteStr,
+        "write_str",
+        &[ValType::I32, ValType::I32],
+        None,
+    ),
+    (
+        Import::WriteInt,
+        "write_int",
+        &[ValType::I64, ValType::I32],
+        None,
+    ),
+    (Import::WriteFloat, "write_float", &[ValType::F64], None),
+    (
+        Import::WriteFixed,
+        "write_fixed",
+        &[ValType::F64, ValType::I32],
+        None,
+    ),
+    (
+        Import::WriteChar,
+        "write_char",
+        &[ValType::I32, ValType::I32],
+        None,
+    ),
+    (
+        Import::PowFloat,
+        "pow_float",
+        &[ValType::F64, ValType::F64],
+        Some(ValType::F64),
+    ),
+    (
+        Import::RemFloat,
+        "rem_float",
+        &[ValType::F64, ValType::F64],
+        Some(ValType::F64),
+    ),
+    (Import::Fail, "fail", &[ValType::I32, ValType::I32], None),
+    (Import::FailToInt, "fail_to_int", &[ValType::F64], None),
+    (Import::FailToChar, "fail_to_char", &[ValType::I64], None),
+];
+
+/// Functions defined in every module for the operations that have to be
+/// checked for errors, in the order of their indices after the imports
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Helper {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Shl,
+    Shr,
+    Neg,
+    FloatToInt,
+    IntToChar,
+}
+
+const HELPERS: [Helper; 11] = [
+    Helper::Add,
+    Helper::Sub,
+    Helper::Mul,
+    Helper::Div,
+    Helper::Rem,
+    Helper::Pow,
+    Helper::Shl,
+    Helper::Shr,
+    Helper::Neg,
+    Helper::FloatToInt,
+    Helper::IntToChar,
+];
+
+fn import_index(import: Import) -> u32 {
+    import as u32
+}
+
+fn helper_index(helper: Helper) -> u32 {
+    (IMPORTS.len() + helper as usize) as u32
+}
+
+fn function_index(function: FunctionId) -> u32 {
+    (IMPORTS.len() + HELPERS.len() + function) as u32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ValType {
+    I32,
+    I64,
+    F64,
+}
+
+impl ValType {
+    fn encode(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+            ValType::F64 => 0x7c,
+        }
+    }
+}
+
+fn val_type(ty: Ty) -> ValType {
+    match ty {
+        Ty::Int => ValType::I64,
+        Ty::Float => ValType::F64,
+        Ty::Bool | Ty::Char => ValType::I32,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Instr {
+    Unreachable,
+    Block(Option<ValType>),
+    Loop,
+    If(Option<ValType>),
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    /// Branches out of the block at the depth on the stack, which is one of
+    /// the given number of innermost blocks
+    BrTable(u32),
+    Return,
+    Call(u32),
+    Drop,
+    LocalGet(u32),
+    LocalSet(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    I32Const(i32),
+    I64Const(i64),
+    F64Const(f64),
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32LtU,
+    I32GtU,
+    I32LeU,
+    I32GeU,
+    I32And,
+    I32Or,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64GtS,
+    I64LeS,
+    I64GeS,
+    I64GtU,
+    F64Eq,
+    F64Ne,
+    F64Lt,
+    F64Gt,
+    F64Le,
+    F64Ge,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64RemS,
+    I64And,
+    I64Or,
+    I64Xor,
+    I64Shl,
+    I64ShrS,
+    F64Abs,
+    F64Neg,
+    F64Add,
+    F64Sub,
+    F64Mul,
+    F64Div,
+    I32WrapI64,
+    I64TruncF64S,
+    I64ExtendI32U,
+    F64ConvertI64S,
+}
+
+impl Instr {
+    fn encode(self, out: &mut Vec<u8>) {
+        let block_type = |ty: Option<ValType>| ty.map_or(0x40, ValType::encode);
+
+        match self {
+            Instr::Unreachable => out.push(0x00),
+            Instr::Block(ty) => out.extend([0x02, block_type(ty)]),
+            Instr::Loop => out.extend([0x03, 0x40]),
+            Instr::If(ty) => out.extend([0x04, block_type(ty)]),
+            Instr::Else => out.push(0x05),
+            Instr::End => out.push(0x0b),
+            Instr::Br(depth) => {
+                out.push(0x0c);
+                unsigned(out, depth.into());
+            }
+            Instr::BrIf(depth) => {
+                out.push(0x0d);
+                unsigned(out, depth.into());
+            }
+            Instr::BrTable(count) => {
+                out.push(0x0e);
+                unsigned(out, count.into());
+
+                for depth in 0..count {
+                    unsigned(out, depth.into());
+                }
+
+                // a depth past the end goes to the last block
+                unsigned(out, (count - 1).into());
+            }
+            Instr::Return => out.push(0x0f),
+            Instr::Call(function) => {
+                out.push(0x10);
+                unsigned(out, function.into());
+            }
+            Instr::Drop => out.push(0x1a),
+            Instr::LocalGet(local) => {
+                out.push(0x20);
+                unsigned(out, local.into());
+            }
+            Instr::LocalSet(local) => {
+                out.push(0x21);
+                unsigned(out, local.into());
+            }
+            Instr::GlobalGet(global) => {
+                out.push(0x23);
+                unsigned(out, global.into());
+            }
+            Instr::GlobalSet(global) => {
+                out.push(0x24);
+                unsigned(out, global.into());
+            }
+            Instr::I32Const(n) => {
+                out.push(0x41);
+                signed(out, n.into());
+            }
+            Instr::I64Const(n) => {
+                out.push(0x42);
+                signed(out, n);
+            }
+            Instr::F64Const(n) => {
+                out.push(0x44);
+                out.extend(n.to_le_bytes());
+            }
+            Instr::I32Eqz => out.push(0x45),
+            Instr::I32Eq => out.push(0x46),
+            Instr::I32Ne => out.push(0x47),
+            Instr::I32LtU => out.push(0x49),
+            Instr::I32GtU => out.push(0x4b),
+            Instr::I32LeU => out.push(0x4d),
+            Instr::I32GeU => out.push(0x4f),
+            Instr::I64Eqz => out.push(0x50),
+            Instr::I64Eq => out.push(0x51),
+            Instr::I64Ne => out.push(0x52),
+            Instr::I64LtS => out.push(0x53),
+            Instr::I64GtS => out.push(0x55),
+            Instr::I64GtU => out.push(0x56),
+            Instr::I64LeS => out.push(0x57),
+            Instr::I64GeS => out.push(0x59),
+            Instr::F64Eq => out.push(0x61),
+            Instr::F64Ne => out.push(0x62),
+            Instr::F64Lt => out.push(0x63),
+            Instr::F64Gt => out.push(0x64),
+            Instr::F64Le => out.push(0x65),
+            Instr::F64Ge => out.push(0x66),
+            Instr::I32And => out.push(0x71),
+            Instr::I32Or => out.push(0x72),
+            Instr::I64Add => out.push(0x7c),
+            Instr::I64Sub => out.push(0x7d),
+            Instr::I64Mul => out.push(0x7e),
+            Instr::I64DivS => out.push(0x7f),
+            Instr::I64RemS => out.push(0x81),
+            Instr::I64And => out.push(0x83),
+            Instr::I64Or => out.push(0x84),
+            Instr::I64Xor => out.push(0x85),
+            Instr::I64Shl => out.push(0x86),
+            Instr::I64ShrS => out.push(0x87),
+            Instr::F64Abs => out.push(0x99),
+            Instr::F64Neg => out.push(0x9a),
+            Instr::F64Add => out.push(0xa0),
+            Instr::F64Sub => out.push(0xa1),
+            Instr::F64Mul => out.push(0xa2),
+            Instr::F64Div => out.push(0xa3),
+            Instr::I32WrapI64 => out.push(0xa7),
+            Instr::I64ExtendI32U => out.push(0xad),
+            Instr::I64TruncF64S => out.push(0xb0),
+            Instr::F64ConvertI64S => out.push(0xb9),
+        }
+    }
+}
+
+/// Writes an unsigned LEB128 number
+fn unsigned(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+
+        n >>= 7;
+
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+/// Writes a signed LEB128 number
+fn signed(out: &mut Vec<u8>, mut n: i64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+
+        n >>= 7;
+
+        // done once the rest is only copies of the sign bit of this byte
+        if (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    unsigned(out, name.len() as u64);
+    out.extend(name.as_bytes());
+}
+
+/// Writes a section with a vector of `items` already encoded in `content`
+fn section(out: &mut Vec<u8>, id: u8, items: usize, content: Vec<u8>) {
+    let mut body = vec![];
+
+    unsigned(&mut body, items as u64);
+    body.extend(content);
+
+    out.push(id);
+    unsigned(out, body.len() as u64);
+    out.extend(body);
+}
+
+/// The instruction for an operator other than the short-circuiting ones,
+/// for operands of type `ty`
+fn binary(op: BinOp, ty: Ty) -> Instr {
+    match (ty, op) {
+        (Ty::Int, BinOp::Add) => Instr::Call(helper_index(Helper::Add)),
+        (Ty::Int, BinOp::Subtract) => Instr::Call(helper_index(Helper::Sub)),
+        (Ty::Int, BinOp::Multiply) => Instr::Call(helper_index(Helper::Mul)),
+        (Ty::Int, BinOp::Divide) => Instr::Call(helper_index(Helper::Div)),
+        (Ty::Int, BinOp::Modulo) => Instr::Call(helper_index(Helper::Rem)),
+        (Ty::Int, BinOp::Power) => Instr::Call(helper_index(Helper::Pow)),
+        (Ty::Int, BinOp::ShiftLeft) => Instr::Call(helper_index(Helper::Shl)),
+        (Ty::Int, BinOp::ShiftRight) => Instr::Call(helper_index(Helper::Shr)),
+        (Ty::Int, BinOp::BitAnd) => Instr::I64And,
+        (Ty::Int, BinOp::BitOr) => Instr::I64Or,
+        (Ty::Int, BinOp::BitXor) => Instr::I64Xor,
+        (Ty::Int, BinOp::Equals) => Instr::I64Eq,
+        (Ty::Int, BinOp::NotEquals) => Instr::I64Ne,
+        (Ty::Int, BinOp::LessThan) => Instr::I64LtS,
+        (Ty::Int, BinOp::LessThanOrEqual) => Instr::I64LeS,
+        (Ty::Int, BinOp::GreaterThan) => Instr::I64GtS,
+        (Ty::Int, BinOp::GreaterThanOrEqual) => Instr::I64GeS,
+        (Ty::Float, BinOp::Add) => Instr::F64Add,
+        (Ty::Float, BinOp::Subtract) => Instr::F64Sub,
+        (Ty::Float, BinOp::Multiply) => Instr::F64Mul,
+        (Ty::Float, BinOp::Divide) => Instr::F64Div,
+        (Ty::Float, BinOp::Modulo) => Instr::Call(import_index(Import::RemFloat)),
+        (Ty::Float, BinOp::Power) => Instr::Call(import_index(Import::PowFloat)),
+        (Ty::Float, BinOp::Equals) => Instr::F64Eq,
+        (Ty::Float, BinOp::NotEquals) => Instr::F64Ne,
+        (Ty::Float, BinOp::LessThan) => Instr::F64Lt,
+        (Ty::Float, BinOp::LessThanOrEqual) => Instr::F64Le,
+        (Ty::Float, BinOp::GreaterThan) => Instr::F64Gt,
+        (Ty::Float, BinOp::GreaterThanOrEqual) => Instr::F64Ge,
+        // a bool is an i32 holding 0 or 1 and a char one holding its code
+        // point, neither of which is ever negative
+        (Ty::Bool | Ty::Char, BinOp::Equals) => Instr::I32Eq,
+        (Ty::Bool | Ty::Char, BinOp::NotEquals) => Instr::I32Ne,
+        (Ty::Bool | Ty::Char, BinOp::LessThan) => Instr::I32LtU,
+        (Ty::Bool | Ty::Char, BinOp::LessThanOrEqual) => Instr::I32LeU,
+        (Ty::Bool | Ty::Char, BinOp::GreaterThan) => Instr::I32GtU,
+        (Ty::Bool | Ty::Char, BinOp::GreaterThanOrEqual) => Instr::I32GeU,
+        _ => unreachable!(),
+    }
+}
+
+struct Compiler {
+    /// Function signatures, referred to by their index
+    types: Vec<(Vec<ValType>, Option<ValType>)>,
+    /// The functions defined in the program, after the imports and helpers,
+    /// which are `None` until their body has been compiled
+    functions: Vec<(u32, Option<Body>)>,
+    /// The first global is the counter `tick()` returns
+    globals: Vec<ValType>,
+    /// The globals of each top level variable, with the one that's set to 1
+    /// once it's initialized
+    slots: Vec<(Option<u32>, u32)>,
+    /// The bytes placed at the start of memory, holding every string literal
+    data: Vec<u8>,
+    strings: HashMap<String, (u32, u32)>,
+    /// The code of the function being compiled
+    code: Vec<Instr>,
+}
+
+struct Body {
+    locals: Vec<ValType>,
+    code: Vec<Instr>,
+}
+
+impl Compiler {
+    fn emit(&mut self, instr: Instr) {
+        self.code.push(instr);
+    }
+
+    fn type_index(&mut self, params: Vec<ValType>, result: Option<ValType>) -> u32 {
+        let ty = (params, result);
+
+        match self.types.iter().position(|other| *other == ty) {
+            Some(index) => index as u32,
+            None => {
+                self.types.push(ty);
+                self.types.len() as u32 - 1
+            }
+        }
+    }
+
+    /// Places a string in memory, returning its pointer and length
+    fn string(&mut self, text: &str) -> (u32, u32) {
+        if let Some(&string) = self.strings.get(text) {
+            return string;
+        }
+
+        let string = (self.data.len() as u32, text.len() as u32);
+
+        self.data.extend(text.as_bytes());
+        self.strings.insert(text.to_string(), string);
+
+        string
+    }
+
+    fn write_str(&mut self, text: &str) {
+        let (ptr, len) = self.string(text);
+
+        self.emit(Instr::I32Const(ptr as i32));
+        self.emit(Instr::I32Const(len as i32));
+        self.emit(Instr::Call(import_index(Import::WriteStr)));
+    }
+
+    /// Emits code that stops the program with a runtime error
+    fn fail(&mut self, message: &str) {
+        let (ptr, len) = self.string(message);
+
+        self.emit(Instr::I32Const(ptr as i32));
+        self.emit(Instr::I32Const(len as i32));
+        self.emit(Instr::Call(import_index(Import::Fail)));
+        self.emit(Instr::Unreachable);
+    }
+
+    /// Compiles a function, whose params and locals are the same as in the
+    /// MIR. If it has more than one block, they're all inside a loop that
+    /// goes to the block whose index is in an extra local:
+    ///
+    /// ```text
+    /// loop
+    ///   block ... block
+    ///     br_table (the first block's depth is 0, the next one's is 1...)
+    ///   end
+    ///   the first block
+    ///   end
+    ///   the next block
+    ///   ...
+    /// end
+    /// ```
+    ///
+    /// so a block jumps to another one by setting the local and branching
+    /// to the loop, or falls through to the block after it.
+    fn compile_function(&mut self, program: &Program, function: &Function) -> Body {
+        let mut locals = function.locals[function.params..]
+            .iter()
+            .map(|local| val_type(local.ty))
+            .collect::<Vec<_>>();
+
+        let count = function.blocks.len() as u32;
+        let dispatch = count > 1 || !function.blocks[0].terminator.successors().is_empty();
+        let block_local = function.locals.len() as u32;
+
+        if dispatch {
+            locals.push(ValType::I32);
+
+            self.emit(Instr::Loop);
+
+            for _ in 0..count {
+                self.emit(Instr::Block(None));
+            }
+
+            self.emit(Instr::LocalGet(block_local));
+            self.emit(Instr::BrTable(count));
+            self.emit(Instr::End);
+        }
+
+        for (index, block) in function.blocks.iter().enumerate() {
+            for statement in &block.statements {
+                self.compile_statement(program, statement);
+            }
+
+            // how many blocks out the loop is from here
+            let depth = count - 1 - index as u32;
+
+            let jump = |compiler: &mut Compiler, target: BlockId, depth: u32| {
+                if target != index + 1 {
+                    compiler.emit(Instr::I32Const(target as i32));
+                    compiler.emit(Instr::LocalSet(block_local));
+                    compiler.emit(Instr::Br(depth));
+                }
+            };
+
+            match &block.terminator {
+                Terminator::Goto(target) => jump(self, *target, depth),
+                Terminator::Branch(cond, then, else_) => {
+                    self.operand(cond);
+                    self.emit(Instr::If(None));
+                    self.emit(Instr::I32Const(*then as i32));
+                    self.emit(Instr::LocalSet(block_local));
+                    self.emit(Instr::Br(depth + 1));
+                    self.emit(Instr::End);
+
+                    jump(self, *else_, depth);
+                }
+                Terminator::Return(value) => {
+                    if let Some(value) = value {
+                        self.operand(value);
+                    }
+
+                    self.emit(Instr::Return);
+                }
+                Terminator::Unreachable => self.emit(Instr::Unreachable),
+            }
+
+            if dispatch {
+                self.emit(Instr::End);
+            }
+        }
+
+        // the loop is only left by returning
+        if dispatch && function.returns.is_some() {
+            self.emit(Instr::Unreachable);
+        }
+
+        Body {
+            locals,
+            code: std::mem::take(&mut self.code),
+        }
+    }
+
+    fn compile_statement(&mut self, program: &Program, statement: &Statement) {
+        match statement {
+            Statement::Assign(local, value) => {
+                self.rvalue(value);
+                self.emit(Instr::LocalSet(*local as u32));
+            }
+            Statement::Eval(value) => {
+                self.rvalue(value);
+
+                let unit = match value {
+                    Rvalue::Call(callee, _, _) => program.functions[*callee].returns.is_none(),
+                    _ => false,
+                };
+
+                if !unit {
+                    self.emit(Instr::Drop);
+                }
+            }
+            Statement::SetGlobal(global, value) => {
+                self.operand(value);
+                self.emit(Instr::GlobalSet(self.slots[*global].0.unwrap()));
+            }
+            Statement::Initialize(global) => {
+                self.emit(Instr::I32Const(1));
+                self.emit(Instr::GlobalSet(self.slots[*global].1));
+            }
+            Statement::CheckInitialized(global, _) => {
+                self.emit(Instr::GlobalGet(self.slots[*global].1));
+                self.emit(Instr::I32Eqz);
+                self.emit(Instr::If(None));
+                self.fail(&format!(
+                    "'{}' is used before it's initialized",
+                    program.globals[*global].name
+                ));
+                self.emit(Instr::End);
+            }
+            Statement::Print(pieces) => {
+                for piece in pieces {
+                    match piece {
+                        Piece::Str(text) => self.write_str(text),
+                        Piece::Value(value, ty, spec) => self.write_value(value, *ty, *spec),
+                    }
+                }
+            }
+            // the runtime shows everything as soon as it's written
+            Statement::Flush => {}
+        }
+    }
+
+    fn operand(&mut self, operand: &Operand) {
+        let instr = match *operand {
+            Operand::Local(local) => Instr::LocalGet(local as u32),
+            Operand::Const(Const::Int(n)) => Instr::I64Const(n),
+            Operand::Const(Const::Float(n)) => Instr::F64Const(n),
+            Operand::Const(Const::Bool(b)) => Instr::I32Const(b as i32),
+            Operand::Const(Const::Char(c)) => Instr::I32Const(c as i32),
+        };
+
+        self.emit(instr);
+    }
+
+    fn rvalue(&mut self, value: &Rvalue) {
+        match value {
+            Rvalue::Use(value) => self.operand(value),
+            Rvalue::Prefix(op, ty, value, _) => {
+                self.operand(value);
+
+                match (op, ty) {
+                    (PrefixOp::Negate, Ty::Int) => {
+                        self.emit(Instr::Call(helper_index(Helper::Neg)))
+                    }
+                    (PrefixOp::Negate, _) => self.emit(Instr::F64Neg),
+                    (PrefixOp::Not, _) => self.emit(Instr::I32Eqz),
+                    (PrefixOp::BitNot, _) => {
+                        self.emit(Instr::I64Const(-1));
+                        self.emit(Instr::I64Xor);
+                    }
+                }
+            }
+            Rvalue::Binary(op, ty, lhs, rhs, _) => {
+                self.operand(lhs);
+                self.operand(rhs);
+                self.emit(binary(*op, *ty));
+            }
+            Rvalue::Cast(from, to, value, _) => {
+                self.operand(value);
+
+                let instr = match (from, to) {
+                    (Ty::Int, Ty::Float) => Instr::F64ConvertI64S,
+                    (Ty::Float, Ty::Int) => Instr::Call(helper_index(Helper::FloatToInt)),
+                    (Ty::Int, Ty::Char) => Instr::Call(helper_index(Helper::IntToChar)),
+                    _ => Instr::I64ExtendI32U,
+                };
+
+                self.emit(instr);
+            }
+            Rvalue::Global(global) => self.emit(Instr::GlobalGet(self.slots[*global].0.unwrap())),
+            Rvalue::Call(callee, args, _) => {
+                for arg in args {
+                    self.operand(arg);
+                }
+
+                self.emit(Instr::Call(function_index(*callee)));
+            }
+            Rvalue::Tick => {
+                self.emit(Instr::GlobalGet(0));
+                self.emit(Instr::GlobalGet(0));
+                self.emit(Instr::I64Const(1));
+                self.emit(Instr::I64Add);
+                self.emit(Instr::GlobalSet(0));
+            }
+        }
+    }
+
+    /// Writes a value the way a placeholder with `spec` shows it
+    fn write_value(&mut self, value: &Operand, ty: Ty, spec: Option<FormatSpec>) {
+        self.operand(value);
+
+        match (ty, spec) {
+            (Ty::Int, spec) => {
+                let radix = match spec {
+                    Some(FormatSpec::Hex) => 16,
+                    Some(FormatSpec::Binary) => 2,
+                    Some(FormatSpec::Octal) => 8,
+                    _ => 10,
+                };
+
+                self.emit(Instr::I32Const(radix));
+                self.emit(Instr::Call(import_index(Import::WriteInt)));
+            }
+            (Ty::Float, Some(FormatSpec::Precision(digits))) => {
+                self.emit(Instr::I32Const(digits as i32));
+                self.emit(Instr::Call(import_index(Import::WriteFixed)));
+            }
+            (Ty::Float, _) => self.emit(Instr::Call(import_index(Import::WriteFloat))),
+            (Ty::Bool, _) => {
+                self.emit(Instr::If(None));
+                self.write_str("true");
+                self.emit(Instr::Else);
+                self.write_str("false");
+                self.emit(Instr::End);
+            }
+            (Ty::Char, spec) => {
+                self.emit(Instr::I32Const((spec == Some(FormatSpec::Debug)) as i32));
+                self.emit(Instr::Call(import_index(Import::WriteChar)));
+            }
+        }
+    }
+
+    /// The type and body of a helper. Its params are the first locals, and
+    /// the ones it declares come right after them.
+    fn helper(&mut self, helper: Helper) -> (u32, Body) {
+        use Instr::*;
+
+        let mut locals = vec![];
+
+        let (params, result) = match helper {
+            Helper::Add | Helper::Sub => {
+                // overflowed if the result's sign can't come from the operands'
+                locals.push(ValType::I64);
+
+                let check = match helper {
+                    Helper::Add => [LocalGet(0), LocalGet(2), I64Xor, LocalGet(1), LocalGet(2)],
+                    _ => [LocalGet(0), LocalGet(1), I64Xor, LocalGet(0), LocalGet(2)],
+                };
+
+                let op = if helper == Helper::Add {
+                    I64Add
+                } else {
+                    I64Sub
+                };
+
+                for instr in [LocalGet(0), LocalGet(1), op, LocalSet(2)]
+                    .into_iter()
+                    .chain(check)
+                    .chain([I64Xor, I64And, I64Const(0), I64LtS, If(None)])
+                {
+                    self.emit(instr);
+                }
+
+                self.fail("integer overflow");
+
+                for instr in [End, LocalGet(2)] {
+                    self.emit(instr);
+                }
+
+                (vec![ValType::I64, ValType::I64], Some(ValType::I64))
+            }
+            Helper::Mul => {
+                locals.push(ValType::I64);
+
+                // -1 * i64::MIN is the one case where dividing the result by
+                // an operand doesn't give back the other one
+                for instr in [
+                    LocalGet(0),
+                    I64Const(-1),
+                    I64Eq,
+                    LocalGet(1),
+                    I64Const(i64::MIN),
+                    I64Eq,
+                    I32And,
+                    LocalGet(1),
+                    I64Const(-1),
+                    I64Eq,
+                    LocalGet(0),
+                    I64Const(i64::MIN),
+                    I64Eq,
+                    I32And,
+                    I32Or,
+                    If(None),
+                ] {
+                    self.emit(instr);
+                }
+
+                self.fail("integer overflow");
+
+                for instr in [
+                    End,
+                    LocalGet(0),
+                    LocalGet(1),
+                    I64Mul,
+                    LocalSet(2),
+                    LocalGet(0),
+                    I64Eqz,
+                    I32Eqz,
+                    If(None),
+                    LocalGet(2),
+                    LocalGet(0),
+                    I64DivS,
+                    LocalGet(1),
+                    I64Ne,
+                    If(None),
+                ] {
+                    self.emit(instr);
+                }
+
+                self.fail("integer overflow");
+
+                for instr in [End, End, LocalGet(2)] {
+                    self.emit(instr);
+                }
+
+                (vec![ValType::I64, ValType::I64], Some(ValType::I64))
+            }
+            Helper::Div | Helper::Rem => {
+                for instr in [LocalGet(1), I64Eqz, If(None)] {
+                    self.emit(instr);
+                }
+
+                self.fail("division by zero");
+
+                for instr in [
+                    End,
+                    LocalGet(0),
+                    I64Const(i64::MIN),
+                    I64Eq,
+                    LocalGet(1),
+                    I64Const(-1),
+                    I64Eq,
+                    I32And,
+                    If(None),
+                ] {
+                    self.emit(instr);
+                }
+
+                self.fail("integer overflow");
+
+                let op = if helper == Helper::Div {
+                    I64DivS
+                } else {
+                    I64RemS
+                };
+
+                for instr in [End, LocalGet(0), LocalGet(1), op] {
+                    self.emit(instr);
+                }
+
+                (vec![ValType::I64, ValType::I64], Some(ValType::I64))
+            }
+            Helper::Pow => {
+                // exponentiation by squaring, the same way `i64::checked_pow`
+                // does it so that it fails in the same cases
+                locals.push(ValType::I64);
+
+                for instr in [LocalGet(1), I64Const(0), I64LtS, If(None)] {
+                    self.emit(instr);
+                }
+
+                self.fail("negative exponent");
+
+                for instr in [
+                    End,
+                    LocalGet(1),
+                    I64Const(u32::MAX.into()),
+                    I64GtS,
+                    If(None),
+                ] {
+                    self.emit(instr);
+                }
+
+                self.fail("integer overflow");
+
+                for instr in [
+                    End,
+                    LocalGet(1),
+                    I64Eqz,
+                    If(None),
+                    I64Const(1),
+                    Return,
+                    End,
+                    I64Const(1),
+                    LocalSet(2),
+                    Block(None),
+                    Loop,
+                    LocalGet(1),
+                    I64Const(1),
+                    I64LeS,
+                    BrIf(1),
+                    LocalGet(1),
+                    I32WrapI64,
+                    I32Const(1),
+                    I32And,
+                    If(None),
+                    LocalGet(2),
+                    LocalGet(0),
+                    Call(helper_index(Helper::Mul)),
+                    LocalSet(2),
+                    End,
+                    LocalGet(1),
+                    I64Const(1),
+                    I64ShrS,
+                    LocalSet(1),
+                    LocalGet(0),
+                    LocalGet(0),
+                    Call(helper_index(Helper::Mul)),
+                    LocalSet(0),
+                    Br(0),
+                    End,
+                    End,
+                    LocalGet(2),
+                    LocalGet(0),
+                    Call(helper_index(Helper::Mul)),
+                ] {
+                    self.emit(instr);
+                }
+
+                (vec![ValType::I64, ValType::I64], Some(ValType::I64))
+            }
+            Helper::Shl | Helper::Shr => {
+                // shifting by a negative amount or by 64 or more is an error
+                // rather than wrapping around
+                for instr in [LocalGet(1), I64Const(63), I64GtU, If(None)] {
+                    self.emit(instr);
+                }
+
+                self.fail("integer overflow");
+
+                let op = if helper == Helper::Shl {
+                    I64Shl
+                } else {
+                    I64ShrS
+                };
+
+                for instr in [End, LocalGet(0), LocalGet(1), op] {
+                    self.emit(instr);
+                }
+
+                (vec![ValType::I64, ValType::I64], Some(ValType::I64))
+            }
+            Helper::Neg => {
+                for instr in [LocalGet(0), I64Const(i64::MIN), I64Eq, If(None)] {
+                    self.emit(instr);
+                }
+
+                self.fail("integer overflow");
+
+                for instr in [End, I64Const(0), LocalGet(0), I64Sub] {
+                    self.emit(instr);
+                }
+
+                (vec![ValType::I64], Some(ValType::I64))
+            }
+            Helper::FloatToInt => {
+                for instr in [
+                    LocalGet(0),
+                    F64Abs,
+                    F64Const(i64::MAX as f64),
+                    F64Lt,
+                    I32Eqz,
+                    If(None),
+                    LocalGet(0),
+                    Call(import_index(Import::FailToInt)),
+                    Unreachable,
+                    End,
+                    LocalGet(0),
+                    I64TruncF64S,
+                ] {
+                    self.emit(instr);
+                }
+
+                (vec![ValType::F64], Some(ValType::I64))
+            }
+            Helper::IntToChar => {
+                // anything above the last code point, or a surrogate
+                for instr in [
+                    LocalGet(0),
+                    I64Const(0x10ffff),
+                    I64GtU,
+                    LocalGet(0),
+                    I64Const(0xd800),
+                    I64GeS,
+                    LocalGet(0),
+                    I64Const(0xdfff),
+                    I64LeS,
+                    I32And,
+                    I32Or,
+                    If(None),
+                    LocalGet(0),
+                    Call(import_index(Import::FailToChar)),
+                    Unreachable,
+                    End,
+                    LocalGet(0),
+                    I32WrapI64,
+                ] {
+                    self.emit(instr);
+                }
+
+                (vec![ValType::I64], Some(ValType::I32))
+            }
+        };
+
+        let code = std::mem::take(&mut self.code);
+        let ty = self.type_index(params, result);
+
+        (ty, Body { locals, code })
+    }
+
+    fn module(mut self, main: u32) -> Vec<u8> {
+        let helpers = HELPERS.map(|helper| self.helper(helper));
+
+        // the types of the imports have to exist before the type section is written
+        let imports = IMPORTS
+            .map(|(_, name, params, result)| (name, self.type_index(params.to_vec(), result)));
+
+        let mut out = b"\0asm".to_vec();
+
+        out.extend(1u32.to_le_bytes());
+
+        let mut types = vec![];
+
+        for (params, result) in &self.types {
+            types.push(0x60);
+            unsigned(&mut types, params.len() as u64);
+            types.extend(params.iter().map(|param| param.encode()));
+            unsigned(&mut types, result.is_some() as u64);
+            types.extend(result.map(ValType::encode));
+        }
+
+        section(&mut out, 1, self.types.len(), types);
+
+        let mut import_section = vec![];
+
+        for (field, ty) in imports {
+            name(&mut import_section, "env");
+            name(&mut import_section, field);
+            import_section.push(0x00);
+            unsigned(&mut import_section, ty.into());
+        }
+
+        section(&mut out, 2, imports.len(), import_section);
+
+        let bodies = helpers
+            .into_iter()
+            .chain(
+                self.functions
+                    .into_iter()
+                    .map(|(ty, body)| (ty, body.unwrap())),
+            )
+            .collect::<Vec<_>>();
+
+        let mut functions = vec![];
+
+        for (ty, _) in &bodies {
+            unsigned(&mut functions, (*ty).into());
+        }
+
+        section(&mut out, 3, bodies.len(), functions);
+
+        // enough pages of 64 KiB to hold the strings
+        let pages = (self.data.len() as u64).div_ceil(0x10000).max(1);
+        let mut memory = vec![0x00];
+
+        unsigned(&mut memory, pages);
+        section(&mut out, 5, 1, memory);
+
+        let mut globals = vec![];
+
+        for global in &self.globals {
+            globals.extend([global.encode(), 0x01]);
+
+            let zero = match global {
+                ValType::I32 => Instr::I32Const(0),
+                ValType::I64 => Instr::I64Const(0),
+                ValType::F64 => Instr::F64Const(0.0),
+            };
+
+            zero.encode(&mut globals);
+            Instr::End.encode(&mut globals);
+        }
+
+        section(&mut out, 6, self.globals.len(), globals);
+
+        let mut exports = vec![];
+
+        name(&mut exports, "memory");
+        exports.push(0x02);
+        unsigned(&mut exports, 0);
+        name(&mut exports, "main");
+        exports.push(0x00);
+        unsigned(&mut exports, main.into());
+
+        section(&mut out, 7, 2, exports);
+
+        let mut code = vec![];
+
+        for (_, body) in &bodies {
+            let mut function = vec![];
+
+            // runs of locals with the same type
+            let mut runs: Vec<(u32, ValType)> = vec![];
+
+            for &local in &body.locals {
+                match runs.last_mut() {
+                    Some((count, ty)) if *ty == local => *count += 1,
+                    _ => runs.push((1, local)),
+                }
+            }
+
+            unsigned(&mut function, runs.len() as u64);
+
+            for (count, ty) in runs {
+                unsigned(&mut function, count.into());
+                function.push(ty.encode());
+            }
+
+            for instr in &body.code {
+                instr.encode(&mut function);
+            }
+
+            Instr::End.encode(&mut function);
+
+            unsigned(&mut code, function.len() as u64);
+            code.extend(function);
+        }
+
+        section(&mut out, 10, bodies.len(), code);
+
+        let mut data = vec![0x00];
+
+        Instr::I32Const(0).encode(&mut data);
+        Instr::End.encode(&mut data);
+        unsigned(&mut data, self.data.len() as u64);
+        data.extend(&self.data);
+
+        section(&mut out, 11, 1, data);
+
+        out
+    }
+}
@@ -0,0 +1,456 @@
+//! Runs whole programs through the `foxglove` binary and checks what they
+//! print, or which errors they're rejected with
+
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Runs `source` as the main file of a program with `args` before its path,
+/// returning whether it succeeded, what it printed and its diagnostics, which
+/// have their colours left out. It only failed if it reported an error, since
+/// the program is still left to exit successfully after reporting them.
+fn execute(args: &[&str], source: &str) -> (bool, String, String) {
+    // tests run in parallel, so each program gets a file of its own
+    static PROGRAMS: AtomicUsize = AtomicUsize::new(0);
+
+    let path = std::env::temp_dir().join(format!(
+        "foxglove-test-{}-{}.fox",
+        std::process::id(),
+        PROGRAMS.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    std::fs::write(&path, source).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_foxglove"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+
+    let mut stderr = String::from_utf8(output.stderr).unwrap();
+
+    while let Some(start) = stderr.find('\x1b') {
+        let end = start + stderr[start..].find('m').unwrap();
+
+        stderr.replace_range(start..=end, "");
+    }
+
+    (
+        output.status.success() && !stderr.contains("] Error:"),
+        String::from_utf8(output.stdout).unwrap(),
+        stderr,
+    )
+}
+
+/// Runs a program, returning what it printed if it succeeded, or its
+/// diagnostics if not
+fn run_with(args: &[&str], source: &str) -> Result<String, String> {
+    match execute(args, source) {
+        (true, stdout, _) => Ok(stdout),
+        (false, _, stderr) => Err(stderr),
+    }
+}
+
+fn run(source: &str) -> Result<String, String> {
+    run_with(&[], source)
+}
+
+/// Runs a program that should be rejected, returning its diagnostics
+fn reject(source: &str) -> String {
+    run(source).expect_err("the program should have been rejected")
+}
+
+#[test]
+fn struct_shadowed_by_type_parameter_or_alias() {
+    let output = run("
+        struct P { x: int }
+        let p = P { x = 1 };
+        func f<P>(v: P): int { return p.x; }
+        println(\"{}\", f(2));
+        { type P = int; println(\"{}\", p.x); };
+    ");
+
+    assert_eq!(output.unwrap(), "1\n1\n");
+}
+
+#[test]
+fn struct_shadowed_by_struct() {
+    let source = "
+        struct P { x: int }
+        impl P { func get(self): int { return self.x; } }
+        let p = P { x = 1 };
+        {
+            struct P { y: int }
+            impl P { func get(self): int { return self.y * 10; } }
+            let q = P { y = 2 };
+            println(\"{} {} {} {}\", p.get(), q.get(), p, q);
+        };
+    ";
+
+    for args in [&[][..], &["--vm"]] {
+        assert_eq!(
+            run_with(args, source).unwrap(),
+            "1 20 P { x = 1 } P { y = 2 }\n"
+        );
+    }
+
+    let errors = reject(
+        "
+        struct P { x: int }
+        let p = P { x = 1 };
+        { struct P { y: bool } println(\"{}\", p.y); };
+    ",
+    );
+
+    assert!(errors.contains("has no field named"));
+}
+
+#[test]
+fn enum_shadowed_by_enum() {
+    let output = run("
+        enum E { A, B }
+        let e = E::A;
+        { enum E { C } println(\"{} {} {}\", e is A, e, E::C); };
+    ");
+
+    assert_eq!(output.unwrap(), "true E::A E::C\n");
+}
+
+#[test]
+fn deep_recursion_runs_out_of_stack_with_an_error() {
+    let errors = reject(
+        "
+        func f(n: int): int { if n == 0 { return 0; }; return f(n - 1) + 1; }
+        println(\"{}\", f(10000000));
+    ",
+    );
+
+    assert!(errors.contains("the stack ran out of room"));
+    assert!(errors.contains("Calls 'f'"));
+}
+
+#[test]
+fn statements_ending_in_a_block_need_no_semicolon() {
+    let output = run("
+        let c = true;
+        if c { println(\"if\"); } else { println(\"else\"); }
+        match some(1) { some(x) => println(\"{}\", x), none => println(\"none\") }
+        loop { break; }
+        { println(\"block\"); 1 }
+        'l: { println(\"label\"); break 'l; }
+        let v = { if c { 2 } else { 3 } };
+        let w = 'k: { if c { break 'k 4; } 5 };
+        println(\"{} {}\", v, w);
+    ");
+
+    assert_eq!(output.unwrap(), "if\n1\nblock\nlabel\n2 4\n");
+
+    assert!(reject("println(\"a\") println(\"b\");").contains("expected"));
+}
+
+#[test]
+fn branches_and_list_elements_join_records_in_any_order() {
+    let output = run("
+        let c = true;
+        let a = if c { {a = 1} } else { {a = 2, b = 3} };
+        let b = if c { {a = 4, b = 5} } else { {a = 6} };
+        let xs = [{a = 7, b = 8}, {a = 9}];
+        let ys = [{a = 10}, {a = 11, b = 12}];
+        let m = match some(13) { some(x) => {a = x, b = 0}, none => {a = 0} };
+        println(\"{} {} {} {} {}\", a.a, b.a, xs[1].a, ys[1].a, m.a);
+    ");
+
+    assert_eq!(output.unwrap(), "1 4 9 11 13\n");
+
+    let errors =
+        reject("let b = if true { {a = 1, b = 2} } else { {a = 1} }; println(\"{}\", b.b);");
+
+    assert!(errors.contains("has no field named"));
+
+    let errors = reject("let d = if true { {a = 1} } else { {b = 1} };");
+
+    assert!(errors.contains("Type mismatch"));
+}
+
+#[test]
+fn explain_types_follows_parts_known_later() {
+    let (ok, _, diagnostics) = execute(
+        &["--explain-types"],
+        "let mut xs = [];\nxs = [1];\nprintln(\"{}\", xs);\n",
+    );
+
+    assert!(ok);
+    assert!(diagnostics.contains("'xs' has type 'List<int>'"));
+    assert!(diagnostics.contains("Which is where its type comes from"));
+    assert!(diagnostics.contains("Which is where that part of its type comes from"));
+}
+
+#[test]
+fn missing_method_lists_the_methods_there_are() {
+    let errors = reject(
+        "
+        struct Point { x: int, f: func(int): int }
+        trait Show { func show(self): str; }
+        impl Point { func len(self): int { return self.x; } }
+        impl Show for Point { func show(self): str { return \"p\"; } }
+        let p = Point { x = 1, f = |a| a + 1 };
+        println(\"{}\", p.f(2));
+        println(\"{}\", p.nope());
+    ",
+    );
+
+    assert!(errors.contains("[35] Error: Type 'Point' has no method named 'nope'"));
+    assert!(errors.contains("Methods it has: 'len', 'show'"));
+}
+
+#[test]
+fn match_reports_unreachable_arms_and_missing_patterns() {
+    let (ok, output, diagnostics) = execute(
+        &[],
+        "
+        enum Shape { Circle(float), Square(float), Dot }
+        let s = Shape::Dot;
+        println(\"{}\", match s { Dot => 1, _ => 2, Circle(r) => 3 });
+    ",
+    );
+
+    assert!(ok);
+    assert_eq!(output, "1\n");
+    assert!(diagnostics.contains("[01] Warning"));
+
+    let errors = reject(
+        "
+        enum Shape { Circle(float), Square(float), Dot }
+        println(\"{}\", match Shape::Dot { Circle(r) => 1 });
+    ",
+    );
+
+    assert!(errors.contains("Patterns not covered: 'Square(_)', 'Dot'"));
+}
+
+#[test]
+fn labeled_blocks_break_with_a_value() {
+    let source = "
+        let c = true;
+        let v = 'b: { if c { break 'b 42; }; 0 };
+        let w = 'b: { if !c { break 'b 1; }; 2 };
+        let u = 'b: { break 'b 7; };
+        println(\"{} {} {}\", v, w, u);
+    ";
+
+    for args in [&[][..], &["--vm"]] {
+        assert_eq!(run_with(args, source).unwrap(), "42 2 7\n");
+    }
+
+    let errors = reject("let v = 'b: { if true { break 'b 42; }; \"no\" };");
+
+    assert!(errors.contains("Type mismatch"));
+}
+
+#[test]
+fn field_access_on_a_non_record() {
+    let errors = reject("println(\"{}\", (5).x);");
+
+    assert!(errors.contains("[07] Error: Cannot access field 'x' on type 'int'"));
+    assert!(errors.contains("This has type 'int', which is not a record"));
+}
+
+#[test]
+fn type_aliases_stand_for_their_type() {
+    let output = run("
+        type Num = int;
+        type Pair = {a: Num, b: Num};
+        let n: Num = 3;
+        let m: int = n + 1;
+        let p: Pair = {a = n, b = m};
+        func add(x: Num, y: int): Num { return x + y; }
+        println(\"{} {} {}\", m, p.b, add(p.a, 2));
+    ");
+
+    assert_eq!(output.unwrap(), "4 4 5\n");
+
+    assert!(reject("type Num = int; let s: Num = \"x\";").contains("Type mismatch"));
+
+    let errors = reject("type A = A;");
+
+    assert!(errors.contains("[14] Error: Type alias 'A' refers to itself"));
+}
+
+#[test]
+fn logical_operators_fold_without_their_skipped_operand() {
+    let source = "
+        let x = false && (1 / 0 == 0);
+        let y = true || (1 / 0 == 0);
+        println(\"{} {}\", x, y);
+    ";
+
+    let folded = run_with(&["--bytecode"], source).unwrap();
+    let unfolded = run_with(&["--bytecode", "--no-fold"], source).unwrap();
+
+    assert!(!folded.contains("Divide"));
+    assert!(unfolded.contains("Divide"));
+
+    for args in [&[][..], &["--vm"], &["--no-fold"]] {
+        assert_eq!(run_with(args, source).unwrap(), "false true\n");
+    }
+}
+
+#[test]
+fn misspelled_variant_pattern_is_not_a_binding() {
+    let source = "
+        enum Color { Red, Green }
+        let c = Color::Green;
+        println(\"{}\", match c { Red => 1, other => 2 });
+    ";
+
+    assert_eq!(run(source).unwrap(), "2\n");
+
+    let errors = reject(
+        "
+        enum Color { Red, Green }
+        println(\"{}\", match Color::Green { Red => 1, Gren => 2 });
+    ",
+    );
+
+    assert!(errors.contains("[09] Error: Enum 'Color' has no variant named 'Gren'"));
+}
+
+#[test]
+fn empty_block_in_a_string_is_rejected() {
+    let errors = reject("println(\"{{}}\");");
+
+    assert!(errors.contains("Empty block in a string"));
+
+    let output = run("let x = 1; println(\"\\{}} {{x}} { {a = 2} }\");");
+
+    assert_eq!(output.unwrap(), "{}} 1 { a = 2 }\n");
+}
+
+#[test]
+fn parenthesized_block_expression_keeps_its_value() {
+    let output = run("
+        let c = true;
+        let x = { (if c { 1 } else { 2 }) };
+        let y = if c { (if c { 1 } else { 2 }) } else { 3 };
+        let z = { (match some(4) { some(v) => v, none => 0 }) };
+        println(\"{} {} {}\", x, y, z);
+    ");
+
+    assert_eq!(output.unwrap(), "1 1 4\n");
+
+    let errors = reject("let c = true; (if c { 1 } else { 2 }) println(\"a\");");
+
+    assert!(errors.contains("expected"));
+}
+
+#[test]
+fn damaged_compiled_program_is_rejected_or_stops_with_an_error() {
+    let dir = std::env::temp_dir();
+    let id = std::process::id();
+    let source = dir.join(format!("foxglove-test-{}-damaged.fox", id));
+    let compiled = dir.join(format!("foxglove-test-{}-damaged.fxc", id));
+    let damaged = dir.join(format!("foxglove-test-{}-damaged-copy.fxc", id));
+
+    let program = "func f(a: int): int { return -a; }\nprintln(\"{}\", f(2) + len(\"ab\"));\n";
+
+    std::fs::write(&source, program).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_foxglove"))
+        .arg("build")
+        .arg(&source)
+        .arg("-o")
+        .arg(&compiled)
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+
+    let bytes = std::fs::read(&compiled).unwrap();
+
+    // damaging the sources would only change how errors are shown
+    let start = bytes
+        .windows(program.len())
+        .position(|window| window == program.as_bytes())
+        .unwrap()
+        + program.len();
+
+    let mut wrong_types = 0;
+
+    for at in start..bytes.len() {
+        let mut bytes = bytes.clone();
+
+        bytes[at] ^= 0x03;
+
+        std::fs::write(&damaged, &bytes).unwrap();
+
+        let output = Command::new(env!("CARGO_BIN_EXE_foxglove"))
+            .args(["run", "--fuel", "1000"])
+            .arg(&damaged)
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        assert!(
+            !stderr.contains("panicked"),
+            "damaging byte {} made it panic: {}",
+            at,
+            stderr
+        );
+
+        if stderr.contains("has the wrong type") {
+            wrong_types += 1;
+        }
+    }
+
+    for path in [source, compiled, damaged] {
+        std::fs::remove_file(path).unwrap();
+    }
+
+    assert!(wrong_types > 0);
+}
+
+#[test]
+fn record_with_more_fields_is_passed_where_fewer_are_expected() {
+    let output = run("
+        func sum(p: {x: int, y: int}): int { return p.x + p.y; }
+        println(\"{}\", sum({x = 1, y = 2, z = 3}));
+    ");
+
+    assert_eq!(output.unwrap(), "3\n");
+
+    let errors = reject(
+        "
+        func sum(p: {x: int, y: int}): int { return p.x + p.y; }
+        println(\"{}\", sum({x = 1}));
+    ",
+    );
+
+    assert!(errors.contains("[18] Error: Missing field 'y'"));
+}
+
+#[test]
+fn only_one_backend_can_be_chosen() {
+    let (ok, _, stderr) = execute(&["--vm", "--native"], "println(\"a\");");
+
+    assert!(!ok);
+    assert!(stderr.contains("cannot be used with"));
+}
+
+#[test]
+fn limits_are_rejected_by_backends_that_ignore_them() {
+    let path = std::env::temp_dir().join(format!("foxglove-test-{}-limits.c", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    for args in [
+        ["--fuel", "10", "--c", path],
+        ["--max-depth", "10", "--wasm", path],
+        ["--fuel", "10", "--native", "-O2"],
+    ] {
+        let (ok, _, stderr) = execute(&args, "println(\"a\");");
+
+        assert!(!ok);
+        assert!(stderr.contains("cannot be used with"));
+    }
+}